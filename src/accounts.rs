@@ -0,0 +1,266 @@
+//! This module contains the loaders fetching secrets from several AWS accounts in one request,
+//! e.g. for a central Vector aggregator with sinks in member accounts. Secrets are requested with
+//! the account as a prefix, e.g. `prod.db_password`, and secrets requested without one are fetched
+//! from every account whose patterns match their name.
+
+use crate::aws::loader::LoadSecrets;
+use crate::aws::options::invalid_request;
+use crate::vector::{FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// The separator between the account and the name of a requested secret.
+const SEPARATOR: char = '.';
+
+/// Split a requested secret into its account and name, if it starts with one of the accounts.
+fn split_account<'a, T>(
+    requested: &'a str,
+    accounts: &BTreeMap<String, T>,
+) -> Option<(&'a str, &'a str)> {
+    requested
+        .split_once(SEPARATOR)
+        .filter(|(account, _)| accounts.contains_key(*account))
+}
+
+/// A struct fetching the secrets requested with an account prefix from the loader of the account.
+pub struct AccountsLoader {
+    accounts: BTreeMap<String, Box<dyn LoadSecrets + Send + Sync>>,
+}
+
+/// Implement the AccountsLoader constructor.
+impl AccountsLoader {
+    pub fn new(accounts: BTreeMap<String, Box<dyn LoadSecrets + Send + Sync>>) -> Self {
+        Self { accounts }
+    }
+}
+
+/// Implement the LoadSecrets trait for AccountsLoader.
+#[async_trait]
+impl LoadSecrets for AccountsLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let mut fetched_secrets = FetchedSecrets::default();
+        let mut batches: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for requested in &secrets.secrets {
+            match split_account(requested, &self.accounts) {
+                Some((account, name)) => {
+                    batches.entry(account).or_default().push(name.to_string());
+                }
+                None => {
+                    let error = format!(
+                        "no account selected, prefix the name with one of: {}",
+                        self.accounts.keys().cloned().collect::<Vec<_>>().join(", ")
+                    );
+                    fetched_secrets
+                        .0
+                        .insert(requested.clone(), invalid_request(error));
+                }
+            }
+        }
+
+        let results = futures::future::join_all(batches.into_iter().map(|(account, names)| {
+            let loader = &self.accounts[account];
+            let secrets_to_fetch = SecretsToFetch {
+                version: secrets.version.clone(),
+                secrets: names,
+            };
+            async move { (account, loader.load(secrets_to_fetch).await) }
+        }))
+        .await;
+        for (account, fetched) in results {
+            for (name, secret) in fetched.0 {
+                let requested = format!("{}{}{}", account, SEPARATOR, name);
+                fetched_secrets.0.insert(requested, secret);
+            }
+        }
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        let (account, name) = split_account(requested, &self.accounts)?;
+        self.accounts[account].current_version(name).await
+    }
+}
+
+/// A struct wrapping a secrets loader to fetch the secrets requested without an account prefix
+/// from every account whose patterns match their name, returning them under account-prefixed
+/// keys, e.g. `prod.db_password` and `dev.db_password` for `db_password`.
+pub struct FanOutLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    /// The patterns of the names fetched from each account, none to fetch all names.
+    accounts: BTreeMap<String, Vec<Regex>>,
+    /// The backends selected by a prefix, e.g. `ssm` for `ssm:/app/db_password`, with whether
+    /// they fetch secrets from the accounts. The prefix is kept in front of the account.
+    backends: Vec<(&'static str, bool)>,
+}
+
+/// Implement the FanOutLoader constructor.
+impl FanOutLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        accounts: BTreeMap<String, Vec<Regex>>,
+        backends: Vec<(&'static str, bool)>,
+    ) -> Self {
+        Self {
+            inner,
+            accounts,
+            backends,
+        }
+    }
+}
+
+/// Implement the FanOutLoader methods.
+impl FanOutLoader {
+    /// The names to fetch for a requested secret, prefixed with the accounts it's fetched from.
+    fn fan_out(&self, requested: &str) -> Vec<String> {
+        let backend = self.backends.iter().find_map(|(backend, has_accounts)| {
+            let name = requested.strip_prefix(backend)?.strip_prefix(':')?;
+            Some((&requested[..backend.len() + 1], name, *has_accounts))
+        });
+        let (backend, name) = match backend {
+            Some((_, _, false)) => return vec![requested.to_string()],
+            Some((backend, name, true)) => (backend, name),
+            None => ("", requested),
+        };
+        if split_account(name, &self.accounts).is_some() {
+            return vec![requested.to_string()];
+        }
+        self.accounts
+            .iter()
+            .filter(|(_, patterns)| {
+                patterns.is_empty() || patterns.iter().any(|pattern| pattern.is_match(name))
+            })
+            .map(|(account, _)| format!("{}{}{}{}", backend, account, SEPARATOR, name))
+            .collect()
+    }
+}
+
+/// Implement the LoadSecrets trait for FanOutLoader.
+#[async_trait]
+impl LoadSecrets for FanOutLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let mut fetched_secrets = FetchedSecrets::default();
+        let mut names = Vec::new();
+        for requested in secrets.secrets {
+            let fanned_out = self.fan_out(&requested);
+            if fanned_out.is_empty() {
+                let error = String::from("the name doesn't match the patterns of any account");
+                fetched_secrets.0.insert(requested, invalid_request(error));
+            }
+            names.extend(fanned_out);
+        }
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            return fetched_secrets;
+        }
+
+        let fetched = self
+            .inner
+            .load(SecretsToFetch {
+                version: secrets.version,
+                secrets: names,
+            })
+            .await;
+        fetched_secrets.0.extend(fetched.0);
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        self.inner.current_version(requested).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::ErrorCode;
+
+    struct MockLoader {
+        account: &'static str,
+    }
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| {
+                        let value = format!("{} in {}", name, self.account);
+                        (name, Ok(value).into())
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    fn accounts_loader() -> AccountsLoader {
+        let mut accounts: BTreeMap<String, Box<dyn LoadSecrets + Send + Sync>> = BTreeMap::new();
+        for account in ["dev", "prod"] {
+            accounts.insert(account.to_string(), Box::new(MockLoader { account }));
+        }
+        AccountsLoader::new(accounts)
+    }
+
+    #[tokio::test]
+    async fn secrets_are_fetched_from_the_account_of_their_prefix() {
+        let fetched_secrets = accounts_loader()
+            .load(SecretsToFetch::from_names(vec![
+                String::from("prod./app/db"),
+                String::from("dev.api.key"),
+                String::from("staging.db"),
+            ]))
+            .await;
+
+        let value = |name: &str| fetched_secrets.0[name].value.clone();
+        assert_eq!(
+            value("prod./app/db"),
+            Some(Secret::new(String::from("/app/db in prod")))
+        );
+        assert_eq!(
+            value("dev.api.key"),
+            Some(Secret::new(String::from("api.key in dev")))
+        );
+        assert_eq!(
+            fetched_secrets.0["staging.db"].code,
+            Some(ErrorCode::InvalidRequest)
+        );
+    }
+
+    #[tokio::test]
+    async fn secrets_without_an_account_are_fetched_from_all_matching_accounts() {
+        let loader = FanOutLoader::new(
+            Box::new(accounts_loader()),
+            BTreeMap::from([
+                (String::from("dev"), Vec::new()),
+                (String::from("prod"), vec![Regex::new("^db").unwrap()]),
+            ]),
+            vec![("ssm", true), ("gcp", false)],
+        );
+
+        assert_eq!(
+            loader.fan_out("db_password"),
+            vec!["dev.db_password", "prod.db_password"]
+        );
+        assert_eq!(loader.fan_out("api_key"), vec!["dev.api_key"]);
+        assert_eq!(loader.fan_out("prod.api_key"), vec!["prod.api_key"]);
+        assert_eq!(loader.fan_out("ssm:db"), vec!["ssm:dev.db", "ssm:prod.db"]);
+        assert_eq!(loader.fan_out("gcp:db"), vec!["gcp:db"]);
+
+        let fetched_secrets = loader
+            .load(SecretsToFetch::from_names(vec![
+                String::from("db_password"),
+                String::from("prod.api_key"),
+            ]))
+            .await;
+        let mut keys: Vec<_> = fetched_secrets.0.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec!["dev.db_password", "prod.api_key", "prod.db_password"]
+        );
+    }
+}
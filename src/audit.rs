@@ -0,0 +1,198 @@
+//! This module contains the audit log recording which secrets were accessed. Secret values are
+//! never recorded.
+
+use crate::aws::loader::LoadSecrets;
+use crate::diagnostics::{Diagnostic, Level};
+use crate::vector::{FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
+
+/// A struct representing the access to a single secret in an audit record.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccessedSecret {
+    pub name: String,
+    pub success: bool,
+}
+
+/// A struct representing a single line of the audit log.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AuditRecord {
+    pub timestamp: String,
+    pub caller: Option<String>,
+    pub backend: String,
+    pub secrets: Vec<AccessedSecret>,
+}
+
+/// Implement the AuditRecord constructor.
+impl AuditRecord {
+    pub fn new(caller: Option<String>, backend: &str, fetched_secrets: &FetchedSecrets) -> Self {
+        let mut secrets: Vec<_> = fetched_secrets
+            .0
+            .iter()
+            .map(|(name, secret)| AccessedSecret {
+                name: name.clone(),
+                success: secret.error.is_none(),
+            })
+            .collect();
+        secrets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            caller,
+            backend: backend.to_string(),
+            secrets,
+        }
+    }
+}
+
+/// Append a record to the audit log as a single JSON line, creating the file if necessary.
+pub fn append(path: &Path, record: &AuditRecord) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    // Write the whole line at once so that concurrent writers don't interleave their records.
+    let line = format!("{}\n", serde_json::to_string(record)?);
+    options.open(path)?.write_all(line.as_bytes())
+}
+
+/// A struct wrapping a secrets loader to record every request it serves in the audit log.
+pub struct AuditedLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    backend: String,
+    /// The identity calling the backend, set once a request reached the backend and it was looked
+    /// up. Records of requests answered before, e.g. from the disk cache, have no caller.
+    caller: Arc<OnceLock<String>>,
+    path: PathBuf,
+}
+
+/// Implement the AuditedLoader constructor.
+impl AuditedLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        backend: &str,
+        caller: Arc<OnceLock<String>>,
+        path: PathBuf,
+    ) -> Self {
+        Self {
+            inner,
+            backend: backend.to_string(),
+            caller,
+            path,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for AuditedLoader.
+#[async_trait]
+impl LoadSecrets for AuditedLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let fetched_secrets = self.inner.load(secrets).await;
+
+        let caller = self.caller.get().cloned();
+        let record = AuditRecord::new(caller, &self.backend, &fetched_secrets);
+        if let Err(error) = append(&self.path, &record) {
+            Diagnostic::new(
+                Level::Warning,
+                "audit_log_write_failed",
+                format!(
+                    "failed to write audit log {}: {}",
+                    self.path.display(),
+                    error
+                ),
+            )
+            .emit();
+        }
+
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        self.inner.current_version(requested).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::FetchedSecret;
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, _secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                [
+                    (
+                        "test.secret_1".to_string(),
+                        FetchedSecret {
+                            value: Some(Secret::new("qwerty".to_string())),
+                            error: None,
+                            code: None,
+                        },
+                    ),
+                    (
+                        "test.secret_2".to_string(),
+                        FetchedSecret {
+                            value: None,
+                            error: Some("failed to fetch".to_string()),
+                            code: None,
+                        },
+                    ),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn audited_loader_appends_records_without_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let secrets_loader = AuditedLoader::new(
+            Box::new(MockLoader {}),
+            "ssm",
+            Arc::new(OnceLock::from(String::from(
+                "arn:aws:iam::123456789012:role/vector",
+            ))),
+            path.clone(),
+        );
+
+        let secrets_to_fetch = SecretsToFetch {
+            version: String::from("1.0"),
+            secrets: vec![String::from("test.secret_1"), String::from("test.secret_2")],
+        };
+        secrets_loader.load(secrets_to_fetch).await;
+        let secrets_to_fetch = SecretsToFetch {
+            version: String::from("1.0"),
+            secrets: vec![String::from("test.secret_1")],
+        };
+        secrets_loader.load(secrets_to_fetch).await;
+
+        let audit_log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = audit_log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(!audit_log.contains("qwerty"));
+
+        let record: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(record["caller"], "arn:aws:iam::123456789012:role/vector");
+        assert_eq!(record["backend"], "ssm");
+        assert_eq!(
+            record["secrets"],
+            serde_json::json!([
+                {"name": "test.secret_1", "success": true},
+                {"name": "test.secret_2", "success": false},
+            ])
+        );
+    }
+}
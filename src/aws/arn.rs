@@ -0,0 +1,370 @@
+//! This module contains helpers for routing fetches of secrets referenced by their full ARN, or
+//! requested from another region than the default one, to a client for their region.
+
+use crate::aws::options::{check_region, invalid_request, RequestedSecret};
+#[cfg(feature = "secretsmanager")]
+use crate::aws::secretsmanager::{SecretStatus, SecretVersion, SecretsManagerFetchSecret};
+#[cfg(feature = "ssm")]
+use crate::aws::ssm::SsmFetchSecret;
+use crate::error::SecretsHelperError;
+use crate::vector::FetchedSecret;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+#[cfg(feature = "ssm")]
+use std::time::SystemTime;
+
+/// The prefixes of the regions of the AWS partitions other than the commercial `aws` one.
+const PARTITIONS: &[(&str, &str)] = &[
+    ("us-gov-", "aws-us-gov"),
+    ("cn-", "aws-cn"),
+    ("us-iso-", "aws-iso"),
+    ("us-isob-", "aws-iso-b"),
+    ("eu-isoe-", "aws-iso-e"),
+    ("us-isof-", "aws-iso-f"),
+    ("eusc-", "aws-eusc"),
+];
+
+/// Get the partition of a region, e.g. `aws-us-gov` for `us-gov-west-1`.
+pub fn partition_of(region: &str) -> &'static str {
+    PARTITIONS
+        .iter()
+        .find(|(prefix, _)| region.starts_with(prefix))
+        .map_or("aws", |(_, partition)| partition)
+}
+
+/// A struct representing the parts of an ARN, e.g.
+/// `arn:aws:ssm:eu-west-1:123456789012:parameter/app/db_password`.
+#[derive(Debug, PartialEq)]
+pub struct Arn<'a> {
+    pub partition: &'a str,
+    pub service: &'a str,
+    pub region: &'a str,
+    pub account: &'a str,
+    pub resource: &'a str,
+}
+
+/// Implement the Arn parser.
+impl<'a> Arn<'a> {
+    /// Parse a secret name as an ARN, returning None if it is a plain name.
+    pub fn parse(name: &'a str) -> Option<Self> {
+        let mut parts = name.splitn(6, ':');
+        if parts.next()? != "arn" {
+            return None;
+        }
+        let arn = Self {
+            partition: parts.next()?,
+            service: parts.next()?,
+            region: parts.next()?,
+            account: parts.next()?,
+            resource: parts.next()?,
+        };
+        (!arn.partition.is_empty() && !arn.service.is_empty() && !arn.resource.is_empty())
+            .then_some(arn)
+    }
+
+    /// Check that a region belongs to the partition of the ARN, since the endpoint a secret is
+    /// fetched from is resolved from the region and credentials are only valid in one partition.
+    pub fn check_partition(&self, region: &str) -> Result<(), String> {
+        let partition = partition_of(region);
+        if partition != self.partition {
+            return Err(format!(
+                "region {} is in the {} partition, not in the {} partition of the ARN",
+                region, partition, self.partition
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Resolve the region a requested secret is fetched from instead of the default one: the region
+/// option, otherwise the region configured for the requested secret or for its name. Secrets
+/// referenced by an ARN with a region are always fetched from that region, so another one is
+/// rejected.
+pub fn requested_region(
+    secret: &RequestedSecret,
+    requested: &str,
+    regions: &HashMap<String, String>,
+) -> Result<Option<String>, String> {
+    let configured = regions.get(requested).or_else(|| regions.get(&secret.name));
+    let Some(region) = secret.option("region").or(configured.map(String::as_str)) else {
+        return Ok(None);
+    };
+    check_region(region)?;
+    match Arn::parse(&secret.name) {
+        Some(arn) if !arn.region.is_empty() && arn.region != region => Err(format!(
+            "region {} conflicts with the region {} of the ARN",
+            region, arn.region
+        )),
+        Some(arn) => {
+            arn.check_partition(region)?;
+            Ok(Some(region.to_string()))
+        }
+        None => Ok(Some(region.to_string())),
+    }
+}
+
+/// A struct holding a client for the default region and lazily created clients for other regions.
+pub struct RegionalClients<C> {
+    default: C,
+    default_region: Option<String>,
+    create_client: Box<dyn Fn(&str) -> C + Send + Sync>,
+    clients: Mutex<HashMap<String, C>>,
+}
+
+/// Implement the RegionalClients methods.
+impl<C: Clone> RegionalClients<C> {
+    pub fn new(
+        default: C,
+        default_region: Option<String>,
+        create_client: impl Fn(&str) -> C + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            default,
+            default_region,
+            create_client: Box::new(create_client),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the client for the region of the secret if it is referenced by an ARN with a region,
+    /// otherwise the client for the default region. The SDK resolves the endpoint of the partition
+    /// from the region, so ARNs whose partition doesn't match the region they would be fetched
+    /// from are rejected instead of being sent to the endpoint of another partition.
+    pub fn client_for(&self, name: &str) -> Result<C, String> {
+        match Arn::parse(name) {
+            Some(arn) if !arn.region.is_empty() => {
+                arn.check_partition(arn.region)?;
+                Ok(self.client_in(arn.region))
+            }
+            Some(arn) => {
+                if let Some(region) = &self.default_region {
+                    arn.check_partition(region)?;
+                }
+                Ok(self.default.clone())
+            }
+            None => Ok(self.default.clone()),
+        }
+    }
+
+    /// Get the client for the region, creating it on first use.
+    pub fn client_in(&self, region: &str) -> C {
+        if self.default_region.as_deref() == Some(region) {
+            return self.default.clone();
+        }
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(region.to_string())
+            .or_insert_with(|| (self.create_client)(region))
+            .clone()
+    }
+}
+
+/// Implement the SsmFetchSecret trait for RegionalClients.
+#[cfg(feature = "ssm")]
+#[async_trait]
+impl<C: SsmFetchSecret + Clone + Send + Sync + 'static> SsmFetchSecret for RegionalClients<C> {
+    async fn fetch_secret(&self, name: String, with_decryption: bool) -> FetchedSecret {
+        let client = match self.client_for(&name) {
+            Ok(client) => client,
+            Err(error) => return invalid_request(error),
+        };
+        client.fetch_secret(name, with_decryption).await
+    }
+
+    async fn parameter_version(&self, name: String) -> Result<Option<String>, SecretsHelperError> {
+        match self.client_for(&name) {
+            Ok(client) => client.parameter_version(name).await,
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn fetch_secret_as_of(
+        &self,
+        name: String,
+        with_decryption: bool,
+        as_of: SystemTime,
+    ) -> FetchedSecret {
+        let client = match self.client_for(&name) {
+            Ok(client) => client,
+            Err(error) => return invalid_request(error),
+        };
+        client
+            .fetch_secret_as_of(name, with_decryption, as_of)
+            .await
+    }
+
+    fn in_region(&self, region: &str) -> Option<Box<dyn SsmFetchSecret + Send + Sync>> {
+        Some(Box::new(self.client_in(region)))
+    }
+}
+
+/// Implement the SecretsManagerFetchSecret trait for RegionalClients.
+#[cfg(feature = "secretsmanager")]
+#[async_trait]
+impl<C: SecretsManagerFetchSecret + Clone + Send + Sync + 'static> SecretsManagerFetchSecret
+    for RegionalClients<C>
+{
+    async fn fetch_secret(&self, name: String, version: SecretVersion) -> FetchedSecret {
+        let client = match self.client_for(&name) {
+            Ok(client) => client,
+            Err(error) => return invalid_request(error),
+        };
+        client.fetch_secret(name, version).await
+    }
+
+    async fn version_id(
+        &self,
+        name: String,
+        stage: String,
+    ) -> Result<Option<String>, SecretsHelperError> {
+        match self.client_for(&name) {
+            Ok(client) => client.version_id(name, stage).await,
+            Err(_) => Ok(None),
+        }
+    }
+
+    async fn status(&self, name: String) -> Result<Option<SecretStatus>, SecretsHelperError> {
+        match self.client_for(&name) {
+            Ok(client) => client.status(name).await,
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn in_region(&self, region: &str) -> Option<Box<dyn SecretsManagerFetchSecret + Send + Sync>> {
+        Some(Box::new(self.client_in(region)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arn_can_be_parsed() {
+        assert_eq!(
+            Arn::parse("arn:aws-cn:ssm:cn-north-1:123456789012:parameter/app/db_password"),
+            Some(Arn {
+                partition: "aws-cn",
+                service: "ssm",
+                region: "cn-north-1",
+                account: "123456789012",
+                resource: "parameter/app/db_password",
+            })
+        );
+        assert_eq!(Arn::parse("/app/db_password"), None);
+        assert_eq!(Arn::parse("arn:aws:ssm"), None);
+    }
+
+    #[test]
+    fn regional_clients_route_arns_to_their_region() {
+        let clients = RegionalClients::new(
+            String::from("us-east-1"),
+            Some(String::from("us-east-1")),
+            |region: &str| region.to_string(),
+        );
+
+        assert_eq!(
+            clients.client_for("/app/db_password"),
+            Ok(String::from("us-east-1"))
+        );
+        assert_eq!(
+            clients.client_for(
+                "arn:aws:secretsmanager:eu-west-1:123456789012:secret:app/db_password-AbCdEf"
+            ),
+            Ok(String::from("eu-west-1"))
+        );
+        assert_eq!(
+            clients.client_for("arn:aws:ssm:us-east-1:123456789012:parameter/app/db_password"),
+            Ok(String::from("us-east-1"))
+        );
+        assert_eq!(clients.clients.lock().unwrap().len(), 1);
+        assert_eq!(clients.client_in("eu-west-1"), "eu-west-1");
+        assert_eq!(clients.client_in("ap-south-1"), "ap-south-1");
+        assert_eq!(clients.clients.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn regional_clients_check_the_partition_of_arns() {
+        let clients = RegionalClients::new(
+            String::from("us-gov-west-1"),
+            Some(String::from("us-gov-west-1")),
+            |region: &str| region.to_string(),
+        );
+
+        assert_eq!(
+            clients.client_for(
+                "arn:aws-us-gov:ssm:us-gov-east-1:123456789012:parameter/app/db_password"
+            ),
+            Ok(String::from("us-gov-east-1"))
+        );
+        assert_eq!(
+            clients.client_for("arn:aws-us-gov:secretsmanager::123456789012:secret:app/db"),
+            Ok(String::from("us-gov-west-1"))
+        );
+        assert_eq!(
+            clients.client_for("arn:aws:secretsmanager::123456789012:secret:app/db"),
+            Err(String::from(
+                "region us-gov-west-1 is in the aws-us-gov partition, not in the aws partition of the ARN"
+            ))
+        );
+        assert_eq!(
+            clients.client_for("arn:aws:ssm:cn-north-1:123456789012:parameter/app/db_password"),
+            Err(String::from(
+                "region cn-north-1 is in the aws-cn partition, not in the aws partition of the ARN"
+            ))
+        );
+        assert_eq!(partition_of("eu-west-1"), "aws");
+        assert_eq!(partition_of("cn-northwest-1"), "aws-cn");
+        assert_eq!(partition_of("us-isob-east-1"), "aws-iso-b");
+    }
+
+    #[test]
+    fn requested_region_can_be_resolved() {
+        let regions = HashMap::from([
+            (String::from("/app/db"), String::from("eu-west-1")),
+            (
+                String::from("/app/api?version=2"),
+                String::from("us-west-2"),
+            ),
+        ]);
+        let known_options = ["region", "version"];
+        let region = |requested: &str| {
+            let secret = RequestedSecret::parse(requested, &known_options).unwrap();
+            requested_region(&secret, requested, &regions)
+        };
+
+        assert_eq!(region("/app/other"), Ok(None));
+        assert_eq!(region("/app/db"), Ok(Some(String::from("eu-west-1"))));
+        assert_eq!(
+            region("/app/db?region=eu-central-1"),
+            Ok(Some(String::from("eu-central-1")))
+        );
+        assert_eq!(
+            region("/app/api?version=2"),
+            Ok(Some(String::from("us-west-2")))
+        );
+        assert_eq!(region("/app/api"), Ok(None));
+        assert_eq!(
+            region("/app/db?region=eu.evil.com"),
+            Err(String::from("invalid region: \"eu.evil.com\""))
+        );
+        assert_eq!(
+            region("arn:aws:ssm:us-east-1:123456789012:parameter/app/db?region=eu-west-1"),
+            Err(String::from(
+                "region eu-west-1 conflicts with the region us-east-1 of the ARN"
+            ))
+        );
+        assert_eq!(
+            region("arn:aws:ssm:us-east-1:123456789012:parameter/app/db?region=us-east-1"),
+            Ok(Some(String::from("us-east-1")))
+        );
+        assert_eq!(
+            region("arn:aws-cn:secretsmanager::123456789012:secret:app/db?region=us-east-1"),
+            Err(String::from(
+                "region us-east-1 is in the aws partition, not in the aws-cn partition of the ARN"
+            ))
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! This module contains the AWS credentials shared by all clients of an AWS SDK config, refreshed
+//! ahead of their expiry so that temporary credentials, e.g. from IRSA or an assumed role, keep
+//! working in resident modes.
+
+use crate::diagnostics::{Diagnostic, Level};
+use crate::error::describe;
+use crate::metrics;
+use aws_config::SdkConfig;
+use aws_credential_types::cache::CredentialsCache;
+use aws_credential_types::provider::{self, future, ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// A struct caching the credentials of a provider for every client using it, refreshing them a
+/// fixed margin before they expire and recording failed refreshes in the logs and metrics.
+#[derive(Debug)]
+pub struct SharedCredentials {
+    provider: SharedCredentialsProvider,
+    refresh_before_expiry: Duration,
+    /// The cached credentials, locked while refreshing them so that concurrent requests wait for
+    /// a single refresh.
+    cached: Mutex<Option<Credentials>>,
+    /// Whether the last attempt to load credentials failed, so that only the first failure and
+    /// the recovery are logged.
+    failing: AtomicBool,
+}
+
+/// Implement the SharedCredentials constructor.
+impl SharedCredentials {
+    pub fn new(provider: SharedCredentialsProvider, refresh_before_expiry: Duration) -> Self {
+        Self {
+            provider,
+            refresh_before_expiry,
+            cached: Mutex::new(None),
+            failing: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Implement the SharedCredentials methods.
+impl SharedCredentials {
+    /// Get the cached credentials, refreshing them if they expire within the margin. If the
+    /// refresh fails, credentials that haven't expired yet are still returned.
+    async fn load(&self) -> provider::Result {
+        let mut cached = self.cached.lock().await;
+        let now = SystemTime::now();
+        if let Some(credentials) = cached.as_ref() {
+            match credentials.expiry() {
+                Some(expiry) if now + self.refresh_before_expiry >= expiry => {}
+                _ => return Ok(credentials.clone()),
+            }
+        }
+
+        let metrics = metrics::credentials();
+        match self.provider.provide_credentials().await {
+            Ok(credentials) => {
+                if self.failing.swap(false, Ordering::Relaxed) {
+                    Diagnostic::new(
+                        Level::Info,
+                        "credentials_recovered",
+                        "loaded AWS credentials again",
+                    )
+                    .emit();
+                }
+                let expiry = credentials
+                    .expiry()
+                    .and_then(|expiry| expiry.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |expiry| expiry.as_secs() as i64);
+                metrics.expiry.set(expiry);
+                *cached = Some(credentials.clone());
+                Ok(credentials)
+            }
+            Err(error) => {
+                metrics.refresh_failures.inc();
+                if !self.failing.swap(true, Ordering::Relaxed) {
+                    Diagnostic::new(
+                        Level::Error,
+                        "credentials_failed",
+                        format!("failed to load AWS credentials: {}", describe(&error)),
+                    )
+                    .emit();
+                }
+                match cached.as_ref() {
+                    Some(credentials)
+                        if credentials.expiry().is_some_and(|expiry| now < expiry) =>
+                    {
+                        Ok(credentials.clone())
+                    }
+                    _ => Err(error),
+                }
+            }
+        }
+    }
+}
+
+/// Implement the ProvideCredentials trait for SharedCredentials.
+impl ProvideCredentials for SharedCredentials {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.load())
+    }
+}
+
+/// Make all clients of the AWS SDK config share one credentials cache instead of each caching
+/// the credentials on its own, refreshing them this long before they expire.
+pub fn share(sdk_config: SdkConfig, refresh_before_expiry: Duration) -> SdkConfig {
+    let Some(provider) = sdk_config.credentials_provider() else {
+        return sdk_config;
+    };
+    with_credentials(sdk_config, provider, refresh_before_expiry)
+}
+
+/// Replace the credentials of the AWS SDK config with those of the provider, shared by all its
+/// clients like the credentials shared by share.
+pub fn with_credentials(
+    sdk_config: SdkConfig,
+    provider: SharedCredentialsProvider,
+    refresh_before_expiry: Duration,
+) -> SdkConfig {
+    let credentials = SharedCredentials::new(provider, refresh_before_expiry);
+
+    let mut builder = SdkConfig::builder()
+        .credentials_provider(SharedCredentialsProvider::new(credentials))
+        // The shared cache already caches the credentials for the clients.
+        .credentials_cache(CredentialsCache::no_caching());
+    builder.set_region(sdk_config.region().cloned());
+    builder.set_endpoint_url(sdk_config.endpoint_url().map(String::from));
+    builder.set_retry_config(sdk_config.retry_config().cloned());
+    builder.set_timeout_config(sdk_config.timeout_config().cloned());
+    builder.set_sleep_impl(sdk_config.sleep_impl());
+    builder.set_time_source(sdk_config.time_source());
+    builder.set_app_name(sdk_config.app_name().cloned());
+    builder.set_http_connector(sdk_config.http_connector().cloned());
+    builder.set_use_fips(sdk_config.use_fips());
+    builder.set_use_dual_stack(sdk_config.use_dual_stack());
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_credential_types::provider::error::CredentialsError;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct MockProvider {
+        calls: Arc<AtomicUsize>,
+        lifetime: Duration,
+    }
+
+    impl ProvideCredentials for MockProvider {
+        fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+        where
+            Self: 'a,
+        {
+            let call = self.calls.fetch_add(1, Ordering::Relaxed);
+            if call == 0 {
+                return future::ProvideCredentials::ready(Err(CredentialsError::not_loaded(
+                    "no credentials",
+                )));
+            }
+            future::ProvideCredentials::ready(Ok(Credentials::new(
+                "AKIDEXAMPLE",
+                "secret",
+                None,
+                Some(SystemTime::now() + self.lifetime),
+                "mock",
+            )))
+        }
+    }
+
+    fn shared_credentials(lifetime: Duration) -> (SharedCredentials, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let provider = SharedCredentialsProvider::new(MockProvider {
+            calls: calls.clone(),
+            lifetime,
+        });
+        let credentials = SharedCredentials::new(provider, Duration::from_secs(300));
+        (credentials, calls)
+    }
+
+    #[tokio::test]
+    async fn credentials_are_cached_until_close_to_expiry() {
+        let (credentials, calls) = shared_credentials(Duration::from_secs(3600));
+        let failures = metrics::credentials().refresh_failures.get();
+
+        assert!(credentials.provide_credentials().await.is_err());
+        assert!(metrics::credentials().refresh_failures.get() > failures);
+        for _ in 0..3 {
+            assert!(credentials.provide_credentials().await.is_ok());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+        // Credentials expiring within the margin are refreshed on every use.
+        let (credentials, calls) = shared_credentials(Duration::from_secs(60));
+        for _ in 0..3 {
+            let _ = credentials.provide_credentials().await;
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn unexpired_credentials_are_kept_if_the_refresh_fails() {
+        let calls = Arc::new(AtomicUsize::new(1));
+        let provider = SharedCredentialsProvider::new(MockProvider {
+            calls: calls.clone(),
+            lifetime: Duration::from_secs(60),
+        });
+        let credentials = SharedCredentials::new(provider, Duration::from_secs(300));
+        assert!(credentials.provide_credentials().await.is_ok());
+
+        // The next refresh fails, but the cached credentials are valid for another minute.
+        calls.store(0, Ordering::Relaxed);
+        assert!(credentials.provide_credentials().await.is_ok());
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+}
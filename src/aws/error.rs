@@ -0,0 +1,150 @@
+//! This module contains helpers for rendering errors of AWS API calls into the messages returned
+//! to Vector.
+
+use crate::vector::ErrorCode;
+use aws_credential_types::provider::error::CredentialsError;
+use aws_smithy_http::result::SdkError;
+use aws_smithy_types::error::metadata::ProvideErrorMetadata;
+use std::error::Error;
+use std::fmt::Debug;
+
+/// Classify a failed AWS API call.
+pub fn classify<E, R>(error: &SdkError<E, R>) -> ErrorCode
+where
+    E: ProvideErrorMetadata + Error + 'static,
+    R: Debug + 'static,
+{
+    if is_credentials_error(error) {
+        return ErrorCode::CredentialsUnavailable;
+    }
+    match error {
+        SdkError::ServiceError(_) => classify_code(error.code()),
+        SdkError::TimeoutError(_) => ErrorCode::Timeout,
+        SdkError::DispatchFailure(failure) if failure.is_timeout() => ErrorCode::Timeout,
+        SdkError::DispatchFailure(_) => ErrorCode::ConnectionFailure,
+        SdkError::ConstructionFailure(_) => ErrorCode::InvalidRequest,
+        SdkError::ResponseError(_) => ErrorCode::ServiceError,
+        _ => ErrorCode::Unknown,
+    }
+}
+
+/// Whether the call failed because no credentials could be loaded, in which case the SDK reports
+/// the credentials provider error somewhere down the chain of sources.
+fn is_credentials_error(error: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if error.is::<CredentialsError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Classify an error code returned by an AWS service.
+pub fn classify_code(code: Option<&str>) -> ErrorCode {
+    match code {
+        Some("ParameterNotFound" | "ParameterVersionNotFound" | "ResourceNotFoundException") => {
+            ErrorCode::NotFound
+        }
+        Some(
+            "AccessDeniedException"
+            | "UnrecognizedClientException"
+            | "ExpiredTokenException"
+            | "InvalidSignatureException"
+            | "MissingAuthenticationTokenException",
+        ) => ErrorCode::AccessDenied,
+        Some("ThrottlingException" | "TooManyRequestsException" | "RequestLimitExceeded") => {
+            ErrorCode::Throttled
+        }
+        Some("LimitExceededException") => ErrorCode::QuotaExceeded,
+        Some("DecryptionFailure" | "InvalidKeyId" | "KMSAccessDeniedException") => {
+            ErrorCode::DecryptionFailure
+        }
+        Some(
+            "ValidationException"
+            | "InvalidParameterException"
+            | "InvalidRequestException"
+            | "InvalidNextTokenException",
+        ) => ErrorCode::InvalidRequest,
+        Some(
+            "InternalServerError"
+            | "InternalServiceError"
+            | "InternalFailure"
+            | "InternalServiceErrorException"
+            | "ServiceUnavailable",
+        ) => ErrorCode::ServiceError,
+        _ => ErrorCode::Unknown,
+    }
+}
+
+/// Append the error code and the request ID of a failed AWS API call to the error message, so that
+/// the failure can be looked up with AWS support.
+pub fn with_metadata(message: String, code: Option<&str>, request_id: Option<&str>) -> String {
+    let metadata: Vec<_> = [("code", code), ("request id", request_id)]
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| format!("{}: {}", key, value)))
+        .collect();
+
+    if metadata.is_empty() {
+        message
+    } else {
+        format!("{} ({})", message, metadata.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_code_classifies_service_error_codes() {
+        assert_eq!(
+            classify_code(Some("ParameterNotFound")),
+            ErrorCode::NotFound
+        );
+        assert_eq!(
+            classify_code(Some("ResourceNotFoundException")),
+            ErrorCode::NotFound
+        );
+        assert_eq!(
+            classify_code(Some("AccessDeniedException")),
+            ErrorCode::AccessDenied
+        );
+        assert_eq!(
+            classify_code(Some("ThrottlingException")),
+            ErrorCode::Throttled
+        );
+        assert_eq!(
+            classify_code(Some("LimitExceededException")),
+            ErrorCode::QuotaExceeded
+        );
+        assert_eq!(
+            classify_code(Some("DecryptionFailure")),
+            ErrorCode::DecryptionFailure
+        );
+        assert_eq!(classify_code(Some("SomethingNew")), ErrorCode::Unknown);
+        assert_eq!(classify_code(None), ErrorCode::Unknown);
+    }
+
+    #[test]
+    fn with_metadata_appends_code_and_request_id() {
+        assert_eq!(
+            with_metadata(
+                String::from("service error: ParameterNotFound"),
+                Some("ParameterNotFound"),
+                Some("c0ffee00-0000-4000-8000-000000000000"),
+            ),
+            "service error: ParameterNotFound (code: ParameterNotFound, \
+             request id: c0ffee00-0000-4000-8000-000000000000)"
+        );
+    }
+
+    #[test]
+    fn with_metadata_keeps_message_without_metadata() {
+        assert_eq!(
+            with_metadata(String::from("dispatch failure"), None, None),
+            "dispatch failure"
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! This module contains helpers for AWS Key Management Service.
+
+use crate::error::describe;
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::DataKeySpec;
+use aws_sdk_kms::Client;
+use std::path::Path;
+
+/// Get the plaintext of the data key whose encrypted copy is stored at the path, generating a new
+/// data key under the KMS key and storing its encrypted copy there if none exists yet. Only the
+/// encrypted copy is ever written to disk.
+pub async fn data_key(client: &Client, key_id: &str, path: &Path) -> Result<Vec<u8>, String> {
+    let plaintext = match std::fs::read(path) {
+        Ok(ciphertext) => {
+            client
+                .decrypt()
+                .key_id(key_id)
+                .ciphertext_blob(Blob::new(ciphertext))
+                .send()
+                .await
+                .map_err(|error| format!("failed to decrypt the data key: {}", describe(&error)))?
+                .plaintext
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            let response = client
+                .generate_data_key()
+                .key_id(key_id)
+                .key_spec(DataKeySpec::Aes256)
+                .send()
+                .await
+                .map_err(|error| format!("failed to generate a data key: {}", describe(&error)))?;
+            let ciphertext = response
+                .ciphertext_blob
+                .ok_or_else(|| String::from("generated data key has no ciphertext"))?;
+            crate::output::write_atomically(path, ciphertext.as_ref()).map_err(|error| {
+                format!("failed to write the data key {}: {}", path.display(), error)
+            })?;
+            response.plaintext
+        }
+        Err(error) => {
+            return Err(format!(
+                "failed to read the data key {}: {}",
+                path.display(),
+                error
+            ))
+        }
+    };
+    plaintext
+        .map(Blob::into_inner)
+        .ok_or_else(|| String::from("data key has no plaintext"))
+}
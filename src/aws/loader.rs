@@ -1,10 +1,380 @@
 //! This module contains a trait that should be implemented by all secret loader implementations.
 
-use crate::vector::{FetchedSecrets, SecretsToFetch};
+use crate::breaker::Breaker;
+use crate::diagnostics::{self, Diagnostic, Level};
+use crate::error::SecretsHelperError;
+use crate::pacing::Pacer;
+use crate::retry::RetryPolicy;
+use crate::vector::{ErrorCode, FetchedSecret, FetchedSecrets, SecretsToFetch};
 use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A struct holding the state shared by all fetches of the request being answered, across all
+/// backends, batches and fallbacks.
+#[derive(Clone, Debug, Default)]
+pub struct RequestScope {
+    /// The time by which all fetches of the request must be finished.
+    deadline: Option<Instant>,
+    /// The number of retries spent by the request, keyed by the breaker of the backend.
+    retries_spent: Arc<Mutex<HashMap<u64, u32>>>,
+}
+
+/// Implement the RequestScope methods.
+impl RequestScope {
+    /// Spend one retry of the request against the retry budget of a backend, returning whether
+    /// the budget allowed it.
+    pub fn spend_retry(&self, backend: u64, budget: u32) -> bool {
+        let mut retries_spent = self.retries_spent.lock().unwrap();
+        let spent = retries_spent.entry(backend).or_default();
+        if *spent >= budget {
+            return false;
+        }
+        *spent += 1;
+        true
+    }
+}
+
+tokio::task_local! {
+    /// The request being answered.
+    static REQUEST: RequestScope;
+}
+
+/// Answer a request whose fetches across all backends, batches and fallbacks must be finished
+/// within the deadline, counted from now, and share a retry budget per backend.
+pub async fn with_deadline<T>(deadline: Option<Duration>, request: impl Future<Output = T>) -> T {
+    let scope = RequestScope {
+        deadline: deadline.map(|deadline| Instant::now() + deadline),
+        ..Default::default()
+    };
+    REQUEST.scope(scope, request).await
+}
+
+/// Answer a request in the scope of another one, e.g. the one of the request a task was spawned
+/// for.
+pub async fn within<T>(scope: Option<RequestScope>, request: impl Future<Output = T>) -> T {
+    match scope {
+        Some(scope) => REQUEST.scope(scope, request).await,
+        None => request.await,
+    }
+}
+
+/// The scope of the request being answered, if any.
+pub fn current_request() -> Option<RequestScope> {
+    REQUEST.try_with(RequestScope::clone).ok()
+}
+
+/// The deadline of the request being answered, if it has one.
+pub fn request_deadline() -> Option<Instant> {
+    REQUEST.try_with(|scope| scope.deadline).ok().flatten()
+}
+
+/// Run a step of answering the request besides fetching, e.g. setting up a backend or the disk
+/// cache, failing it once the deadline of the request passed.
+pub async fn before_deadline<T>(step: impl Future<Output = T>) -> Result<T, SecretsHelperError> {
+    match request_deadline() {
+        Some(deadline) => tokio::time::timeout_at(deadline, step)
+            .await
+            .map_err(|_| SecretsHelperError::DeadlineExceeded),
+        None => Ok(step.await),
+    }
+}
 
 /// A trait for loading secrets from AWS backends.
 #[async_trait]
 pub trait LoadSecrets {
     async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets;
+
+    /// Get the current version of a requested secret without fetching its value, None if the
+    /// backend can't determine it.
+    async fn current_version(&self, _requested: &str) -> Option<String> {
+        None
+    }
+}
+
+/// A struct holding the settings shared by all secret loader implementations.
+#[derive(Clone, Debug, Default)]
+pub struct LoaderSettings {
+    pub retry_policy: RetryPolicy,
+    /// The time after which unfinished fetches are cancelled and reported as failed, counted from
+    /// the start of every load unless the request being answered has a deadline of its own.
+    pub deadline: Option<Duration>,
+    /// Whether to write the timing of every fetch to stderr.
+    pub timings: bool,
+    /// The pacer every fetch call, including retries, waits for before it starts.
+    pub pacer: Option<Arc<Pacer>>,
+    /// The circuit breaker shared by all fetch calls, and the retry budget of every request.
+    pub breaker: Option<Arc<Breaker>>,
+}
+
+/// A struct representing the timing of a single fetch, written to stderr as a JSON line. Secret
+/// values are never included.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Timing<'a> {
+    pub secret: &'a str,
+    pub duration_ms: u128,
+    pub attempts: u32,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+}
+
+/// Implement the Timing constructor.
+impl<'a> Timing<'a> {
+    pub fn new(
+        secret: &'a str,
+        duration: Duration,
+        attempts: u32,
+        fetched_secret: &FetchedSecret,
+    ) -> Self {
+        Self {
+            secret,
+            duration_ms: duration.as_millis(),
+            attempts,
+            status: if fetched_secret.error.is_none() {
+                "ok"
+            } else {
+                "error"
+            },
+            code: fetched_secret.code,
+        }
+    }
+}
+
+/// Get the deadline and scope of the request the loader settings are applied to. Loads outside of
+/// a request, e.g. by embedding applications, get a retry budget of their own.
+fn request_of(settings: &LoaderSettings) -> (Option<Instant>, RequestScope) {
+    let deadline =
+        request_deadline().or_else(|| settings.deadline.map(|deadline| Instant::now() + deadline));
+    (deadline, current_request().unwrap_or_default())
+}
+
+/// Make a call to the backend, e.g. to fetch a secret, applying the loader settings: every attempt
+/// waits for the pacer and fails right away while the circuit is open, failed attempts are retried
+/// as allowed and the call fails once the deadline passed.
+async fn call<F, Fut>(
+    settings: &LoaderSettings,
+    (deadline, request): &(Option<Instant>, RequestScope),
+    attempts: &AtomicU32,
+    call: F,
+) -> FetchedSecret
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = FetchedSecret>,
+{
+    let breaker = settings.breaker.as_deref();
+    let call_with_retries = settings.retry_policy.run_while(
+        || async {
+            if let Some(pacer) = &settings.pacer {
+                pacer.wait().await;
+            }
+            if let Some(Err(failure)) = breaker.map(Breaker::check) {
+                return failure;
+            }
+            attempts.fetch_add(1, Ordering::Relaxed);
+            let fetched_secret = call().await;
+            if let Some(breaker) = breaker {
+                breaker.record(&fetched_secret);
+            }
+            fetched_secret
+        },
+        || breaker.is_none_or(|breaker| breaker.allow_retry(request)),
+    );
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(*deadline, call_with_retries)
+            .await
+            .unwrap_or_else(|_| SecretsHelperError::DeadlineExceeded.into()),
+        None => call_with_retries.await,
+    }
+}
+
+/// Look up something about a secret other than its value, e.g. its current version, applying the
+/// loader settings like fetches do. None if it can't be determined or the lookup failed.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+pub async fn look_up<T, F, Fut>(settings: &LoaderSettings, lookup: F) -> Option<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Option<T>, SecretsHelperError>>,
+{
+    let attempts = AtomicU32::new(0);
+    // Retries and the breaker only go by whether an attempt failed, the value is kept aside.
+    let looked_up = Mutex::new(None);
+    let called = call(settings, &request_of(settings), &attempts, || async {
+        match lookup().await {
+            Ok(value) => {
+                *looked_up.lock().unwrap() = value;
+                FetchedSecret {
+                    value: None,
+                    error: None,
+                    code: None,
+                }
+            }
+            Err(error) => error.into(),
+        }
+    })
+    .await;
+    match called.error {
+        Some(_) => None,
+        None => looked_up.into_inner().unwrap(),
+    }
+}
+
+/// Fetch all secrets concurrently using the fetch function, applying the loader settings.
+pub async fn fetch_all<F, Fut>(
+    secrets: SecretsToFetch,
+    settings: &LoaderSettings,
+    fetch: F,
+) -> FetchedSecrets
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = FetchedSecret>,
+{
+    let request = request_of(settings);
+    let (fetch, request) = (&fetch, &request);
+
+    let create_task = |secret_name: String| {
+        let secret_to_fetch = secret_name.clone();
+        let task = async move {
+            let start = Instant::now();
+            let attempts = AtomicU32::new(0);
+            let fetched_secret = call(settings, request, &attempts, || {
+                fetch(secret_to_fetch.clone())
+            })
+            .await;
+
+            if settings.timings {
+                let attempts = attempts.load(Ordering::Relaxed);
+                let timing =
+                    Timing::new(&secret_to_fetch, start.elapsed(), attempts, &fetched_secret);
+                match diagnostics::format() {
+                    // Timings were JSON lines before diagnostics could be, so they stay ones.
+                    diagnostics::Format::Text => {
+                        eprintln!("{}", serde_json::to_string(&timing).unwrap())
+                    }
+                    diagnostics::Format::Json => Diagnostic::new(
+                        Level::Info,
+                        "fetch_timing",
+                        format!(
+                            "fetching {} took {} ms and {} attempts",
+                            timing.secret, timing.duration_ms, timing.attempts
+                        ),
+                    )
+                    .secret(timing.secret)
+                    .code(timing.code)
+                    .detail("duration_ms", timing.duration_ms as u64)
+                    .detail("attempts", timing.attempts)
+                    .detail("status", timing.status)
+                    .emit(),
+                }
+            }
+            fetched_secret
+        };
+        (secret_name, task)
+    };
+
+    // Run tasks concurrently.
+    let (secret_names, tasks): (Vec<_>, Vec<_>) =
+        secrets.secrets.into_iter().map(create_task).unzip();
+    let results: Vec<_> = futures::future::join_all(tasks).await;
+
+    // Create a FetchedSecrets struct from the results.
+    let mut fetched_secrets = FetchedSecrets::default();
+    secret_names
+        .into_iter()
+        .zip(results)
+        .for_each(|(secret_name, result)| {
+            fetched_secrets.0.insert(secret_name, result);
+        });
+
+    fetched_secrets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+
+    #[test]
+    fn timing_is_serialized_without_values() {
+        let fetched_secret = FetchedSecret {
+            value: None,
+            error: Some(String::from("rate exceeded")),
+            code: Some(ErrorCode::Throttled),
+        };
+        let timing = Timing::new("test.secret", Duration::from_millis(42), 3, &fetched_secret);
+        assert_eq!(
+            serde_json::to_string(&timing).unwrap(),
+            "{\"secret\":\"test.secret\",\"duration_ms\":42,\"attempts\":3,\"status\":\"error\",\"code\":\"THROTTLED\"}"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_all_reports_unfinished_fetches_after_deadline() {
+        let settings = LoaderSettings {
+            deadline: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let secrets_to_fetch =
+            SecretsToFetch::from_names(vec![String::from("test.fast"), String::from("test.slow")]);
+
+        let fetched_secrets = fetch_all(secrets_to_fetch, &settings, |name| async move {
+            if name == "test.slow" {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            }
+            FetchedSecret {
+                value: Some(Secret::new(String::from("qwerty"))),
+                error: None,
+                code: None,
+            }
+        })
+        .await;
+
+        assert_eq!(
+            fetched_secrets.0["test.fast"].value,
+            Some(Secret::new(String::from("qwerty")))
+        );
+        assert_eq!(
+            fetched_secrets.0["test.slow"],
+            FetchedSecret {
+                value: None,
+                error: Some(String::from("deadline exceeded")),
+                code: Some(ErrorCode::DeadlineExceeded),
+            }
+        );
+    }
+
+    #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+    #[tokio::test]
+    async fn look_up_is_retried_and_bounded_by_the_deadline() {
+        let settings = LoaderSettings {
+            retry_policy: RetryPolicy {
+                initial_backoff: Duration::from_millis(1),
+                ..Default::default()
+            },
+            deadline: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let attempts = AtomicU32::new(0);
+        let version = look_up(&settings, || async {
+            match attempts.fetch_add(1, Ordering::Relaxed) {
+                0 => Err(SecretsHelperError::Throttled(String::from("rate exceeded"))),
+                _ => Ok(Some(String::from("3"))),
+            }
+        })
+        .await;
+        assert_eq!(version, Some(String::from("3")));
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+
+        let version = look_up(&settings, || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(Some(String::from("3")))
+        })
+        .await;
+        assert_eq!(version, None);
+    }
 }
@@ -0,0 +1,282 @@
+//! This module contains a minimal client of CloudWatch Logs, which only puts log events into a log
+//! stream, e.g. the metric records in the Embedded Metric Format on hosts without a CloudWatch
+//! agent.
+
+use crate::aws::loader::before_deadline;
+use crate::error::describe;
+use aws_config::SdkConfig;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sigv4::http_request::{sign, SignableRequest, SigningParams, SigningSettings};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use serde_json::{json, Value};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The time after which a call to CloudWatch Logs is given up, so that a hanging connection
+/// doesn't hold up the request whose metrics are put.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A struct representing a failed call to CloudWatch Logs.
+#[derive(Debug, PartialEq)]
+pub struct CallFailure {
+    /// The type of the exception returned by CloudWatch Logs, if it returned one.
+    code: Option<String>,
+    message: String,
+}
+
+/// Implement the CallFailure constructor.
+impl CallFailure {
+    fn new(code: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            code: code.map(str::to_string),
+            message: message.into(),
+        }
+    }
+}
+
+/// Implement the Display trait for CallFailure.
+impl fmt::Display for CallFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{}: {}", code, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// A struct representing a log stream events are put into. The stream is created the first time
+/// events are put, unless it already exists.
+pub struct LogStream {
+    sdk_config: Shared<BoxFuture<'static, Arc<SdkConfig>>>,
+    group: String,
+    stream: String,
+    client: hyper::Client<HttpsConnector<HttpConnector>>,
+    created: tokio::sync::OnceCell<()>,
+}
+
+/// Implement the LogStream constructor.
+impl LogStream {
+    /// Create the log stream of the group, calling CloudWatch Logs with the AWS SDK config, which
+    /// is only awaited once events are put.
+    pub fn new(
+        group: &str,
+        stream: &str,
+        sdk_config: impl Future<Output = SdkConfig> + Send + 'static,
+    ) -> Self {
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Self {
+            sdk_config: sdk_config.map(Arc::new).boxed().shared(),
+            group: group.to_string(),
+            stream: stream.to_string(),
+            client: hyper::Client::builder().build(https),
+            created: tokio::sync::OnceCell::new(),
+        }
+    }
+}
+
+/// Implement the LogStream methods.
+impl LogStream {
+    /// Put the messages into the log stream as events with the timestamp, creating the stream if
+    /// this is the first time. Bounded by the deadline of the request being answered.
+    pub async fn put(&self, messages: &[String], timestamp_ms: u128) -> Result<(), CallFailure> {
+        let put = async {
+            self.created
+                .get_or_try_init(|| async {
+                    let stream = json!({"logGroupName": self.group, "logStreamName": self.stream});
+                    match self.call("CreateLogStream", &stream).await {
+                        Err(failure)
+                            if failure.code.as_deref()
+                                == Some("ResourceAlreadyExistsException") =>
+                        {
+                            Ok(())
+                        }
+                        created => created,
+                    }
+                })
+                .await?;
+            let events: Vec<Value> = messages
+                .iter()
+                .map(|message| json!({"timestamp": timestamp_ms, "message": message}))
+                .collect();
+            let events = json!({
+                "logGroupName": self.group,
+                "logStreamName": self.stream,
+                "logEvents": events,
+            });
+            self.call("PutLogEvents", &events).await
+        };
+        match tokio::time::timeout(TIMEOUT, before_deadline(put)).await {
+            Ok(Ok(put)) => put,
+            Ok(Err(error)) => Err(CallFailure::new(None, error.to_string())),
+            Err(_) => Err(CallFailure::new(None, "timed out")),
+        }
+    }
+
+    /// Call the action of the CloudWatch Logs JSON API with the body, signed with the credentials
+    /// of the AWS SDK config.
+    async fn call(&self, action: &str, body: &Value) -> Result<(), CallFailure> {
+        let sdk_config = self.sdk_config.clone().await;
+        let region = sdk_config
+            .region()
+            .ok_or_else(|| CallFailure::new(None, "no AWS region is configured"))?;
+        let endpoint = match sdk_config.endpoint_url() {
+            Some(endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => format!("https://logs.{}.amazonaws.com", region),
+        };
+        let credentials = match sdk_config.credentials_provider() {
+            Some(provider) => provider.provide_credentials().await.map_err(|error| {
+                CallFailure::new(
+                    None,
+                    format!("failed to load credentials: {}", describe(&error)),
+                )
+            })?,
+            None => return Err(CallFailure::new(None, "no AWS credentials are configured")),
+        };
+
+        let mut request = hyper::Request::post(format!("{}/", endpoint))
+            .header("content-type", "application/x-amz-json-1.1")
+            .header("x-amz-target", format!("Logs_20140328.{}", action))
+            .body(body.to_string())
+            .map_err(|error| CallFailure::new(None, error.to_string()))?;
+        let mut params = SigningParams::builder()
+            .access_key(credentials.access_key_id())
+            .secret_key(credentials.secret_access_key())
+            .region(region.as_ref())
+            .service_name("logs")
+            .time(SystemTime::now())
+            .settings(SigningSettings::default());
+        params.set_security_token(credentials.session_token());
+        let params = params
+            .build()
+            .map_err(|error| CallFailure::new(None, error.to_string()))?;
+        let (instructions, _) = sign(SignableRequest::from(&request), &params)
+            .map_err(|error| CallFailure::new(None, error.to_string()))?
+            .into_parts();
+        instructions.apply_to_request(&mut request);
+
+        let response = self
+            .client
+            .request(request.map(hyper::Body::from))
+            .await
+            .map_err(|error| CallFailure::new(None, describe(&error)))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap_or_default();
+        Err(failure(status, &body))
+    }
+}
+
+/// Create the failure of a call from its error response, whose `__type` is the exception type,
+/// optionally prefixed with its namespace and a `#`.
+fn failure(status: hyper::StatusCode, body: &[u8]) -> CallFailure {
+    let body: Value = serde_json::from_slice(body).unwrap_or_default();
+    let code = body["__type"]
+        .as_str()
+        .map(|code| code.rsplit('#').next().unwrap_or(code));
+    let message = body["message"]
+        .as_str()
+        .or_else(|| body["Message"].as_str())
+        .map_or_else(|| format!("HTTP status {}", status), str::to_string);
+    CallFailure::new(code, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_credential_types::provider::SharedCredentialsProvider;
+    use aws_credential_types::Credentials;
+    use aws_sdk_sts::config::Region;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::Mutex;
+
+    #[test]
+    fn failures_are_parsed_from_error_responses() {
+        let body = br#"{"__type": "com.amazonaws.logs#ResourceNotFoundException", "message": "The specified log group does not exist."}"#;
+        assert_eq!(
+            failure(hyper::StatusCode::BAD_REQUEST, body),
+            CallFailure::new(
+                Some("ResourceNotFoundException"),
+                "The specified log group does not exist."
+            )
+        );
+        assert_eq!(
+            failure(hyper::StatusCode::BAD_GATEWAY, b"").to_string(),
+            "HTTP status 502 Bad Gateway"
+        );
+    }
+
+    #[tokio::test]
+    async fn events_are_put_into_a_stream_created_once() {
+        // The stream already exists, which isn't a failure.
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorded = calls.clone();
+        let app = Router::new().route(
+            "/",
+            post(move |headers: HeaderMap, body: String| async move {
+                let target = headers["x-amz-target"].to_str().unwrap().to_string();
+                assert!(headers["authorization"]
+                    .to_str()
+                    .unwrap()
+                    .contains("/eu-west-1/logs/aws4_request"));
+                recorded.lock().unwrap().push((target.clone(), body));
+                match target.as_str() {
+                    "Logs_20140328.CreateLogStream" => (
+                        StatusCode::BAD_REQUEST,
+                        r#"{"__type": "ResourceAlreadyExistsException", "message": "exists"}"#,
+                    ),
+                    _ => (StatusCode::OK, "{}"),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let sdk_config = SdkConfig::builder()
+            .region(Region::new("eu-west-1"))
+            .endpoint_url(endpoint)
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                "AKID", "SECRET", None, None, "test",
+            )))
+            .build();
+        let log_stream = LogStream::new("/vector/helper", "host-1", async { sdk_config });
+        for _ in 0..2 {
+            let put = log_stream.put(&[String::from("{\"a\":1}")], 1000).await;
+            assert_eq!(put, Ok(()));
+        }
+
+        let calls = calls.lock().unwrap();
+        let targets: Vec<&str> = calls.iter().map(|(target, _)| target.as_str()).collect();
+        assert_eq!(
+            targets,
+            [
+                "Logs_20140328.CreateLogStream",
+                "Logs_20140328.PutLogEvents",
+                "Logs_20140328.PutLogEvents"
+            ]
+        );
+        let events: Value = serde_json::from_str(&calls[1].1).unwrap();
+        assert_eq!(
+            events,
+            json!({
+                "logGroupName": "/vector/helper",
+                "logStreamName": "host-1",
+                "logEvents": [{"timestamp": 1000, "message": "{\"a\":1}"}],
+            })
+        );
+    }
+}
@@ -1,3 +1,16 @@
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+pub mod arn;
+pub mod credentials;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+pub mod error;
+pub mod kms;
 pub mod loader;
+pub mod logs;
+pub mod options;
+#[cfg(feature = "secretsmanager")]
 pub mod secretsmanager;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+pub mod seed;
+#[cfg(feature = "ssm")]
 pub mod ssm;
+pub mod sts;
@@ -0,0 +1,133 @@
+//! This module contains the parser of per-secret options passed as a query string after the name
+//! of a requested secret, e.g. `my-secret?key=password&stage=AWSPREVIOUS`.
+
+use crate::error::SecretsHelperError;
+use crate::vector::FetchedSecret;
+use std::collections::HashMap;
+
+/// A struct representing a requested secret split into its name and options.
+#[derive(Debug, PartialEq)]
+pub struct RequestedSecret {
+    pub name: String,
+    options: HashMap<String, String>,
+}
+
+/// Implement the RequestedSecret methods.
+impl RequestedSecret {
+    /// Parse a requested secret, rejecting options that are not in the list of known ones. Neither
+    /// SSM Parameter Store nor Secrets Manager allow `?` in names, so it always starts the options.
+    #[cfg_attr(
+        not(any(
+            feature = "ssm",
+            feature = "secretsmanager",
+            feature = "gcp",
+            feature = "azure"
+        )),
+        allow(dead_code)
+    )]
+    pub fn parse(requested: &str, known_options: &[&str]) -> Result<Self, String> {
+        let Some((name, query)) = requested.split_once('?') else {
+            return Ok(Self {
+                name: requested.to_string(),
+                options: HashMap::new(),
+            });
+        };
+
+        let mut options = HashMap::new();
+        for option in query.split('&').filter(|option| !option.is_empty()) {
+            let Some((key, value)) = option.split_once('=') else {
+                return Err(format!("option without a value: {}", option));
+            };
+            if !known_options.contains(&key) {
+                return Err(format!("unknown option: {}", key));
+            }
+            if options.insert(key.to_string(), value.to_string()).is_some() {
+                return Err(format!("duplicate option: {}", key));
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            options,
+        })
+    }
+
+    /// Get the value of an option.
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options.get(key).map(String::as_str)
+    }
+
+    /// Get the value of a boolean option.
+    #[cfg_attr(not(feature = "ssm"), allow(dead_code))]
+    pub fn bool_option(&self, key: &str) -> Result<Option<bool>, String> {
+        self.option(key)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("invalid value of the {} option: {}", key, value))
+            })
+            .transpose()
+    }
+}
+
+/// Check that a region only has the characters of AWS regions, e.g. `eu-west-1`, since it ends up
+/// in the endpoint of the client.
+pub fn check_region(region: &str) -> Result<(), String> {
+    let valid = region
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    match !region.is_empty() && valid {
+        true => Ok(()),
+        false => Err(format!("invalid region: {:?}", region)),
+    }
+}
+
+/// Create the result of a secret that was requested with invalid options.
+pub fn invalid_request(error: String) -> FetchedSecret {
+    SecretsHelperError::InvalidRequest(error).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requested_secret_without_options_can_be_parsed() {
+        let secret = RequestedSecret::parse("/app/param", &["version"]).unwrap();
+        assert_eq!(secret.name, "/app/param");
+        assert_eq!(secret.option("version"), None);
+    }
+
+    #[test]
+    fn requested_secret_with_options_can_be_parsed() {
+        let secret = RequestedSecret::parse(
+            "/app/param?version=3&decrypt=false",
+            &["version", "decrypt"],
+        )
+        .unwrap();
+        assert_eq!(secret.name, "/app/param");
+        assert_eq!(secret.option("version"), Some("3"));
+        assert_eq!(secret.bool_option("decrypt"), Ok(Some(false)));
+    }
+
+    #[test]
+    fn requested_secret_with_invalid_options_is_rejected() {
+        assert_eq!(
+            RequestedSecret::parse("my-secret?stage=AWSCURRENT", &["key"]),
+            Err(String::from("unknown option: stage"))
+        );
+        assert_eq!(
+            RequestedSecret::parse("my-secret?key", &["key"]),
+            Err(String::from("option without a value: key"))
+        );
+        assert_eq!(
+            RequestedSecret::parse("my-secret?key=a&key=b", &["key"]),
+            Err(String::from("duplicate option: key"))
+        );
+        assert_eq!(
+            RequestedSecret::parse("/app/param?decrypt=no", &["decrypt"])
+                .unwrap()
+                .bool_option("decrypt"),
+            Err(String::from("invalid value of the decrypt option: no"))
+        );
+    }
+}
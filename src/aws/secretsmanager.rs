@@ -1,56 +1,324 @@
 //! This module contains the secrets loader implementation for AWS Secrets Manager.
 
+use crate::aws::arn::requested_region;
+use crate::aws::error::{classify, with_metadata};
+use crate::aws::loader::{fetch_all, look_up, LoaderSettings};
+use crate::aws::options::{invalid_request, RequestedSecret};
+use crate::diagnostics::{Diagnostic, Level};
+use crate::error::{describe, SecretsHelperError};
+use crate::extract;
+use crate::secret::Secret;
 use crate::vector::{FetchedSecret, FetchedSecrets, SecretsToFetch};
 use crate::LoadSecrets;
 use async_trait::async_trait;
-use aws_sdk_secretsmanager::error::SdkError::ServiceError;
+use aws_sdk_secretsmanager::error::ProvideErrorMetadata;
+use aws_sdk_secretsmanager::error::SdkError::{self, ServiceError};
+use aws_sdk_secretsmanager::operation::RequestId;
 use aws_sdk_secretsmanager::Client;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// The options of a requested secret.
+const OPTIONS: &[&str] = &["key", "stage", "version", "region"];
+
+/// The time the next rotation of a secret can be late by before it's reported as failing, since
+/// rotations run within a window after their scheduled time.
+const ROTATION_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A struct selecting the version of a secret, the current one if neither field is set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecretVersion {
+    pub stage: Option<String>,
+    pub id: Option<String>,
+}
+
+/// An enum representing how the status of fetched secrets is checked with DescribeSecret.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum StatusChecks {
+    /// The status isn't checked.
+    #[default]
+    Off,
+    /// Secrets scheduled for deletion or whose rotation is failing are reported on stderr.
+    Warn,
+    /// Secrets scheduled for deletion or whose rotation is failing are also failed.
+    Strict,
+}
+
+/// A struct representing the lifecycle status of a secret, as described by DescribeSecret.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SecretStatus {
+    /// The time the secret will be deleted at, if it's scheduled for deletion.
+    pub deletion_date: Option<SystemTime>,
+    pub rotation_enabled: bool,
+    pub next_rotation_date: Option<SystemTime>,
+    /// Whether a version other than the current one is labelled AWSPENDING, which a rotation
+    /// that didn't finish leaves behind.
+    pub pending_version: bool,
+}
+
+/// Implement the SecretStatus methods.
+impl SecretStatus {
+    /// Describe the problems of the secret at the time, e.g. its upcoming deletion.
+    pub fn problems(&self, now: SystemTime) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(deletion_date) = self.deletion_date {
+            let days = deletion_date
+                .duration_since(now)
+                .map_or(0, |left| left.as_secs() / (24 * 60 * 60));
+            problems.push(format!(
+                "the secret is scheduled for deletion on {} (in {} days)",
+                humantime::format_rfc3339_seconds(deletion_date),
+                days
+            ));
+        }
+        if !self.rotation_enabled {
+            return problems;
+        }
+        if let Some(next_rotation_date) = self.next_rotation_date {
+            if next_rotation_date + ROTATION_GRACE_PERIOD < now {
+                problems.push(format!(
+                    "the rotation scheduled for {} hasn't happened, its rotation function may be failing",
+                    humantime::format_rfc3339_seconds(next_rotation_date)
+                ));
+            }
+        }
+        if self.pending_version {
+            problems.push(String::from(
+                "a version is still labelled AWSPENDING, the last rotation may have failed",
+            ));
+        }
+        problems
+    }
+}
 
 /// A trait for fetching a single secret from AWS Secrets Manager.
 #[async_trait]
 pub trait SecretsManagerFetchSecret {
-    async fn fetch_secret(&self, name: String) -> FetchedSecret;
+    async fn fetch_secret(&self, name: String, version: SecretVersion) -> FetchedSecret;
+
+    /// Get the id of the version of a secret with the staging label without fetching its value,
+    /// None if it can't be determined.
+    async fn version_id(
+        &self,
+        _name: String,
+        _stage: String,
+    ) -> Result<Option<String>, SecretsHelperError> {
+        Ok(None)
+    }
+
+    /// Get the lifecycle status of a secret, None if it can't be determined.
+    async fn status(&self, _name: String) -> Result<Option<SecretStatus>, SecretsHelperError> {
+        Ok(None)
+    }
+
+    /// Get a client fetching secrets from the region instead, None if this client can't.
+    fn in_region(&self, _region: &str) -> Option<Box<dyn SecretsManagerFetchSecret + Send + Sync>> {
+        None
+    }
 }
 
 /// Implement the SecretsManagerGetSecret trait for the AWS SDK Secrets Manager client.
 #[async_trait]
 impl SecretsManagerFetchSecret for Client {
-    async fn fetch_secret(&self, name: String) -> FetchedSecret {
-        match self.get_secret_value().secret_id(name).send().await {
+    async fn fetch_secret(&self, name: String, version: SecretVersion) -> FetchedSecret {
+        match self
+            .get_secret_value()
+            .secret_id(name)
+            .set_version_stage(version.stage)
+            .set_version_id(version.id)
+            .send()
+            .await
+        {
             Ok(response) => match response.secret_string {
                 Some(secret) => FetchedSecret {
-                    value: Some(secret),
+                    value: Some(Secret::new(secret)),
                     error: None,
+                    code: None,
                 },
-                None => FetchedSecret {
-                    value: None,
-                    error: Some(String::from("secret not found")),
-                },
-            },
-            Err(error) => match error {
-                ServiceError(error) => FetchedSecret {
-                    value: None,
-                    error: Some(format!("service error: {}", error.into_err())),
-                },
-                _ => FetchedSecret {
-                    value: None,
-                    error: Some(error.to_string()),
-                },
+                None => SecretsHelperError::NotFound(String::from("secret not found")).into(),
             },
+            Err(error) => failure(error).into(),
         }
     }
+
+    async fn version_id(
+        &self,
+        name: String,
+        stage: String,
+    ) -> Result<Option<String>, SecretsHelperError> {
+        let response = self
+            .describe_secret()
+            .secret_id(name)
+            .send()
+            .await
+            .map_err(failure)?;
+        Ok(response
+            .version_ids_to_stages
+            .into_iter()
+            .flatten()
+            .find(|(_, stages)| stages.contains(&stage))
+            .map(|(id, _)| id))
+    }
+
+    async fn status(&self, name: String) -> Result<Option<SecretStatus>, SecretsHelperError> {
+        let response = self
+            .describe_secret()
+            .secret_id(name)
+            .send()
+            .await
+            .map_err(failure)?;
+        let time = |date: Option<&aws_smithy_types::DateTime>| {
+            date.and_then(|date| SystemTime::try_from(*date).ok())
+        };
+        let pending_version = response
+            .version_ids_to_stages()
+            .into_iter()
+            .flat_map(|versions| versions.values())
+            .any(|stages| {
+                stages.iter().any(|stage| stage == "AWSPENDING")
+                    && !stages.iter().any(|stage| stage == "AWSCURRENT")
+            });
+        Ok(Some(SecretStatus {
+            deletion_date: time(response.deleted_date()),
+            rotation_enabled: response.rotation_enabled().unwrap_or_default(),
+            next_rotation_date: time(response.next_rotation_date()),
+            pending_version,
+        }))
+    }
 }
 
 /// A struct for loading secrets from AWS Secrets Manager.
 pub struct SecretsManagerSecretsLoader {
     client: Box<dyn SecretsManagerFetchSecret + Send + Sync>,
+    status_checks: StatusChecks,
+    /// The regions configured for secrets living outside the default region, keyed by name.
+    regions: HashMap<String, String>,
+    settings: LoaderSettings,
+}
+
+/// Create the error of a secret whose API call failed.
+fn failure<E, R>(error: SdkError<E, R>) -> SecretsHelperError
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: std::fmt::Debug + 'static,
+    SdkError<E, R>: RequestId,
+{
+    let error_code = classify(&error);
+    let code = error.code().map(str::to_string);
+    let request_id = error.request_id().map(str::to_string);
+    let message = match error {
+        ServiceError(error) => format!("service error: {}", error.into_err()),
+        _ => describe(&error),
+    };
+    let message = with_metadata(message, code.as_deref(), request_id.as_deref());
+    SecretsHelperError::new(error_code, message)
+}
+
+/// Parse a requested secret without its selector, returning the rest of the request and the name
+/// with its options.
+pub fn parse_requested(requested: &str) -> Result<(&str, RequestedSecret), String> {
+    let (requested, _) = extract::split_yaml_selector(requested)?;
+    Ok((requested, RequestedSecret::parse(requested, OPTIONS)?))
 }
 
 /// Implement the SecretsManagerSecretsLoader constructor.
 impl SecretsManagerSecretsLoader {
-    pub fn new(client: impl SecretsManagerFetchSecret + Send + Sync + 'static) -> Self {
+    pub fn new(
+        client: impl SecretsManagerFetchSecret + Send + Sync + 'static,
+        status_checks: StatusChecks,
+        regions: HashMap<String, String>,
+        settings: LoaderSettings,
+    ) -> Self {
         Self {
             client: Box::new(client),
+            status_checks,
+            regions,
+            settings,
+        }
+    }
+}
+
+/// Implement the SecretsManagerSecretsLoader methods.
+impl SecretsManagerSecretsLoader {
+    /// Fetch a single requested secret, applying the options passed along with its name.
+    async fn fetch(&self, requested: String) -> FetchedSecret {
+        let (requested, yaml_path) = match extract::split_yaml_selector(&requested) {
+            Ok(split) => split,
+            Err(error) => return invalid_request(error),
+        };
+        let secret = match RequestedSecret::parse(requested, OPTIONS) {
+            Ok(secret) => secret,
+            Err(error) => return invalid_request(error),
+        };
+        if secret.option("key").is_some() && yaml_path.is_some() {
+            return invalid_request(String::from(
+                "the key option can't be combined with a YAML selector",
+            ));
+        }
+        let regional_client;
+        let client = match requested_region(&secret, requested, &self.regions) {
+            Ok(Some(region)) => match self.client.in_region(&region) {
+                Some(client) => {
+                    regional_client = client;
+                    regional_client.as_ref()
+                }
+                None => {
+                    return invalid_request(format!("can't fetch secrets from region {}", region))
+                }
+            },
+            Ok(None) => self.client.as_ref(),
+            Err(error) => return invalid_request(error),
+        };
+        let version = SecretVersion {
+            stage: secret.option("stage").map(str::to_string),
+            id: secret.option("version").map(str::to_string),
+        };
+        let fetched_secret = client.fetch_secret(secret.name.clone(), version).await;
+        let fetched_secret = self
+            .check_status(client, &secret.name, fetched_secret)
+            .await;
+        match (secret.option("key"), yaml_path) {
+            (Some(key), _) => extract::apply(fetched_secret, |value| extract::json_key(value, key)),
+            (None, Some(path)) => {
+                extract::apply(fetched_secret, |value| extract::yaml_path(value, path))
+            }
+            (None, None) => fetched_secret,
+        }
+    }
+}
+
+/// Implement the SecretsManagerSecretsLoader status checks.
+impl SecretsManagerSecretsLoader {
+    /// Check the status of a fetched secret if enabled, reporting its problems on stderr and
+    /// failing it in strict mode. Secrets that failed to be fetched aren't checked.
+    async fn check_status(
+        &self,
+        client: &(dyn SecretsManagerFetchSecret + Send + Sync),
+        name: &str,
+        fetched_secret: FetchedSecret,
+    ) -> FetchedSecret {
+        if self.status_checks == StatusChecks::Off || fetched_secret.error.is_some() {
+            return fetched_secret;
+        }
+        let Some(status) = look_up(&self.settings, || client.status(name.to_string())).await else {
+            return fetched_secret;
+        };
+        let problems = status.problems(SystemTime::now());
+        for problem in &problems {
+            Diagnostic::new(
+                Level::Warning,
+                "secret_status_problem",
+                format!("{}: {}", name, problem),
+            )
+            .secret(name)
+            .emit();
+        }
+        match (self.status_checks, problems.is_empty()) {
+            (StatusChecks::Strict, false) => {
+                SecretsHelperError::Validation(problems.join("; ")).into()
+            }
+            _ => fetched_secret,
         }
     }
 }
@@ -59,33 +327,39 @@ impl SecretsManagerSecretsLoader {
 #[async_trait]
 impl LoadSecrets for SecretsManagerSecretsLoader {
     async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
-        let create_task = |secret_name: String| {
-            let secret_to_fetch = secret_name.clone();
-            let task = async { self.client.fetch_secret(secret_to_fetch).await };
-            (secret_name, task)
-        };
-
-        // Run tasks concurrently.
-        let (secret_names, tasks): (Vec<_>, Vec<_>) =
-            secrets.secrets.into_iter().map(create_task).unzip();
-        let results: Vec<_> = futures::future::join_all(tasks).await;
-
-        // Create a FetchedSecrets struct from the results.
-        let mut fetched_secrets = FetchedSecrets::default();
-        secret_names
-            .into_iter()
-            .zip(results)
-            .for_each(|(secret_name, result)| {
-                fetched_secrets.0.insert(secret_name, result);
-            });
+        fetch_all(secrets, &self.settings, |requested| self.fetch(requested)).await
+    }
 
-        fetched_secrets
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        let (requested, secret) = parse_requested(requested).ok()?;
+        // Version ids never change, unlike the versions staging labels point to.
+        if let Some(id) = secret.option("version") {
+            return Some(id.to_string());
+        }
+        let stage = secret.option("stage").unwrap_or("AWSCURRENT");
+        let name = &secret.name;
+        match requested_region(&secret, requested, &self.regions).ok()? {
+            Some(region) => {
+                let client = self.client.in_region(&region)?;
+                look_up(&self.settings, || {
+                    client.version_id(name.clone(), stage.to_string())
+                })
+                .await
+            }
+            None => {
+                look_up(&self.settings, || {
+                    self.client.version_id(name.clone(), stage.to_string())
+                })
+                .await
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vector::ErrorCode;
 
     #[tokio::test]
     async fn ssm_secrets_loader_loads_secrets() {
@@ -93,15 +367,17 @@ mod tests {
 
         #[async_trait]
         impl SecretsManagerFetchSecret for MockSecretsManagerFetchSecret {
-            async fn fetch_secret(&self, name: String) -> FetchedSecret {
+            async fn fetch_secret(&self, name: String, _version: SecretVersion) -> FetchedSecret {
                 match name.as_str() {
                     "test.secret_1" => FetchedSecret {
-                        value: Some("qwerty".to_string()),
+                        value: Some(Secret::new("qwerty".to_string())),
                         error: None,
+                        code: None,
                     },
                     "test.secret_2" => FetchedSecret {
                         value: None,
                         error: Some("failed to fetch".to_string()),
+                        code: None,
                     },
                     _ => unreachable!(),
                 }
@@ -113,7 +389,12 @@ mod tests {
             secrets: vec![String::from("test.secret_1"), String::from("test.secret_2")],
         };
 
-        let secrets_loader = SecretsManagerSecretsLoader::new(MockSecretsManagerFetchSecret {});
+        let secrets_loader = SecretsManagerSecretsLoader::new(
+            MockSecretsManagerFetchSecret {},
+            StatusChecks::Off,
+            HashMap::new(),
+            LoaderSettings::default(),
+        );
         let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
 
         assert_eq!(
@@ -123,8 +404,9 @@ mod tests {
                     (
                         "test.secret_1".to_string(),
                         FetchedSecret {
-                            value: Some("qwerty".to_string()),
+                            value: Some(Secret::new("qwerty".to_string())),
                             error: None,
+                            code: None,
                         }
                     ),
                     (
@@ -132,6 +414,7 @@ mod tests {
                         FetchedSecret {
                             value: None,
                             error: Some("failed to fetch".to_string()),
+                            code: None,
                         }
                     )
                 ]
@@ -141,4 +424,149 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn status_problems_are_described() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let day = Duration::from_secs(24 * 60 * 60);
+        let status = SecretStatus {
+            deletion_date: Some(now + 7 * day),
+            rotation_enabled: true,
+            next_rotation_date: Some(now - 2 * day),
+            pending_version: true,
+        };
+        assert_eq!(
+            status.problems(now),
+            vec![
+                "the secret is scheduled for deletion on 2023-11-21T22:13:20Z (in 7 days)",
+                "the rotation scheduled for 2023-11-12T22:13:20Z hasn't happened, its rotation function may be failing",
+                "a version is still labelled AWSPENDING, the last rotation may have failed",
+            ]
+        );
+
+        // Rotations are only late once the grace period is over, and only if rotation is enabled.
+        let status = SecretStatus {
+            next_rotation_date: Some(now - day / 2),
+            rotation_enabled: true,
+            ..Default::default()
+        };
+        assert!(status.problems(now).is_empty());
+        let status = SecretStatus {
+            next_rotation_date: Some(now - 2 * day),
+            pending_version: true,
+            ..Default::default()
+        };
+        assert!(status.problems(now).is_empty());
+    }
+
+    #[tokio::test]
+    async fn strict_status_checks_fail_secrets_scheduled_for_deletion() {
+        struct MockSecretsManagerFetchSecret {}
+
+        #[async_trait]
+        impl SecretsManagerFetchSecret for MockSecretsManagerFetchSecret {
+            async fn fetch_secret(&self, name: String, _version: SecretVersion) -> FetchedSecret {
+                match name.as_str() {
+                    "denied" => SecretsHelperError::AccessDenied(String::from("denied")).into(),
+                    _ => Ok(String::from("qwerty")).into(),
+                }
+            }
+
+            async fn status(
+                &self,
+                name: String,
+            ) -> Result<Option<SecretStatus>, SecretsHelperError> {
+                // Secrets that failed to be fetched aren't described.
+                assert_ne!(name, "denied");
+                Ok(Some(SecretStatus {
+                    deletion_date: (name == "deleted")
+                        .then(|| SystemTime::now() + Duration::from_secs(3600)),
+                    ..Default::default()
+                }))
+            }
+        }
+
+        for (status_checks, code) in [
+            (StatusChecks::Warn, None),
+            (StatusChecks::Strict, Some(ErrorCode::ValidationFailed)),
+        ] {
+            let secrets_loader = SecretsManagerSecretsLoader::new(
+                MockSecretsManagerFetchSecret {},
+                status_checks,
+                HashMap::new(),
+                LoaderSettings::default(),
+            );
+            let fetched_secrets = secrets_loader
+                .load(SecretsToFetch::from_names(vec![
+                    String::from("deleted"),
+                    String::from("kept"),
+                    String::from("denied"),
+                ]))
+                .await;
+            assert_eq!(fetched_secrets.0["deleted"].code, code);
+            assert_eq!(fetched_secrets.0["kept"].code, None);
+            assert_eq!(
+                fetched_secrets.0["denied"].code,
+                Some(ErrorCode::AccessDenied)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn secrets_manager_secrets_loader_applies_options() {
+        struct MockSecretsManagerFetchSecret {}
+
+        #[async_trait]
+        impl SecretsManagerFetchSecret for MockSecretsManagerFetchSecret {
+            async fn fetch_secret(&self, name: String, version: SecretVersion) -> FetchedSecret {
+                assert_eq!(name, "test.secret");
+                // Invalid requests are rejected before fetching.
+                assert_ne!(version.stage.as_deref(), Some("INVALID"));
+                let password = version.stage.unwrap_or(String::from("AWSCURRENT"));
+                FetchedSecret {
+                    value: Some(Secret::new(format!("{{\"password\": \"{}\"}}", password))),
+                    error: None,
+                    code: None,
+                }
+            }
+        }
+
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("test.secret?key=password&stage=AWSPREVIOUS"),
+            String::from("test.secret?key=username"),
+            String::from("test.secret?label=foo"),
+            String::from("test.secret?key=password&stage=INVALID#yaml:password"),
+        ]);
+
+        let secrets_loader = SecretsManagerSecretsLoader::new(
+            MockSecretsManagerFetchSecret {},
+            StatusChecks::Off,
+            HashMap::new(),
+            LoaderSettings::default(),
+        );
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        assert_eq!(
+            fetched_secrets.0["test.secret?key=password&stage=AWSPREVIOUS"].value,
+            Some(Secret::new(String::from("AWSPREVIOUS")))
+        );
+        assert_eq!(
+            fetched_secrets.0["test.secret?key=username"].code,
+            Some(ErrorCode::NotFound)
+        );
+        assert_eq!(
+            fetched_secrets.0["test.secret?label=foo"],
+            FetchedSecret {
+                value: None,
+                error: Some(String::from("unknown option: label")),
+                code: Some(ErrorCode::InvalidRequest),
+            }
+        );
+        assert_eq!(
+            fetched_secrets.0["test.secret?key=password&stage=INVALID#yaml:password"].error,
+            Some(String::from(
+                "the key option can't be combined with a YAML selector"
+            ))
+        );
+    }
 }
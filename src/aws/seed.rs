@@ -0,0 +1,245 @@
+//! This module contains the seeding of SSM parameters and Secrets Manager secrets from a fixtures
+//! file, intended for LocalStack or sandbox accounts, e.g.
+//!
+//! ```toml
+//! [ssm]
+//! "/app/db_password" = "hunter2"
+//! "/app/hosts" = { value = "a,b", type = "StringList" }
+//!
+//! [secretsmanager]
+//! "app/db" = '{"username": "vector", "password": "hunter2"}'
+//! ```
+
+use crate::aws::error::with_metadata;
+use crate::error::describe;
+use async_trait::async_trait;
+use aws_smithy_http::result::SdkError;
+use aws_smithy_types::error::metadata::ProvideErrorMetadata;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Debug;
+use std::path::Path;
+
+/// A struct representing the fixtures file, keyed by backend and then by name.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub ssm: BTreeMap<String, Fixture>,
+    #[serde(default)]
+    pub secretsmanager: BTreeMap<String, Fixture>,
+}
+
+/// An enum representing a single fixture, either just its value or its value along with the type
+/// of the SSM parameter.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Fixture {
+    Value(String),
+    Parameter {
+        value: String,
+        #[serde(rename = "type")]
+        parameter_type: ParameterType,
+    },
+}
+
+/// An enum representing the types of SSM parameters.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum ParameterType {
+    String,
+    StringList,
+    SecureString,
+}
+
+/// Implement the Fixture methods.
+impl Fixture {
+    /// The value of the fixture.
+    pub fn value(&self) -> &str {
+        match self {
+            Fixture::Value(value) | Fixture::Parameter { value, .. } => value,
+        }
+    }
+
+    /// The type of the SSM parameter, None if not given.
+    pub fn parameter_type(&self) -> Option<ParameterType> {
+        match self {
+            Fixture::Value(_) => None,
+            Fixture::Parameter { parameter_type, .. } => Some(*parameter_type),
+        }
+    }
+}
+
+/// Implement the Fixtures methods.
+impl Fixtures {
+    /// Load the fixtures file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| format!("failed to read {}: {}", path.display(), error))?;
+        Self::parse(&contents)
+            .map_err(|error| format!("failed to parse {}: {}", path.display(), error))
+    }
+
+    /// Parse the contents of the fixtures file.
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+}
+
+/// A trait for creating a secret or updating its value if it already exists.
+#[async_trait]
+pub trait PutSecret {
+    async fn put_secret(&self, name: &str, fixture: &Fixture) -> Result<(), String>;
+}
+
+/// Implement the PutSecret trait for the AWS SDK SSM Parameter Store client. Parameters are created
+/// as SecureString unless another type is given.
+#[cfg(feature = "ssm")]
+#[async_trait]
+impl PutSecret for aws_sdk_ssm::Client {
+    async fn put_secret(&self, name: &str, fixture: &Fixture) -> Result<(), String> {
+        let parameter_type = match fixture.parameter_type() {
+            Some(ParameterType::String) => aws_sdk_ssm::types::ParameterType::String,
+            Some(ParameterType::StringList) => aws_sdk_ssm::types::ParameterType::StringList,
+            Some(ParameterType::SecureString) | None => {
+                aws_sdk_ssm::types::ParameterType::SecureString
+            }
+        };
+        self.put_parameter()
+            .name(name)
+            .value(fixture.value())
+            .r#type(parameter_type)
+            .overwrite(true)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(render)
+    }
+}
+
+/// Implement the PutSecret trait for the AWS SDK Secrets Manager client, storing a new version of
+/// the secret if it already exists.
+#[cfg(feature = "secretsmanager")]
+#[async_trait]
+impl PutSecret for aws_sdk_secretsmanager::Client {
+    async fn put_secret(&self, name: &str, fixture: &Fixture) -> Result<(), String> {
+        if fixture.parameter_type().is_some() {
+            return Err(String::from("types are only supported for SSM parameters"));
+        }
+        let created = self
+            .create_secret()
+            .name(name)
+            .secret_string(fixture.value())
+            .send()
+            .await;
+        match created {
+            Ok(_) => Ok(()),
+            Err(error) if error.code() == Some("ResourceExistsException") => self
+                .put_secret_value()
+                .secret_id(name)
+                .secret_string(fixture.value())
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(render),
+            Err(error) => Err(render(error)),
+        }
+    }
+}
+
+/// Render a failed AWS API call like the errors of fetched secrets.
+fn render<E, R>(error: SdkError<E, R>) -> String
+where
+    E: ProvideErrorMetadata + Error + 'static,
+    R: Debug + 'static,
+{
+    let message = match (&error, error.message()) {
+        (SdkError::ServiceError(_), Some(message)) => format!("service error: {}", message),
+        _ => describe(&error),
+    };
+    with_metadata(message, error.code(), None)
+}
+
+/// Create or update all fixtures of a backend one after the other, returning the names of those
+/// that failed along with the errors.
+pub async fn seed(
+    client: &(dyn PutSecret + Send + Sync),
+    fixtures: &BTreeMap<String, Fixture>,
+) -> Vec<(String, String)> {
+    let mut errors = Vec::new();
+    for (name, fixture) in fixtures {
+        if let Err(error) = client.put_secret(name, fixture).await {
+            errors.push((name.clone(), error));
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct MockPutSecret {
+        stored: Mutex<BTreeMap<String, String>>,
+    }
+
+    #[async_trait]
+    impl PutSecret for MockPutSecret {
+        async fn put_secret(&self, name: &str, fixture: &Fixture) -> Result<(), String> {
+            if name.contains("denied") {
+                return Err(String::from("AccessDeniedException"));
+            }
+            self.stored
+                .lock()
+                .unwrap()
+                .insert(name.to_string(), fixture.value().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fixtures_can_be_parsed() {
+        let fixtures = Fixtures::parse(
+            "[ssm]\n\"/app/db_password\" = \"hunter2\"\n\"/app/hosts\" = { value = \"a,b\", type = \"StringList\" }\n\n[secretsmanager]\n\"app/db\" = \"{}\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            fixtures.ssm["/app/db_password"],
+            Fixture::Value(String::from("hunter2"))
+        );
+        assert_eq!(fixtures.ssm["/app/hosts"].value(), "a,b");
+        assert_eq!(
+            fixtures.ssm["/app/hosts"].parameter_type(),
+            Some(ParameterType::StringList)
+        );
+        assert_eq!(fixtures.secretsmanager["app/db"].value(), "{}");
+    }
+
+    #[test]
+    fn fixtures_with_invalid_settings_are_rejected() {
+        assert!(Fixtures::parse("[ssm]\n\"/a\" = { value = \"a\", type = \"Binary\" }\n").is_err());
+        assert!(Fixtures::parse("[vault]\n\"/a\" = \"a\"\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn seed_reports_failed_fixtures() {
+        let fixtures =
+            Fixtures::parse("[ssm]\n\"/app/a\" = \"1\"\n\"/app/denied\" = \"2\"\n").unwrap();
+        let client = MockPutSecret {
+            stored: Mutex::new(BTreeMap::new()),
+        };
+
+        let errors = seed(&client, &fixtures.ssm).await;
+
+        assert_eq!(
+            errors,
+            vec![(
+                String::from("/app/denied"),
+                String::from("AccessDeniedException")
+            )]
+        );
+        assert_eq!(client.stored.lock().unwrap()["/app/a"], "1");
+    }
+}
@@ -1,15 +1,53 @@
 //! This module contains the secrets loader implementation for AWS SSM Parameter Store.
 
+use crate::aws::arn::{requested_region, Arn};
+use crate::aws::error::{classify, with_metadata};
+use crate::aws::loader::{fetch_all, look_up, LoaderSettings};
+use crate::aws::options::{invalid_request, RequestedSecret};
+use crate::error::{describe, SecretsHelperError};
+use crate::extract;
+use crate::secret::Secret;
 use crate::vector::{FetchedSecret, FetchedSecrets, SecretsToFetch};
 use crate::LoadSecrets;
 use async_trait::async_trait;
-use aws_sdk_ssm::error::SdkError::ServiceError;
+use aws_sdk_ssm::error::ProvideErrorMetadata;
+use aws_sdk_ssm::error::SdkError::{self, ServiceError};
+use aws_sdk_ssm::operation::RequestId;
+use aws_sdk_ssm::primitives::DateTime;
+use aws_sdk_ssm::types::ParameterStringFilter;
 use aws_sdk_ssm::Client;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+/// The options of a requested parameter.
+const OPTIONS: &[&str] = &["version", "decrypt", "region"];
 
 /// A trait for fetching a single secret from AWS SSM Parameter Store.
 #[async_trait]
 pub trait SsmFetchSecret {
     async fn fetch_secret(&self, name: String, with_decryption: bool) -> FetchedSecret;
+
+    /// Get the current version of a parameter without fetching its value, None if it can't be
+    /// determined.
+    async fn parameter_version(&self, _name: String) -> Result<Option<String>, SecretsHelperError> {
+        Ok(None)
+    }
+
+    /// Fetch the value a parameter had at the time, i.e. the value of its last version modified
+    /// before then, from its history.
+    async fn fetch_secret_as_of(
+        &self,
+        _name: String,
+        _with_decryption: bool,
+        _as_of: SystemTime,
+    ) -> FetchedSecret {
+        invalid_request(String::from("the history of parameters isn't available"))
+    }
+
+    /// Get a client fetching parameters from the region instead, None if this client can't.
+    fn in_region(&self, _region: &str) -> Option<Box<dyn SsmFetchSecret + Send + Sync>> {
+        None
+    }
 }
 
 /// Implement the SsmFetchSecret trait for the AWS SDK SSM Parameter Store client.
@@ -26,78 +64,296 @@ impl SsmFetchSecret for Client {
             Ok(response) => match response.parameter {
                 Some(parameter) => match parameter.value {
                     Some(value) => FetchedSecret {
-                        value: Some(value),
+                        value: Some(Secret::new(value)),
                         error: None,
+                        code: None,
                     },
-                    None => FetchedSecret {
-                        value: None,
-                        error: Some(String::from("parameter value not found")),
-                    },
-                },
-                None => FetchedSecret {
-                    value: None,
-                    error: Some(String::from("parameter not found")),
+                    None => SecretsHelperError::NotFound(String::from("parameter value not found"))
+                        .into(),
                 },
+                None => SecretsHelperError::NotFound(String::from("parameter not found")).into(),
             },
-            Err(error) => match error {
-                ServiceError(error) => FetchedSecret {
-                    value: None,
-                    error: Some(format!("service error: {}", error.into_err())),
-                },
-                _ => FetchedSecret {
-                    value: None,
-                    error: Some(error.to_string()),
-                },
+            Err(error) => failure(error).into(),
+        }
+    }
+
+    async fn fetch_secret_as_of(
+        &self,
+        name: String,
+        with_decryption: bool,
+        as_of: SystemTime,
+    ) -> FetchedSecret {
+        let before = DateTime::from(as_of);
+        let mut latest: Option<(DateTime, Option<String>)> = None;
+        let mut next_token = None;
+        loop {
+            let response = match self
+                .get_parameter_history()
+                .name(&name)
+                .with_decryption(with_decryption)
+                .set_next_token(next_token)
+                .send()
+                .await
+            {
+                Ok(response) => response,
+                Err(error) => return failure(error).into(),
+            };
+            for parameter in response.parameters.unwrap_or_default() {
+                let Some(modified) = parameter.last_modified_date else {
+                    continue;
+                };
+                if modified <= before && latest.as_ref().is_none_or(|(last, _)| *last < modified) {
+                    latest = Some((modified, parameter.value));
+                }
+            }
+            next_token = response.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                break;
+            }
+        }
+        match latest {
+            Some((_, Some(value))) => FetchedSecret {
+                value: Some(Secret::new(value)),
+                error: None,
+                code: None,
             },
+            Some((_, None)) => {
+                SecretsHelperError::NotFound(String::from("parameter value not found")).into()
+            }
+            // Only the last 100 versions of a parameter are kept.
+            None => SecretsHelperError::NotFound(format!(
+                "no version of the parameter in its history was modified before {}",
+                humantime::format_rfc3339_seconds(as_of)
+            ))
+            .into(),
+        }
+    }
+
+    async fn parameter_version(&self, name: String) -> Result<Option<String>, SecretsHelperError> {
+        // Parameters shared from other accounts aren't listed by DescribeParameters.
+        if Arn::parse(&name).is_some() {
+            return Ok(None);
         }
+        let filter = ParameterStringFilter::builder()
+            .key("Name")
+            .option("Equals")
+            .values(name)
+            .build();
+        let response = self
+            .describe_parameters()
+            .parameter_filters(filter)
+            .send()
+            .await
+            .map_err(failure)?;
+        let parameter = response.parameters.into_iter().flatten().next();
+        Ok(parameter.map(|parameter| parameter.version.to_string()))
     }
 }
 
+/// Create the error of a parameter whose API call failed.
+fn failure<E, R>(error: SdkError<E, R>) -> SecretsHelperError
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: std::fmt::Debug + 'static,
+    SdkError<E, R>: RequestId,
+{
+    let error_code = classify(&error);
+    let code = error.code().map(str::to_string);
+    let request_id = error.request_id().map(str::to_string);
+    let message = match error {
+        ServiceError(error) => format!("service error: {}", error.into_err()),
+        _ => describe(&error),
+    };
+    let message = with_metadata(message, code.as_deref(), request_id.as_deref());
+    SecretsHelperError::new(error_code, message)
+}
+
+/// An enum representing the selector extracting a field of the value of a requested parameter.
+#[derive(Debug, PartialEq)]
+enum Selector<'a> {
+    /// A JSON Pointer selecting a field of a JSON document.
+    JsonPointer(&'a str),
+    /// A dot-separated path selecting a field of a YAML document.
+    YamlPath(&'a str),
+}
+
+/// Split the selector extracting a field of the value off a requested parameter, returning the
+/// rest of the request and the selector. Only one selector can be used.
+fn split_selectors(requested: &str) -> Result<(&str, Option<Selector<'_>>), String> {
+    let (requested, yaml_path) = extract::split_yaml_selector(requested)?;
+    // A JSON Pointer selecting a field of a JSON document can follow the name after a `|`, which
+    // can't appear in parameter names.
+    match (requested.split_once('|'), yaml_path) {
+        (Some(_), Some(_)) => Err(String::from(
+            "a JSON Pointer can't be combined with a YAML selector",
+        )),
+        (Some((requested, pointer)), None) => Ok((requested, Some(Selector::JsonPointer(pointer)))),
+        (None, Some(path)) => Ok((requested, Some(Selector::YamlPath(path)))),
+        (None, None) => Ok((requested, None)),
+    }
+}
+
+/// Parse a requested parameter without its selector, returning the rest of the request and the
+/// name with its options.
+pub fn parse_requested(requested: &str) -> Result<(&str, RequestedSecret), String> {
+    let (requested, _) = split_selectors(requested)?;
+    Ok((requested, RequestedSecret::parse(requested, OPTIONS)?))
+}
+
+/// Check that a parameter referenced by ARN, e.g. one shared from another account through AWS RAM,
+/// is referenced by a complete SSM parameter ARN, since the API can only resolve shared parameters
+/// by their full ARN including the region and the owning account.
+fn check_parameter_arn(name: &str) -> Result<(), String> {
+    let Some(arn) = Arn::parse(name) else {
+        return Ok(());
+    };
+    if arn.service != "ssm" || !arn.resource.starts_with("parameter/") {
+        return Err(String::from("not an SSM parameter ARN"));
+    }
+    if arn.region.is_empty() || arn.account.is_empty() {
+        return Err(String::from(
+            "parameter ARN must include the region and account",
+        ));
+    }
+    Ok(())
+}
+
 /// A struct for loading secrets from AWS SSM Parameter Store.
 pub struct SsmSecretsLoader {
     client: Box<dyn SsmFetchSecret + Send + Sync>,
     with_decryption: bool,
+    /// The time at which parameters are resolved from their history instead of their current
+    /// value.
+    as_of: Option<SystemTime>,
+    /// The regions configured for secrets living outside the default region, keyed by name.
+    regions: HashMap<String, String>,
+    settings: LoaderSettings,
 }
 
 /// Implement the SsmSecretsLoader constructor.
 impl SsmSecretsLoader {
-    pub fn new(client: impl SsmFetchSecret + Send + Sync + 'static, with_decryption: bool) -> Self {
+    pub fn new(
+        client: impl SsmFetchSecret + Send + Sync + 'static,
+        with_decryption: bool,
+        as_of: Option<SystemTime>,
+        regions: HashMap<String, String>,
+        settings: LoaderSettings,
+    ) -> Self {
         Self {
             client: Box::new(client),
             with_decryption,
+            as_of,
+            regions,
+            settings,
         }
     }
 }
 
-/// Implement the LoadSecrets trait for SsmSecretsLoader.
-#[async_trait]
-impl LoadSecrets for SsmSecretsLoader {
-    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
-        let create_task = |secret_name: String| {
-            let secret_to_fetch = secret_name.clone();
-            let task = async {
-                self.client
-                    .fetch_secret(secret_to_fetch, self.with_decryption)
+/// Implement the SsmSecretsLoader methods.
+impl SsmSecretsLoader {
+    /// Fetch a single requested parameter, applying the options passed along with its name.
+    async fn fetch(&self, requested: String) -> FetchedSecret {
+        let (requested, selector) = match split_selectors(&requested) {
+            Ok(split) => split,
+            Err(error) => return invalid_request(error),
+        };
+        let secret = match RequestedSecret::parse(requested, OPTIONS) {
+            Ok(secret) => secret,
+            Err(error) => return invalid_request(error),
+        };
+        if let Err(error) = check_parameter_arn(&secret.name) {
+            return invalid_request(error);
+        }
+        let region = match requested_region(&secret, requested, &self.regions) {
+            Ok(region) => region,
+            Err(error) => return invalid_request(error),
+        };
+        let with_decryption = match secret.bool_option("decrypt") {
+            Ok(decrypt) => decrypt.unwrap_or(self.with_decryption),
+            Err(error) => return invalid_request(error),
+        };
+        if self.as_of.is_some() && secret.option("version").is_some() {
+            return invalid_request(String::from(
+                "the version option can't be combined with --as-of",
+            ));
+        }
+        // Parameter versions are selected with a suffix of the name.
+        let name = match secret.option("version") {
+            Some(version) => format!("{}:{}", secret.name, version),
+            None => secret.name,
+        };
+        let regional_client;
+        let client = match region {
+            Some(region) => match self.client.in_region(&region) {
+                Some(client) => {
+                    regional_client = client;
+                    regional_client.as_ref()
+                }
+                None => {
+                    return invalid_request(format!(
+                        "can't fetch parameters from region {}",
+                        region
+                    ))
+                }
+            },
+            None => self.client.as_ref(),
+        };
+        let fetched_secret = match self.as_of {
+            Some(as_of) => {
+                client
+                    .fetch_secret_as_of(name, with_decryption, as_of)
                     .await
-            };
-            (secret_name, task)
+            }
+            None => client.fetch_secret(name, with_decryption).await,
         };
+        select(fetched_secret, selector)
+    }
+}
 
-        // Run tasks concurrently.
-        let (secret_names, tasks): (Vec<_>, Vec<_>) =
-            secrets.secrets.into_iter().map(create_task).unzip();
-        let results: Vec<_> = futures::future::join_all(tasks).await;
+/// Extract the field selected by the selector from the value of a fetched parameter, if any.
+fn select(fetched_secret: FetchedSecret, selector: Option<Selector>) -> FetchedSecret {
+    match selector {
+        Some(Selector::JsonPointer(pointer)) => extract::apply(fetched_secret, |value| {
+            extract::json_pointer(value, pointer)
+        }),
+        Some(Selector::YamlPath(path)) => {
+            extract::apply(fetched_secret, |value| extract::yaml_path(value, path))
+        }
+        None => fetched_secret,
+    }
+}
 
-        // Create a FetchedSecrets struct from the results.
-        let mut fetched_secrets = FetchedSecrets::default();
-        secret_names
-            .into_iter()
-            .zip(results)
-            .for_each(|(secret_name, result)| {
-                fetched_secrets.0.insert(secret_name, result);
-            });
+/// Implement the LoadSecrets trait for SsmSecretsLoader.
+#[async_trait]
+impl LoadSecrets for SsmSecretsLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        fetch_all(secrets, &self.settings, |requested| self.fetch(requested)).await
+    }
 
-        fetched_secrets
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        let (_, secret) = parse_requested(requested).ok()?;
+        match secret.option("version") {
+            // Numbered versions never change, unlike labels which can be moved.
+            Some(version) if version.chars().all(|c| c.is_ascii_digit()) => {
+                Some(version.to_string())
+            }
+            Some(_) => None,
+            None => {
+                let name = &secret.name;
+                match requested_region(&secret, requested, &self.regions).ok()? {
+                    Some(region) => {
+                        let client = self.client.in_region(&region)?;
+                        look_up(&self.settings, || client.parameter_version(name.clone())).await
+                    }
+                    None => {
+                        look_up(&self.settings, || {
+                            self.client.parameter_version(name.clone())
+                        })
+                        .await
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -114,12 +370,14 @@ mod tests {
             async fn fetch_secret(&self, name: String, _with_decryption: bool) -> FetchedSecret {
                 match name.as_str() {
                     "test.secret_1" => FetchedSecret {
-                        value: Some("qwerty".to_string()),
+                        value: Some(Secret::new("qwerty".to_string())),
                         error: None,
+                        code: None,
                     },
                     "test.secret_2" => FetchedSecret {
                         value: None,
                         error: Some("failed to fetch".to_string()),
+                        code: None,
                     },
                     _ => unreachable!(),
                 }
@@ -131,7 +389,13 @@ mod tests {
             secrets: vec![String::from("test.secret_1"), String::from("test.secret_2")],
         };
 
-        let secrets_loader = SsmSecretsLoader::new(MockSsmFetchSecret {}, true);
+        let secrets_loader = SsmSecretsLoader::new(
+            MockSsmFetchSecret {},
+            true,
+            None,
+            HashMap::new(),
+            LoaderSettings::default(),
+        );
         let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
 
         assert_eq!(
@@ -141,8 +405,9 @@ mod tests {
                     (
                         "test.secret_1".to_string(),
                         FetchedSecret {
-                            value: Some("qwerty".to_string()),
+                            value: Some(Secret::new("qwerty".to_string())),
                             error: None,
+                            code: None,
                         }
                     ),
                     (
@@ -150,6 +415,7 @@ mod tests {
                         FetchedSecret {
                             value: None,
                             error: Some("failed to fetch".to_string()),
+                            code: None,
                         }
                     )
                 ]
@@ -159,4 +425,192 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn parameter_arns_must_be_complete() {
+        assert!(check_parameter_arn("/app/db_password").is_ok());
+        assert!(check_parameter_arn(
+            "arn:aws:ssm:eu-west-1:123456789012:parameter/app/db_password"
+        )
+        .is_ok());
+        assert_eq!(
+            check_parameter_arn("arn:aws:ssm:eu-west-1::parameter/app/db_password"),
+            Err(String::from(
+                "parameter ARN must include the region and account"
+            ))
+        );
+        assert_eq!(
+            check_parameter_arn(
+                "arn:aws:secretsmanager:eu-west-1:123456789012:secret:app/db_password"
+            ),
+            Err(String::from("not an SSM parameter ARN"))
+        );
+    }
+
+    #[test]
+    fn selectors_are_split_off_the_request() {
+        assert_eq!(
+            split_selectors("/app/config?version=2|/db/password"),
+            Ok((
+                "/app/config?version=2",
+                Some(Selector::JsonPointer("/db/password"))
+            ))
+        );
+        assert_eq!(
+            split_selectors("/app/config#yaml:db.password"),
+            Ok(("/app/config", Some(Selector::YamlPath("db.password"))))
+        );
+        assert_eq!(split_selectors("/app/config"), Ok(("/app/config", None)));
+        assert_eq!(
+            split_selectors("/app/config|/db#yaml:db.password"),
+            Err(String::from(
+                "a JSON Pointer can't be combined with a YAML selector"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn ssm_secrets_loader_applies_options() {
+        struct MockSsmFetchSecret {}
+
+        #[async_trait]
+        impl SsmFetchSecret for MockSsmFetchSecret {
+            async fn fetch_secret(&self, name: String, with_decryption: bool) -> FetchedSecret {
+                FetchedSecret {
+                    value: Some(Secret::new(format!(
+                        "{{\"name\": \"{} {}\"}}",
+                        name, with_decryption
+                    ))),
+                    error: None,
+                    code: None,
+                }
+            }
+        }
+
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("/app/param?version=3&decrypt=false|/name"),
+            String::from("/app/param?key=password"),
+        ]);
+
+        let secrets_loader = SsmSecretsLoader::new(
+            MockSsmFetchSecret {},
+            true,
+            None,
+            HashMap::new(),
+            LoaderSettings::default(),
+        );
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        assert_eq!(
+            fetched_secrets.0["/app/param?version=3&decrypt=false|/name"].value,
+            Some(Secret::new(String::from("/app/param:3 false")))
+        );
+        assert_eq!(
+            fetched_secrets.0["/app/param?key=password"].error,
+            Some(String::from("unknown option: key"))
+        );
+    }
+
+    #[tokio::test]
+    async fn ssm_secrets_loader_fetches_from_requested_regions() {
+        struct MockSsmFetchSecret {
+            region: &'static str,
+        }
+
+        #[async_trait]
+        impl SsmFetchSecret for MockSsmFetchSecret {
+            async fn fetch_secret(&self, name: String, _with_decryption: bool) -> FetchedSecret {
+                Ok(format!("{} in {}", name, self.region)).into()
+            }
+
+            fn in_region(&self, region: &str) -> Option<Box<dyn SsmFetchSecret + Send + Sync>> {
+                let region = if region == "eu-west-1" {
+                    "eu-west-1"
+                } else {
+                    "other"
+                };
+                Some(Box::new(MockSsmFetchSecret { region }))
+            }
+        }
+
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("/app/db"),
+            String::from("/app/api"),
+            String::from("/app/api?region=eu-west-1"),
+        ]);
+
+        let regions = HashMap::from([(String::from("/app/db"), String::from("eu-west-1"))]);
+        let secrets_loader = SsmSecretsLoader::new(
+            MockSsmFetchSecret {
+                region: "us-east-1",
+            },
+            true,
+            None,
+            regions,
+            LoaderSettings::default(),
+        );
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        let value = |name: &str| fetched_secrets.0[name].value.clone();
+        assert_eq!(
+            value("/app/db"),
+            Some(Secret::new(String::from("/app/db in eu-west-1")))
+        );
+        assert_eq!(
+            value("/app/api"),
+            Some(Secret::new(String::from("/app/api in us-east-1")))
+        );
+        assert_eq!(
+            value("/app/api?region=eu-west-1"),
+            Some(Secret::new(String::from("/app/api in eu-west-1")))
+        );
+    }
+
+    #[tokio::test]
+    async fn ssm_secrets_loader_resolves_parameters_as_of_a_time() {
+        struct MockSsmFetchSecret {}
+
+        #[async_trait]
+        impl SsmFetchSecret for MockSsmFetchSecret {
+            async fn fetch_secret(&self, name: String, _with_decryption: bool) -> FetchedSecret {
+                Ok(format!("current {}", name)).into()
+            }
+
+            async fn fetch_secret_as_of(
+                &self,
+                name: String,
+                _with_decryption: bool,
+                as_of: SystemTime,
+            ) -> FetchedSecret {
+                let as_of = as_of.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+                Ok(format!("{} as of {}", name, as_of.as_secs())).into()
+            }
+        }
+
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("/app/db"),
+            String::from("/app/api?version=2"),
+        ]);
+
+        let as_of = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1700000000);
+        let secrets_loader = SsmSecretsLoader::new(
+            MockSsmFetchSecret {},
+            true,
+            Some(as_of),
+            HashMap::new(),
+            LoaderSettings::default(),
+        );
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        assert_eq!(
+            fetched_secrets.0["/app/db"].value,
+            Some(Secret::new(String::from("/app/db as of 1700000000")))
+        );
+        assert_eq!(
+            fetched_secrets.0["/app/api?version=2"].error,
+            Some(String::from(
+                "the version option can't be combined with --as-of"
+            ))
+        );
+    }
 }
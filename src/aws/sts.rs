@@ -0,0 +1,297 @@
+//! This module contains helpers for AWS Security Token Service, including the chaining of roles
+//! assumed one after the other, e.g. through an intermediate security-broker role.
+
+use crate::aws::credentials::SharedCredentials;
+use crate::error::describe;
+use aws_config::SdkConfig;
+use aws_credential_types::cache::CredentialsCache;
+use aws_credential_types::provider::error::CredentialsError;
+use aws_credential_types::provider::{self, future, ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use aws_sdk_sts::types::Tag;
+use aws_sdk_sts::Client;
+use aws_smithy_types::error::metadata::ProvideErrorMetadata;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::time::{Duration, SystemTime};
+
+/// A struct representing the identity whose credentials are used to call AWS.
+#[derive(Debug, PartialEq)]
+pub struct CallerIdentity {
+    pub account: String,
+    pub arn: String,
+    pub user_id: String,
+}
+
+/// Implement the Display trait for CallerIdentity, rendering one line per field.
+impl fmt::Display for CallerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "account: {}", self.account)?;
+        writeln!(f, "arn: {}", self.arn)?;
+        writeln!(f, "user id: {}", self.user_id)
+    }
+}
+
+/// Get the identity whose credentials are used to call AWS.
+pub async fn caller_identity(client: &Client) -> Result<CallerIdentity, String> {
+    match client.get_caller_identity().send().await {
+        Ok(response) => Ok(CallerIdentity {
+            account: response.account.unwrap_or_default(),
+            arn: response
+                .arn
+                .ok_or_else(|| String::from("caller identity has no ARN"))?,
+            user_id: response.user_id.unwrap_or_default(),
+        }),
+        Err(error) => Err(crate::error::describe(&error)),
+    }
+}
+
+/// Get the ARN of the identity whose credentials are used to call AWS.
+pub async fn caller_arn(client: &Client) -> Result<String, String> {
+    caller_identity(client).await.map(|identity| identity.arn)
+}
+
+/// A struct representing the MFA device required by the trust policy of the first role of a chain.
+#[derive(Clone, Debug)]
+pub struct Mfa {
+    pub serial_number: String,
+    /// The current code of the device, None to prompt for it on the terminal whenever the role is
+    /// assumed.
+    pub token_code: Option<String>,
+}
+
+/// Implement the Mfa methods.
+impl Mfa {
+    /// Get the code of the device, prompting for it if none was passed.
+    async fn token_code(&self) -> Result<String, String> {
+        if let Some(token_code) = &self.token_code {
+            return parse_token_code(token_code);
+        }
+        let serial_number = self.serial_number.clone();
+        tokio::task::spawn_blocking(move || prompt_token_code(&serial_number))
+            .await
+            .map_err(|error| error.to_string())?
+    }
+}
+
+/// Check that an MFA code is made of six digits, ignoring surrounding whitespace.
+fn parse_token_code(token_code: &str) -> Result<String, String> {
+    let token_code = token_code.trim();
+    if token_code.len() != 6 || !token_code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(String::from("the MFA code must be six digits"));
+    }
+    Ok(token_code.to_string())
+}
+
+/// Prompt for the code of the MFA device on the terminal. Stdin must be a terminal so that the
+/// request of Vector is never mistaken for a code, but the code is read from the controlling
+/// terminal since stdin is locked while the request is handled.
+fn prompt_token_code(serial_number: &str) -> Result<String, String> {
+    if !std::io::stdin().is_terminal() {
+        return Err(String::from(
+            "--mfa-token is required unless stdin is a terminal",
+        ));
+    }
+    let failed = |error: std::io::Error| format!("failed to read the MFA code: {}", error);
+    let mut terminal = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(failed)?;
+    write!(terminal, "MFA code for {}: ", serial_number).map_err(failed)?;
+    let mut token_code = String::new();
+    BufReader::new(terminal)
+        .read_line(&mut token_code)
+        .map_err(failed)?;
+    parse_token_code(&token_code)
+}
+
+/// A struct representing the session tags passed when assuming the first role of a chain, so that
+/// attribute-based IAM policies can authorize the helper by tag. Transitive tags are kept by the
+/// sessions of the following roles.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionTags {
+    pub tags: Vec<(String, String)>,
+    pub transitive_tag_keys: Vec<String>,
+}
+
+/// Implement the SessionTags methods.
+impl SessionTags {
+    /// Check that every transitive tag key is the key of a tag.
+    pub fn check(&self) -> Result<(), String> {
+        match self
+            .transitive_tag_keys
+            .iter()
+            .find(|key| !self.tags.iter().any(|(tag_key, _)| tag_key == *key))
+        {
+            Some(key) => Err(format!("transitive tag key {} isn't a session tag", key)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parse a session tag given as `key=value`.
+pub fn parse_session_tag(tag: &str) -> Result<(String, String), String> {
+    match tag.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("{} is not of the form key=value", tag)),
+    }
+}
+
+/// A struct providing the credentials of a role assumed with the credentials of the client, one hop
+/// of a role chain.
+#[derive(Debug)]
+pub struct AssumedRole {
+    client: Client,
+    role_arn: String,
+    session_name: String,
+    /// The MFA device required to assume the role, if any.
+    mfa: Option<Mfa>,
+    tags: Option<SessionTags>,
+}
+
+/// Implement the AssumedRole methods.
+impl AssumedRole {
+    /// Assume the role and convert the temporary credentials returned by STS.
+    async fn assume(&self) -> provider::Result {
+        let failed = |message: String| {
+            CredentialsError::provider_error(format!(
+                "failed to assume {}: {}",
+                self.role_arn, message
+            ))
+        };
+        let mut request = self
+            .client
+            .assume_role()
+            .role_arn(&self.role_arn)
+            .role_session_name(&self.session_name);
+        if let Some(mfa) = &self.mfa {
+            request = request
+                .serial_number(&mfa.serial_number)
+                .token_code(mfa.token_code().await.map_err(failed)?);
+        }
+        if let Some(tags) = &self.tags {
+            for (key, value) in &tags.tags {
+                request = request.tags(Tag::builder().key(key).value(value).build());
+            }
+            for key in &tags.transitive_tag_keys {
+                request = request.transitive_tag_keys(key);
+            }
+        }
+        let response =
+            request
+                .send()
+                .await
+                .map_err(|error| match (error.code(), error.message()) {
+                    (Some(code), Some(message)) => failed(format!("{}: {}", code, message)),
+                    _ => failed(describe(&error)),
+                })?;
+        let credentials = response
+            .credentials
+            .ok_or_else(|| failed(String::from("no credentials returned")))?;
+        let (Some(access_key_id), Some(secret_access_key)) =
+            (credentials.access_key_id, credentials.secret_access_key)
+        else {
+            return Err(failed(String::from("incomplete credentials returned")));
+        };
+        let expiry = credentials
+            .expiration
+            .and_then(|expiration| SystemTime::try_from(expiration).ok());
+        Ok(Credentials::new(
+            access_key_id,
+            secret_access_key,
+            credentials.session_token,
+            expiry,
+            "AssumedRole",
+        ))
+    }
+}
+
+/// Implement the ProvideCredentials trait for AssumedRole.
+impl ProvideCredentials for AssumedRole {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.assume())
+    }
+}
+
+/// Chain the roles on top of the credentials of the AWS SDK config, assuming each role with the
+/// credentials of the previous one and the first one with the MFA device and session tags if any.
+/// The credentials
+/// of every hop but the last are cached and refreshed this long before they expire, the last one
+/// is left to the caller. None if the config has no credentials.
+pub fn assume_roles(
+    sdk_config: &SdkConfig,
+    roles: &[String],
+    session_name: &str,
+    mfa: Option<&Mfa>,
+    tags: Option<&SessionTags>,
+    endpoint_url: Option<&str>,
+    refresh_before_expiry: Duration,
+) -> Option<SharedCredentialsProvider> {
+    let mut provider = sdk_config.credentials_provider()?;
+    for (hop, role_arn) in roles.iter().enumerate() {
+        let credentials = SharedCredentials::new(provider, refresh_before_expiry);
+        let mut config_builder = aws_sdk_sts::config::Builder::from(sdk_config)
+            .credentials_provider(credentials)
+            .credentials_cache(CredentialsCache::no_caching());
+        if let Some(endpoint_url) = endpoint_url {
+            config_builder = config_builder.endpoint_url(endpoint_url);
+        }
+        provider = SharedCredentialsProvider::new(AssumedRole {
+            client: Client::from_conf(config_builder.build()),
+            role_arn: role_arn.clone(),
+            session_name: session_name.to_string(),
+            mfa: mfa.filter(|_| hop == 0).cloned(),
+            tags: tags.filter(|_| hop == 0).cloned(),
+        });
+    }
+    Some(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_tags_can_be_parsed() {
+        assert_eq!(
+            parse_session_tag("team=observability"),
+            Ok((String::from("team"), String::from("observability")))
+        );
+        assert_eq!(
+            parse_session_tag("env="),
+            Ok((String::from("env"), String::new()))
+        );
+        assert!(parse_session_tag("=value").is_err());
+        assert!(parse_session_tag("team").is_err());
+
+        let tags = SessionTags {
+            tags: vec![(String::from("team"), String::from("observability"))],
+            transitive_tag_keys: vec![String::from("team")],
+        };
+        assert_eq!(tags.check(), Ok(()));
+        let tags = SessionTags {
+            transitive_tag_keys: vec![String::from("env")],
+            ..tags
+        };
+        assert_eq!(
+            tags.check(),
+            Err(String::from("transitive tag key env isn't a session tag"))
+        );
+    }
+
+    #[test]
+    fn mfa_codes_must_be_six_digits() {
+        assert_eq!(parse_token_code(" 123456\n"), Ok(String::from("123456")));
+        for token_code in ["12345", "1234567", "12345a", ""] {
+            assert_eq!(
+                parse_token_code(token_code),
+                Err(String::from("the MFA code must be six digits"))
+            );
+        }
+    }
+}
@@ -0,0 +1,410 @@
+//! This module contains the secrets loader implementation for Azure Key Vault.
+
+use crate::aws::loader::{fetch_all, LoadSecrets, LoaderSettings};
+use crate::aws::options::{invalid_request, RequestedSecret};
+use crate::error::{describe, SecretsHelperError};
+use crate::extract;
+use crate::healthcheck::PingEndpoint;
+use crate::http;
+use crate::vector::{FetchedSecret, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// The version of the Key Vault REST API.
+const API_VERSION: &str = "7.4";
+/// The resource access tokens are requested for.
+const RESOURCE: &str = "https://vault.azure.net";
+/// The endpoint of the Instance Metadata Service issuing tokens for managed identities.
+const IMDS_TOKEN_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+/// The time after which the Instance Metadata Service is considered unreachable, e.g. outside of
+/// Azure.
+const IMDS_TIMEOUT: Duration = Duration::from_secs(5);
+/// Tokens are refreshed this long before they expire.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+/// An enum representing how access tokens are obtained.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AzureCredential {
+    /// A service principal authenticating with a client secret.
+    ServicePrincipal {
+        authority_host: String,
+        tenant_id: String,
+        client_id: String,
+        client_secret: String,
+    },
+    /// The managed identity of the VM, either system-assigned or the user-assigned one with the
+    /// client id.
+    ManagedIdentity { client_id: Option<String> },
+}
+
+/// Implement the AzureCredential methods.
+impl AzureCredential {
+    /// Select the credential from the environment variables also used by the Azure SDKs: a
+    /// service principal if `AZURE_TENANT_ID`, `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET` are all
+    /// set, otherwise a managed identity.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        match (
+            var("AZURE_TENANT_ID"),
+            var("AZURE_CLIENT_ID"),
+            var("AZURE_CLIENT_SECRET"),
+        ) {
+            (Some(tenant_id), Some(client_id), Some(client_secret)) => {
+                AzureCredential::ServicePrincipal {
+                    authority_host: var("AZURE_AUTHORITY_HOST")
+                        .unwrap_or_else(|| String::from("https://login.microsoftonline.com")),
+                    tenant_id,
+                    client_id,
+                    client_secret,
+                }
+            }
+            (_, client_id, _) => AzureCredential::ManagedIdentity { client_id },
+        }
+    }
+}
+
+/// A struct representing a token response of Microsoft Entra ID or the Instance Metadata Service,
+/// which return the lifetime as a number and a string respectively.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Value,
+}
+
+/// Implement the TokenResponse methods.
+impl TokenResponse {
+    fn lifetime(&self) -> Duration {
+        let seconds = match &self.expires_in {
+            Value::Number(seconds) => seconds.as_u64(),
+            Value::String(seconds) => seconds.parse().ok(),
+            _ => None,
+        };
+        Duration::from_secs(seconds.unwrap_or_default())
+    }
+}
+
+/// A trait for fetching a single secret from Azure Key Vault.
+#[async_trait]
+pub trait AzureFetchSecret {
+    /// Fetch a version of the secret from the vault at the URL, the current one if none is given.
+    async fn fetch_secret(
+        &self,
+        vault_url: String,
+        name: String,
+        version: Option<String>,
+    ) -> FetchedSecret;
+}
+
+/// A struct for calling the Key Vault API, caching the access token until shortly before it
+/// expires.
+#[derive(Clone)]
+pub struct AzureClient {
+    http: reqwest::Client,
+    credential: AzureCredential,
+    token: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+/// Implement the AzureClient constructor.
+impl AzureClient {
+    pub fn new(http: reqwest::Client, credential: AzureCredential) -> Self {
+        Self {
+            http,
+            credential,
+            token: Default::default(),
+        }
+    }
+}
+
+/// Implement the AzureClient methods.
+impl AzureClient {
+    /// Check that an access token can be obtained with the credential.
+    pub async fn check_credentials(&self) -> Result<(), String> {
+        self.token()
+            .await
+            .map(|_| ())
+            .map_err(|error| error.to_string())
+    }
+
+    /// Get a cached access token, requesting a new one if it is missing or about to expire.
+    async fn token(&self) -> Result<String, SecretsHelperError> {
+        // The lock is held while requesting a token, so that concurrent fetches share one request.
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires_at)) = cached.as_ref() {
+            if Instant::now() + TOKEN_REFRESH_MARGIN < *expires_at {
+                return Ok(token.clone());
+            }
+        }
+
+        let request = match &self.credential {
+            AzureCredential::ServicePrincipal {
+                authority_host,
+                tenant_id,
+                client_id,
+                client_secret,
+            } => {
+                let url = format!(
+                    "{}/{}/oauth2/v2.0/token",
+                    authority_host.trim_end_matches('/'),
+                    tenant_id
+                );
+                let scope = format!("{}/.default", RESOURCE);
+                self.http.post(url).form(&[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("scope", &scope),
+                ])
+            }
+            AzureCredential::ManagedIdentity { client_id } => {
+                let mut query = vec![("api-version", "2018-02-01"), ("resource", RESOURCE)];
+                if let Some(client_id) = client_id {
+                    query.push(("client_id", client_id));
+                }
+                self.http
+                    .get(IMDS_TOKEN_ENDPOINT)
+                    .header("Metadata", "true")
+                    .query(&query)
+                    .timeout(IMDS_TIMEOUT)
+            }
+        };
+        let response: TokenResponse = http::get_json(request).await.map_err(|error| {
+            SecretsHelperError::Credentials(format!(
+                "failed to get an Azure access token: {}",
+                error
+            ))
+        })?;
+
+        let expires_at = Instant::now() + response.lifetime();
+        *cached = Some((response.access_token.clone(), expires_at));
+        Ok(response.access_token)
+    }
+}
+
+/// A struct representing the response of the GetSecret API.
+#[derive(Deserialize)]
+struct SecretBundle {
+    value: Option<String>,
+}
+
+/// Implement the AzureFetchSecret trait for AzureClient.
+#[async_trait]
+impl AzureFetchSecret for AzureClient {
+    async fn fetch_secret(
+        &self,
+        vault_url: String,
+        name: String,
+        version: Option<String>,
+    ) -> FetchedSecret {
+        let token = match self.token().await {
+            Ok(token) => token,
+            Err(error) => return error.into(),
+        };
+        let url = format!(
+            "{}/secrets/{}/{}",
+            vault_url.trim_end_matches('/'),
+            name,
+            version.unwrap_or_default()
+        );
+        let request = self
+            .http
+            .get(url)
+            .query(&[("api-version", API_VERSION)])
+            .bearer_auth(token);
+        http::get_json::<SecretBundle>(request)
+            .await
+            .and_then(|bundle| {
+                bundle.value.ok_or_else(|| {
+                    SecretsHelperError::NotFound(String::from("secret value not found"))
+                })
+            })
+            .into()
+    }
+}
+
+/// A struct for loading secrets from Azure Key Vault.
+pub struct AzureSecretsLoader {
+    client: Box<dyn AzureFetchSecret + Send + Sync>,
+    /// The URL of the vault replacing the one derived from the vault name, e.g. for testing.
+    endpoint_url: Option<String>,
+    /// The vault secrets are fetched from unless the request names one.
+    default_vault: Option<String>,
+    settings: LoaderSettings,
+}
+
+/// Implement the AzureSecretsLoader constructor.
+impl AzureSecretsLoader {
+    pub fn new(
+        client: impl AzureFetchSecret + Send + Sync + 'static,
+        endpoint_url: Option<String>,
+        default_vault: Option<String>,
+        settings: LoaderSettings,
+    ) -> Self {
+        Self {
+            client: Box::new(client),
+            endpoint_url,
+            default_vault,
+            settings,
+        }
+    }
+}
+
+/// Implement the AzureSecretsLoader methods.
+impl AzureSecretsLoader {
+    /// Get the URL of the vault with the name, or of the default vault.
+    pub fn vault_url(&self, vault: Option<&str>) -> Result<String, String> {
+        if let Some(endpoint_url) = &self.endpoint_url {
+            return Ok(endpoint_url.clone());
+        }
+        match vault.or(self.default_vault.as_deref()) {
+            Some(vault) => Ok(format!("https://{}.vault.azure.net", vault)),
+            None => Err(String::from(
+                "no vault selected with --azure-vault or the vault option",
+            )),
+        }
+    }
+
+    /// Fetch a single requested secret, applying the options passed along with its name.
+    async fn fetch(&self, requested: String) -> FetchedSecret {
+        let (requested, yaml_path) = match extract::split_yaml_selector(&requested) {
+            Ok(split) => split,
+            Err(error) => return invalid_request(error),
+        };
+        let secret = match RequestedSecret::parse(requested, &["vault", "version"]) {
+            Ok(secret) => secret,
+            Err(error) => return invalid_request(error),
+        };
+        let vault_url = match self.vault_url(secret.option("vault")) {
+            Ok(vault_url) => vault_url,
+            Err(error) => return invalid_request(error),
+        };
+        let version = secret.option("version").map(str::to_string);
+        let fetched_secret = self
+            .client
+            .fetch_secret(vault_url, secret.name.clone(), version)
+            .await;
+        match yaml_path {
+            Some(path) => extract::apply(fetched_secret, |value| extract::yaml_path(value, path)),
+            None => fetched_secret,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for AzureSecretsLoader.
+#[async_trait]
+impl LoadSecrets for AzureSecretsLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        fetch_all(secrets, &self.settings, |requested| self.fetch(requested)).await
+    }
+}
+
+/// A struct checking that a vault is reachable.
+pub struct VaultEndpoint {
+    pub http: reqwest::Client,
+    pub vault_url: Result<String, String>,
+}
+
+/// Implement the PingEndpoint trait for VaultEndpoint.
+#[async_trait]
+impl PingEndpoint for VaultEndpoint {
+    async fn ping(&self) -> Result<(), String> {
+        let vault_url = self.vault_url.as_ref().map_err(Clone::clone)?;
+        // Any response, even an unauthorized one, proves that the vault is reachable.
+        match self.http.get(vault_url).send().await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(describe(&error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::ErrorCode;
+
+    struct MockAzureFetchSecret {}
+
+    #[async_trait]
+    impl AzureFetchSecret for MockAzureFetchSecret {
+        async fn fetch_secret(
+            &self,
+            vault_url: String,
+            name: String,
+            version: Option<String>,
+        ) -> FetchedSecret {
+            // Return the URL of the secret as the value to check how requests are resolved.
+            let value = format!("{}/{}/{}", vault_url, name, version.unwrap_or_default());
+            FetchedSecret {
+                value: Some(Secret::new(value)),
+                error: None,
+                code: None,
+            }
+        }
+    }
+
+    fn value(fetched_secrets: &FetchedSecrets, name: &str) -> String {
+        let secret = &fetched_secrets.0[name];
+        match &secret.value {
+            Some(value) => value.expose_secret().clone(),
+            None => secret.error.clone().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn azure_secrets_loader_resolves_vaults() {
+        let secrets_loader = AzureSecretsLoader::new(
+            MockAzureFetchSecret {},
+            None,
+            Some(String::from("default-vault")),
+            LoaderSettings::default(),
+        );
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("db-password"),
+            String::from("db-password?vault=other&version=abc"),
+        ]);
+
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        assert_eq!(
+            value(&fetched_secrets, "db-password"),
+            "https://default-vault.vault.azure.net/db-password/"
+        );
+        assert_eq!(
+            value(&fetched_secrets, "db-password?vault=other&version=abc"),
+            "https://other.vault.azure.net/db-password/abc"
+        );
+    }
+
+    #[tokio::test]
+    async fn azure_secrets_loader_requires_a_vault() {
+        let secrets_loader = AzureSecretsLoader::new(
+            MockAzureFetchSecret {},
+            None,
+            None,
+            LoaderSettings::default(),
+        );
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![String::from("db-password")]);
+
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        assert_eq!(
+            fetched_secrets.0["db-password"].code,
+            Some(ErrorCode::InvalidRequest)
+        );
+    }
+
+    #[test]
+    fn token_lifetime_is_a_number_or_a_string() {
+        let response: TokenResponse =
+            serde_json::from_str(r#"{"access_token":"t","expires_in":3599}"#).unwrap();
+        assert_eq!(response.lifetime(), Duration::from_secs(3599));
+        let response: TokenResponse =
+            serde_json::from_str(r#"{"access_token":"t","expires_in":"86399"}"#).unwrap();
+        assert_eq!(response.lifetime(), Duration::from_secs(86399));
+    }
+}
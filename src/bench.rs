@@ -0,0 +1,162 @@
+//! This module contains the benchmark run by the bench subcommand.
+
+use crate::aws::loader::LoadSecrets;
+use crate::vector::{ErrorCode, SecretsToFetch};
+use futures::StreamExt;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A struct holding the settings of a benchmark.
+#[derive(Clone, Debug)]
+pub struct BenchSettings {
+    /// The number of times the secrets are fetched.
+    pub iterations: usize,
+    /// The number of requests in flight at the same time.
+    pub concurrency: usize,
+}
+
+/// A struct representing the outcome of a benchmark.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub backend: String,
+    pub requests: usize,
+    pub concurrency: usize,
+    /// The number of secrets that could not be fetched, including throttled ones.
+    pub errors: usize,
+    /// The number of secrets that could not be fetched because of throttling.
+    pub throttled: usize,
+    /// The latencies of all requests, sorted in ascending order.
+    pub latencies: Vec<Duration>,
+}
+
+/// Implement the Report methods.
+impl Report {
+    /// The latency below which the given percentage of requests completed, using the nearest rank
+    /// method.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+/// Implement the Display trait for Report, rendering one line per statistic.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "backend: {}", self.backend)?;
+        writeln!(
+            f,
+            "requests: {} ({} concurrent)",
+            self.requests, self.concurrency
+        )?;
+        writeln!(f, "errors: {}", self.errors)?;
+        writeln!(f, "throttled: {}", self.throttled)?;
+        for percentile in [50.0, 95.0, 99.0] {
+            writeln!(f, "p{}: {:.1?}", percentile, self.percentile(percentile))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetch the secrets through the loader the configured number of times, measuring the latency of
+/// every request.
+pub async fn run(
+    loader: &dyn LoadSecrets,
+    backend: &str,
+    secrets: Vec<String>,
+    settings: &BenchSettings,
+) -> Report {
+    let mut report = Report {
+        backend: backend.to_string(),
+        requests: settings.iterations,
+        concurrency: settings.concurrency,
+        ..Default::default()
+    };
+
+    let requests = (0..settings.iterations).map(|_| {
+        let secrets_to_fetch = SecretsToFetch::from_names(secrets.clone());
+        async move {
+            let start = Instant::now();
+            let fetched_secrets = loader.load(secrets_to_fetch).await;
+            (start.elapsed(), fetched_secrets)
+        }
+    });
+    let mut responses = futures::stream::iter(requests).buffer_unordered(settings.concurrency);
+    while let Some((latency, fetched_secrets)) = responses.next().await {
+        report.latencies.push(latency);
+        for secret in fetched_secrets.0.values() {
+            if secret.error.is_some() {
+                report.errors += 1;
+            }
+            if secret.code == Some(ErrorCode::Throttled) {
+                report.throttled += 1;
+            }
+        }
+    }
+
+    report.latencies.sort();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::{FetchedSecret, FetchedSecrets};
+    use async_trait::async_trait;
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            let fetched_secrets = secrets.secrets.into_iter().map(|name| {
+                let secret = match name.as_str() {
+                    "test.secret_1" => FetchedSecret {
+                        value: Some(Secret::new("qwerty".to_string())),
+                        error: None,
+                        code: None,
+                    },
+                    _ => FetchedSecret {
+                        value: None,
+                        error: Some("rate exceeded".to_string()),
+                        code: Some(ErrorCode::Throttled),
+                    },
+                };
+                (name, secret)
+            });
+            FetchedSecrets(fetched_secrets.collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn bench_counts_requests_and_throttled_secrets() {
+        let settings = BenchSettings {
+            iterations: 10,
+            concurrency: 3,
+        };
+        let secrets = vec![String::from("test.secret_1"), String::from("test.secret_2")];
+
+        let report = run(&MockLoader {}, "ssm", secrets, &settings).await;
+
+        assert_eq!(report.requests, 10);
+        assert_eq!(report.latencies.len(), 10);
+        assert_eq!(report.errors, 10);
+        assert_eq!(report.throttled, 10);
+    }
+
+    #[test]
+    fn report_percentiles_use_nearest_rank() {
+        let report = Report {
+            latencies: (1..=100).map(Duration::from_millis).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(report.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(report.percentile(95.0), Duration::from_millis(95));
+        assert_eq!(report.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(Report::default().percentile(99.0), Duration::ZERO);
+    }
+}
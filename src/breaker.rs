@@ -0,0 +1,187 @@
+//! This module contains the circuit breaker shared by all fetches from a backend and the retry budget
+//! of every request to it, so that a backend that is down, e.g. during a regional outage, stops
+//! being called and the remaining secrets fail right away instead of running into the exec timeout
+//! of Vector.
+
+use crate::aws::loader::RequestScope;
+use crate::diagnostics::{Diagnostic, Level};
+use crate::error::SecretsHelperError;
+use crate::retry::RetryPolicy;
+use crate::vector::FetchedSecret;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The time an open circuit stays open before a fetch is let through again, which only matters to
+/// long-running modes like the gRPC server.
+const COOLDOWN: Duration = Duration::from_secs(30);
+
+/// A struct representing the state of the breaker.
+#[derive(Debug, Default)]
+struct State {
+    /// The number of fetch calls that failed with transient errors in a row.
+    consecutive_failures: u32,
+    /// The time the circuit was opened at, None while it's closed.
+    opened_at: Option<Instant>,
+}
+
+/// A struct opening the circuit to a backend after a number of fetch calls in a row failed with
+/// transient errors, and limiting the number of retries across all fetches of a request.
+#[derive(Debug)]
+pub struct Breaker {
+    /// The identifier of the breaker, under which requests count the retries spent on it.
+    id: u64,
+    /// The number of failures in a row opening the circuit, None to never open it.
+    threshold: Option<u32>,
+    /// The number of retries of a request, None if retries aren't limited.
+    retry_budget: Option<u32>,
+    state: Mutex<State>,
+}
+
+/// Implement the Breaker constructor.
+impl Breaker {
+    pub fn new(threshold: Option<u32>, retry_budget: Option<u32>) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            threshold,
+            retry_budget,
+            state: Default::default(),
+        }
+    }
+}
+
+/// Implement the Breaker methods.
+impl Breaker {
+    /// Check whether a fetch call may be made at the time, returning the failure of the secret if
+    /// the circuit is open.
+    fn check_at(&self, now: Instant) -> Result<(), FetchedSecret> {
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            Some(opened_at) if now < opened_at + COOLDOWN => {
+                Err(SecretsHelperError::Service(format!(
+                    "circuit open: the backend failed {} times in a row",
+                    state.consecutive_failures
+                ))
+                .into())
+            }
+            Some(_) => {
+                // Let the next call through, and open the circuit again right away if it fails.
+                state.opened_at = None;
+                state.consecutive_failures = self.threshold.unwrap_or(1).saturating_sub(1);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Check whether a fetch call may be made, returning the failure of the secret if the circuit
+    /// is open.
+    pub fn check(&self) -> Result<(), FetchedSecret> {
+        self.check_at(Instant::now())
+    }
+
+    /// Record the result of a fetch call, opening the circuit once the threshold of transient
+    /// failures in a row is reached. Any other result shows that the backend is up.
+    fn record_at(&self, fetched_secret: &FetchedSecret, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if !fetched_secret.code.is_some_and(RetryPolicy::is_transient) {
+            state.consecutive_failures = 0;
+            return;
+        }
+        state.consecutive_failures += 1;
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        if state.opened_at.is_none() && state.consecutive_failures >= threshold {
+            state.opened_at = Some(now);
+            Diagnostic::new(
+                Level::Warning,
+                "circuit_opened",
+                format!(
+                    "the backend failed {} times in a row, failing the remaining secrets without calling it for {}",
+                    state.consecutive_failures,
+                    humantime::format_duration(COOLDOWN)
+                ),
+            )
+            .detail("consecutive_failures", state.consecutive_failures)
+            .emit();
+        }
+    }
+
+    /// Record the result of a fetch call.
+    pub fn record(&self, fetched_secret: &FetchedSecret) {
+        self.record_at(fetched_secret, Instant::now())
+    }
+
+    /// Whether a failed fetch of the request may be retried, spending one retry of its budget if
+    /// so. The budget is per request, so that resident modes don't run out of retries for good.
+    pub fn allow_retry(&self, request: &RequestScope) -> bool {
+        if self.state.lock().unwrap().opened_at.is_some() {
+            return false;
+        }
+        match self.retry_budget {
+            Some(budget) => request.spend_retry(self.id, budget),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::ErrorCode;
+
+    fn throttled() -> FetchedSecret {
+        SecretsHelperError::Throttled(String::from("rate exceeded")).into()
+    }
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures() {
+        let breaker = Breaker::new(Some(2), None);
+        let now = Instant::now();
+
+        breaker.record_at(&throttled(), now);
+        breaker.record_at(&Ok(String::from("qwerty")).into(), now);
+        breaker.record_at(&throttled(), now);
+        assert!(breaker.check_at(now).is_ok());
+        assert!(breaker.allow_retry(&RequestScope::default()));
+
+        breaker.record_at(&throttled(), now);
+        let failure = breaker.check_at(now).unwrap_err();
+        assert_eq!(failure.code, Some(ErrorCode::ServiceError));
+        assert_eq!(
+            failure.error.as_deref(),
+            Some("circuit open: the backend failed 2 times in a row")
+        );
+        assert!(!breaker.allow_retry(&RequestScope::default()));
+
+        // A single call is let through after the cooldown, which opens the circuit again if it
+        // fails.
+        let later = now + COOLDOWN;
+        assert!(breaker.check_at(later).is_ok());
+        breaker.record_at(&throttled(), later);
+        assert!(breaker.check_at(later).is_err());
+    }
+
+    #[test]
+    fn retries_are_limited_by_the_budget_of_every_request() {
+        let breaker = Breaker::new(None, Some(2));
+        let other_backend = Breaker::new(None, Some(2));
+        let request = RequestScope::default();
+        assert!(breaker.allow_retry(&request));
+        assert!(breaker.allow_retry(&request));
+        assert!(!breaker.allow_retry(&request));
+        assert!(other_backend.allow_retry(&request));
+
+        // The next request gets the whole budget again.
+        let next_request = RequestScope::default();
+        assert!(breaker.allow_retry(&next_request));
+
+        for _ in 0..10 {
+            breaker.record(&throttled());
+        }
+        assert!(breaker.check().is_ok());
+    }
+}
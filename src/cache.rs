@@ -0,0 +1,659 @@
+//! This module contains the encrypted on-disk cache of fetched secrets, used to skip refetching
+//! secrets on rapid restarts of Vector and to fall back to the last fetched values when AWS can't
+//! be reached.
+
+use crate::aws::loader::LoadSecrets;
+use crate::diagnostics::{Diagnostic, Level};
+use crate::retry::RetryPolicy;
+use crate::secret::Secret;
+use crate::vector::{ErrorCode, FetchedSecret, FetchedSecrets, SecretsToFetch};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+/// The length of the AES-256 keys encrypting the cache.
+pub const KEY_LEN: usize = 32;
+/// The length of the nonce stored in front of every encrypted entry.
+const NONCE_LEN: usize = 12;
+/// The extension of the files holding cache entries.
+const ENTRY_EXTENSION: &str = "entry";
+
+/// A struct representing a cached secret value, when it was fetched and its version if known.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub value: Secret<String>,
+    /// Seconds since the Unix epoch.
+    pub fetched_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The backend and requested name of the secret, to list the cached secrets. Entries written
+    /// by earlier versions of the helper don't have them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Implement the CacheEntry methods.
+impl CacheEntry {
+    /// The time since the secret was fetched.
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(unix_time(SystemTime::now()).saturating_sub(self.fetched_at))
+    }
+
+    /// The fetched secret returned for the entry.
+    fn into_fetched_secret(self) -> FetchedSecret {
+        FetchedSecret {
+            value: Some(self.value),
+            error: None,
+            code: None,
+        }
+    }
+}
+
+/// A struct representing a directory of cached secrets, each encrypted with AES-256-GCM in its own
+/// file named after a hash of the backend and the requested name.
+pub struct DiskCache {
+    dir: PathBuf,
+    cipher: Aes256Gcm,
+    ttl: Duration,
+}
+
+/// Implement the DiskCache constructor.
+impl DiskCache {
+    pub fn new(dir: &Path, key: &[u8], ttl: Duration) -> Result<Self, String> {
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| format!("cache key must be {} bytes long", KEY_LEN))?;
+        create_private_dir(dir)
+            .map_err(|error| format!("failed to create {}: {}", dir.display(), error))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            cipher,
+            ttl,
+        })
+    }
+}
+
+/// Implement the DiskCache methods.
+impl DiskCache {
+    /// The hash identifying the entry of a secret, which is also authenticated along with the
+    /// entry so that entries can't be swapped between secrets.
+    fn entry_id(backend: &str, name: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(backend)
+            .chain_update([0])
+            .chain_update(name)
+            .finalize();
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, ENTRY_EXTENSION))
+    }
+
+    /// Get the cached entry of a secret. Entries that are missing, corrupted or encrypted with
+    /// another key are treated alike, as if the secret was never cached.
+    pub fn get(&self, backend: &str, name: &str) -> Option<CacheEntry> {
+        self.read_entry(&Self::entry_id(backend, name))
+    }
+
+    /// Read and decrypt the entry with the id, None if it can't be.
+    fn read_entry(&self, id: &str) -> Option<CacheEntry> {
+        let contents = std::fs::read(self.entry_path(id)).ok()?;
+        if contents.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = contents.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: id.as_bytes(),
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .ok()?;
+        serde_json::from_slice(&plaintext).ok()
+    }
+
+    /// Encrypt and store the value of a secret and its version if known, replacing its previous
+    /// entry.
+    pub fn put(
+        &self,
+        backend: &str,
+        name: &str,
+        value: &Secret<String>,
+        version: Option<&str>,
+    ) -> Result<(), String> {
+        let id = Self::entry_id(backend, name);
+        let entry = CacheEntry {
+            value: value.clone(),
+            fetched_at: unix_time(SystemTime::now()),
+            version: version.map(str::to_string),
+            backend: Some(backend.to_string()),
+            name: Some(name.to_string()),
+        };
+        let plaintext = serde_json::to_vec(&entry).map_err(|error| error.to_string())?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: &plaintext,
+            aad: id.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| String::from("failed to encrypt the cache entry"))?;
+
+        let mut contents = nonce.to_vec();
+        contents.extend_from_slice(&ciphertext);
+        crate::output::write_atomically(&self.entry_path(&id), &contents)
+            .map_err(|error| error.to_string())
+    }
+
+    /// Whether the entry is recent enough to be returned without refetching the secret.
+    pub fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        entry.age() < self.ttl
+    }
+
+    /// List the entries of all cached secrets, None for those that can't be decrypted, e.g.
+    /// because they were encrypted with another key.
+    pub fn entries(&self) -> std::io::Result<Vec<Option<CacheEntry>>> {
+        let mut entries = Vec::new();
+        for dir_entry in std::fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|ext| ext == ENTRY_EXTENSION) {
+                let id = path.file_stem().unwrap_or_default().to_string_lossy();
+                entries.push(self.read_entry(&id));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Remove the entry of a secret, returning whether it was cached.
+    pub fn evict(&self, backend: &str, name: &str) -> std::io::Result<bool> {
+        match std::fs::remove_file(self.entry_path(&Self::entry_id(backend, name))) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Remove all entries, returning how many were removed. Keys stored in the directory are kept.
+    pub fn clear(&self) -> std::io::Result<usize> {
+        let mut removed = 0;
+        for dir_entry in std::fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|ext| ext == ENTRY_EXTENSION) {
+                std::fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Convert a time to seconds since the Unix epoch.
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Create the directory and its parents, making the directory accessible only by its owner.
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Read the host key encrypting the cache from the file, generating a random key readable only by
+/// its owner if the file doesn't exist yet.
+pub fn host_key(path: &Path) -> Result<Vec<u8>, String> {
+    match std::fs::read(path) {
+        Ok(key) if key.len() == KEY_LEN => return Ok(key),
+        Ok(_) => {
+            return Err(format!(
+                "cache key file {} must contain exactly {} bytes",
+                path.display(),
+                KEY_LEN
+            ))
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(format!("failed to read {}: {}", path.display(), error)),
+    }
+
+    let key = Aes256Gcm::generate_key(OsRng);
+    let write = || -> std::io::Result<()> {
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        // Temporary files are created with 0600 permissions on Unix.
+        let mut file = NamedTempFile::new_in(dir)?;
+        file.write_all(&key)?;
+        file.as_file().sync_all()?;
+        file.persist_noclobber(path)?;
+        Ok(())
+    };
+    match write() {
+        Ok(()) => Ok(key.to_vec()),
+        // Another helper generated the key first, use that one.
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => host_key(path),
+        Err(error) => Err(format!("failed to write {}: {}", path.display(), error)),
+    }
+}
+
+/// Whether a failed fetch is served from the last cached value. Only failures suggesting that AWS
+/// can't be reached qualify, never ones like access being denied.
+fn falls_back_to_cache(code: ErrorCode) -> bool {
+    RetryPolicy::is_transient(code) || code == ErrorCode::DeadlineExceeded
+}
+
+/// A struct wrapping a secrets loader to return fresh cached values instead of fetching them, and
+/// stale cached values if fetching them fails because AWS can't be reached. If enabled, stale
+/// cached values are also returned if the version of the secret didn't change.
+pub struct CachedLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    backend: String,
+    cache: Arc<DiskCache>,
+    if_changed: bool,
+}
+
+/// Implement the CachedLoader constructor.
+impl CachedLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        backend: &str,
+        cache: Arc<DiskCache>,
+        if_changed: bool,
+    ) -> Self {
+        Self {
+            inner,
+            backend: backend.to_string(),
+            cache,
+            if_changed,
+        }
+    }
+}
+
+/// Implement the CachedLoader methods.
+impl CachedLoader {
+    /// Store a fetched value in the cache, logging failures since the value can be returned
+    /// regardless.
+    fn put(&self, name: &str, value: &Secret<String>, version: Option<&str>) {
+        if let Err(error) = self.cache.put(&self.backend, name, value, version) {
+            Diagnostic::new(
+                Level::Warning,
+                "cache_write_failed",
+                format!("failed to cache {}: {}", name, error),
+            )
+            .secret(name)
+            .emit();
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for CachedLoader.
+#[async_trait]
+impl LoadSecrets for CachedLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let mut fetched_secrets = FetchedSecrets::default();
+        let mut stale = HashMap::new();
+        let mut misses = Vec::new();
+        for name in secrets.secrets {
+            match self.cache.get(&self.backend, &name) {
+                Some(entry) if self.cache.is_fresh(&entry) => {
+                    fetched_secrets.0.insert(name, entry.into_fetched_secret());
+                }
+                Some(entry) => {
+                    stale.insert(name.clone(), entry);
+                    misses.push(name);
+                }
+                None => misses.push(name),
+            }
+        }
+        if misses.is_empty() {
+            return fetched_secrets;
+        }
+
+        // Look up the current versions of the secrets that aren't fresh, to return the cached
+        // values of those that didn't change and to store the versions of the fetched ones.
+        let versions: HashMap<String, String> = if self.if_changed {
+            let lookups = misses.iter().map(|name| async move {
+                let version = self.inner.current_version(name).await;
+                version.map(|version| (name.clone(), version))
+            });
+            join_all(lookups).await.into_iter().flatten().collect()
+        } else {
+            HashMap::new()
+        };
+        let mut changed = Vec::new();
+        for name in misses {
+            match (stale.remove(&name), versions.get(&name)) {
+                (Some(entry), Some(version)) if entry.version.as_ref() == Some(version) => {
+                    // Store the entry again to restart its TTL.
+                    self.put(&name, &entry.value, Some(version));
+                    fetched_secrets.0.insert(name, entry.into_fetched_secret());
+                }
+                (entry, _) => {
+                    if let Some(entry) = entry {
+                        stale.insert(name.clone(), entry);
+                    }
+                    changed.push(name);
+                }
+            }
+        }
+        if changed.is_empty() {
+            return fetched_secrets;
+        }
+
+        let secrets_to_fetch = SecretsToFetch {
+            version: secrets.version,
+            secrets: changed,
+        };
+        for (name, secret) in self.inner.load(secrets_to_fetch).await.0 {
+            let secret = match (&secret.value, secret.code, stale.remove(&name)) {
+                (Some(value), _, _) => {
+                    self.put(&name, value, versions.get(&name).map(String::as_str));
+                    secret
+                }
+                (None, Some(code), Some(entry)) if falls_back_to_cache(code) => {
+                    Diagnostic::new(
+                        Level::Warning,
+                        "cache_fallback",
+                        format!(
+                            "serving {} from the cache after failing to fetch it: {}",
+                            name,
+                            secret.error.as_deref().unwrap_or_default()
+                        ),
+                    )
+                    .secret(&name)
+                    .code(Some(code))
+                    .emit();
+                    entry.into_fetched_secret()
+                }
+                _ => secret,
+            };
+            fetched_secrets.0.insert(name, secret);
+        }
+        fetched_secrets
+    }
+}
+
+/// Fetch the secrets through the loader and store their values in the cache along with their
+/// versions if requested, returning the errors of the secrets that couldn't be fetched or cached.
+/// Values are never returned.
+pub async fn warm(
+    cache: &DiskCache,
+    backend: &str,
+    loader: &(dyn LoadSecrets + Send + Sync),
+    secrets: SecretsToFetch,
+    with_versions: bool,
+) -> Vec<(String, String)> {
+    // Versions are looked up before fetching, so that a secret changing in between is refetched
+    // later rather than cached with the wrong version.
+    let mut versions = HashMap::new();
+    if with_versions {
+        for name in &secrets.secrets {
+            if let Some(version) = loader.current_version(name).await {
+                versions.insert(name.clone(), version);
+            }
+        }
+    }
+    let mut errors = Vec::new();
+    for (name, secret) in loader.load(secrets).await.0 {
+        let result = match secret.value {
+            Some(value) => cache.put(
+                backend,
+                &name,
+                &value,
+                versions.get(&name).map(String::as_str),
+            ),
+            None => Err(secret.error.unwrap_or_default()),
+        };
+        if let Err(error) = result {
+            errors.push((name, error));
+        }
+    }
+    errors.sort();
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct MockLoader {
+        requested: Arc<Mutex<Vec<String>>>,
+        code: Option<ErrorCode>,
+        version: Option<String>,
+    }
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            self.requested
+                .lock()
+                .unwrap()
+                .extend(secrets.secrets.iter().cloned());
+            let fetched_secrets = secrets.secrets.into_iter().map(|name| {
+                let secret = match self.code {
+                    Some(code) => FetchedSecret {
+                        value: None,
+                        error: Some(String::from("failed to fetch")),
+                        code: Some(code),
+                    },
+                    None => FetchedSecret {
+                        value: Some(Secret::new(format!("{}-value", name))),
+                        error: None,
+                        code: None,
+                    },
+                };
+                (name, secret)
+            });
+            FetchedSecrets(fetched_secrets.collect())
+        }
+
+        async fn current_version(&self, _requested: &str) -> Option<String> {
+            self.version.clone()
+        }
+    }
+
+    fn mock_loader(code: Option<ErrorCode>) -> MockLoader {
+        MockLoader {
+            requested: Default::default(),
+            code,
+            version: None,
+        }
+    }
+
+    fn secrets_to_fetch() -> SecretsToFetch {
+        SecretsToFetch::from_names(vec![String::from("test.secret_1")])
+    }
+
+    #[test]
+    fn cache_entries_are_encrypted_and_bound_to_the_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path(), &[7; KEY_LEN], Duration::from_secs(60)).unwrap();
+        cache
+            .put(
+                "ssm",
+                "test.secret_1",
+                &Secret::new(String::from("qwerty")),
+                None,
+            )
+            .unwrap();
+
+        let entry = cache.get("ssm", "test.secret_1").unwrap();
+        assert_eq!(entry.value.expose_secret(), "qwerty");
+        assert!(cache.is_fresh(&entry));
+        assert_eq!(cache.get("secretsmanager", "test.secret_1"), None);
+
+        let path = std::fs::read_dir(dir.path()).unwrap().next().unwrap();
+        let contents = std::fs::read(path.unwrap().path()).unwrap();
+        assert!(!contents.windows(6).any(|window| window == b"qwerty"));
+
+        let other_key = DiskCache::new(dir.path(), &[8; KEY_LEN], Duration::from_secs(60)).unwrap();
+        assert_eq!(other_key.get("ssm", "test.secret_1"), None);
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert_eq!(cache.get("ssm", "test.secret_1"), None);
+    }
+
+    #[test]
+    fn cache_entries_can_be_listed_and_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path(), &[7; KEY_LEN], Duration::from_secs(60)).unwrap();
+        let value = Secret::new(String::from("qwerty"));
+        cache
+            .put("ssm", "test.secret_1", &value, Some("3"))
+            .unwrap();
+        cache.put("ssm", "test.secret_2", &value, None).unwrap();
+
+        let mut entries = cache.entries().unwrap();
+        entries.sort_by(|a, b| a.as_ref().unwrap().name.cmp(&b.as_ref().unwrap().name));
+        let entry = entries[0].as_ref().unwrap();
+        assert_eq!(entry.backend.as_deref(), Some("ssm"));
+        assert_eq!(entry.name.as_deref(), Some("test.secret_1"));
+        assert_eq!(entry.version.as_deref(), Some("3"));
+        assert!(entry.age() < Duration::from_secs(60));
+
+        let other_key = DiskCache::new(dir.path(), &[8; KEY_LEN], Duration::from_secs(60)).unwrap();
+        assert_eq!(other_key.entries().unwrap(), vec![None, None]);
+
+        assert!(cache.evict("ssm", "test.secret_1").unwrap());
+        assert!(!cache.evict("ssm", "test.secret_1").unwrap());
+        assert!(!cache.evict("secretsmanager", "test.secret_2").unwrap());
+        assert_eq!(cache.get("ssm", "test.secret_1"), None);
+        assert_eq!(cache.entries().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn cached_loader_skips_fetching_fresh_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache =
+            Arc::new(DiskCache::new(dir.path(), &[7; KEY_LEN], Duration::from_secs(60)).unwrap());
+        let inner = mock_loader(None);
+        let requested = inner.requested.clone();
+        let loader = CachedLoader::new(Box::new(inner), "ssm", cache, false);
+
+        loader.load(secrets_to_fetch()).await;
+        let fetched_secrets = loader.load(secrets_to_fetch()).await;
+
+        assert_eq!(
+            fetched_secrets.0["test.secret_1"].value,
+            Some(Secret::new(String::from("test.secret_1-value")))
+        );
+        assert_eq!(requested.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn cached_loader_falls_back_to_stale_secrets_when_aws_is_unreachable() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(DiskCache::new(dir.path(), &[7; KEY_LEN], Duration::ZERO).unwrap());
+        cache
+            .put(
+                "ssm",
+                "test.secret_1",
+                &Secret::new(String::from("qwerty")),
+                None,
+            )
+            .unwrap();
+
+        let loader = CachedLoader::new(
+            Box::new(mock_loader(Some(ErrorCode::ConnectionFailure))),
+            "ssm",
+            cache.clone(),
+            false,
+        );
+        let fetched_secrets = loader.load(secrets_to_fetch()).await;
+        assert_eq!(
+            fetched_secrets.0["test.secret_1"].value,
+            Some(Secret::new(String::from("qwerty")))
+        );
+
+        let loader = CachedLoader::new(
+            Box::new(mock_loader(Some(ErrorCode::AccessDenied))),
+            "ssm",
+            cache,
+            false,
+        );
+        let fetched_secrets = loader.load(secrets_to_fetch()).await;
+        assert_eq!(
+            fetched_secrets.0["test.secret_1"].code,
+            Some(ErrorCode::AccessDenied)
+        );
+    }
+
+    #[tokio::test]
+    async fn cached_loader_only_refetches_changed_secrets_if_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Arc::new(DiskCache::new(dir.path(), &[7; KEY_LEN], Duration::ZERO).unwrap());
+        let value = Secret::new(String::from("qwerty"));
+        cache
+            .put("ssm", "test.secret_1", &value, Some("1"))
+            .unwrap();
+
+        for (version, fetched) in [("1", false), ("2", true)] {
+            let inner = MockLoader {
+                version: Some(String::from(version)),
+                ..mock_loader(None)
+            };
+            let requested = inner.requested.clone();
+            let loader = CachedLoader::new(Box::new(inner), "ssm", cache.clone(), true);
+
+            let fetched_secrets = loader.load(secrets_to_fetch()).await;
+
+            assert_eq!(requested.lock().unwrap().is_empty(), !fetched);
+            assert!(fetched_secrets.0["test.secret_1"].value.is_some());
+        }
+        let entry = cache.get("ssm", "test.secret_1").unwrap();
+        assert_eq!(entry.version, Some(String::from("2")));
+        assert_eq!(entry.value.expose_secret(), "test.secret_1-value");
+    }
+
+    #[tokio::test]
+    async fn warm_caches_fetched_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path(), &[7; KEY_LEN], Duration::from_secs(60)).unwrap();
+
+        let errors = warm(&cache, "ssm", &mock_loader(None), secrets_to_fetch(), false).await;
+        assert!(errors.is_empty());
+        let entry = cache.get("ssm", "test.secret_1").unwrap();
+        assert_eq!(entry.value.expose_secret(), "test.secret_1-value");
+
+        let loader = mock_loader(Some(ErrorCode::NotFound));
+        let errors = warm(&cache, "ssm", &loader, secrets_to_fetch(), false).await;
+        assert_eq!(
+            errors,
+            vec![(
+                String::from("test.secret_1"),
+                String::from("failed to fetch")
+            )]
+        );
+    }
+
+    #[test]
+    fn host_key_is_generated_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.key");
+
+        let key = host_key(&path).unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        assert_eq!(host_key(&path).unwrap(), key);
+
+        std::fs::write(&path, b"short").unwrap();
+        assert!(host_key(&path).is_err());
+    }
+}
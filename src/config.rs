@@ -0,0 +1,529 @@
+//! This module contains the config file declaring per-secret settings and the roles to assume,
+//! e.g.
+//!
+//! ```toml
+//! role_chain = [
+//!     "arn:aws:iam::111111111111:role/security-broker",
+//!     "arn:aws:iam::222222222222:role/secrets-reader",
+//! ]
+//!
+//! [secrets."/app/db_password".validate]
+//! non_empty = true
+//! min_length = 16
+//!
+//! [secrets."/app/debug_sink_token"]
+//! optional = true
+//!
+//! [secrets."/shared/license_key"]
+//! region = "us-east-1"
+//!
+//! [accounts.prod]
+//! role_arn = "arn:aws:iam::333333333333:role/secrets-reader"
+//! secrets = ["^db_"]
+//! ```
+
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+use crate::aws::arn::Arn;
+use crate::aws::options::check_region;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use toml::Spanned;
+
+/// A struct representing the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The roles assumed one after the other to fetch secrets, starting with the credentials of
+    /// the default credential provider chain.
+    #[serde(default)]
+    pub role_chain: Vec<String>,
+    /// The settings of individual secrets, keyed by the requested name.
+    #[serde(default)]
+    pub secrets: HashMap<String, SecretConfig>,
+    /// The AWS accounts secrets are fetched from, keyed by the name prefixing their secrets.
+    #[serde(default)]
+    pub accounts: HashMap<AccountName, AccountConfig>,
+}
+
+/// A struct representing the settings of a single secret.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SecretConfig {
+    /// The rules the value of the secret must satisfy.
+    pub validate: Option<Validation>,
+    /// Whether a secret that doesn't exist is returned with an empty value instead of failing.
+    #[serde(default)]
+    pub optional: bool,
+    /// The value returned for the secret if it doesn't exist, which makes it optional.
+    pub default: Option<String>,
+    /// Whether a value holding a JSON object is expanded into a key per field, e.g. `name.user`.
+    #[serde(default)]
+    pub expand_json: bool,
+    /// The AWS region the secret is fetched from instead of the default one.
+    pub region: Option<String>,
+}
+
+/// A struct holding the name of an account, which can't contain the `.` separating it from the
+/// names of its secrets.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(try_from = "String")]
+pub struct AccountName(pub String);
+
+/// Implement conversion of a string into an AccountName.
+impl TryFrom<String> for AccountName {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match name.is_empty() || name.contains('.') {
+            true => Err(format!(
+                "invalid account name {:?}, it must be non-empty and can't contain '.'",
+                name
+            )),
+            false => Ok(AccountName(name)),
+        }
+    }
+}
+
+/// A struct representing an AWS account secrets are fetched from.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    /// The profile of the shared AWS config files whose credentials are used, the default
+    /// credential provider chain if not set.
+    pub profile: Option<String>,
+    /// The role assumed in the account after the role chain.
+    pub role_arn: Option<String>,
+    /// The region of the account, the default region if not set.
+    pub region: Option<String>,
+    /// The patterns of the secrets requested without an account that are fetched from the
+    /// account, all of them if empty.
+    #[serde(default)]
+    #[cfg_attr(
+        not(any(feature = "ssm", feature = "secretsmanager")),
+        allow(dead_code)
+    )]
+    pub secrets: Vec<Pattern>,
+}
+
+/// A struct representing the rules a secret value must satisfy.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Validation {
+    #[serde(default)]
+    pub non_empty: bool,
+    pub regex: Option<Pattern>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    #[serde(default)]
+    pub json: bool,
+}
+
+/// A struct holding a regular expression compiled when the config file is loaded.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Pattern(pub Regex);
+
+/// Implement conversion of a string into a Pattern.
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&pattern).map(Pattern)
+    }
+}
+
+/// A struct representing a problem found when checking the config file, at a line and column
+/// counted from 1.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Implement the Problem constructor.
+impl Problem {
+    /// Create a problem at the byte offset in the contents of the config file.
+    fn at(contents: &str, offset: usize, message: impl Into<String>) -> Self {
+        let before = &contents[..offset.min(contents.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: message.into(),
+        }
+    }
+}
+
+/// A struct representing the config file with the positions of the role ARNs and secret names,
+/// used to report the problems found by Config::check. The file has already been parsed as a
+/// Config, so unknown keys are rejected there.
+#[derive(Deserialize)]
+struct CheckedConfig {
+    #[serde(default)]
+    role_chain: Vec<Spanned<String>>,
+    #[serde(default)]
+    secrets: HashMap<Spanned<String>, CheckedSecretConfig>,
+    #[serde(default)]
+    accounts: HashMap<String, CheckedAccountConfig>,
+}
+
+/// A struct representing an account as written in the config file.
+#[derive(Deserialize)]
+struct CheckedAccountConfig {
+    role_arn: Option<Spanned<String>>,
+}
+
+/// A struct representing the settings of a single secret as written in the config file, telling
+/// an explicit `optional = false` apart from the default.
+#[derive(Deserialize)]
+struct CheckedSecretConfig {
+    validate: Option<Validation>,
+    optional: Option<bool>,
+    default: Option<String>,
+    region: Option<String>,
+}
+
+/// Implement the CheckedConfig methods.
+impl CheckedConfig {
+    /// Check that the roles of the role chain and the accounts are ARNs of IAM roles.
+    fn role_problems(&self, contents: &str) -> Vec<Problem> {
+        let account_roles = self
+            .accounts
+            .values()
+            .filter_map(|account| account.role_arn.as_ref());
+        self.role_chain
+            .iter()
+            .chain(account_roles)
+            .filter(|role_arn| {
+                !role_arn.get_ref().starts_with("arn:") || !role_arn.get_ref().contains(":role/")
+            })
+            .map(|role_arn| {
+                Problem::at(
+                    contents,
+                    role_arn.span().start,
+                    format!("not the ARN of an IAM role: {}", role_arn.get_ref()),
+                )
+            })
+            .collect()
+    }
+
+    /// Check the settings of the secrets, reporting the problems at their names.
+    fn secret_problems(&self, contents: &str) -> Vec<Problem> {
+        self.secrets
+            .iter()
+            .flat_map(|(name, secret)| {
+                secret
+                    .problems(name.get_ref())
+                    .into_iter()
+                    .map(|message| Problem::at(contents, name.span().start, message))
+            })
+            .collect()
+    }
+}
+
+/// Implement the CheckedSecretConfig methods.
+impl CheckedSecretConfig {
+    /// Check the settings of the secret with the name, returning the messages of the problems.
+    fn problems(&self, name: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        // Options of the requested names are kept in the key, except for optional, which the
+        // optional secrets are looked up without.
+        let options = name.split_once('?').map_or("", |(_, options)| options);
+        if options
+            .split('&')
+            .any(|option| option.starts_with("optional="))
+        {
+            problems.push(format!(
+                "{}: the optional option is ignored in secret names, set optional = true instead",
+                name
+            ));
+        }
+        if let Some(Err(error)) = self.region.as_deref().map(check_region) {
+            problems.push(format!("{}: {}", name, error));
+        }
+        // Secrets referenced by ARN can only be fetched from a region of their partition.
+        #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+        if let (Some(arn), Some(region)) = (
+            Arn::parse(name.split_once('?').map_or(name, |(base, _)| base)),
+            self.region.as_deref(),
+        ) {
+            if let Err(error) = arn.check_partition(region) {
+                problems.push(format!("{}: {}", name, error));
+            }
+        }
+        if self.optional == Some(false) && self.default.is_some() {
+            problems.push(format!(
+                "{}: optional = false conflicts with default, which makes the secret optional",
+                name
+            ));
+        }
+        if let Some(validation) = &self.validate {
+            problems.extend(
+                validation
+                    .problems()
+                    .into_iter()
+                    .map(|problem| format!("{}: {}", name, problem)),
+            );
+        }
+        problems
+    }
+}
+
+/// Implement the Validation methods.
+impl Validation {
+    /// Check that the rules don't conflict, returning the messages of the problems.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let (Some(min_length), Some(max_length)) = (self.min_length, self.max_length) {
+            if min_length > max_length {
+                problems.push(format!(
+                    "min_length {} exceeds max_length {}",
+                    min_length, max_length
+                ));
+            }
+        }
+        if self.non_empty && self.max_length == Some(0) {
+            problems.push(String::from("non_empty conflicts with max_length 0"));
+        }
+        problems
+    }
+}
+
+/// Implement the Config methods.
+impl Config {
+    /// Load the config file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| format!("failed to read {}: {}", path.display(), error))?;
+        Self::parse(&contents)
+            .map_err(|error| format!("failed to parse {}: {}", path.display(), error))
+    }
+
+    /// Parse the contents of the config file.
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Check the contents of the config file, returning the problems found, e.g. unknown keys,
+    /// invalid regexes or conflicting settings of a secret, ordered by their position.
+    pub fn check(contents: &str) -> Vec<Problem> {
+        // The file can't be checked any further if it can't be parsed.
+        if let Err(error) = Self::parse(contents) {
+            let offset = error.span().map_or(0, |span| span.start);
+            return vec![Problem::at(contents, offset, error.message().trim())];
+        }
+        let Ok(config) = toml::from_str::<CheckedConfig>(contents) else {
+            return Vec::new();
+        };
+
+        let mut problems = config.role_problems(contents);
+        problems.extend(config.secret_problems(contents));
+        problems.sort_by_key(|problem| (problem.line, problem.column));
+        problems
+    }
+
+    /// The validation rules of all secrets that have them.
+    pub fn validations(&self) -> HashMap<String, Validation> {
+        self.secrets
+            .iter()
+            .filter_map(|(name, secret)| Some((name.clone(), secret.validate.clone()?)))
+            .collect()
+    }
+
+    /// The names of the secrets expanded into a key per field.
+    pub fn expanded_secrets(&self) -> HashSet<String> {
+        self.secrets
+            .iter()
+            .filter(|(_, secret)| secret.expand_json)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The regions of the secrets fetched from another region than the default one, keyed by name.
+    #[cfg_attr(
+        not(any(feature = "ssm", feature = "secretsmanager")),
+        allow(dead_code)
+    )]
+    pub fn secret_regions(&self) -> HashMap<String, String> {
+        self.secrets
+            .iter()
+            .filter_map(|(name, secret)| Some((name.clone(), secret.region.clone()?)))
+            .collect()
+    }
+
+    /// The values returned for the optional secrets if they don't exist, keyed by name.
+    pub fn optional_secrets(&self) -> HashMap<String, String> {
+        self.secrets
+            .iter()
+            .filter(|(_, secret)| secret.optional || secret.default.is_some())
+            .map(|(name, secret)| (name.clone(), secret.default.clone().unwrap_or_default()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_can_be_parsed() {
+        let config = Config::parse(
+            "[secrets.\"/app/db_password\".validate]\nnon_empty = true\nregex = \"^[a-z]+$\"\n\n[secrets.\"/app/api_key\"]\n",
+        )
+        .unwrap();
+
+        let validations = config.validations();
+        assert_eq!(validations.len(), 1);
+        let validation = &validations["/app/db_password"];
+        assert!(validation.non_empty);
+        assert!(validation.regex.as_ref().unwrap().0.is_match("qwerty"));
+        assert_eq!(validation.min_length, None);
+    }
+
+    #[test]
+    fn role_chains_can_be_parsed() {
+        let config = Config::parse("role_chain = [\"arn:a\", \"arn:b\"]\n").unwrap();
+
+        assert_eq!(config.role_chain, vec!["arn:a", "arn:b"]);
+        assert!(Config::default().role_chain.is_empty());
+    }
+
+    #[test]
+    fn accounts_can_be_parsed() {
+        let config = Config::parse(
+            "[accounts.prod-account]
+role_arn = \"arn:aws:iam::333333333333:role/reader\"
+secrets = [\"^db_\"]
+
+[accounts.dev]
+profile = \"dev\"
+region = \"eu-west-1\"
+",
+        )
+        .unwrap();
+
+        let prod = &config.accounts[&AccountName(String::from("prod-account"))];
+        assert_eq!(
+            prod.role_arn.as_deref(),
+            Some("arn:aws:iam::333333333333:role/reader")
+        );
+        assert!(prod.secrets[0].0.is_match("db_password"));
+        let dev = &config.accounts[&AccountName(String::from("dev"))];
+        assert_eq!(dev.profile.as_deref(), Some("dev"));
+        assert_eq!(dev.region.as_deref(), Some("eu-west-1"));
+        assert!(dev.secrets.is_empty());
+
+        assert!(Config::parse("[accounts.\"prod.eu\"]\n").is_err());
+        let problems = Config::check("[accounts.a]\nrole_arn = \"arn:aws:iam::1:user/a\"\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!((problems[0].line, problems[0].column), (2, 12));
+    }
+
+    #[test]
+    fn expanded_secrets_can_be_parsed() {
+        let config =
+            Config::parse("[secrets.db]\nexpand_json = true\n\n[secrets.token]\n").unwrap();
+
+        assert_eq!(
+            config.expanded_secrets(),
+            HashSet::from([String::from("db")])
+        );
+    }
+
+    #[test]
+    fn secret_regions_can_be_parsed() {
+        let config =
+            Config::parse("[secrets.db]\nregion = \"eu-west-1\"\n\n[secrets.token]\n").unwrap();
+
+        assert_eq!(
+            config.secret_regions(),
+            HashMap::from([(String::from("db"), String::from("eu-west-1"))])
+        );
+        assert_eq!(
+            Config::check("[secrets.db]\nregion = \"EU West\"\n"),
+            vec![Problem {
+                line: 1,
+                column: 10,
+                message: String::from("db: invalid region: \"EU West\""),
+            }]
+        );
+        #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+        assert_eq!(
+            Config::check(
+                "[secrets.\"arn:aws-us-gov:secretsmanager::1:secret:db\"]\nregion = \"us-east-1\"\n"
+            )[0]
+            .message,
+            "arn:aws-us-gov:secretsmanager::1:secret:db: region us-east-1 is in the aws partition, \
+             not in the aws-us-gov partition of the ARN"
+        );
+    }
+
+    #[test]
+    fn optional_secrets_have_defaults() {
+        let config = Config::parse(
+            "[secrets.a]
+optional = true
+
+[secrets.b]
+default = \"none\"
+
+[secrets.c]
+optional = false
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.optional_secrets(),
+            HashMap::from([
+                (String::from("a"), String::new()),
+                (String::from("b"), String::from("none")),
+            ])
+        );
+    }
+
+    #[test]
+    fn config_with_invalid_settings_is_rejected() {
+        assert!(Config::parse("[secrets.\"/app/db_password\".validate]\nregex = \"(\"\n").is_err());
+        assert!(
+            Config::parse("[secrets.\"/app/db_password\".validate]\nnonempty = true\n").is_err()
+        );
+    }
+
+    #[test]
+    fn problems_are_reported_with_their_position() {
+        let problems = Config::check(
+            "role_chain = [\"arn:aws:iam::1:user/a\"]
+
+[secrets.\"/app/a\"]
+optional = false
+default = \"none\"
+
+[secrets.\"/app/b\".validate]
+min_length = 8
+max_length = 4
+",
+        );
+        assert_eq!(
+            problems
+                .iter()
+                .map(|problem| (problem.line, problem.column))
+                .collect::<Vec<_>>(),
+            vec![(1, 15), (3, 10), (7, 10)]
+        );
+        assert_eq!(
+            problems[2].message,
+            "/app/b: min_length 8 exceeds max_length 4"
+        );
+
+        let problems = Config::check("[secrets.a]\noptinal = true\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!((problems[0].line, problems[0].column), (2, 1));
+        assert!(problems[0].message.starts_with("unknown field `optinal`"));
+
+        assert!(Config::check("[secrets.a]\noptional = true\n").is_empty());
+    }
+}
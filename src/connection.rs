@@ -0,0 +1,136 @@
+//! This module contains the HTTP connection pools shared by all clients of the helper, so that
+//! resident modes like `serve` keep their connections alive across requests and config reloads
+//! instead of paying for a new TCP and TLS handshake every time.
+
+use aws_smithy_async::rt::sleep::SharedAsyncSleep;
+use aws_smithy_client::erase::DynConnector;
+use aws_smithy_client::http_connector::{ConnectorSettings, HttpConnector};
+use aws_smithy_client::hyper_ext;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A struct representing the settings of the connection pools.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolSettings {
+    /// Time after which idle connections are closed, None to keep them open.
+    pub idle_timeout: Option<Duration>,
+    /// Maximum number of idle connections kept per host, None for no limit.
+    pub max_idle_per_host: Option<usize>,
+    /// Interval of TCP keep-alive probes on idle connections, None to disable them.
+    pub tcp_keepalive: Option<Duration>,
+}
+
+/// The connect and read timeouts of an AWS client, the AWS clients with the same timeouts share
+/// a connection pool.
+type Timeouts = (Option<Duration>, Option<Duration>);
+
+/// A struct handing out HTTP connectors that share their connection pools.
+pub struct Connections {
+    settings: PoolSettings,
+    aws: Mutex<HashMap<Timeouts, DynConnector>>,
+    #[cfg(any(feature = "gcp", feature = "azure", feature = "op"))]
+    reqwest: std::sync::OnceLock<reqwest::Client>,
+}
+
+/// Implement the Connections constructor.
+impl Connections {
+    pub fn new(settings: PoolSettings) -> Self {
+        Self {
+            settings,
+            aws: Mutex::new(HashMap::new()),
+            #[cfg(any(feature = "gcp", feature = "azure", feature = "op"))]
+            reqwest: std::sync::OnceLock::new(),
+        }
+    }
+}
+
+/// Implement the Connections methods.
+impl Connections {
+    /// The HTTP connector of the AWS clients. Clients are built with the connector of their
+    /// timeouts, which is only created the first time those timeouts are seen.
+    pub fn aws(self: &Arc<Self>) -> HttpConnector {
+        let connections = self.clone();
+        HttpConnector::ConnectorFn(Arc::new(
+            move |settings: &ConnectorSettings, sleep: Option<SharedAsyncSleep>| {
+                Some(connections.aws_connector(settings, sleep))
+            },
+        ))
+    }
+
+    /// Get the connector of the timeouts, creating it if needed. Clones of a connector share its
+    /// connection pool.
+    fn aws_connector(
+        &self,
+        settings: &ConnectorSettings,
+        sleep: Option<SharedAsyncSleep>,
+    ) -> DynConnector {
+        let timeouts = (settings.connect_timeout(), settings.read_timeout());
+        let mut connectors = self.aws.lock().unwrap();
+        connectors
+            .entry(timeouts)
+            .or_insert_with(|| {
+                let mut http = hyper::client::HttpConnector::new();
+                // The HTTPS connector enforces the scheme instead.
+                http.enforce_http(false);
+                http.set_keepalive(self.settings.tcp_keepalive);
+                let https = hyper_rustls::HttpsConnectorBuilder::new()
+                    .with_native_roots()
+                    .https_or_http()
+                    .enable_http1()
+                    .enable_http2()
+                    .wrap_connector(http);
+
+                let mut client = hyper::Client::builder();
+                client.pool_idle_timeout(self.settings.idle_timeout);
+                if let Some(max_idle_per_host) = self.settings.max_idle_per_host {
+                    client.pool_max_idle_per_host(max_idle_per_host);
+                }
+                let mut adapter = hyper_ext::Adapter::builder()
+                    .connector_settings(settings.clone())
+                    .hyper_builder(client);
+                if let Some(sleep) = sleep {
+                    adapter = adapter.sleep_impl(sleep);
+                }
+                DynConnector::new(adapter.build(https))
+            })
+            .clone()
+    }
+
+    /// The HTTP client of the backends outside of AWS, whose clones share its connection pool.
+    #[cfg(any(feature = "gcp", feature = "azure", feature = "op"))]
+    pub fn reqwest(&self) -> reqwest::Client {
+        self.reqwest
+            .get_or_init(|| {
+                let mut builder = reqwest::Client::builder()
+                    .pool_idle_timeout(self.settings.idle_timeout)
+                    .tcp_keepalive(self.settings.tcp_keepalive);
+                if let Some(max_idle_per_host) = self.settings.max_idle_per_host {
+                    builder = builder.pool_max_idle_per_host(max_idle_per_host);
+                }
+                builder.build().expect("failed to create the HTTP client")
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn aws_clients_with_the_same_timeouts_share_a_connector() {
+        let connections = Arc::new(Connections::new(PoolSettings::default()));
+        let short = ConnectorSettings::builder()
+            .connect_timeout(Duration::from_secs(1))
+            .build();
+        let long = ConnectorSettings::builder()
+            .connect_timeout(Duration::from_secs(5))
+            .build();
+
+        for settings in [&short, &short, &long] {
+            assert!(connections.aws().connector(settings, None).is_some());
+        }
+        assert_eq!(connections.aws.lock().unwrap().len(), 2);
+    }
+}
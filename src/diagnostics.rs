@@ -0,0 +1,144 @@
+//! This module contains the diagnostics written to stderr, e.g. warnings and credential problems,
+//! either as free-form text or as JSON lines with stable fields for agents parsing them.
+
+use crate::vector::ErrorCode;
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// An enum representing the format of the diagnostics written to stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Free-form lines of text.
+    #[default]
+    Text,
+    /// A JSON object per line with the timestamp, level, event and message of the diagnostic.
+    Json,
+}
+
+/// The format of the diagnostics of the process, set by every run of the helper.
+static FORMAT: RwLock<Format> = RwLock::new(Format::Text);
+
+/// Set the format of all diagnostics written by the process from now on.
+pub fn set_format(format: Format) {
+    *FORMAT.write().unwrap() = format;
+}
+
+/// The format of the diagnostics of the process, text until set otherwise.
+pub fn format() -> Format {
+    *FORMAT.read().unwrap()
+}
+
+/// An enum representing the severity of a diagnostic.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A struct representing a diagnostic. Its event is a stable identifier of what happened, e.g.
+/// `secret_fetch_failed`, while its message is the text written in the text format.
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    timestamp: String,
+    level: Level,
+    event: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+    /// Other fields specific to the event.
+    #[serde(flatten)]
+    details: Map<String, Value>,
+}
+
+/// Implement the Diagnostic constructor.
+impl Diagnostic {
+    pub fn new(level: Level, event: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            level,
+            event,
+            message: message.into(),
+            secret: None,
+            code: None,
+            details: Map::new(),
+        }
+    }
+}
+
+/// Implement the Diagnostic methods.
+impl Diagnostic {
+    /// Set the name of the secret the diagnostic is about.
+    pub fn secret(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    /// Set the error code of the secret the diagnostic is about.
+    pub fn code(mut self, code: Option<ErrorCode>) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// Add a field specific to the event.
+    pub fn detail(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.details.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Render the diagnostic as a line in the format, without the line break.
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.message.clone(),
+            Format::Json => serde_json::to_string(self).unwrap(),
+        }
+    }
+
+    /// Write the diagnostic to stderr in the format of the process.
+    pub fn emit(self) {
+        eprintln!("{}", self.render(format()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnostics_are_rendered_in_both_formats() {
+        let diagnostic = Diagnostic::new(
+            Level::Warning,
+            "cache_write_failed",
+            "failed to cache /app/a: disk full",
+        )
+        .secret("/app/a")
+        .code(Some(ErrorCode::NotFound))
+        .detail("attempts", 2);
+
+        assert_eq!(
+            diagnostic.render(Format::Text),
+            "failed to cache /app/a: disk full"
+        );
+        let json: Value = serde_json::from_str(&diagnostic.render(Format::Json)).unwrap();
+        assert!(json["timestamp"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(json["level"], "warning");
+        assert_eq!(json["event"], "cache_write_failed");
+        assert_eq!(json["message"], "failed to cache /app/a: disk full");
+        assert_eq!(json["secret"], "/app/a");
+        assert_eq!(json["code"], "NOT_FOUND");
+        assert_eq!(json["attempts"], 2);
+
+        let json: Value = serde_json::from_str(
+            &Diagnostic::new(Level::Info, "shutting_down", "bye").render(Format::Json),
+        )
+        .unwrap();
+        assert!(json.get("secret").is_none());
+        assert!(json.get("code").is_none());
+    }
+}
@@ -0,0 +1,125 @@
+//! This module contains the comparison of fetched secrets against a previous snapshot of them, e.g.
+//! an earlier output of the helper or the disk cache. Values are only compared by their digests and
+//! never written anywhere.
+
+use crate::output::digest;
+use crate::secret::Secret;
+use crate::vector::{ErrorCode, FetchedSecrets};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// An enum representing how a secret differs from its previous snapshot.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The value is different.
+    Changed,
+    /// The secret exists but wasn't in the snapshot.
+    Added,
+    /// The secret was in the snapshot but doesn't exist anymore.
+    Removed,
+    /// The secret couldn't be fetched, so it can't be compared.
+    Failed(String),
+}
+
+/// Implement the Display trait for Change.
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Changed => write!(f, "changed"),
+            Change::Added => write!(f, "added"),
+            Change::Removed => write!(f, "removed"),
+            Change::Failed(_) => write!(f, "failed"),
+        }
+    }
+}
+
+/// Whether a fetched value is the same as the one in the snapshot, which is either the value
+/// itself or its digest if the snapshot was written with --hash-only.
+fn is_unchanged(previous: &Secret<String>, current: &Secret<String>) -> bool {
+    let current = digest(current.expose_secret());
+    digest(previous.expose_secret()) == current || *previous.expose_secret() == current
+}
+
+/// Compare the fetched secrets against the values of the snapshot, keyed by name, returning the
+/// secrets that differ ordered by name. Secrets that were never found are the same in both.
+pub fn compare(
+    previous: &BTreeMap<String, Secret<String>>,
+    current: &FetchedSecrets,
+) -> Vec<(String, Change)> {
+    let mut changes: Vec<_> = current
+        .0
+        .iter()
+        .filter_map(|(name, secret)| {
+            let change = match (previous.get(name), &secret.value) {
+                (Some(previous), Some(current)) if is_unchanged(previous, current) => None,
+                (Some(_), Some(_)) => Some(Change::Changed),
+                (None, Some(_)) => Some(Change::Added),
+                (Some(_), None) if secret.code == Some(ErrorCode::NotFound) => {
+                    Some(Change::Removed)
+                }
+                (None, None) if secret.code == Some(ErrorCode::NotFound) => None,
+                (_, None) => Some(Change::Failed(secret.error.clone().unwrap_or_default())),
+            };
+            Some((name.clone(), change?))
+        })
+        .collect();
+    changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+
+    #[test]
+    fn secrets_are_compared_by_digest() {
+        let previous = BTreeMap::from([
+            (String::from("same"), Secret::new(String::from("a"))),
+            // The digest of "b", as written with --hash-only.
+            (
+                String::from("same_hashed"),
+                Secret::new(String::from(
+                    "3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d",
+                )),
+            ),
+            (String::from("rotated"), Secret::new(String::from("old"))),
+            (String::from("deleted"), Secret::new(String::from("d"))),
+            (String::from("broken"), Secret::new(String::from("e"))),
+        ]);
+        let mut current = FetchedSecrets::default();
+        for (name, value) in [
+            ("same", "a"),
+            ("same_hashed", "b"),
+            ("rotated", "new"),
+            ("created", "c"),
+        ] {
+            current
+                .0
+                .insert(name.to_string(), Ok(value.to_string()).into());
+        }
+        for name in ["deleted", "never_existed"] {
+            current.0.insert(
+                name.to_string(),
+                SecretsHelperError::NotFound(String::from("not found")).into(),
+            );
+        }
+        current.0.insert(
+            String::from("broken"),
+            SecretsHelperError::AccessDenied(String::from("denied")).into(),
+        );
+
+        assert_eq!(
+            compare(&previous, &current),
+            vec![
+                (
+                    String::from("broken"),
+                    Change::Failed(String::from("denied"))
+                ),
+                (String::from("created"), Change::Added),
+                (String::from("deleted"), Change::Removed),
+                (String::from("rotated"), Change::Changed),
+            ]
+        );
+    }
+}
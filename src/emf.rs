@@ -0,0 +1,289 @@
+//! This module contains the metric records in the CloudWatch Embedded Metric Format, which the
+//! CloudWatch agent or a log driver turns into CloudWatch metrics without a statsd or Prometheus
+//! scraper.
+
+use crate::aws::loader::LoadSecrets;
+use crate::aws::logs::LogStream;
+use crate::diagnostics::{Diagnostic, Level};
+use crate::vector::{ErrorCode, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// An enum representing where the metric records are written.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Destination {
+    Stderr,
+    File(PathBuf),
+    /// A log stream of CloudWatch Logs the records are put into, created if it doesn't exist.
+    CloudWatchLogs {
+        group: String,
+        stream: String,
+    },
+}
+
+/// Implement the Destination methods.
+impl Destination {
+    /// Parse a destination, either `stderr`, `cloudwatch:GROUP:STREAM` for a log stream of
+    /// CloudWatch Logs, or the path of a file the records are appended to.
+    pub fn parse(destination: &str) -> Result<Self, String> {
+        match destination {
+            "" => Err(String::from("the destination must not be empty")),
+            "stderr" => Ok(Destination::Stderr),
+            // Neither log group nor log stream names can contain colons.
+            _ => match destination.strip_prefix("cloudwatch:") {
+                Some(log_stream) => match log_stream.split_once(':') {
+                    Some((group, stream))
+                        if !group.is_empty() && !stream.is_empty() && !stream.contains(':') =>
+                    {
+                        Ok(Destination::CloudWatchLogs {
+                            group: group.to_string(),
+                            stream: stream.to_string(),
+                        })
+                    }
+                    _ => Err(String::from(
+                        "the CloudWatch Logs destination must be cloudwatch:GROUP:STREAM",
+                    )),
+                },
+                None => Ok(Destination::File(PathBuf::from(destination))),
+            },
+        }
+    }
+}
+
+/// An enum representing where the metric records are sent, along with the client sending them.
+pub enum Sink {
+    Stderr,
+    File(PathBuf),
+    LogStream(Box<LogStream>),
+}
+
+/// Implement the Sink constructor.
+impl Sink {
+    /// Create the sink of the destination. A log stream calls CloudWatch Logs with the AWS SDK
+    /// config, which is only awaited once records are sent.
+    pub fn new(
+        destination: &Destination,
+        sdk_config: impl Future<Output = SdkConfig> + Send + 'static,
+    ) -> Self {
+        match destination {
+            Destination::Stderr => Sink::Stderr,
+            Destination::File(path) => Sink::File(path.clone()),
+            Destination::CloudWatchLogs { group, stream } => {
+                Sink::LogStream(Box::new(LogStream::new(group, stream, sdk_config)))
+            }
+        }
+    }
+}
+
+/// Implement the Sink methods.
+impl Sink {
+    /// Send the records with the timestamp, one JSON object per line or log event.
+    async fn send(&self, records: &[Value], timestamp_ms: u128) -> Result<(), String> {
+        let lines = records
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| error.to_string())?;
+        // Write all lines at once so that concurrent writers don't interleave their records.
+        let text = lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect::<String>();
+        match self {
+            Sink::Stderr => std::io::stderr().write_all(text.as_bytes()),
+            Sink::File(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(text.as_bytes())),
+            Sink::LogStream(log_stream) => {
+                return log_stream
+                    .put(&lines, timestamp_ms)
+                    .await
+                    .map_err(|failure| failure.to_string())
+            }
+        }
+        .map_err(|error| error.to_string())
+    }
+}
+
+/// Create the metric records of a request: one with the number of fetched secrets, errors and
+/// the latency per backend, and one with the number of errors per backend and error class.
+pub fn records(
+    namespace: &str,
+    backend: &str,
+    fetched_secrets: &FetchedSecrets,
+    latency_ms: f64,
+    timestamp_ms: u128,
+) -> Vec<Value> {
+    let mut errors: BTreeMap<&str, u64> = BTreeMap::new();
+    for secret in fetched_secrets.0.values() {
+        if secret.error.is_some() {
+            let class = secret.code.unwrap_or(ErrorCode::Unknown).as_str();
+            *errors.entry(class).or_default() += 1;
+        }
+    }
+    let failed: u64 = errors.values().sum();
+
+    let mut records = vec![json!({
+        "_aws": {
+            "Timestamp": timestamp_ms,
+            "CloudWatchMetrics": [{
+                "Namespace": namespace,
+                "Dimensions": [["Backend"]],
+                "Metrics": [
+                    {"Name": "SecretsFetched", "Unit": "Count"},
+                    {"Name": "FetchErrors", "Unit": "Count"},
+                    {"Name": "Latency", "Unit": "Milliseconds"},
+                ],
+            }],
+        },
+        "Backend": backend,
+        "SecretsFetched": fetched_secrets.0.len() as u64 - failed,
+        "FetchErrors": failed,
+        "Latency": latency_ms,
+    })];
+    for (class, count) in errors {
+        records.push(json!({
+            "_aws": {
+                "Timestamp": timestamp_ms,
+                "CloudWatchMetrics": [{
+                    "Namespace": namespace,
+                    "Dimensions": [["Backend", "ErrorClass"]],
+                    "Metrics": [{"Name": "FetchErrors", "Unit": "Count"}],
+                }],
+            },
+            "Backend": backend,
+            "ErrorClass": class,
+            "FetchErrors": count,
+        }));
+    }
+    records
+}
+
+/// A struct wrapping a secrets loader to write metric records about every request it serves.
+pub struct EmfLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    backend: String,
+    namespace: String,
+    sink: Sink,
+}
+
+/// Implement the EmfLoader constructor.
+impl EmfLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        backend: &str,
+        namespace: &str,
+        sink: Sink,
+    ) -> Self {
+        Self {
+            inner,
+            backend: backend.to_string(),
+            namespace: namespace.to_string(),
+            sink,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for EmfLoader.
+#[async_trait]
+impl LoadSecrets for EmfLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let start = Instant::now();
+        let fetched_secrets = self.inner.load(secrets).await;
+        let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |timestamp| timestamp.as_millis());
+        let records = records(
+            &self.namespace,
+            &self.backend,
+            &fetched_secrets,
+            latency_ms,
+            timestamp_ms,
+        );
+        if let Err(error) = self.sink.send(&records, timestamp_ms).await {
+            Diagnostic::new(
+                Level::Warning,
+                "metric_records_write_failed",
+                format!("failed to write metric records: {}", error),
+            )
+            .emit();
+        }
+
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        self.inner.current_version(requested).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+
+    #[test]
+    fn destinations_can_be_parsed() {
+        assert_eq!(Destination::parse("stderr"), Ok(Destination::Stderr));
+        assert_eq!(
+            Destination::parse("/var/log/emf.log"),
+            Ok(Destination::File(PathBuf::from("/var/log/emf.log")))
+        );
+        assert_eq!(
+            Destination::parse("cloudwatch:/vector/helper:host-1"),
+            Ok(Destination::CloudWatchLogs {
+                group: String::from("/vector/helper"),
+                stream: String::from("host-1"),
+            })
+        );
+        assert!(Destination::parse("cloudwatch:/vector/helper").is_err());
+        assert!(Destination::parse("cloudwatch::host-1").is_err());
+        assert!(Destination::parse("").is_err());
+    }
+
+    #[test]
+    fn records_count_secrets_and_errors_by_class() {
+        let mut fetched_secrets = FetchedSecrets::default();
+        fetched_secrets
+            .0
+            .insert(String::from("a"), Ok(String::from("1")).into());
+        for name in ["b", "c"] {
+            fetched_secrets.0.insert(
+                name.to_string(),
+                SecretsHelperError::NotFound(String::from("not found")).into(),
+            );
+        }
+        fetched_secrets.0.insert(
+            String::from("d"),
+            SecretsHelperError::Throttled(String::from("slow down")).into(),
+        );
+
+        let records = records("Helper", "ssm", &fetched_secrets, 12.5, 1000);
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["_aws"]["Timestamp"], 1000);
+        assert_eq!(
+            records[0]["_aws"]["CloudWatchMetrics"][0]["Namespace"],
+            "Helper"
+        );
+        assert_eq!(records[0]["Backend"], "ssm");
+        assert_eq!(records[0]["SecretsFetched"], 1);
+        assert_eq!(records[0]["FetchErrors"], 3);
+        assert_eq!(records[0]["Latency"], 12.5);
+        assert_eq!(records[1]["ErrorClass"], "NOT_FOUND");
+        assert_eq!(records[1]["FetchErrors"], 2);
+        assert_eq!(records[2]["ErrorClass"], "THROTTLED");
+        assert_eq!(records[2]["FetchErrors"], 1);
+    }
+}
@@ -0,0 +1,231 @@
+//! This module contains helpers for rendering errors into the messages returned to Vector and
+//! written to stderr.
+
+use crate::secret::Secret;
+use crate::vector::{ErrorCode, FetchedSecret};
+use std::error::Error;
+
+/// An enum representing why a secret couldn't be fetched, so that applications embedding the
+/// loaders can match on the kind of failure. The CLI renders it into the `error` and `code` fields
+/// returned to Vector.
+#[derive(Clone, Debug, PartialEq, thiserror::Error)]
+pub enum SecretsHelperError {
+    /// No credentials could be loaded, or they were rejected while obtaining a token.
+    #[error("{0}")]
+    Credentials(String),
+    /// The backend couldn't be reached.
+    #[error("{0}")]
+    Transport(String),
+    /// The backend didn't respond in time.
+    #[error("{0}")]
+    Timeout(String),
+    /// The whole request didn't finish before the deadline.
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    AccessDenied(String),
+    #[error("{0}")]
+    Throttled(String),
+    /// A quota or resource limit of the account was reached, which retrying doesn't resolve.
+    #[error("{0}")]
+    QuotaExceeded(String),
+    /// The secret value couldn't be decrypted by the backend.
+    #[error("{0}")]
+    Decryption(String),
+    /// The secret value couldn't be decoded or didn't have the expected structure.
+    #[error("{0}")]
+    Decode(String),
+    /// The requested secret or its options are invalid.
+    #[error("{0}")]
+    InvalidRequest(String),
+    /// The backend failed to handle the request.
+    #[error("{0}")]
+    Service(String),
+    /// The secret value failed one of the validation rules of the config file.
+    #[error("{0}")]
+    Validation(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Implement the SecretsHelperError constructor.
+impl SecretsHelperError {
+    /// Create the error of the kind classified by the code.
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        match code {
+            ErrorCode::CredentialsUnavailable => Self::Credentials(message),
+            ErrorCode::ConnectionFailure => Self::Transport(message),
+            ErrorCode::Timeout => Self::Timeout(message),
+            ErrorCode::DeadlineExceeded => Self::DeadlineExceeded,
+            ErrorCode::NotFound => Self::NotFound(message),
+            ErrorCode::AccessDenied => Self::AccessDenied(message),
+            ErrorCode::Throttled => Self::Throttled(message),
+            ErrorCode::QuotaExceeded => Self::QuotaExceeded(message),
+            ErrorCode::DecryptionFailure => Self::Decryption(message),
+            ErrorCode::InvalidRequest => Self::InvalidRequest(message),
+            ErrorCode::ServiceError => Self::Service(message),
+            ErrorCode::ValidationFailed => Self::Validation(message),
+            ErrorCode::Unknown => Self::Other(message),
+        }
+    }
+}
+
+/// Implement the SecretsHelperError methods.
+impl SecretsHelperError {
+    /// The code classifying the error in the output to Vector.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::Credentials(_) => ErrorCode::CredentialsUnavailable,
+            Self::Transport(_) => ErrorCode::ConnectionFailure,
+            Self::Timeout(_) => ErrorCode::Timeout,
+            Self::DeadlineExceeded => ErrorCode::DeadlineExceeded,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::AccessDenied(_) => ErrorCode::AccessDenied,
+            Self::Throttled(_) => ErrorCode::Throttled,
+            Self::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+            Self::Decryption(_) => ErrorCode::DecryptionFailure,
+            Self::Decode(_) | Self::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            Self::Service(_) => ErrorCode::ServiceError,
+            Self::Validation(_) => ErrorCode::ValidationFailed,
+            Self::Other(_) => ErrorCode::Unknown,
+        }
+    }
+}
+
+/// Implement the From trait to render a SecretsHelperError into the result of a secret.
+impl From<SecretsHelperError> for FetchedSecret {
+    fn from(error: SecretsHelperError) -> Self {
+        FetchedSecret {
+            value: None,
+            code: Some(error.code()),
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Implement the From trait to render the value or error of a secret into its result.
+impl From<Result<String, SecretsHelperError>> for FetchedSecret {
+    fn from(result: Result<String, SecretsHelperError>) -> Self {
+        match result {
+            Ok(value) => FetchedSecret {
+                value: Some(Secret::new(value)),
+                error: None,
+                code: None,
+            },
+            Err(error) => error.into(),
+        }
+    }
+}
+
+/// Implement the FetchedSecret methods.
+impl FetchedSecret {
+    /// Turn the result of a secret into the value or the error it failed with.
+    pub fn into_result(self) -> Result<Secret<String>, SecretsHelperError> {
+        match self.value {
+            Some(value) => Ok(value),
+            None => Err(SecretsHelperError::new(
+                self.code.unwrap_or(ErrorCode::Unknown),
+                self.error.unwrap_or_default(),
+            )),
+        }
+    }
+}
+
+/// Describe an error along with the chain of errors that caused it, e.g. `dispatch failure: io
+/// error: dns error: failed to lookup address information`, so that the actual cause reaches the
+/// operator. Causes whose message is already part of the description are skipped.
+pub fn describe(error: &(dyn Error + 'static)) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        let cause = error.to_string();
+        if !cause.is_empty() && !description.contains(&cause) {
+            description = format!("{}: {}", description, cause);
+        }
+        source = error.source();
+    }
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct ChainedError {
+        message: &'static str,
+        source: Option<Box<ChainedError>>,
+    }
+
+    impl fmt::Display for ChainedError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.message)
+        }
+    }
+
+    impl Error for ChainedError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            self.source
+                .as_deref()
+                .map(|source| source as &(dyn Error + 'static))
+        }
+    }
+
+    fn chain(messages: &[&'static str]) -> ChainedError {
+        messages
+            .iter()
+            .rev()
+            .fold(None, |source, message| {
+                Some(ChainedError {
+                    message,
+                    source: source.map(Box::new),
+                })
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn errors_are_rendered_into_fetched_secrets() {
+        let fetched_secret: FetchedSecret =
+            SecretsHelperError::NotFound(String::from("secret not found")).into();
+        assert_eq!(fetched_secret.code, Some(ErrorCode::NotFound));
+        assert_eq!(fetched_secret.error.as_deref(), Some("secret not found"));
+        assert_eq!(
+            fetched_secret.into_result(),
+            Err(SecretsHelperError::NotFound(String::from(
+                "secret not found"
+            )))
+        );
+
+        let fetched_secret: FetchedSecret =
+            SecretsHelperError::Decode(String::from("invalid base64")).into();
+        assert_eq!(fetched_secret.code, Some(ErrorCode::InvalidRequest));
+
+        let fetched_secret: FetchedSecret = SecretsHelperError::DeadlineExceeded.into();
+        assert_eq!(fetched_secret.error.as_deref(), Some("deadline exceeded"));
+    }
+
+    #[test]
+    fn describe_includes_the_source_chain() {
+        assert_eq!(
+            describe(&chain(&["dispatch failure", "io error", "dns error"])),
+            "dispatch failure: io error: dns error"
+        );
+        assert_eq!(describe(&chain(&["not found"])), "not found");
+    }
+
+    #[test]
+    fn describe_skips_causes_already_in_the_message() {
+        assert_eq!(
+            describe(&chain(&[
+                "error sending request: connection refused",
+                "connection refused",
+                "",
+            ])),
+            "error sending request: connection refused"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! This module contains the exit codes of the helper and the policy deciding when a response with
+//! failed secrets results in a non-zero exit code.
+
+use crate::vector::{ErrorCode, FetchedSecrets};
+use clap::ValueEnum;
+
+// Other errors, e.g. failing to write the output file, exit with 1 and invalid command line
+// arguments exit with 2, as for any clap-based CLI.
+
+/// The request could not be read or parsed.
+pub const INPUT_ERROR: i32 = 3;
+/// Secrets could not be fetched because no credentials could be loaded.
+pub const CREDENTIALS_FAILURE: i32 = 4;
+/// Some, but not all, secrets could not be fetched.
+pub const PARTIAL_FAILURE: i32 = 5;
+/// None of the requested secrets could be fetched.
+pub const TOTAL_FAILURE: i32 = 6;
+
+/// A struct representing a failure that ends a command without a response, with the message
+/// written to stderr and the code the helper exits with.
+#[derive(Debug, PartialEq)]
+pub struct Failure {
+    pub code: i32,
+    pub message: String,
+}
+
+/// Implement the Failure constructor.
+impl Failure {
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// An enum representing which fetch failures result in a non-zero exit code. The response is
+/// written regardless.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum ExitOn {
+    /// Always exit with 0 once the response is written, leaving failures to the caller.
+    #[default]
+    Never,
+    /// Exit with a non-zero code only if none of the secrets could be fetched.
+    Total,
+    /// Exit with a non-zero code if any of the secrets could not be fetched.
+    Partial,
+}
+
+/// Determine the exit code for the fetched secrets.
+pub fn code(fetched_secrets: &FetchedSecrets, exit_on: ExitOn) -> i32 {
+    let mut tally = Tally::default();
+    tally.add(fetched_secrets);
+    tally.code(exit_on)
+}
+
+/// A struct counting the fetched and failed secrets of a response written in several parts, to
+/// determine its exit code without holding all fetched secrets.
+#[derive(Debug, Default)]
+pub struct Tally {
+    secrets: usize,
+    failed: usize,
+    credentials_unavailable: bool,
+}
+
+/// Implement the Tally methods.
+impl Tally {
+    /// Count the fetched secrets of a part of the response.
+    pub fn add(&mut self, fetched_secrets: &FetchedSecrets) {
+        for secret in fetched_secrets.0.values() {
+            self.secrets += 1;
+            if secret.error.is_some() {
+                self.failed += 1;
+            }
+            if secret.code == Some(ErrorCode::CredentialsUnavailable) {
+                self.credentials_unavailable = true;
+            }
+        }
+    }
+
+    /// Determine the exit code for the counted secrets.
+    pub fn code(&self, exit_on: ExitOn) -> i32 {
+        let total = self.failed > 0 && self.failed == self.secrets;
+        let fail = match exit_on {
+            ExitOn::Never => false,
+            ExitOn::Total => total,
+            ExitOn::Partial => self.failed > 0,
+        };
+        if !fail {
+            0
+        } else if self.credentials_unavailable {
+            CREDENTIALS_FAILURE
+        } else if total {
+            TOTAL_FAILURE
+        } else {
+            PARTIAL_FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::FetchedSecret;
+
+    fn fetched_secrets(codes: &[Option<ErrorCode>]) -> FetchedSecrets {
+        FetchedSecrets(
+            codes
+                .iter()
+                .enumerate()
+                .map(|(i, code)| {
+                    let secret = match code {
+                        Some(code) => FetchedSecret {
+                            value: None,
+                            error: Some(String::from("failed to fetch")),
+                            code: Some(*code),
+                        },
+                        None => FetchedSecret {
+                            value: Some(Secret::new(String::from("qwerty"))),
+                            error: None,
+                            code: None,
+                        },
+                    };
+                    (format!("test.secret_{}", i), secret)
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn exit_code_depends_on_the_policy() {
+        let partial = fetched_secrets(&[None, Some(ErrorCode::NotFound)]);
+        assert_eq!(code(&partial, ExitOn::Never), 0);
+        assert_eq!(code(&partial, ExitOn::Total), 0);
+        assert_eq!(code(&partial, ExitOn::Partial), PARTIAL_FAILURE);
+
+        let total = fetched_secrets(&[Some(ErrorCode::NotFound), Some(ErrorCode::Throttled)]);
+        assert_eq!(code(&total, ExitOn::Never), 0);
+        assert_eq!(code(&total, ExitOn::Total), TOTAL_FAILURE);
+        assert_eq!(code(&total, ExitOn::Partial), TOTAL_FAILURE);
+
+        assert_eq!(code(&fetched_secrets(&[None]), ExitOn::Partial), 0);
+        assert_eq!(code(&fetched_secrets(&[]), ExitOn::Total), 0);
+    }
+
+    #[test]
+    fn exit_code_reports_credentials_failures() {
+        let fetched_secrets = fetched_secrets(&[
+            Some(ErrorCode::CredentialsUnavailable),
+            Some(ErrorCode::CredentialsUnavailable),
+        ]);
+        assert_eq!(code(&fetched_secrets, ExitOn::Total), CREDENTIALS_FAILURE);
+    }
+
+    #[test]
+    fn tallies_of_several_parts_match_the_whole() {
+        let mut tally = Tally::default();
+        tally.add(&fetched_secrets(&[None]));
+        tally.add(&fetched_secrets(&[Some(ErrorCode::NotFound)]));
+        assert_eq!(tally.code(ExitOn::Total), 0);
+        assert_eq!(tally.code(ExitOn::Partial), PARTIAL_FAILURE);
+    }
+}
@@ -0,0 +1,278 @@
+//! This module contains the expansion of secrets holding JSON objects into a key per field, e.g.
+//! `my-secret.user` and `my-secret.pass` for `{"user": "...", "pass": "..."}`, so that every field
+//! can be referenced as a separate secret in Vector.
+
+use crate::aws::loader::LoadSecrets;
+use crate::extract;
+use crate::vector::{ErrorCode, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+
+/// The separator between the name of an expanded secret and its fields.
+const SEPARATOR: char = '.';
+
+/// A struct wrapping a secrets loader to expand the fetched secrets holding JSON objects, and to
+/// resolve requested fields of them, e.g. `my-secret.user`, by fetching the whole secret.
+pub struct ExpandedLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    /// Whether all secrets are expanded, instead of only those in the set.
+    all: bool,
+    /// The names of the secrets expanded even if not all are.
+    secrets: HashSet<String>,
+}
+
+/// Implement the ExpandedLoader constructor.
+impl ExpandedLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        all: bool,
+        secrets: HashSet<String>,
+    ) -> Self {
+        Self {
+            inner,
+            all,
+            secrets,
+        }
+    }
+}
+
+/// Implement the ExpandedLoader methods.
+impl ExpandedLoader {
+    fn is_expanded(&self, name: &str) -> bool {
+        self.all || self.secrets.contains(name)
+    }
+
+    /// Split a requested field of an expanded secret into the name of the secret and the field,
+    /// if the secret is one of the expanded secrets.
+    fn split_field<'a>(&self, requested: &'a str) -> Option<(&'a str, &'a str)> {
+        requested.match_indices(SEPARATOR).find_map(|(index, _)| {
+            let name = &requested[..index];
+            self.secrets
+                .contains(name)
+                .then(|| (name, &requested[index + 1..]))
+        })
+    }
+
+    /// Fetch the requested secrets along with the secrets holding the requested fields, returning
+    /// the fields under their requested names instead of the secrets holding them.
+    async fn load_with_fields(
+        &self,
+        version: &str,
+        mut names: Vec<String>,
+        fields: BTreeMap<String, (String, String)>,
+    ) -> FetchedSecrets {
+        let requested: HashSet<_> = names.iter().cloned().collect();
+        names.extend(fields.values().map(|(name, _)| name.clone()));
+        names.sort();
+        names.dedup();
+        let mut fetched_secrets = self
+            .inner
+            .load(SecretsToFetch {
+                version: version.to_string(),
+                secrets: names,
+            })
+            .await;
+        for (field_name, (name, field)) in &fields {
+            let Some(secret) = fetched_secrets.0.get(name) else {
+                continue;
+            };
+            let secret = extract::apply(secret.clone(), |value| extract::json_key(value, field));
+            fetched_secrets.0.insert(field_name.clone(), secret);
+        }
+        for (name, _) in fields.values() {
+            if !requested.contains(name) {
+                fetched_secrets.0.remove(name);
+            }
+        }
+        fetched_secrets
+    }
+}
+
+/// Parse a secret value as a JSON object, None if it's anything else.
+fn json_object(value: &str) -> Option<Map<String, Value>> {
+    serde_json::from_str(value).ok()
+}
+
+/// Implement the LoadSecrets trait for ExpandedLoader.
+#[async_trait]
+impl LoadSecrets for ExpandedLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        // Fields of the secrets known to be expanded are fetched along with the other secrets.
+        let (fields, names): (Vec<_>, Vec<_>) = secrets
+            .secrets
+            .into_iter()
+            .partition(|requested| self.split_field(requested).is_some());
+        let fields: BTreeMap<_, _> = fields
+            .into_iter()
+            .map(|requested| {
+                let (name, field) = self.split_field(&requested).unwrap();
+                let split = (name.to_string(), field.to_string());
+                (requested, split)
+            })
+            .collect();
+
+        let mut fetched_secrets = self.load_with_fields(&secrets.version, names, fields).await;
+
+        // When all secrets are expanded, a requested name that doesn't exist may be the field of
+        // the secret named like its part before the last separator.
+        if self.all {
+            let fields: BTreeMap<_, _> = fetched_secrets
+                .0
+                .iter()
+                .filter(|(_, secret)| secret.code == Some(ErrorCode::NotFound))
+                .filter_map(|(requested, _)| {
+                    let (name, field) = requested.rsplit_once(SEPARATOR)?;
+                    let split = (name.to_string(), field.to_string());
+                    Some((requested.clone(), split))
+                })
+                .collect();
+            if !fields.is_empty() {
+                let fetched_fields = self
+                    .load_with_fields(&secrets.version, Vec::new(), fields)
+                    .await;
+                // Fields that weren't found either keep the error of the requested name.
+                for (requested, secret) in fetched_fields.0 {
+                    if secret.code != Some(ErrorCode::NotFound) {
+                        fetched_secrets.0.insert(requested, secret);
+                    }
+                }
+            }
+        }
+
+        // Requested names take precedence over the keys of expanded fields.
+        let mut expanded = Vec::new();
+        for (name, secret) in &fetched_secrets.0 {
+            let object = secret
+                .value
+                .as_ref()
+                .filter(|_| self.is_expanded(name))
+                .and_then(|value| json_object(value.expose_secret()));
+            for (field, value) in object.into_iter().flatten() {
+                let key = format!("{}{}{}", name, SEPARATOR, field);
+                expanded.push((key, extract::render(&value)));
+            }
+        }
+        for (key, value) in expanded {
+            fetched_secrets.0.entry(key).or_insert(Ok(value).into());
+        }
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        match self.split_field(requested) {
+            Some((name, _)) => self.inner.current_version(name).await,
+            None => self.inner.current_version(requested).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+    use crate::secret::Secret;
+    use std::sync::{Arc, Mutex};
+
+    struct MockLoader {
+        requested: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl LoadSecrets for Arc<MockLoader> {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            self.requested
+                .lock()
+                .unwrap()
+                .extend(secrets.secrets.iter().cloned());
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| {
+                        let secret = match name.as_str() {
+                            "db" => Ok(String::from("{\"user\": \"u\", \"port\": 5432}")).into(),
+                            "token" => Ok(String::from("qwerty")).into(),
+                            _ => SecretsHelperError::NotFound(String::from("not found")).into(),
+                        };
+                        (name, secret)
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    fn value(fetched_secrets: &FetchedSecrets, name: &str) -> Option<Secret<String>> {
+        fetched_secrets.0[name].value.clone()
+    }
+
+    #[tokio::test]
+    async fn configured_secrets_are_expanded_into_fields() {
+        let inner = Arc::new(MockLoader {
+            requested: Mutex::new(Vec::new()),
+        });
+        let loader = ExpandedLoader::new(
+            Box::new(inner.clone()),
+            false,
+            HashSet::from([String::from("db"), String::from("token")]),
+        );
+
+        let fetched_secrets = loader
+            .load(SecretsToFetch::from_names(vec![
+                String::from("db"),
+                String::from("token"),
+                String::from("db.user"),
+                String::from("db.password"),
+            ]))
+            .await;
+
+        assert_eq!(
+            value(&fetched_secrets, "db.user"),
+            Some(Secret::new(String::from("u")))
+        );
+        assert_eq!(
+            value(&fetched_secrets, "db.port"),
+            Some(Secret::new(String::from("5432")))
+        );
+        assert_eq!(
+            fetched_secrets.0["db.password"].code,
+            Some(ErrorCode::NotFound)
+        );
+        // Values that aren't JSON objects are returned as they are.
+        assert_eq!(
+            value(&fetched_secrets, "token"),
+            Some(Secret::new(String::from("qwerty")))
+        );
+        assert_eq!(fetched_secrets.0.len(), 5);
+        assert_eq!(*inner.requested.lock().unwrap(), vec!["db", "token"]);
+    }
+
+    #[tokio::test]
+    async fn fields_of_any_secret_are_fetched_when_all_are_expanded() {
+        let inner = Arc::new(MockLoader {
+            requested: Mutex::new(Vec::new()),
+        });
+        let loader = ExpandedLoader::new(Box::new(inner.clone()), true, HashSet::new());
+
+        let fetched_secrets = loader
+            .load(SecretsToFetch::from_names(vec![
+                String::from("db.user"),
+                String::from("missing.user"),
+            ]))
+            .await;
+
+        assert_eq!(
+            value(&fetched_secrets, "db.user"),
+            Some(Secret::new(String::from("u")))
+        );
+        assert_eq!(
+            fetched_secrets.0["missing.user"].code,
+            Some(ErrorCode::NotFound)
+        );
+        assert_eq!(fetched_secrets.0.len(), 2);
+        assert_eq!(
+            *inner.requested.lock().unwrap(),
+            vec!["db.user", "missing.user", "db", "missing"]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! This module contains helpers for extracting a single field from structured secret values.
+
+use crate::error::SecretsHelperError;
+use crate::vector::FetchedSecret;
+use serde_json::Value;
+
+/// Render an extracted JSON value, returning strings without quotes.
+pub fn render(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Extract a top-level key from a secret value holding a JSON object.
+pub fn json_key(value: &str, key: &str) -> Result<String, SecretsHelperError> {
+    let object: serde_json::Map<String, Value> = serde_json::from_str(value).map_err(|_| {
+        SecretsHelperError::Decode(String::from("secret value is not a JSON object"))
+    })?;
+    object.get(key).map(render).ok_or_else(|| {
+        SecretsHelperError::NotFound(format!("key not found in secret value: {}", key))
+    })
+}
+
+/// Extract the field a JSON Pointer, e.g. `/database/password`, refers to from a secret value
+/// holding a JSON document.
+#[cfg(feature = "ssm")]
+pub fn json_pointer(value: &str, pointer: &str) -> Result<String, SecretsHelperError> {
+    if !pointer.is_empty() && !pointer.starts_with('/') {
+        return Err(SecretsHelperError::InvalidRequest(format!(
+            "invalid JSON pointer: {}",
+            pointer
+        )));
+    }
+    let document: Value = serde_json::from_str(value).map_err(|_| {
+        SecretsHelperError::Decode(String::from("secret value is not a JSON document"))
+    })?;
+    document.pointer(pointer).map(render).ok_or_else(|| {
+        SecretsHelperError::NotFound(format!("field not found in secret value: {}", pointer))
+    })
+}
+
+/// Split a `#yaml:<path>` selector off a requested secret. Neither SSM Parameter Store nor Secrets
+/// Manager allow `#` in names, so it always starts the selector.
+#[cfg_attr(
+    not(any(
+        feature = "ssm",
+        feature = "secretsmanager",
+        feature = "gcp",
+        feature = "azure"
+    )),
+    allow(dead_code)
+)]
+pub fn split_yaml_selector(requested: &str) -> Result<(&str, Option<&str>), String> {
+    match requested.split_once('#') {
+        Some((requested, selector)) => match selector.strip_prefix("yaml:") {
+            Some(path) => Ok((requested, Some(path))),
+            None => Err(format!("unknown field selector: {}", selector)),
+        },
+        None => Ok((requested, None)),
+    }
+}
+
+/// Extract the field a dot-separated path, e.g. `credentials.password`, refers to from a secret
+/// value holding a YAML document. Numeric path segments index into sequences.
+pub fn yaml_path(value: &str, path: &str) -> Result<String, SecretsHelperError> {
+    let document: serde_yaml::Value = serde_yaml::from_str(value).map_err(|_| {
+        SecretsHelperError::Decode(String::from("secret value is not a YAML document"))
+    })?;
+    let field =
+        path.split('.')
+            .try_fold(&document, |field, segment| match segment.parse::<usize>() {
+                Ok(index) if field.is_sequence() => field.get(index),
+                _ => field.get(segment),
+            });
+    match field {
+        Some(serde_yaml::Value::String(value)) => Ok(value.clone()),
+        Some(field) => Ok(serde_yaml::to_string(field)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string()),
+        None => Err(SecretsHelperError::NotFound(format!(
+            "field not found in secret value: {}",
+            path
+        ))),
+    }
+}
+
+/// Apply an extraction to a fetched secret, leaving failed fetches untouched.
+pub fn apply<F>(secret: FetchedSecret, extract: F) -> FetchedSecret
+where
+    F: FnOnce(&str) -> Result<String, SecretsHelperError>,
+{
+    let Some(value) = &secret.value else {
+        return secret;
+    };
+    extract(value.expose_secret()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "secretsmanager")]
+    #[test]
+    fn json_key_can_be_extracted() {
+        let value = "{\"username\": \"vector\", \"password\": \"qwerty\", \"port\": 5432}";
+        assert_eq!(json_key(value, "password"), Ok(String::from("qwerty")));
+        assert_eq!(json_key(value, "port"), Ok(String::from("5432")));
+        assert_eq!(
+            json_key(value, "host"),
+            Err(SecretsHelperError::NotFound(String::from(
+                "key not found in secret value: host"
+            )))
+        );
+        assert_eq!(
+            json_key("qwerty", "password"),
+            Err(SecretsHelperError::Decode(String::from(
+                "secret value is not a JSON object"
+            )))
+        );
+    }
+
+    #[cfg(feature = "ssm")]
+    #[test]
+    fn json_pointer_can_be_extracted() {
+        let value = "{\"database\": {\"password\": \"qwerty\", \"hosts\": [\"a\", \"b\"]}}";
+        assert_eq!(
+            json_pointer(value, "/database/password"),
+            Ok(String::from("qwerty"))
+        );
+        assert_eq!(
+            json_pointer(value, "/database/hosts/1"),
+            Ok(String::from("b"))
+        );
+        assert_eq!(
+            json_pointer(value, "/database/user"),
+            Err(SecretsHelperError::NotFound(String::from(
+                "field not found in secret value: /database/user"
+            )))
+        );
+        assert_eq!(
+            json_pointer(value, "database"),
+            Err(SecretsHelperError::InvalidRequest(String::from(
+                "invalid JSON pointer: database"
+            )))
+        );
+    }
+
+    #[test]
+    fn yaml_selector_can_be_split() {
+        assert_eq!(
+            split_yaml_selector("my-secret?stage=AWSPREVIOUS#yaml:credentials.password"),
+            Ok(("my-secret?stage=AWSPREVIOUS", Some("credentials.password")))
+        );
+        assert_eq!(split_yaml_selector("my-secret"), Ok(("my-secret", None)));
+        assert_eq!(
+            split_yaml_selector("my-secret#toml:password"),
+            Err(String::from("unknown field selector: toml:password"))
+        );
+    }
+
+    #[test]
+    fn yaml_path_can_be_extracted() {
+        let value = "credentials:\n  password: qwerty\n  port: 5432\nhosts:\n  - a\n  - b\n";
+        assert_eq!(
+            yaml_path(value, "credentials.password"),
+            Ok(String::from("qwerty"))
+        );
+        assert_eq!(
+            yaml_path(value, "credentials.port"),
+            Ok(String::from("5432"))
+        );
+        assert_eq!(yaml_path(value, "hosts.1"), Ok(String::from("b")));
+        assert_eq!(
+            yaml_path(value, "credentials.username"),
+            Err(SecretsHelperError::NotFound(String::from(
+                "field not found in secret value: credentials.username"
+            )))
+        );
+    }
+}
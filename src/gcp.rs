@@ -0,0 +1,287 @@
+//! This module contains the secrets loader implementation for Google Cloud Secret Manager.
+
+use crate::aws::loader::{fetch_all, LoadSecrets, LoaderSettings};
+use crate::aws::options::{invalid_request, RequestedSecret};
+use crate::error::{describe, SecretsHelperError};
+use crate::extract;
+use crate::healthcheck::PingEndpoint;
+use crate::http;
+use crate::vector::{FetchedSecret, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use gcp_auth::{Token, TokenProvider};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// The endpoint of the Secret Manager API unless overridden with --endpoint-url.
+const DEFAULT_ENDPOINT: &str = "https://secretmanager.googleapis.com";
+/// The OAuth scope of access tokens for the Secret Manager API.
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// A trait for fetching a single secret version from Google Cloud Secret Manager.
+#[async_trait]
+pub trait GcpFetchSecret {
+    /// Get the project secrets are fetched from unless the request names one.
+    async fn default_project(&self) -> Result<String, SecretsHelperError>;
+
+    /// Fetch the secret version with the resource name, e.g.
+    /// `projects/my-project/secrets/db_password/versions/latest`.
+    async fn fetch_secret(&self, name: String) -> FetchedSecret;
+}
+
+/// A struct for calling the Secret Manager API with Application Default Credentials.
+#[derive(Clone)]
+pub struct GcpClient {
+    http: reqwest::Client,
+    endpoint: String,
+    /// The credentials, or why none could be found. A missing credential is reported for every
+    /// fetched secret, like it is for AWS, instead of failing to start.
+    auth: Result<Arc<dyn TokenProvider>, String>,
+}
+
+/// Implement the GcpClient constructor.
+impl GcpClient {
+    pub async fn new(http: reqwest::Client, endpoint_url: Option<&str>) -> Self {
+        let auth = gcp_auth::provider()
+            .await
+            .map_err(|error| format!("failed to load Google credentials: {}", describe(&error)));
+        Self {
+            http,
+            endpoint: endpoint_url
+                .unwrap_or(DEFAULT_ENDPOINT)
+                .trim_end_matches('/')
+                .to_string(),
+            auth,
+        }
+    }
+}
+
+/// Implement the GcpClient methods.
+impl GcpClient {
+    /// Check that Application Default Credentials are found and an access token can be obtained.
+    pub async fn check_credentials(&self) -> Result<(), String> {
+        self.token()
+            .await
+            .map(|_| ())
+            .map_err(|error| error.to_string())
+    }
+
+    /// Get an access token, which the provider caches until it expires.
+    async fn token(&self) -> Result<Arc<Token>, SecretsHelperError> {
+        let auth = self
+            .auth
+            .as_ref()
+            .map_err(|error| SecretsHelperError::Credentials(error.clone()))?;
+        auth.token(&[SCOPE]).await.map_err(|error| {
+            SecretsHelperError::Credentials(format!(
+                "failed to get a Google access token: {}",
+                describe(&error)
+            ))
+        })
+    }
+}
+
+/// Implement the PingEndpoint trait for GcpClient.
+#[async_trait]
+impl PingEndpoint for GcpClient {
+    async fn ping(&self) -> Result<(), String> {
+        // Any response, even an error status, proves that the endpoint is reachable.
+        match self.http.get(&self.endpoint).send().await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(describe(&error)),
+        }
+    }
+}
+
+/// A struct representing the response of the AccessSecretVersion API.
+#[derive(Deserialize)]
+struct AccessSecretVersionResponse {
+    payload: SecretPayload,
+}
+
+/// A struct representing the payload of a secret version.
+#[derive(Deserialize)]
+struct SecretPayload {
+    /// The base64-encoded value.
+    data: String,
+}
+
+/// Implement the GcpFetchSecret trait for GcpClient.
+#[async_trait]
+impl GcpFetchSecret for GcpClient {
+    async fn default_project(&self) -> Result<String, SecretsHelperError> {
+        let auth = self
+            .auth
+            .as_ref()
+            .map_err(|error| SecretsHelperError::Credentials(error.clone()))?;
+        match auth.project_id().await {
+            Ok(project) => Ok(project.to_string()),
+            Err(error) => Err(SecretsHelperError::InvalidRequest(format!(
+                "failed to determine the Google Cloud project: {}",
+                describe(&error)
+            ))),
+        }
+    }
+
+    async fn fetch_secret(&self, name: String) -> FetchedSecret {
+        let token = match self.token().await {
+            Ok(token) => token,
+            Err(error) => return error.into(),
+        };
+        let url = format!("{}/v1/{}:access", self.endpoint, name);
+        let request = self.http.get(url).bearer_auth(token.as_str());
+        http::get_json::<AccessSecretVersionResponse>(request)
+            .await
+            .and_then(|response| http::decode_base64(&response.payload.data))
+            .into()
+    }
+}
+
+/// A struct for loading secrets from Google Cloud Secret Manager.
+pub struct GcpSecretsLoader {
+    client: Box<dyn GcpFetchSecret + Send + Sync>,
+    settings: LoaderSettings,
+}
+
+/// Implement the GcpSecretsLoader constructor.
+impl GcpSecretsLoader {
+    pub fn new(
+        client: impl GcpFetchSecret + Send + Sync + 'static,
+        settings: LoaderSettings,
+    ) -> Self {
+        Self {
+            client: Box::new(client),
+            settings,
+        }
+    }
+}
+
+/// Implement the GcpSecretsLoader methods.
+impl GcpSecretsLoader {
+    /// Get the resource name of the requested secret version. Full resource names are used as is,
+    /// defaulting to the latest version, while short names are resolved in the project and version
+    /// selected with options.
+    async fn resource_name(&self, secret: &RequestedSecret) -> Result<String, FetchedSecret> {
+        if secret.name.starts_with("projects/") {
+            if secret.option("project").is_some() || secret.option("version").is_some() {
+                return Err(invalid_request(String::from(
+                    "options can't be combined with a full resource name",
+                )));
+            }
+            return Ok(if secret.name.contains("/versions/") {
+                secret.name.clone()
+            } else {
+                format!("{}/versions/latest", secret.name)
+            });
+        }
+        let project = match secret.option("project") {
+            Some(project) => project.to_string(),
+            None => self
+                .client
+                .default_project()
+                .await
+                .map_err(FetchedSecret::from)?,
+        };
+        Ok(format!(
+            "projects/{}/secrets/{}/versions/{}",
+            project,
+            secret.name,
+            secret.option("version").unwrap_or("latest")
+        ))
+    }
+
+    /// Fetch a single requested secret, applying the options passed along with its name.
+    async fn fetch(&self, requested: String) -> FetchedSecret {
+        let (requested, yaml_path) = match extract::split_yaml_selector(&requested) {
+            Ok(split) => split,
+            Err(error) => return invalid_request(error),
+        };
+        let secret = match RequestedSecret::parse(requested, &["project", "version"]) {
+            Ok(secret) => secret,
+            Err(error) => return invalid_request(error),
+        };
+        let name = match self.resource_name(&secret).await {
+            Ok(name) => name,
+            Err(fetched_secret) => return fetched_secret,
+        };
+        let fetched_secret = self.client.fetch_secret(name).await;
+        match yaml_path {
+            Some(path) => extract::apply(fetched_secret, |value| extract::yaml_path(value, path)),
+            None => fetched_secret,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for GcpSecretsLoader.
+#[async_trait]
+impl LoadSecrets for GcpSecretsLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        fetch_all(secrets, &self.settings, |requested| self.fetch(requested)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+
+    struct MockGcpFetchSecret {}
+
+    #[async_trait]
+    impl GcpFetchSecret for MockGcpFetchSecret {
+        async fn default_project(&self) -> Result<String, SecretsHelperError> {
+            Ok(String::from("test-project"))
+        }
+
+        async fn fetch_secret(&self, name: String) -> FetchedSecret {
+            // Return the resource name as the value to check how requests are resolved.
+            FetchedSecret {
+                value: Some(Secret::new(name)),
+                error: None,
+                code: None,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn gcp_secrets_loader_resolves_resource_names() {
+        let secrets_loader =
+            GcpSecretsLoader::new(MockGcpFetchSecret {}, LoaderSettings::default());
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("db_password"),
+            String::from("db_password?project=other&version=3"),
+            String::from("projects/p/secrets/s"),
+            String::from("projects/p/secrets/s/versions/2"),
+            String::from("projects/p/secrets/s?version=2"),
+        ]);
+
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        let value = |name: &str| {
+            let secret = &fetched_secrets.0[name];
+            match &secret.value {
+                Some(value) => value.expose_secret().clone(),
+                None => secret.error.clone().unwrap(),
+            }
+        };
+        assert_eq!(
+            value("db_password"),
+            "projects/test-project/secrets/db_password/versions/latest"
+        );
+        assert_eq!(
+            value("db_password?project=other&version=3"),
+            "projects/other/secrets/db_password/versions/3"
+        );
+        assert_eq!(
+            value("projects/p/secrets/s"),
+            "projects/p/secrets/s/versions/latest"
+        );
+        assert_eq!(
+            value("projects/p/secrets/s/versions/2"),
+            "projects/p/secrets/s/versions/2"
+        );
+        assert_eq!(
+            value("projects/p/secrets/s?version=2"),
+            "options can't be combined with a full resource name"
+        );
+    }
+}
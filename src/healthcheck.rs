@@ -0,0 +1,202 @@
+//! This module contains the checks run by the healthcheck subcommand.
+
+use crate::aws::loader::LoadSecrets;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+use crate::error::describe;
+use crate::vector::SecretsToFetch;
+use async_trait::async_trait;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+use aws_config::SdkConfig;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+use aws_credential_types::provider::ProvideCredentials;
+use std::fmt;
+
+/// A trait for checking that the endpoint of a backend is reachable.
+#[async_trait]
+pub trait PingEndpoint {
+    async fn ping(&self) -> Result<(), String>;
+}
+
+/// Implement the PingEndpoint trait for the AWS SDK SSM Parameter Store client.
+#[cfg(feature = "ssm")]
+#[async_trait]
+impl PingEndpoint for aws_sdk_ssm::Client {
+    async fn ping(&self) -> Result<(), String> {
+        match self.describe_parameters().max_results(1).send().await {
+            // Any response from the service, even an access denied error, proves that the endpoint
+            // is reachable.
+            Ok(_) | Err(aws_sdk_ssm::error::SdkError::ServiceError(_)) => Ok(()),
+            Err(error) => Err(describe(&error)),
+        }
+    }
+}
+
+/// Implement the PingEndpoint trait for the AWS SDK Secrets Manager client.
+#[cfg(feature = "secretsmanager")]
+#[async_trait]
+impl PingEndpoint for aws_sdk_secretsmanager::Client {
+    async fn ping(&self) -> Result<(), String> {
+        match self.list_secrets().max_results(1).send().await {
+            // Any response from the service, even an access denied error, proves that the endpoint
+            // is reachable.
+            Ok(_) | Err(aws_sdk_secretsmanager::error::SdkError::ServiceError(_)) => Ok(()),
+            Err(error) => Err(describe(&error)),
+        }
+    }
+}
+
+/// A struct representing the outcome of all health checks.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report(pub Vec<(&'static str, Result<(), String>)>);
+
+/// Implement the Report methods.
+impl Report {
+    /// Whether all checks have passed.
+    pub fn is_healthy(&self) -> bool {
+        self.0.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+/// Implement the Display trait for Report, rendering one line per check.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (check, result) in &self.0 {
+            match result {
+                Ok(()) => writeln!(f, "{}: ok", check)?,
+                Err(error) => writeln!(f, "{}: failed: {}", check, error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that credentials resolve using the default credential provider chain.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+pub async fn check_credentials(aws_sdk_config: &SdkConfig) -> Result<(), String> {
+    match aws_sdk_config.credentials_provider() {
+        Some(provider) => provider
+            .provide_credentials()
+            .await
+            .map(|_| ())
+            .map_err(|error| describe(&error)),
+        None => Err(String::from("no credentials provider configured")),
+    }
+}
+
+/// Check that a canary secret can be read through the secrets loader.
+pub async fn check_canary(loader: &dyn LoadSecrets, name: String) -> Result<(), String> {
+    let secrets_to_fetch = SecretsToFetch {
+        version: String::from("1.0"),
+        secrets: vec![name.clone()],
+    };
+    let mut fetched_secrets = loader.load(secrets_to_fetch).await;
+    match fetched_secrets.0.remove(&name) {
+        Some(secret) => secret
+            .into_result()
+            .map(|_| ())
+            .map_err(|error| error.to_string()),
+        None => Err(String::from("secret not returned")),
+    }
+}
+
+/// Run the endpoint and canary checks, after the credentials check has already been run.
+pub async fn run(
+    credentials: Result<(), String>,
+    endpoint: &dyn PingEndpoint,
+    canary: Option<(&dyn LoadSecrets, String)>,
+) -> Report {
+    let mut report = Report(vec![
+        ("credentials", credentials),
+        ("endpoint", endpoint.ping().await),
+    ]);
+    if let Some((loader, name)) = canary {
+        report.0.push(("canary", check_canary(loader, name).await));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::{FetchedSecret, FetchedSecrets};
+
+    struct MockPingEndpoint {}
+
+    #[async_trait]
+    impl PingEndpoint for MockPingEndpoint {
+        async fn ping(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            let name = secrets.secrets[0].clone();
+            let secret = match name.as_str() {
+                "test.canary" => FetchedSecret {
+                    value: Some(Secret::new("qwerty".to_string())),
+                    error: None,
+                    code: None,
+                },
+                _ => FetchedSecret {
+                    value: None,
+                    error: Some("parameter not found".to_string()),
+                    code: None,
+                },
+            };
+            FetchedSecrets([(name, secret)].iter().cloned().collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn healthcheck_passes_with_readable_canary() {
+        let report = run(
+            Ok(()),
+            &MockPingEndpoint {},
+            Some((&MockLoader {}, String::from("test.canary"))),
+        )
+        .await;
+
+        assert!(report.is_healthy());
+        assert_eq!(
+            report.to_string(),
+            "credentials: ok\nendpoint: ok\ncanary: ok\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn healthcheck_fails_with_unreadable_canary() {
+        let report = run(
+            Ok(()),
+            &MockPingEndpoint {},
+            Some((&MockLoader {}, String::from("test.missing"))),
+        )
+        .await;
+
+        assert!(!report.is_healthy());
+        assert_eq!(
+            report.to_string(),
+            "credentials: ok\nendpoint: ok\ncanary: failed: parameter not found\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn healthcheck_fails_without_credentials() {
+        let report = run(
+            Err(String::from("no credentials")),
+            &MockPingEndpoint {},
+            None,
+        )
+        .await;
+
+        assert!(!report.is_healthy());
+        assert_eq!(
+            report.to_string(),
+            "credentials: failed: no credentials\nendpoint: ok\n"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! This module contains helpers shared by the backends outside of AWS, which are called over plain
+//! HTTPS APIs instead of through an SDK.
+
+use crate::error::{describe, SecretsHelperError};
+use crate::vector::ErrorCode;
+use base64::Engine;
+use reqwest::{RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Classify an HTTP error status into an error code.
+pub fn classify_status(status: StatusCode) -> ErrorCode {
+    match status {
+        StatusCode::NOT_FOUND => ErrorCode::NotFound,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => ErrorCode::AccessDenied,
+        StatusCode::TOO_MANY_REQUESTS => ErrorCode::Throttled,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => ErrorCode::Timeout,
+        StatusCode::BAD_REQUEST => ErrorCode::InvalidRequest,
+        status if status.is_server_error() => ErrorCode::ServiceError,
+        _ => ErrorCode::Unknown,
+    }
+}
+
+/// Classify an error sending a request or reading its response into an error code.
+pub fn classify_error(error: &reqwest::Error) -> ErrorCode {
+    if error.is_timeout() {
+        ErrorCode::Timeout
+    } else if error.is_connect() {
+        ErrorCode::ConnectionFailure
+    } else {
+        ErrorCode::Unknown
+    }
+}
+
+/// Extract the message from the JSON body of an error response, which is either nested in an
+/// `error` object or at the top level depending on the API.
+fn error_message(body: &str) -> Option<String> {
+    let body: Value = serde_json::from_str(body).ok()?;
+    let message = body
+        .pointer("/error/message")
+        .or_else(|| body.get("message"))?;
+    message.as_str().map(str::to_string)
+}
+
+/// Describe an error sending a request or reading its response.
+fn request_error(error: reqwest::Error) -> SecretsHelperError {
+    SecretsHelperError::new(classify_error(&error), describe(&error))
+}
+
+/// Decode a base64-encoded secret value, which must hold UTF-8 text to be passed to Vector.
+#[cfg_attr(not(any(feature = "gcp", feature = "k8s")), allow(dead_code))]
+pub fn decode_base64(data: &str) -> Result<String, SecretsHelperError> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|_| {
+            SecretsHelperError::Decode(String::from("secret payload is not valid base64"))
+        })?;
+    String::from_utf8(data)
+        .map_err(|_| SecretsHelperError::Decode(String::from("secret value is not valid UTF-8")))
+}
+
+/// Send the request and parse the JSON body of a successful response, classifying a failure.
+pub async fn get_json<T: DeserializeOwned>(
+    request: RequestBuilder,
+) -> Result<T, SecretsHelperError> {
+    let response = request.send().await.map_err(request_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        let message = match error_message(&body) {
+            Some(message) => format!("service error: {}: {}", status, message),
+            None => format!("service error: {}", status),
+        };
+        return Err(SecretsHelperError::new(classify_status(status), message));
+    }
+    response.json().await.map_err(request_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_are_classified() {
+        assert_eq!(classify_status(StatusCode::NOT_FOUND), ErrorCode::NotFound);
+        assert_eq!(
+            classify_status(StatusCode::FORBIDDEN),
+            ErrorCode::AccessDenied
+        );
+        assert_eq!(
+            classify_status(StatusCode::TOO_MANY_REQUESTS),
+            ErrorCode::Throttled
+        );
+        assert_eq!(
+            classify_status(StatusCode::SERVICE_UNAVAILABLE),
+            ErrorCode::ServiceError
+        );
+        assert_eq!(classify_status(StatusCode::IM_A_TEAPOT), ErrorCode::Unknown);
+    }
+
+    #[test]
+    fn error_messages_are_extracted_from_either_shape() {
+        assert_eq!(
+            error_message(r#"{"error":{"code":404,"message":"Secret not found"}}"#),
+            Some(String::from("Secret not found"))
+        );
+        assert_eq!(
+            error_message(r#"{"status":404,"message":"item not found"}"#),
+            Some(String::from("item not found"))
+        );
+        assert_eq!(error_message("<html>"), None);
+    }
+
+    #[test]
+    fn payloads_must_be_utf8() {
+        assert_eq!(decode_base64("cXdlcnR5"), Ok(String::from("qwerty")));
+        assert_eq!(
+            decode_base64("/w=="),
+            Err(SecretsHelperError::Decode(String::from(
+                "secret value is not valid UTF-8"
+            )))
+        );
+        assert_eq!(
+            decode_base64("!"),
+            Err(SecretsHelperError::Decode(String::from(
+                "secret payload is not valid base64"
+            )))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! This module contains helpers for reading the secrets to fetch from their source.
+
+use crate::vector::SecretsToFetch;
+use clap::ValueEnum;
+use serde::de::{DeserializeSeed, Deserializer, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::io::BufRead;
+
+/// The formats the request with the secrets to fetch can be read in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum InputFormat {
+    /// The JSON request passed by the Vector exec backend.
+    #[default]
+    Vector,
+    /// One secret name per line, blank lines are ignored.
+    Lines,
+}
+
+/// Read the secrets to fetch from the input in the given format.
+pub fn read(input: impl BufRead, format: InputFormat) -> Result<SecretsToFetch, String> {
+    let mut secrets = Vec::new();
+    let version = read_names(input, format, &mut |name| secrets.push(name))?;
+    Ok(SecretsToFetch { version, secrets })
+}
+
+/// Read the secrets to fetch from the input in the given format, passing every name to the
+/// callback as soon as it's parsed instead of collecting them, and return the version of the
+/// request. Names passed before the input turns out to be invalid must be discarded.
+pub fn read_names(
+    input: impl BufRead,
+    format: InputFormat,
+    on_name: &mut dyn FnMut(String),
+) -> Result<String, String> {
+    match format {
+        InputFormat::Vector => {
+            let mut deserializer = serde_json::Deserializer::from_reader(input);
+            RequestSeed(on_name)
+                .deserialize(&mut deserializer)
+                .and_then(|version| deserializer.end().map(|_| version))
+                .map_err(|_| String::from("failed to parse JSON"))
+        }
+        InputFormat::Lines => {
+            for line in input.lines() {
+                let line = line.map_err(|error| format!("failed to read lines: {}", error))?;
+                let name = line.trim();
+                if !name.is_empty() {
+                    on_name(name.to_string());
+                }
+            }
+            Ok(String::from("1.0"))
+        }
+    }
+}
+
+/// A struct deserializing the JSON request of Vector into its version, passing the names of the
+/// secrets to the callback.
+struct RequestSeed<'a>(&'a mut dyn FnMut(String));
+
+/// Implement the DeserializeSeed trait for RequestSeed.
+impl<'de> DeserializeSeed<'de> for RequestSeed<'_> {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<String, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+/// Implement the Visitor trait for RequestSeed.
+impl<'de> Visitor<'de> for RequestSeed<'_> {
+    type Value = String;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a request with a version and secrets")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<String, A::Error> {
+        let on_name = self.0;
+        let (mut version, mut secrets) = (None, false);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "version" if version.is_some() => return Err(A::Error::duplicate_field("version")),
+                "version" => version = Some(map.next_value()?),
+                "secrets" if secrets => return Err(A::Error::duplicate_field("secrets")),
+                "secrets" => {
+                    map.next_value_seed(NamesSeed(&mut *on_name))?;
+                    secrets = true;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if !secrets {
+            return Err(A::Error::missing_field("secrets"));
+        }
+        version.ok_or_else(|| A::Error::missing_field("version"))
+    }
+}
+
+/// A struct deserializing the secrets of the JSON request, passing every name to the callback.
+struct NamesSeed<'a>(&'a mut dyn FnMut(String));
+
+/// Implement the DeserializeSeed trait for NamesSeed.
+impl<'de> DeserializeSeed<'de> for NamesSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+/// Implement the Visitor trait for NamesSeed.
+impl<'de> Visitor<'de> for NamesSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of secret names")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(name) = seq.next_element()? {
+            (self.0)(name);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_parses_vector_format() {
+        let input = "{\"version\": \"1.0\", \"secrets\": [\"some_secret\"]}".as_bytes();
+        let secrets_to_fetch = read(input, InputFormat::Vector).unwrap();
+        assert_eq!(secrets_to_fetch.secrets, vec!["some_secret"]);
+    }
+
+    #[test]
+    fn read_rejects_invalid_vector_format() {
+        let input = "some_secret\n".as_bytes();
+        assert_eq!(
+            read(input, InputFormat::Vector).unwrap_err(),
+            "failed to parse JSON"
+        );
+    }
+
+    #[test]
+    fn read_names_passes_names_as_they_are_parsed() {
+        let input = r#"{"secrets": ["a", "b"], "extra": {"c": 1}, "version": "1.0"}"#;
+        let mut names = Vec::new();
+        let version = read_names(input.as_bytes(), InputFormat::Vector, &mut |name| {
+            names.push(name)
+        });
+        assert_eq!(version.unwrap(), "1.0");
+        assert_eq!(names, vec!["a", "b"]);
+
+        for input in [
+            r#"{"version": "1.0"}"#,
+            r#"{"version": "1.0", "secrets": ["a"]} trailing"#,
+            r#"{"version": "1.0", "secrets": ["a"], "secrets": ["b"]}"#,
+            r#"{"version": "1.0", "secrets": ["a", 1]}"#,
+        ] {
+            assert_eq!(
+                read_names(input.as_bytes(), InputFormat::Vector, &mut |_| ()).unwrap_err(),
+                "failed to parse JSON"
+            );
+        }
+    }
+
+    #[test]
+    fn read_parses_lines_format() {
+        let input = "/app/db_password\n\n  /app/api_key \n".as_bytes();
+        let secrets_to_fetch = read(input, InputFormat::Lines).unwrap();
+        assert_eq!(secrets_to_fetch.version, "1.0");
+        assert_eq!(
+            secrets_to_fetch.secrets,
+            vec!["/app/db_password", "/app/api_key"]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! This module contains the interactive prompt offered when the helper is started from a terminal
+//! without any secrets to fetch, instead of silently waiting for a request from Vector.
+
+use crate::aws::loader::{with_deadline, LoadSecrets};
+use crate::vector::SecretsToFetch;
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+/// The usage printed before the first prompt.
+const USAGE: &str = "\
+stdin is a terminal, so this isn't a request from Vector. Type the names of secrets to fetch them,
+several separated by spaces, or press Ctrl-D to exit. Values are masked unless --reveal is passed.
+
+Vector sends a JSON request on stdin instead, which can be reproduced with e.g.
+  echo '{\"version\":\"1.0\",\"secrets\":[\"/app/db_password\"]}' | vector-aws-secrets-helper ssm
+or the names can be passed as arguments, e.g. vector-aws-secrets-helper ssm /app/db_password
+
+";
+
+/// The prompt printed before reading every line.
+const PROMPT: &str = "secret> ";
+
+/// The mask printed instead of the values of fetched secrets unless they are revealed.
+const MASK: &str = "********";
+
+/// Print the usage, then fetch the secrets named on every line read until the end of the input,
+/// printing the value or the error of each one. Every line gets the deadline on its own.
+pub async fn prompt(
+    secrets_loader: &(dyn LoadSecrets + Send + Sync),
+    reveal: bool,
+    max_secrets: usize,
+    deadline: Option<Duration>,
+    reader: &mut dyn BufRead,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    write!(writer, "{}", USAGE)?;
+    let mut line = String::new();
+    loop {
+        write!(writer, "{}", PROMPT)?;
+        writer.flush()?;
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            // Finish the prompt line left open by Ctrl-D.
+            writeln!(writer)?;
+            return Ok(());
+        }
+
+        let mut seen = HashSet::new();
+        let names: Vec<String> = line
+            .split_whitespace()
+            .filter(|name| seen.insert(*name))
+            .map(str::to_string)
+            .collect();
+        if names.is_empty() {
+            continue;
+        }
+        let secrets_to_fetch = SecretsToFetch::from_names(names.clone());
+        if let Err(error) = secrets_to_fetch.check_limit(max_secrets) {
+            writeln!(writer, "{}", error)?;
+            continue;
+        }
+        let fetched_secrets = with_deadline(deadline, secrets_loader.load(secrets_to_fetch)).await;
+        for name in names {
+            let fetched_secret = fetched_secrets.0.get(&name);
+            match fetched_secret.and_then(|fetched| fetched.value.as_ref()) {
+                Some(value) if reveal => writeln!(writer, "{}: {}", name, value.expose_secret())?,
+                Some(_) => writeln!(writer, "{}: {}", name, MASK)?,
+                None => {
+                    let error = fetched_secret.and_then(|fetched| fetched.error.as_deref());
+                    let code = fetched_secret.and_then(|fetched| fetched.code);
+                    match code {
+                        Some(code) => write!(writer, "{}: {}: ", name, code.as_str())?,
+                        None => write!(writer, "{}: ", name)?,
+                    }
+                    writeln!(writer, "{}", error.unwrap_or("not fetched"))?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+    use crate::vector::{FetchedSecret, FetchedSecrets};
+    use async_trait::async_trait;
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            let mut fetched_secrets = FetchedSecrets::default();
+            for name in secrets.secrets {
+                let fetched_secret: FetchedSecret = if name.contains("missing") {
+                    SecretsHelperError::NotFound(String::from("not here")).into()
+                } else {
+                    Ok(format!("value of {}", name)).into()
+                };
+                fetched_secrets.0.insert(name, fetched_secret);
+            }
+            fetched_secrets
+        }
+    }
+
+    async fn run_prompt(input: &str, reveal: bool) -> String {
+        let mut output = Vec::new();
+        prompt(
+            &MockLoader {},
+            reveal,
+            2,
+            None,
+            &mut input.as_bytes(),
+            &mut output,
+        )
+        .await
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn prompt_fetches_the_names_typed_on_every_line() {
+        let output = run_prompt("/app/db /app/missing\n\n/a /b /c\n", false).await;
+
+        assert!(output.starts_with(USAGE));
+        assert_eq!(
+            &output[USAGE.len()..],
+            "secret> /app/db: ********\n\
+             /app/missing: NOT_FOUND: not here\n\
+             secret> secret> too many secrets requested: 3 exceeds the limit of 2\n\
+             secret> \n"
+        );
+
+        let output = run_prompt("/app/db /app/db\n", true).await;
+
+        assert_eq!(
+            &output[USAGE.len()..],
+            "secret> /app/db: value of /app/db\nsecret> \n"
+        );
+    }
+}
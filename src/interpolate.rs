@@ -0,0 +1,266 @@
+//! This module contains the expansion of `${VAR}` placeholders in requested secret names from the
+//! environment of the helper, since Vector can't template the names it passes to the helper. Only
+//! the variables allowed with `--interpolate-env` are expanded, so that whoever sends requests
+//! can't read the rest of the environment, e.g. the AWS credentials, through the secret names.
+
+use crate::aws::loader::LoadSecrets;
+use crate::aws::options::invalid_request;
+use crate::vector::{FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+/// Expand the `${VAR}` placeholders in a requested secret name with the values looked up, failing
+/// if a placeholder is malformed or its variable can't be looked up.
+pub fn expand(
+    requested: &str,
+    lookup: impl Fn(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = requested;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("unterminated placeholder in {}", requested));
+        };
+        let variable = &rest[start + 2..start + end];
+        let valid = variable.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && variable
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("invalid placeholder ${{{}}}", variable));
+        }
+        expanded.push_str(&lookup(variable)?);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// A struct holding the environment variables placeholders may be expanded with, as names or as
+/// prefixes ending with `*`, e.g. `DEPLOY_ENV` or `APP_*`. Without any, names are taken literally.
+#[derive(Clone, Debug, Default)]
+pub struct AllowedVariables(Vec<String>);
+
+/// Implement the AllowedVariables constructor.
+impl AllowedVariables {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self(patterns)
+    }
+}
+
+/// Implement the AllowedVariables methods.
+impl AllowedVariables {
+    /// Whether placeholders are expanded at all.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether placeholders may be expanded with the variable.
+    fn allows(&self, variable: &str) -> bool {
+        self.0
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => variable.starts_with(prefix),
+                None => variable == pattern,
+            })
+    }
+
+    /// Look up an allowed variable with the lookup function, failing if it isn't allowed or set.
+    fn lookup(
+        &self,
+        variable: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<String, String> {
+        if !self.allows(variable) {
+            return Err(format!(
+                "environment variable {} isn't allowed by --interpolate-env",
+                variable
+            ));
+        }
+        lookup(variable).ok_or_else(|| format!("environment variable {} is not set", variable))
+    }
+}
+
+/// Expand the placeholders in a requested secret name from the allowed environment variables,
+/// returning the name as is if no variables are allowed.
+pub fn expand_env(requested: &str, allowed: &AllowedVariables) -> Result<String, String> {
+    if allowed.is_empty() {
+        return Ok(requested.to_string());
+    }
+    expand(requested, |variable| {
+        allowed.lookup(variable, |variable| std::env::var(variable).ok())
+    })
+}
+
+/// A struct wrapping a secrets loader to expand the placeholders in the requested names before
+/// fetching them, returning the secrets under the names as requested.
+pub struct InterpolatedLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    allowed: AllowedVariables,
+}
+
+/// Implement the InterpolatedLoader constructor.
+impl InterpolatedLoader {
+    pub fn new(inner: Box<dyn LoadSecrets + Send + Sync>, allowed: AllowedVariables) -> Self {
+        Self { inner, allowed }
+    }
+}
+
+/// Implement the LoadSecrets trait for InterpolatedLoader.
+#[async_trait]
+impl LoadSecrets for InterpolatedLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let mut fetched_secrets = FetchedSecrets::default();
+        let mut requested_names: HashMap<String, Vec<String>> = HashMap::new();
+        for requested in secrets.secrets {
+            match expand_env(&requested, &self.allowed) {
+                Ok(name) => requested_names.entry(name).or_default().push(requested),
+                Err(error) => {
+                    fetched_secrets.0.insert(requested, invalid_request(error));
+                }
+            }
+        }
+        if requested_names.is_empty() {
+            return fetched_secrets;
+        }
+
+        let names = requested_names.keys().cloned().collect();
+        let fetched = self
+            .inner
+            .load(SecretsToFetch {
+                version: secrets.version,
+                secrets: names,
+            })
+            .await;
+        for (name, secret) in fetched.0 {
+            for requested in requested_names.remove(&name).unwrap_or_default() {
+                fetched_secrets.0.insert(requested, secret.clone());
+            }
+        }
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        let name = expand_env(requested, &self.allowed).ok()?;
+        self.inner.current_version(&name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::ErrorCode;
+
+    fn env(variable: &str) -> Option<String> {
+        match variable {
+            "DEPLOY_ENV" => Some(String::from("prod")),
+            "REGION_1" => Some(String::from("eu-west-1")),
+            "AWS_SECRET_ACCESS_KEY" => Some(String::from("wJalrXUtnFEMI")),
+            _ => None,
+        }
+    }
+
+    fn lookup(variable: &str) -> Result<String, String> {
+        let allowed = AllowedVariables::new(vec![
+            String::from("DEPLOY_ENV"),
+            String::from("REGION_*"),
+            String::from("MISSING"),
+        ]);
+        allowed.lookup(variable, env)
+    }
+
+    #[test]
+    fn placeholders_are_expanded() {
+        assert_eq!(
+            expand("/app/${DEPLOY_ENV}/db_password", lookup),
+            Ok(String::from("/app/prod/db_password"))
+        );
+        assert_eq!(
+            expand("${DEPLOY_ENV}-${REGION_1}/$key", lookup),
+            Ok(String::from("prod-eu-west-1/$key"))
+        );
+        assert_eq!(
+            expand("no_placeholders", lookup),
+            Ok(String::from("no_placeholders"))
+        );
+    }
+
+    #[test]
+    fn invalid_placeholders_are_rejected() {
+        assert_eq!(
+            expand("/app/${MISSING}/db", lookup),
+            Err(String::from("environment variable MISSING is not set"))
+        );
+        assert_eq!(
+            expand("/app/${DEPLOY_ENV", lookup),
+            Err(String::from(
+                "unterminated placeholder in /app/${DEPLOY_ENV"
+            ))
+        );
+        assert_eq!(
+            expand("/app/${1ENV}", lookup),
+            Err(String::from("invalid placeholder ${1ENV}"))
+        );
+        assert_eq!(
+            expand("/app/${}", lookup),
+            Err(String::from("invalid placeholder ${}"))
+        );
+    }
+
+    #[test]
+    fn only_allowed_variables_are_expanded() {
+        assert_eq!(
+            expand("/app/${AWS_SECRET_ACCESS_KEY}", lookup),
+            Err(String::from(
+                "environment variable AWS_SECRET_ACCESS_KEY isn't allowed by --interpolate-env"
+            ))
+        );
+        assert_eq!(
+            expand_env("/app/${DEPLOY_ENV}/db", &AllowedVariables::default()),
+            Ok(String::from("/app/${DEPLOY_ENV}/db"))
+        );
+    }
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| (name.clone(), Ok(format!("value of {}", name)).into()))
+                    .collect(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn interpolated_loader_returns_secrets_under_the_requested_names() {
+        std::env::set_var("INTERPOLATE_TEST_ENV", "staging");
+        let allowed = AllowedVariables::new(vec![String::from("INTERPOLATE_TEST_*")]);
+        let loader = InterpolatedLoader::new(Box::new(MockLoader {}), allowed);
+
+        let fetched_secrets = loader
+            .load(SecretsToFetch::from_names(vec![
+                String::from("/app/${INTERPOLATE_TEST_ENV}/db"),
+                String::from("/app/staging/db"),
+                String::from("/app/${INTERPOLATE_TEST_MISSING}/db"),
+            ]))
+            .await;
+
+        for name in ["/app/${INTERPOLATE_TEST_ENV}/db", "/app/staging/db"] {
+            assert_eq!(
+                fetched_secrets.0[name].value,
+                Some(Secret::new(String::from("value of /app/staging/db")))
+            );
+        }
+        assert_eq!(
+            fetched_secrets.0["/app/${INTERPOLATE_TEST_MISSING}/db"].code,
+            Some(ErrorCode::InvalidRequest)
+        );
+    }
+}
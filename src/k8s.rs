@@ -0,0 +1,727 @@
+//! This module contains the secrets loader implementation for Kubernetes Secrets.
+
+use crate::aws::loader::{fetch_all, LoadSecrets, LoaderSettings};
+use crate::aws::options::invalid_request;
+use crate::error::{describe, SecretsHelperError};
+use crate::extract;
+use crate::healthcheck::PingEndpoint;
+use crate::http;
+use crate::vector::{FetchedSecret, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// The directory the service account of a pod is mounted at.
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+/// Tokens of exec plugins are refreshed this long before they expire.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// A trait for fetching a single Secret from the Kubernetes API.
+#[async_trait]
+pub trait K8sFetchSecret {
+    /// Get the namespace secrets are fetched from unless the request names one.
+    async fn default_namespace(&self) -> Result<String, SecretsHelperError>;
+
+    /// Fetch the base64-encoded data of the Secret in the namespace.
+    async fn fetch_secret(
+        &self,
+        namespace: String,
+        name: String,
+    ) -> Result<BTreeMap<String, String>, SecretsHelperError>;
+}
+
+/// A struct representing a kubeconfig file, of which only the current context is used.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Kubeconfig {
+    current_context: Option<String>,
+    #[serde(default)]
+    clusters: Vec<NamedCluster>,
+    #[serde(default)]
+    contexts: Vec<NamedContext>,
+    #[serde(default)]
+    users: Vec<NamedUser>,
+}
+
+/// A struct representing a named cluster of a kubeconfig file.
+#[derive(Deserialize)]
+struct NamedCluster {
+    name: String,
+    cluster: KubeconfigCluster,
+}
+
+/// A struct representing how to reach the API server of a cluster.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct KubeconfigCluster {
+    server: String,
+    certificate_authority: Option<PathBuf>,
+    certificate_authority_data: Option<String>,
+    #[serde(default)]
+    insecure_skip_tls_verify: bool,
+}
+
+/// A struct representing a named context of a kubeconfig file.
+#[derive(Deserialize)]
+struct NamedContext {
+    name: String,
+    context: KubeconfigContext,
+}
+
+/// A struct representing the cluster, user and namespace selected by a context.
+#[derive(Deserialize)]
+struct KubeconfigContext {
+    cluster: String,
+    user: String,
+    namespace: Option<String>,
+}
+
+/// A struct representing a named user of a kubeconfig file.
+#[derive(Deserialize)]
+struct NamedUser {
+    name: String,
+    user: KubeconfigUser,
+}
+
+/// A struct representing how a user authenticates, with a bearer token, a client certificate or
+/// an exec plugin such as `aws eks get-token`.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct KubeconfigUser {
+    token: Option<String>,
+    #[serde(rename = "tokenFile")]
+    token_file: Option<PathBuf>,
+    client_certificate: Option<PathBuf>,
+    client_certificate_data: Option<String>,
+    client_key: Option<PathBuf>,
+    client_key_data: Option<String>,
+    exec: Option<ExecConfig>,
+}
+
+/// A struct representing an exec plugin printing an ExecCredential with a token.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExecConfig {
+    api_version: String,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: Option<Vec<ExecEnvVar>>,
+}
+
+/// A struct representing an environment variable passed to an exec plugin.
+#[derive(Clone, Deserialize)]
+struct ExecEnvVar {
+    name: String,
+    value: String,
+}
+
+/// A struct representing the ExecCredential printed by an exec plugin.
+#[derive(Deserialize)]
+struct ExecCredential {
+    status: ExecCredentialStatus,
+}
+
+/// A struct representing the token issued by an exec plugin.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExecCredentialStatus {
+    token: Option<String>,
+    expiration_timestamp: Option<String>,
+}
+
+/// An enum representing how requests to the API server are authenticated, besides a client
+/// certificate which is configured on the HTTP client.
+enum Auth {
+    None,
+    Token(String),
+    /// A token read again for every request, since projected service account tokens rotate.
+    TokenFile(PathBuf),
+    Exec(ExecConfig),
+}
+
+/// A struct representing a cluster resolved from the in-cluster service account or a kubeconfig.
+struct Cluster {
+    http: reqwest::Client,
+    server: String,
+    namespace: String,
+    auth: Auth,
+}
+
+/// Implement the Cluster methods.
+impl Cluster {
+    /// Resolve the cluster of the pod the helper runs in, falling back to the current context of
+    /// the kubeconfig outside of a cluster.
+    fn load() -> Result<Self, String> {
+        match (
+            std::env::var("KUBERNETES_SERVICE_HOST"),
+            std::env::var("KUBERNETES_SERVICE_PORT"),
+        ) {
+            (Ok(host), Ok(port)) => Self::in_cluster(&host, &port),
+            _ => Self::from_kubeconfig(),
+        }
+    }
+
+    /// Resolve the cluster with the service account mounted into the pod.
+    fn in_cluster(host: &str, port: &str) -> Result<Self, String> {
+        let dir = Path::new(SERVICE_ACCOUNT_DIR);
+        let ca = read(&dir.join("ca.crt"))?;
+        let ca = reqwest::Certificate::from_pem(&ca).map_err(|error| {
+            format!(
+                "invalid service account CA certificate: {}",
+                describe(&error)
+            )
+        })?;
+        let namespace = std::fs::read_to_string(dir.join("namespace"))
+            .map(|namespace| namespace.trim().to_string())
+            .unwrap_or_else(|_| String::from("default"));
+        // IPv6 addresses must be bracketed in URLs.
+        let host = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host.to_string()
+        };
+        Ok(Self {
+            http: build_http(reqwest::Client::builder().add_root_certificate(ca))?,
+            server: format!("https://{}:{}", host, port),
+            namespace,
+            auth: Auth::TokenFile(dir.join("token")),
+        })
+    }
+
+    /// Resolve the cluster of the current context of the kubeconfig at `KUBECONFIG`, or
+    /// `~/.kube/config` if unset. Only the first file of a `KUBECONFIG` list is read.
+    fn from_kubeconfig() -> Result<Self, String> {
+        let path = match std::env::var_os("KUBECONFIG").filter(|paths| !paths.is_empty()) {
+            Some(paths) => std::env::split_paths(&paths).next().unwrap_or_default(),
+            None => match std::env::var_os("HOME") {
+                Some(home) => Path::new(&home).join(".kube").join("config"),
+                None => return Err(String::from("no in-cluster service account or kubeconfig")),
+            },
+        };
+        let contents = std::fs::read_to_string(&path).map_err(|error| {
+            format!(
+                "no in-cluster service account and failed to read the kubeconfig {}: {}",
+                path.display(),
+                error
+            )
+        })?;
+        let kubeconfig: Kubeconfig = serde_yaml::from_str(&contents)
+            .map_err(|error| format!("invalid kubeconfig {}: {}", path.display(), error))?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        Self::from_context(kubeconfig, base_dir)
+    }
+
+    /// Resolve the cluster of the current context, with relative paths in the kubeconfig resolved
+    /// against its directory.
+    fn from_context(kubeconfig: Kubeconfig, base_dir: &Path) -> Result<Self, String> {
+        let context_name = kubeconfig
+            .current_context
+            .ok_or_else(|| String::from("kubeconfig has no current context"))?;
+        let context = kubeconfig
+            .contexts
+            .into_iter()
+            .find(|context| context.name == context_name)
+            .ok_or_else(|| format!("kubeconfig has no context {}", context_name))?
+            .context;
+        let cluster = kubeconfig
+            .clusters
+            .into_iter()
+            .find(|cluster| cluster.name == context.cluster)
+            .ok_or_else(|| format!("kubeconfig has no cluster {}", context.cluster))?
+            .cluster;
+        // A user without any credentials is valid, e.g. behind `kubectl proxy`.
+        let user = kubeconfig
+            .users
+            .into_iter()
+            .find(|user| user.name == context.user)
+            .map(|user| user.user)
+            .unwrap_or_default();
+
+        let mut builder = reqwest::Client::builder();
+        let ca = match (
+            cluster.certificate_authority_data,
+            cluster.certificate_authority,
+        ) {
+            (Some(data), _) => Some(decode(&data)?),
+            (None, Some(path)) => Some(read(&base_dir.join(path))?),
+            (None, None) => None,
+        };
+        if let Some(ca) = ca {
+            let ca = reqwest::Certificate::from_pem(&ca)
+                .map_err(|error| format!("invalid cluster CA certificate: {}", describe(&error)))?;
+            builder = builder.add_root_certificate(ca);
+        }
+        if cluster.insecure_skip_tls_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let certificate = match (user.client_certificate_data, user.client_certificate) {
+            (Some(data), _) => Some(decode(&data)?),
+            (None, Some(path)) => Some(read(&base_dir.join(path))?),
+            (None, None) => None,
+        };
+        let key = match (user.client_key_data, user.client_key) {
+            (Some(data), _) => Some(decode(&data)?),
+            (None, Some(path)) => Some(read(&base_dir.join(path))?),
+            (None, None) => None,
+        };
+        if let (Some(mut pem), Some(key)) = (certificate, key) {
+            pem.extend_from_slice(&key);
+            let identity = reqwest::Identity::from_pem(&pem)
+                .map_err(|error| format!("invalid client certificate: {}", describe(&error)))?;
+            builder = builder.identity(identity);
+        }
+
+        let auth = match (user.token, user.token_file, user.exec) {
+            (Some(token), _, _) => Auth::Token(token),
+            (None, Some(path), _) => Auth::TokenFile(base_dir.join(path)),
+            (None, None, Some(mut exec)) => {
+                // Like kubectl, commands with a path but not in the PATH are relative to the
+                // kubeconfig.
+                if exec.command.contains(std::path::MAIN_SEPARATOR) {
+                    exec.command = base_dir.join(&exec.command).display().to_string();
+                }
+                Auth::Exec(exec)
+            }
+            (None, None, None) => Auth::None,
+        };
+        Ok(Self {
+            http: build_http(builder)?,
+            server: cluster.server.trim_end_matches('/').to_string(),
+            namespace: context.namespace.unwrap_or_else(|| String::from("default")),
+            auth,
+        })
+    }
+}
+
+/// Read a file referenced by the cluster config.
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|error| format!("failed to read {}: {}", path.display(), error))
+}
+
+/// Decode base64-encoded data embedded in a kubeconfig.
+fn decode(data: &str) -> Result<Vec<u8>, String> {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|error| format!("invalid base64 data in the kubeconfig: {}", error))
+}
+
+/// Build the HTTP client trusting the cluster CA and presenting the client certificate.
+fn build_http(builder: reqwest::ClientBuilder) -> Result<reqwest::Client, String> {
+    builder
+        .build()
+        .map_err(|error| format!("failed to create the HTTP client: {}", describe(&error)))
+}
+
+/// Run the exec plugin and get the token of the ExecCredential it prints, along with when the
+/// token expires if the plugin says so.
+async fn exec_token(exec: &ExecConfig) -> Result<(String, Option<Instant>), String> {
+    let mut command = tokio::process::Command::new(&exec.command);
+    command.args(&exec.args);
+    for var in exec.env.iter().flatten() {
+        command.env(&var.name, &var.value);
+    }
+    // Plugins read the API version and whether they may prompt from this variable.
+    command.env(
+        "KUBERNETES_EXEC_INFO",
+        serde_json::json!({
+            "apiVersion": exec.api_version,
+            "kind": "ExecCredential",
+            "spec": {"interactive": false},
+        })
+        .to_string(),
+    );
+    command.stdin(std::process::Stdio::null());
+    let output = command
+        .output()
+        .await
+        .map_err(|error| format!("failed to run {}: {}", exec.command, error))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed with {}: {}",
+            exec.command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let credential: ExecCredential = serde_json::from_slice(&output.stdout)
+        .map_err(|error| format!("invalid ExecCredential from {}: {}", exec.command, error))?;
+    let token = credential
+        .status
+        .token
+        .ok_or_else(|| format!("ExecCredential from {} has no token", exec.command))?;
+    let expires_at = match credential.status.expiration_timestamp {
+        Some(timestamp) => {
+            let expiration = humantime::parse_rfc3339_weak(&timestamp)
+                .map_err(|error| format!("invalid ExecCredential expiration: {}", error))?;
+            let lifetime = expiration
+                .duration_since(SystemTime::now())
+                .unwrap_or_default();
+            Some(Instant::now() + lifetime)
+        }
+        None => None,
+    };
+    Ok((token, expires_at))
+}
+
+/// A token of an exec plugin and when it expires, if ever.
+type CachedToken = (String, Option<Instant>);
+
+/// A struct for calling the Kubernetes API, caching the token of an exec plugin until shortly
+/// before it expires.
+#[derive(Clone)]
+pub struct K8sClient {
+    /// The cluster, or why none could be resolved. A missing cluster config is reported for every
+    /// fetched secret, like missing credentials are for AWS, instead of failing to start.
+    cluster: Result<Arc<Cluster>, String>,
+    token: Arc<Mutex<Option<CachedToken>>>,
+}
+
+/// Implement the K8sClient constructor.
+impl K8sClient {
+    pub fn new(endpoint_url: Option<&str>) -> Self {
+        let cluster = Cluster::load().map(|mut cluster| {
+            if let Some(endpoint_url) = endpoint_url {
+                cluster.server = endpoint_url.trim_end_matches('/').to_string();
+            }
+            Arc::new(cluster)
+        });
+        Self {
+            cluster,
+            token: Default::default(),
+        }
+    }
+}
+
+/// Implement the K8sClient methods.
+impl K8sClient {
+    /// Check that the cluster config resolves and a token can be obtained.
+    pub async fn check_credentials(&self) -> Result<(), String> {
+        let cluster = self.cluster()?;
+        self.token(cluster).await.map(|_| ())
+    }
+
+    /// Get the resolved cluster.
+    fn cluster(&self) -> Result<&Cluster, String> {
+        self.cluster.as_deref().map_err(Clone::clone)
+    }
+
+    /// Get the bearer token of requests, if the cluster is authenticated with one.
+    async fn token(&self, cluster: &Cluster) -> Result<Option<String>, String> {
+        match &cluster.auth {
+            Auth::None => Ok(None),
+            Auth::Token(token) => Ok(Some(token.clone())),
+            Auth::TokenFile(path) => std::fs::read_to_string(path)
+                .map(|token| Some(token.trim().to_string()))
+                .map_err(|error| format!("failed to read {}: {}", path.display(), error)),
+            Auth::Exec(exec) => {
+                // The lock is held while running the plugin, so that concurrent fetches share it.
+                let mut cached = self.token.lock().await;
+                if let Some((token, expires_at)) = cached.as_ref() {
+                    let fresh = match expires_at {
+                        Some(expires_at) => Instant::now() + TOKEN_REFRESH_MARGIN < *expires_at,
+                        None => true,
+                    };
+                    if fresh {
+                        return Ok(Some(token.clone()));
+                    }
+                }
+                let (token, expires_at) = exec_token(exec).await?;
+                *cached = Some((token.clone(), expires_at));
+                Ok(Some(token))
+            }
+        }
+    }
+}
+
+/// Implement the PingEndpoint trait for K8sClient.
+#[async_trait]
+impl PingEndpoint for K8sClient {
+    async fn ping(&self) -> Result<(), String> {
+        let cluster = self.cluster()?;
+        // Any response, even an unauthorized one, proves that the API server is reachable.
+        match cluster
+            .http
+            .get(format!("{}/version", cluster.server))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => Err(describe(&error)),
+        }
+    }
+}
+
+/// A struct representing the response of reading a Secret.
+#[derive(Deserialize)]
+struct SecretResponse {
+    /// The base64-encoded values by key, missing if the Secret is empty.
+    #[serde(default)]
+    data: BTreeMap<String, String>,
+}
+
+/// Implement the K8sFetchSecret trait for K8sClient.
+#[async_trait]
+impl K8sFetchSecret for K8sClient {
+    async fn default_namespace(&self) -> Result<String, SecretsHelperError> {
+        match self.cluster() {
+            Ok(cluster) => Ok(cluster.namespace.clone()),
+            Err(error) => Err(SecretsHelperError::Credentials(error)),
+        }
+    }
+
+    async fn fetch_secret(
+        &self,
+        namespace: String,
+        name: String,
+    ) -> Result<BTreeMap<String, String>, SecretsHelperError> {
+        let cluster = self.cluster().map_err(SecretsHelperError::Credentials)?;
+        let token = self
+            .token(cluster)
+            .await
+            .map_err(SecretsHelperError::Credentials)?;
+        let url = format!(
+            "{}/api/v1/namespaces/{}/secrets/{}",
+            cluster.server, namespace, name
+        );
+        let mut request = cluster.http.get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = http::get_json::<SecretResponse>(request).await?;
+        Ok(response.data)
+    }
+}
+
+/// A struct representing a requested secret in the form `namespace/secret-name#key`, where the
+/// namespace defaults to the one of the cluster config and the key may be left out of Secrets
+/// with a single key.
+#[derive(Debug, PartialEq)]
+struct SecretReference<'a> {
+    namespace: Option<&'a str>,
+    name: &'a str,
+    key: Option<&'a str>,
+}
+
+/// Implement the SecretReference methods.
+impl<'a> SecretReference<'a> {
+    fn parse(requested: &'a str) -> Result<Self, String> {
+        let (path, key) = match requested.split_once('#') {
+            Some((path, key)) => (path, Some(key)),
+            None => (requested, None),
+        };
+        let (namespace, name) = match path.split_once('/') {
+            Some((namespace, name)) => (Some(namespace), name),
+            None => (None, path),
+        };
+        if namespace == Some("") || name.is_empty() || name.contains('/') || key == Some("") {
+            return Err(format!(
+                "invalid secret {}, expected namespace/secret-name#key",
+                requested
+            ));
+        }
+        Ok(Self {
+            namespace,
+            name,
+            key,
+        })
+    }
+}
+
+/// Select the value of the key from the data of a Secret.
+fn select_key(data: BTreeMap<String, String>, key: Option<&str>) -> FetchedSecret {
+    let value = match key {
+        Some(key) => data.get(key),
+        None if data.len() == 1 => data.values().next(),
+        None => {
+            return invalid_request(format!(
+                "secret has {} keys, select one with #key",
+                data.len()
+            ))
+        }
+    };
+    match value {
+        Some(value) => http::decode_base64(value).into(),
+        None => {
+            SecretsHelperError::NotFound(format!("secret has no key {}", key.unwrap_or_default()))
+                .into()
+        }
+    }
+}
+
+/// A struct for loading secrets from Kubernetes Secrets.
+pub struct K8sSecretsLoader {
+    client: Box<dyn K8sFetchSecret + Send + Sync>,
+    settings: LoaderSettings,
+}
+
+/// Implement the K8sSecretsLoader constructor.
+impl K8sSecretsLoader {
+    pub fn new(
+        client: impl K8sFetchSecret + Send + Sync + 'static,
+        settings: LoaderSettings,
+    ) -> Self {
+        Self {
+            client: Box::new(client),
+            settings,
+        }
+    }
+}
+
+/// Implement the K8sSecretsLoader methods.
+impl K8sSecretsLoader {
+    /// Fetch a single requested secret, applying the `#yaml:` selector following the key.
+    async fn fetch(&self, requested: String) -> FetchedSecret {
+        let (requested, yaml_path) = match requested.rsplit_once("#yaml:") {
+            Some((requested, path)) => (requested, Some(path)),
+            None => (requested.as_str(), None),
+        };
+        let reference = match SecretReference::parse(requested) {
+            Ok(reference) => reference,
+            Err(error) => return invalid_request(error),
+        };
+        let namespace = match reference.namespace {
+            Some(namespace) => namespace.to_string(),
+            None => match self.client.default_namespace().await {
+                Ok(namespace) => namespace,
+                Err(error) => return error.into(),
+            },
+        };
+        let fetched_secret = match self
+            .client
+            .fetch_secret(namespace, reference.name.to_string())
+            .await
+        {
+            Ok(data) => select_key(data, reference.key),
+            Err(error) => return error.into(),
+        };
+        match yaml_path {
+            Some(path) => extract::apply(fetched_secret, |value| extract::yaml_path(value, path)),
+            None => fetched_secret,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for K8sSecretsLoader.
+#[async_trait]
+impl LoadSecrets for K8sSecretsLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        fetch_all(secrets, &self.settings, |requested| self.fetch(requested)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::ErrorCode;
+
+    struct MockK8sFetchSecret {}
+
+    #[async_trait]
+    impl K8sFetchSecret for MockK8sFetchSecret {
+        async fn default_namespace(&self) -> Result<String, SecretsHelperError> {
+            Ok(String::from("vector"))
+        }
+
+        async fn fetch_secret(
+            &self,
+            namespace: String,
+            name: String,
+        ) -> Result<BTreeMap<String, String>, SecretsHelperError> {
+            match (namespace.as_str(), name.as_str()) {
+                // "admin" and "hunter2".
+                ("vector", "db") => Ok(BTreeMap::from([
+                    (String::from("username"), String::from("YWRtaW4=")),
+                    (String::from("password"), String::from("aHVudGVyMg==")),
+                    // "user: admin".
+                    (String::from("config"), String::from("dXNlcjogYWRtaW4=")),
+                ])),
+                // "qwerty".
+                ("monitoring", "token") => Ok(BTreeMap::from([(
+                    String::from("token"),
+                    String::from("cXdlcnR5"),
+                )])),
+                _ => Err(SecretsHelperError::NotFound(String::from(
+                    "service error: 404 Not Found",
+                ))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn k8s_secrets_loader_selects_keys() {
+        let secrets_loader =
+            K8sSecretsLoader::new(MockK8sFetchSecret {}, LoaderSettings::default());
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("vector/db#password"),
+            String::from("db#username"),
+            String::from("monitoring/token"),
+            String::from("db#config#yaml:user"),
+            String::from("db"),
+            String::from("db#missing"),
+            String::from("other/db#password"),
+            String::from("a/b/c#key"),
+        ]);
+
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        let secret = |name: &str| &fetched_secrets.0[name];
+        assert_eq!(
+            secret("vector/db#password").value,
+            Some(Secret::new(String::from("hunter2")))
+        );
+        assert_eq!(
+            secret("db#username").value,
+            Some(Secret::new(String::from("admin")))
+        );
+        assert_eq!(
+            secret("monitoring/token").value,
+            Some(Secret::new(String::from("qwerty")))
+        );
+        assert_eq!(
+            secret("db#config#yaml:user").value,
+            Some(Secret::new(String::from("admin")))
+        );
+        assert_eq!(secret("db").code, Some(ErrorCode::InvalidRequest));
+        assert_eq!(secret("db#missing").code, Some(ErrorCode::NotFound));
+        assert_eq!(secret("other/db#password").code, Some(ErrorCode::NotFound));
+        assert_eq!(secret("a/b/c#key").code, Some(ErrorCode::InvalidRequest));
+    }
+
+    #[test]
+    fn kubeconfig_current_context_is_resolved() {
+        let kubeconfig: Kubeconfig = serde_yaml::from_str(
+            r#"
+current-context: dev
+clusters:
+  - name: dev-cluster
+    cluster:
+      server: https://dev.example.com:6443/
+      insecure-skip-tls-verify: true
+contexts:
+  - name: prod
+    context: {cluster: prod-cluster, user: prod-user}
+  - name: dev
+    context: {cluster: dev-cluster, user: dev-user, namespace: vector}
+users:
+  - name: dev-user
+    user: {token: abc}
+"#,
+        )
+        .unwrap();
+
+        let cluster = Cluster::from_context(kubeconfig, Path::new("/")).unwrap();
+
+        assert_eq!(cluster.server, "https://dev.example.com:6443");
+        assert_eq!(cluster.namespace, "vector");
+        assert!(matches!(cluster.auth, Auth::Token(token) if token == "abc"));
+    }
+}
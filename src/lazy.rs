@@ -0,0 +1,134 @@
+//! This module contains the secrets loader created the first time a request reaches it, so that
+//! requests answered without the backend, e.g. empty ones or those served from the disk cache,
+//! don't pay for loading credentials and creating clients.
+
+use crate::aws::loader::{before_deadline, LoadSecrets};
+use crate::vector::{FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::future::Future;
+use std::sync::Arc;
+
+/// A type alias for the secrets loaders wrapped by the other loaders.
+type Loader = Box<dyn LoadSecrets + Send + Sync>;
+
+/// A struct wrapping the creation of a secrets loader, which is only awaited once a request with
+/// secrets needs it.
+pub struct LazyLoader {
+    loader: Shared<BoxFuture<'static, Arc<Loader>>>,
+}
+
+/// Implement the LazyLoader constructor.
+impl LazyLoader {
+    pub fn new(create: impl Future<Output = Loader> + Send + 'static) -> Self {
+        Self {
+            loader: create.map(Arc::new).boxed().shared(),
+        }
+    }
+}
+
+/// Implement the LazyLoader methods.
+impl LazyLoader {
+    /// Get the loader, creating it if this is the first time it's needed. Concurrent requests wait
+    /// for the same creation, which carries on where it was if a request stops waiting for it.
+    async fn loader(&self) -> Arc<Loader> {
+        self.loader.clone().await
+    }
+}
+
+/// Implement the LoadSecrets trait for LazyLoader.
+#[async_trait]
+impl LoadSecrets for LazyLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        if secrets.secrets.is_empty() {
+            return FetchedSecrets::default();
+        }
+        // Creating the loader, e.g. resolving credentials, counts towards the deadline.
+        match before_deadline(self.loader()).await {
+            Ok(loader) => loader.load(secrets).await,
+            Err(error) => FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| (name, error.clone().into()))
+                    .collect(),
+            ),
+        }
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        before_deadline(self.loader())
+            .await
+            .ok()?
+            .current_version(requested)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::loader::with_deadline;
+    use crate::vector::ErrorCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| (name, Ok(String::from("value")).into()))
+                    .collect(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn loaders_are_created_once_secrets_are_requested() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let loader = LazyLoader::new({
+            let created = created.clone();
+            async move {
+                created.fetch_add(1, Ordering::Relaxed);
+                Box::new(MockLoader {}) as Loader
+            }
+        });
+
+        let fetched_secrets = loader.load(SecretsToFetch::from_names(Vec::new())).await;
+        assert!(fetched_secrets.0.is_empty());
+        assert_eq!(created.load(Ordering::Relaxed), 0);
+
+        for _ in 0..2 {
+            let fetched_secrets = loader
+                .load(SecretsToFetch::from_names(vec![String::from("a")]))
+                .await;
+            assert_eq!(fetched_secrets.0.len(), 1);
+        }
+        assert_eq!(created.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn creating_loaders_counts_towards_the_deadline() {
+        let loader = LazyLoader::new(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Box::new(MockLoader {}) as Loader
+        });
+        let secrets_to_fetch = || SecretsToFetch::from_names(vec![String::from("a")]);
+
+        let deadline = Some(Duration::from_millis(20));
+        let fetched_secrets = with_deadline(deadline, loader.load(secrets_to_fetch())).await;
+        assert_eq!(
+            fetched_secrets.0["a"].code,
+            Some(ErrorCode::DeadlineExceeded)
+        );
+
+        // The creation carries on for the next request.
+        let fetched_secrets = loader.load(secrets_to_fetch()).await;
+        assert_eq!(fetched_secrets.0["a"].error, None);
+    }
+}
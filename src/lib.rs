@@ -0,0 +1,3833 @@
+//! A helper fetching the secrets referenced in Vector configs from AWS and other backends. The
+//! binary runs it against stdin and stdout, while applications embedding it call [`run`] with
+//! their own arguments, reader and writer.
+
+use crate::aws::loader::{LoadSecrets, LoaderSettings};
+use crate::diagnostics::{Diagnostic, Level};
+use aws_config::{AppName, SdkConfig};
+#[cfg(feature = "secretsmanager")]
+use aws_sdk_secretsmanager::Client as SecretsManagerClient;
+#[cfg(feature = "ssm")]
+use aws_sdk_ssm::Client as SsmClient;
+use aws_sdk_sts::config::Region;
+use aws_sdk_sts::Client as StsClient;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+use aws_smithy_types::retry::RetryConfig;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+use aws_smithy_types::timeout::TimeoutConfig;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+#[cfg(feature = "ssm")]
+use std::time::SystemTime;
+
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+mod accounts;
+mod audit;
+mod aws;
+#[cfg(feature = "azure")]
+mod azure;
+mod bench;
+mod breaker;
+mod cache;
+mod config;
+mod connection;
+mod diagnostics;
+mod diff;
+mod emf;
+mod error;
+mod exit;
+mod expand;
+mod extract;
+#[cfg(feature = "gcp")]
+mod gcp;
+mod healthcheck;
+#[cfg(any(feature = "gcp", feature = "azure", feature = "k8s", feature = "op"))]
+mod http;
+mod input;
+mod interactive;
+mod interpolate;
+#[cfg(feature = "k8s")]
+mod k8s;
+mod lazy;
+mod metrics;
+#[cfg(all(feature = "ssm", feature = "secretsmanager"))]
+mod multi;
+#[cfg(feature = "op")]
+mod op;
+mod optional;
+mod output;
+mod pacing;
+mod render;
+mod retry;
+mod scan;
+mod secret;
+mod server;
+mod validate;
+mod vector;
+
+pub use error::SecretsHelperError;
+pub use vector::ErrorCode;
+
+#[cfg(not(any(
+    feature = "ssm",
+    feature = "secretsmanager",
+    feature = "gcp",
+    feature = "azure",
+    feature = "k8s",
+    feature = "op"
+)))]
+compile_error!(
+    "at least one of the ssm, secretsmanager, gcp, azure, k8s and op features must be enabled"
+);
+
+/// A helper tool for Vector to retrieve secrets from AWS SSM Parameter Store and AWS Secrets
+/// Manager using the exec backend.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+/// Arguments shared by all commands.
+#[derive(Args, Clone)]
+struct GlobalArgs {
+    /// Change endpoint URL for the command.
+    #[arg(short, long)]
+    endpoint_url: Option<String>,
+    /// Change the endpoint URL of SSM Parameter Store, taking precedence over --endpoint-url, e.g.
+    /// when fetching from both AWS backends.
+    #[cfg(feature = "ssm")]
+    #[arg(long, value_name = "URL")]
+    ssm_endpoint_url: Option<String>,
+    /// Change the endpoint URL of Secrets Manager, taking precedence over --endpoint-url, e.g.
+    /// when fetching from both AWS backends.
+    #[cfg(feature = "secretsmanager")]
+    #[arg(long, value_name = "URL")]
+    secretsmanager_endpoint_url: Option<String>,
+    /// Change the endpoint URL of AWS STS, used to assume roles and to get the caller identity.
+    #[arg(long, value_name = "URL")]
+    sts_endpoint_url: Option<String>,
+    /// Assume this role before fetching secrets. Repeat it to assume each role with the credentials
+    /// of the previous one, overriding the role chain of the config file.
+    #[arg(long, value_name = "ARN")]
+    role_arn: Vec<String>,
+    /// Session name of the assumed roles, e.g. to attribute API calls in CloudTrail.
+    #[arg(long, default_value = "vector-aws-secrets-helper")]
+    role_session_name: String,
+    /// Serial number or ARN of the MFA device required to assume the first role of the chain. Not
+    /// supported by serve and --persistent.
+    #[arg(long, value_name = "SERIAL")]
+    mfa_serial: Option<String>,
+    /// Current code of the MFA device. Prompted for on the terminal if not passed, as long as stdin
+    /// is a terminal.
+    #[arg(long, value_name = "CODE", requires = "mfa_serial")]
+    mfa_token: Option<String>,
+    /// Session tag passed when assuming the first role of the chain, e.g. team=observability, so
+    /// that attribute-based IAM policies can authorize the helper. Can be passed several times.
+    #[arg(long, value_name = "KEY=VALUE", value_parser = aws::sts::parse_session_tag)]
+    session_tag: Vec<(String, String)>,
+    /// Keys of the session tags kept by the sessions of the following roles of the chain,
+    /// separated by commas.
+    #[arg(
+        long,
+        value_name = "KEYS",
+        value_delimiter = ',',
+        requires = "session_tag"
+    )]
+    transitive_tag_keys: Vec<String>,
+    /// Path to a TOML config file declaring per-secret settings, e.g. validation rules.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Append a record of the accessed secrets, never their values, to this JSON lines file.
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<PathBuf>,
+    /// Application name appended to the user agent of AWS API calls, e.g. to attribute them in
+    /// CloudTrail.
+    #[arg(long, alias = "user-agent-suffix", value_parser = |name: &str| AppName::new(name.to_string()))]
+    app_name: Option<AppName>,
+    /// Maximum number of attempts to fetch a secret. Only throttling, timeouts, connection
+    /// failures and service errors are retried.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    max_attempts: u32,
+    /// Time after which unfinished fetches are cancelled and reported as failed, e.g. 10s, so
+    /// that whatever was fetched is returned before Vector's own timeout kills the helper.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    deadline: Option<Duration>,
+    /// Maximum number of secrets in a single request. Larger requests are rejected before any
+    /// AWS API calls are made.
+    #[arg(long, default_value_t = 1000)]
+    max_secrets: usize,
+    /// Write a JSON line with the duration, attempts and status of every fetch to stderr. Secret
+    /// values are never included.
+    #[arg(long)]
+    timings: bool,
+    /// Format of the warnings and errors written to stderr. The JSON format writes an object per
+    /// line with stable fields, and also reports every secret that couldn't be fetched.
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    diagnostics: diagnostics::Format,
+    /// Write metric records in the CloudWatch Embedded Metric Format for every request to stderr,
+    /// append them to a file, e.g. one tailed by the CloudWatch agent, or put them into a log
+    /// stream of CloudWatch Logs with `cloudwatch:GROUP:STREAM`.
+    #[arg(
+        long,
+        value_name = "stderr|cloudwatch:GROUP:STREAM|PATH",
+        value_parser = emf::Destination::parse
+    )]
+    emf: Option<emf::Destination>,
+    /// CloudWatch namespace of the metric records.
+    #[arg(long, default_value = "VectorSecretsHelper", requires = "emf")]
+    emf_namespace: String,
+    /// Start at most this many fetch calls per second per backend, including retries, instead of
+    /// starting them all at once, e.g. to avoid tripping account-level throttles when a fleet
+    /// restarts.
+    #[arg(long, value_name = "RATE", value_parser = pacing::parse_rate)]
+    requests_per_second: Option<f64>,
+    /// Expand every secret holding a JSON object into a key per field, e.g. `my-secret.user`, and
+    /// resolve requested fields of secrets by fetching the whole secret.
+    #[arg(long)]
+    expand_json: bool,
+    /// Expand `${VAR}` placeholders in requested names with these environment variables, given as
+    /// names or prefixes ending with `*`, separated by commas, e.g. DEPLOY_ENV,APP_*. Never applies
+    /// to requests over gRPC.
+    #[arg(long, value_name = "VARS", value_delimiter = ',')]
+    interpolate_env: Vec<String>,
+    /// Stop calling a backend once this many fetch calls in a row failed with throttling,
+    /// timeouts, connection failures or service errors, failing the remaining secrets right away.
+    #[arg(long, value_name = "FAILURES", value_parser = clap::value_parser!(u32).range(1..))]
+    circuit_breaker: Option<u32>,
+    /// Maximum number of retries across all fetches of a request from a backend, on top of the
+    /// first attempts. Every request, e.g. every line in persistent mode, gets the whole budget.
+    #[arg(long, value_name = "RETRIES")]
+    retry_budget: Option<u32>,
+    /// Time after which establishing a connection to AWS, including resolving its name, fails,
+    /// e.g. 2s. Failed connections are retried like other transient failures.
+    #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    connect_timeout: Option<Duration>,
+    /// Time after which waiting for AWS to send the first byte of a response fails, e.g. 5s.
+    #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+    #[arg(long, value_parser = humantime::parse_duration)]
+    read_timeout: Option<Duration>,
+    #[command(flatten)]
+    cache: CacheArgs,
+    #[command(flatten)]
+    pool: PoolArgs,
+    /// Refresh temporary AWS credentials, e.g. from IRSA or an assumed role, this long before they
+    /// expire.
+    #[arg(long, default_value = "5m", value_parser = humantime::parse_duration)]
+    refresh_before_expiry: Duration,
+    /// Check whether Secrets Manager secrets are scheduled for deletion or their rotation is
+    /// failing with DescribeSecret, warning on stderr or also failing them in strict mode.
+    #[cfg(feature = "secretsmanager")]
+    #[arg(long, value_enum, default_value_t = aws::secretsmanager::StatusChecks::Off)]
+    status_checks: aws::secretsmanager::StatusChecks,
+    /// Resolve SSM parameters to the value they had at this time, e.g. 2024-05-01T12:00:00Z, from
+    /// their history instead of fetching their current value. Only the last 100 versions of a
+    /// parameter are kept.
+    #[cfg(feature = "ssm")]
+    #[arg(
+        long,
+        value_name = "TIMESTAMP",
+        value_parser = humantime::parse_rfc3339_weak,
+        conflicts_with = "disk_cache"
+    )]
+    as_of: Option<SystemTime>,
+    /// Name of the Azure Key Vault secrets are fetched from unless a secret selects another one.
+    #[cfg(feature = "azure")]
+    #[arg(long, value_name = "NAME")]
+    azure_vault: Option<String>,
+    /// Whether stdin is a terminal, which is passed to run rather than parsed.
+    #[arg(skip)]
+    stdin_is_terminal: bool,
+    /// The HTTP connection pools of the run, created once a client needs them and shared with the
+    /// clones of the arguments, e.g. the ones config reloads recreate the clients with.
+    #[arg(skip)]
+    connections: Arc<OnceLock<Arc<connection::Connections>>>,
+}
+
+/// Arguments of the encrypted on-disk cache of fetched secrets.
+#[derive(Args, Clone)]
+struct CacheArgs {
+    /// Cache fetched secrets encrypted in this directory, to skip refetching them on rapid
+    /// restarts and to fall back to them when AWS can't be reached.
+    #[arg(long, value_name = "DIR", requires = "cache_key")]
+    disk_cache: Option<PathBuf>,
+    /// Time for which cached secrets are returned without refetching them. Older cached secrets
+    /// are only returned if AWS can't be reached.
+    #[arg(long, default_value = "15m", value_parser = humantime::parse_duration)]
+    cache_ttl: Duration,
+    /// Encrypt the cache with the 32-byte key in this file, generating it if it doesn't exist.
+    #[arg(
+        long,
+        value_name = "PATH",
+        group = "cache_key",
+        requires = "disk_cache"
+    )]
+    cache_key_file: Option<PathBuf>,
+    /// Encrypt the cache with a data key generated by this KMS key, storing only the encrypted
+    /// data key in the cache directory.
+    #[arg(
+        long,
+        value_name = "KEY_ID",
+        group = "cache_key",
+        requires = "disk_cache"
+    )]
+    cache_kms_key_id: Option<String>,
+    /// Once cached secrets expire, only refetch those whose version changed, comparing the SSM
+    /// parameter version or Secrets Manager version id with the cached one.
+    #[arg(long, requires = "disk_cache")]
+    if_changed: bool,
+}
+
+/// Arguments of the HTTP connection pools shared by all clients, which matter most in resident
+/// modes like `serve`.
+#[derive(Args, Clone)]
+struct PoolArgs {
+    /// Time after which idle pooled connections are closed, e.g. 5m.
+    #[arg(long, default_value = "90s", value_parser = humantime::parse_duration)]
+    pool_idle_timeout: Duration,
+    /// Maximum number of idle connections kept open per host.
+    #[arg(long, value_name = "COUNT")]
+    pool_max_idle_per_host: Option<usize>,
+    /// Send TCP keep-alive probes on idle connections at this interval, e.g. 30s, so that
+    /// connections aren't silently dropped by NAT gateways or load balancers.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    tcp_keepalive: Option<Duration>,
+}
+
+/// Implement the GlobalArgs methods.
+impl GlobalArgs {
+    /// The HTTP connection pools of the run, shared by all of its clients so that they outlive the
+    /// clients recreated on config reloads.
+    fn connections(&self) -> Arc<connection::Connections> {
+        self.connections
+            .get_or_init(|| {
+                Arc::new(connection::Connections::new(connection::PoolSettings {
+                    idle_timeout: Some(self.pool.pool_idle_timeout),
+                    max_idle_per_host: self.pool.pool_max_idle_per_host,
+                    tcp_keepalive: self.pool.tcp_keepalive,
+                }))
+            })
+            .clone()
+    }
+
+    /// Load the config file if one was passed.
+    fn load_config(&self) -> Result<config::Config, String> {
+        match &self.config {
+            Some(path) => config::Config::load(path),
+            None => Ok(Default::default()),
+        }
+    }
+
+    /// The session tags passed when assuming roles, None if there are none.
+    fn session_tags(&self) -> Option<aws::sts::SessionTags> {
+        (!self.session_tag.is_empty()).then(|| aws::sts::SessionTags {
+            tags: self.session_tag.clone(),
+            transitive_tag_keys: self.transitive_tag_keys.clone(),
+        })
+    }
+
+    /// The environment variables placeholders in requested names may be expanded with.
+    fn allowed_variables(&self) -> interpolate::AllowedVariables {
+        interpolate::AllowedVariables::new(self.interpolate_env.clone())
+    }
+
+    /// The settings of the secret loaders.
+    fn loader_settings(&self) -> LoaderSettings {
+        LoaderSettings {
+            retry_policy: retry::RetryPolicy {
+                max_attempts: self.max_attempts,
+                ..Default::default()
+            },
+            deadline: self.deadline,
+            timings: self.timings,
+            pacer: self
+                .requests_per_second
+                .map(|rate| Arc::new(pacing::Pacer::new(rate))),
+            breaker: (self.circuit_breaker.is_some() || self.retry_budget.is_some()).then(|| {
+                Arc::new(breaker::Breaker::new(
+                    self.circuit_breaker,
+                    self.retry_budget,
+                ))
+            }),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Get secrets from AWS Systems Manager Parameter Store.
+    #[cfg(feature = "ssm")]
+    Ssm(FetchArgs),
+    /// Get secrets from AWS Secrets Manager.
+    #[cfg(feature = "secretsmanager")]
+    Secretsmanager(FetchArgs),
+    /// Get secrets from SSM Parameter Store and Secrets Manager in one request, e.g. while
+    /// migrating secrets between them.
+    #[cfg(all(feature = "ssm", feature = "secretsmanager"))]
+    Multi(MultiArgs),
+    /// Get secrets from Google Cloud Secret Manager.
+    #[cfg(feature = "gcp")]
+    Gcp(FetchArgs),
+    /// Get secrets from Azure Key Vault.
+    #[cfg(feature = "azure")]
+    Azure(FetchArgs),
+    /// Get secrets from Kubernetes Secrets.
+    #[cfg(feature = "k8s")]
+    K8s(FetchArgs),
+    /// Get secrets from a 1Password Connect server.
+    #[cfg(feature = "op")]
+    Op(FetchArgs),
+    /// Fetch a single secret with the current flags and print it, reporting the backend, region
+    /// and credentials used on stderr, e.g. to debug a secret failing at Vector startup.
+    Get(GetArgs),
+    /// Serve secrets from all backends to other services over gRPC.
+    Serve(ServeArgs),
+    /// Check that credentials resolve and the backend is reachable, exiting with 1 otherwise.
+    Healthcheck(HealthcheckArgs),
+    /// Extract the secrets referenced in Vector configs as requests for the helper.
+    Scan(ScanArgs),
+    /// Fetch secrets repeatedly and report latency percentiles and throttling.
+    Bench(BenchArgs),
+    /// Print the account, ARN and region the helper uses to call AWS.
+    Whoami,
+    /// Fetch secrets into the disk cache without printing them, so that later fetches are cache
+    /// hits.
+    Warm(WarmArgs),
+    /// Render a template file with `{{ secret "name" }}` placeholders replaced by the fetched
+    /// secrets, e.g. to hydrate a companion config file of Vector.
+    Render(RenderArgs),
+    /// Create or update SSM parameters and Secrets Manager secrets from a fixtures file, e.g. to
+    /// provision LocalStack or a sandbox account for integration tests.
+    #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+    Seed(SeedArgs),
+    /// Check config files of the helper for unknown keys, invalid regexes and conflicting
+    /// settings, printing every problem with its line and column and exiting with 1 if any.
+    ValidateConfig(ValidateConfigArgs),
+    /// Fetch secrets and print which of them changed, were added or removed since a previous
+    /// output of the helper or the disk cache, comparing digests without revealing values.
+    Diff(DiffArgs),
+    /// Inspect the disk cache without revealing values, or remove cached secrets so that they
+    /// are refetched, e.g. after an emergency rotation.
+    #[command(subcommand)]
+    Cache(CacheCommands),
+}
+
+/// Implement the Commands methods.
+impl Commands {
+    /// Whether the command keeps running and answering requests, i.e. serve and --persistent
+    /// fetches, so that credentials are refreshed without anyone at the terminal.
+    fn is_resident(&self) -> bool {
+        match self {
+            Commands::Serve(_) => true,
+            #[cfg(feature = "ssm")]
+            Commands::Ssm(args) => args.persistent,
+            #[cfg(feature = "secretsmanager")]
+            Commands::Secretsmanager(args) => args.persistent,
+            #[cfg(all(feature = "ssm", feature = "secretsmanager"))]
+            Commands::Multi(args) => args.fetch.persistent,
+            #[cfg(feature = "gcp")]
+            Commands::Gcp(args) => args.persistent,
+            #[cfg(feature = "azure")]
+            Commands::Azure(args) => args.persistent,
+            #[cfg(feature = "k8s")]
+            Commands::K8s(args) => args.persistent,
+            #[cfg(feature = "op")]
+            Commands::Op(args) => args.persistent,
+            _ => false,
+        }
+    }
+}
+
+/// Implement the Commands conversions.
+impl Commands {
+    /// Split a command fetching from a single backend into the backend and its arguments, or give
+    /// back any other command.
+    fn into_fetch(self) -> Result<(Backend, FetchArgs), Box<Self>> {
+        match self {
+            #[cfg(feature = "ssm")]
+            Commands::Ssm(args) => Ok((Backend::Ssm, args)),
+            #[cfg(feature = "secretsmanager")]
+            Commands::Secretsmanager(args) => Ok((Backend::Secretsmanager, args)),
+            #[cfg(feature = "gcp")]
+            Commands::Gcp(args) => Ok((Backend::Gcp, args)),
+            #[cfg(feature = "azure")]
+            Commands::Azure(args) => Ok((Backend::Azure, args)),
+            #[cfg(feature = "k8s")]
+            Commands::K8s(args) => Ok((Backend::K8s, args)),
+            #[cfg(feature = "op")]
+            Commands::Op(args) => Ok((Backend::Op, args)),
+            command => Err(Box::new(command)),
+        }
+    }
+}
+
+/// The subcommands of the cache command.
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// List the cached secrets with their backend, age, freshness and version, never their
+    /// values.
+    Status,
+    /// Remove all cached secrets.
+    Clear,
+    /// Remove cached secrets, so that they are refetched on the next request.
+    Evict(CacheEvictArgs),
+}
+
+/// Arguments of the cache evict command.
+#[derive(Args)]
+struct CacheEvictArgs {
+    /// Names of the secrets to remove, as requested.
+    #[arg(required = true)]
+    names: Vec<String>,
+    /// Only remove the secrets cached for this backend instead of for all backends.
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+}
+
+/// Arguments shared by the commands fetching secrets for Vector.
+#[derive(Args)]
+struct FetchArgs {
+    /// Names of the secrets to fetch instead of reading the request from stdin.
+    secrets: Vec<String>,
+    /// Read the request from a file instead of stdin.
+    #[arg(long, value_name = "PATH", conflicts_with = "secrets")]
+    input_file: Option<PathBuf>,
+    /// Format of the request read from the input file or stdin.
+    #[arg(long, value_enum, default_value_t)]
+    input_format: input::InputFormat,
+    /// Atomically write the response to a file readable only by its owner instead of stdout.
+    #[arg(long, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+    /// Format the fetched secrets are written in. Formats writing a file per secret require
+    /// --output-dir.
+    #[arg(long, value_enum, default_value_t)]
+    output_format: output::OutputFormat,
+    /// Directory the files of the secrets are written to, named after their keys.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "output_file",
+        required_if_eq_any([("output_format", "systemd-creds"), ("output_format", "docker-secrets")])
+    )]
+    output_dir: Option<PathBuf>,
+    /// Name of the Kubernetes Secret written with --output-format k8s-secret.
+    #[arg(
+        long,
+        value_name = "NAME",
+        required_if_eq("output_format", "k8s-secret")
+    )]
+    secret_name: Option<String>,
+    /// Namespace of the Kubernetes Secret, the namespace of the kubectl context if not set.
+    #[arg(long, requires = "secret_name")]
+    namespace: Option<String>,
+    /// Encode the fetched values, e.g. when they contain characters that break templating of the
+    /// Vector config.
+    #[arg(long, value_enum, value_name = "ENCODING")]
+    encode_values: Option<output::ValueEncoding>,
+    /// Return the hex-encoded SHA-256 digest of every value instead of the value, e.g. to detect
+    /// changed secrets without handling them.
+    #[arg(long, conflicts_with = "encode_values")]
+    hash_only: bool,
+    /// Strip this prefix from the keys of the output, e.g. /prod/vector/. Note that Vector expects
+    /// the keys to be the requested names.
+    #[arg(long, value_name = "PREFIX")]
+    strip_prefix: Option<String>,
+    /// Convert the keys of the output to this case, e.g. to use them as environment variables.
+    #[arg(long, value_enum, value_name = "CASE")]
+    key_case: Option<output::KeyCase>,
+    /// Which fetch failures result in a non-zero exit code. The response is written regardless.
+    #[arg(long, value_enum, default_value_t)]
+    exit_on: exit::ExitOn,
+    /// Keep answering requests, one per line of stdin, with a response line each until stdin is
+    /// closed, reusing the clients and caches between requests.
+    #[arg(long, conflicts_with_all = ["secrets", "input_file", "output_file", "exit_on"])]
+    persistent: bool,
+    /// Keep refreshing the files written to --output-dir at this interval, e.g. 5m, fetching the
+    /// request read once at start again every time.
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        requires = "output_dir",
+        conflicts_with = "persistent",
+        value_parser = humantime::parse_duration
+    )]
+    watch: Option<Duration>,
+    /// Print only the value of the single requested secret, without a trailing line break,
+    /// instead of the response, e.g. for command substitution in shell scripts.
+    #[arg(
+        long,
+        conflicts_with_all = ["output_file", "output_format", "hash_only", "exit_on", "persistent"]
+    )]
+    raw: bool,
+    /// Show the values of the secrets fetched at the interactive prompt, offered when stdin is a
+    /// terminal, instead of masking them.
+    #[arg(long)]
+    reveal: bool,
+}
+
+/// Arguments of the multi command.
+#[cfg(all(feature = "ssm", feature = "secretsmanager"))]
+#[derive(Args)]
+struct MultiArgs {
+    #[command(flatten)]
+    fetch: FetchArgs,
+    /// Backends secrets without a backend prefix, e.g. `ssm:`, are looked up in, in order. A
+    /// secret that isn't found in one backend is looked up in the next one.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "ssm,secretsmanager"
+    )]
+    precedence: Vec<Backend>,
+}
+
+/// Arguments of the get command.
+#[derive(Args)]
+struct GetArgs {
+    /// The backend to fetch the secret from.
+    #[arg(value_enum)]
+    backend: Backend,
+    /// Name of the secret, with options like in the requests of Vector, e.g. /app/db?optional=true.
+    name: String,
+    /// Print only the value of the secret, without a trailing line break, instead of its JSON
+    /// entry.
+    #[arg(long)]
+    raw: bool,
+}
+
+/// Arguments of the serve command.
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on for gRPC requests, e.g. 127.0.0.1:50051.
+    #[arg(long, value_name = "ADDR")]
+    grpc: SocketAddr,
+    /// Path to a PEM-encoded certificate to serve gRPC over TLS.
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// Path to the PEM-encoded private key of the TLS certificate.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// Maximum time to spend on a single request, unless the client sets a shorter deadline.
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    request_timeout: Duration,
+    /// Address to expose Prometheus metrics on at the /metrics path, e.g. 127.0.0.1:9090.
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+}
+
+/// Arguments of the healthcheck command.
+#[derive(Args)]
+struct HealthcheckArgs {
+    /// The backend to check.
+    #[arg(value_enum)]
+    backend: Backend,
+    /// Name of a secret that must be readable for the check to pass.
+    #[arg(long)]
+    canary: Option<String>,
+}
+
+/// Arguments of the scan command.
+#[derive(Args)]
+struct ScanArgs {
+    /// Vector config files or directories to scan.
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Only extract references to this Vector secrets backend, e.g. aws_ssm.
+    #[arg(long, value_name = "NAME")]
+    secret_backend: Option<String>,
+    /// Fetch the extracted secrets from the backend instead of printing the request, printing
+    /// whether each of them could be fetched and exiting with 1 if any of them can't be.
+    #[arg(long, value_enum, requires = "secret_backend")]
+    fetch: Option<Backend>,
+}
+
+/// Arguments of the validate-config command.
+#[derive(Args)]
+struct ValidateConfigArgs {
+    /// Config files to check.
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+}
+
+/// Arguments of the diff command.
+#[derive(Args)]
+struct DiffArgs {
+    /// The backend to fetch the secrets from.
+    #[arg(value_enum)]
+    backend: Backend,
+    /// Names of the secrets to compare, all secrets of the previous output by default.
+    #[arg(required_unless_present = "against")]
+    secrets: Vec<String>,
+    /// A previous output of the helper to compare against, written with or without --hash-only.
+    #[arg(
+        long,
+        value_name = "PATH",
+        required_unless_present = "against_cache",
+        conflicts_with = "against_cache"
+    )]
+    against: Option<PathBuf>,
+    /// Compare against the values in the disk cache instead, which requires --disk-cache.
+    #[arg(long)]
+    against_cache: bool,
+    /// Exit with 1 if any secret differs, like git diff.
+    #[arg(long)]
+    exit_code: bool,
+}
+
+/// Arguments of the bench command.
+#[derive(Args)]
+struct BenchArgs {
+    /// The backend to benchmark.
+    #[arg(value_enum)]
+    backend: Backend,
+    /// Names of the secrets fetched in every request.
+    #[arg(required = true)]
+    secrets: Vec<String>,
+    /// Number of requests to make.
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    iterations: u64,
+    /// Number of requests in flight at the same time.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    concurrency: u64,
+}
+
+/// Arguments of the warm command.
+#[derive(Args)]
+struct WarmArgs {
+    /// The backend to fetch the secrets from.
+    #[arg(value_enum)]
+    backend: Backend,
+    /// Names of the secrets to cache instead of reading the request from stdin.
+    secrets: Vec<String>,
+    /// Read the request from a file instead of stdin.
+    #[arg(long, value_name = "PATH", conflicts_with = "secrets")]
+    input_file: Option<PathBuf>,
+    /// Format of the request read from the input file or stdin.
+    #[arg(long, value_enum, default_value_t)]
+    input_format: input::InputFormat,
+}
+
+/// Arguments of the render command.
+#[derive(Args)]
+struct RenderArgs {
+    /// The backend to fetch the secrets from.
+    #[arg(value_enum)]
+    backend: Backend,
+    /// The template file to render.
+    template: PathBuf,
+    /// Atomically write the rendered file to this path, readable only by its owner, instead of
+    /// stdout.
+    #[arg(long, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+}
+
+/// Arguments of the seed command.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+#[derive(Args)]
+struct SeedArgs {
+    /// TOML file with the values of the parameters and secrets, keyed by backend and name.
+    fixtures: PathBuf,
+}
+
+/// The backends secrets can be retrieved from, depending on the enabled features.
+#[derive(Clone, Copy, ValueEnum)]
+enum Backend {
+    #[cfg(feature = "ssm")]
+    Ssm,
+    #[cfg(feature = "secretsmanager")]
+    Secretsmanager,
+    #[cfg(feature = "gcp")]
+    Gcp,
+    #[cfg(feature = "azure")]
+    Azure,
+    #[cfg(feature = "k8s")]
+    K8s,
+    #[cfg(feature = "op")]
+    Op,
+}
+
+/// Implement the Backend methods.
+impl Backend {
+    /// The name of the backend, as used on the command line.
+    fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "ssm")]
+            Backend::Ssm => "ssm",
+            #[cfg(feature = "secretsmanager")]
+            Backend::Secretsmanager => "secretsmanager",
+            #[cfg(feature = "gcp")]
+            Backend::Gcp => "gcp",
+            #[cfg(feature = "azure")]
+            Backend::Azure => "azure",
+            #[cfg(feature = "k8s")]
+            Backend::K8s => "k8s",
+            #[cfg(feature = "op")]
+            Backend::Op => "op",
+        }
+    }
+
+    /// Whether the backend is an AWS service, called with the AWS credentials.
+    fn is_aws(&self) -> bool {
+        match self {
+            #[cfg(feature = "ssm")]
+            Backend::Ssm => true,
+            #[cfg(feature = "secretsmanager")]
+            Backend::Secretsmanager => true,
+            #[cfg(feature = "gcp")]
+            Backend::Gcp => false,
+            #[cfg(feature = "azure")]
+            Backend::Azure => false,
+            #[cfg(feature = "k8s")]
+            Backend::K8s => false,
+            #[cfg(feature = "op")]
+            Backend::Op => false,
+        }
+    }
+}
+
+/// Load the AWS SDK config using the default credential provider chain, or the last role of the
+/// role chain if any, whose credentials are shared by all clients created from the config. The
+/// role chain of the config file is overridden by the one of the command line. The config of an
+/// account uses its profile and region, and assumes its role after the role chain.
+async fn load_aws_sdk_config(
+    role_chain: &[String],
+    account: Option<&config::AccountConfig>,
+    global: &GlobalArgs,
+) -> SdkConfig {
+    let mut loader = aws_config::from_env().http_connector(global.connections().aws());
+    if let Some(app_name) = &global.app_name {
+        loader = loader.app_name(app_name.clone());
+    }
+    if let Some(profile) = account.and_then(|account| account.profile.as_ref()) {
+        loader = loader.profile_name(profile);
+    }
+    if let Some(region) = account.and_then(|account| account.region.clone()) {
+        loader = loader.region(Region::new(region));
+    }
+    let aws_sdk_config = loader.load().await;
+
+    let mut role_chain = match global.role_arn.is_empty() {
+        true => role_chain.to_vec(),
+        false => global.role_arn.clone(),
+    };
+    role_chain.extend(account.and_then(|account| account.role_arn.clone()));
+    if role_chain.is_empty() {
+        return aws::credentials::share(aws_sdk_config, global.refresh_before_expiry);
+    }
+    let mfa = global
+        .mfa_serial
+        .as_ref()
+        .map(|serial_number| aws::sts::Mfa {
+            serial_number: serial_number.clone(),
+            token_code: global.mfa_token.clone(),
+        });
+    let tags = global.session_tags();
+    let assumed = aws::sts::assume_roles(
+        &aws_sdk_config,
+        &role_chain,
+        &global.role_session_name,
+        mfa.as_ref(),
+        tags.as_ref(),
+        sts_endpoint_url(global).as_deref(),
+        global.refresh_before_expiry,
+    );
+    match assumed {
+        Some(provider) => aws::credentials::with_credentials(
+            aws_sdk_config,
+            provider,
+            global.refresh_before_expiry,
+        ),
+        None => aws_sdk_config,
+    }
+}
+
+/// A struct holding the AWS SDK config, only loaded the first time a client needs it, so that
+/// requests answered without calling AWS don't pay for resolving the region and credentials, e.g.
+/// on hosts without IMDS. Clones share the loaded config.
+#[derive(Clone)]
+struct LazySdkConfig {
+    sdk_config: Arc<tokio::sync::OnceCell<SdkConfig>>,
+    /// The ARN of the identity calling AWS, only looked up the first time it's needed.
+    caller: Arc<tokio::sync::OnceCell<Option<String>>>,
+    role_chain: Vec<String>,
+    account: Option<config::AccountConfig>,
+    /// The configs of the accounts in the config file, keyed by account name.
+    #[cfg_attr(
+        not(any(feature = "ssm", feature = "secretsmanager")),
+        allow(dead_code)
+    )]
+    accounts: BTreeMap<String, LazySdkConfig>,
+    global: GlobalArgs,
+}
+
+/// Implement the LazySdkConfig constructor.
+impl LazySdkConfig {
+    fn new(config: &config::Config, global: &GlobalArgs) -> Self {
+        let new = |account: Option<config::AccountConfig>, accounts| Self {
+            sdk_config: Arc::new(tokio::sync::OnceCell::new()),
+            caller: Arc::new(tokio::sync::OnceCell::new()),
+            role_chain: config.role_chain.clone(),
+            account,
+            accounts,
+            global: global.clone(),
+        };
+        let accounts = config
+            .accounts
+            .iter()
+            .map(|(name, account)| {
+                let sdk_config = new(Some(account.clone()), BTreeMap::new());
+                (name.0.clone(), sdk_config)
+            })
+            .collect();
+        new(None, accounts)
+    }
+}
+
+/// Implement the LazySdkConfig methods.
+impl LazySdkConfig {
+    /// Get the AWS SDK config, loading it if this is the first time it's needed.
+    async fn get(&self) -> &SdkConfig {
+        self.sdk_config
+            .get_or_init(|| {
+                load_aws_sdk_config(&self.role_chain, self.account.as_ref(), &self.global)
+            })
+            .await
+    }
+
+    /// Get the ARN of the identity calling AWS, looking it up with the AWS SDK config if this is
+    /// the first time it's needed. None if it can't be looked up, which is only warned about.
+    async fn caller_arn(&self) -> Option<String> {
+        let caller = self.caller.get_or_init(|| async {
+            let client = sts_client(self.get().await, &self.global);
+            match aws::sts::caller_arn(&client).await {
+                Ok(caller) => Some(caller),
+                Err(error) => {
+                    Diagnostic::new(
+                        Level::Warning,
+                        "caller_identity_failed",
+                        format!(
+                            "failed to get the caller identity for the audit log: {}",
+                            error
+                        ),
+                    )
+                    .emit();
+                    None
+                }
+            }
+        });
+        caller.await.clone()
+    }
+}
+
+/// Create a secrets loader for an AWS backend from the AWS SDK config, or one per account routing
+/// the account-prefixed secrets to them if the config file has accounts.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+async fn aws_loader(
+    aws_sdk_config: &LazySdkConfig,
+    create: impl Fn(&SdkConfig) -> Box<dyn LoadSecrets + Send + Sync>,
+) -> Box<dyn LoadSecrets + Send + Sync> {
+    if aws_sdk_config.accounts.is_empty() {
+        return create(aws_sdk_config.get().await);
+    }
+    let mut loaders = BTreeMap::new();
+    for (name, account) in &aws_sdk_config.accounts {
+        loaders.insert(name.clone(), create(account.get().await));
+    }
+    Box::new(accounts::AccountsLoader::new(loaders))
+}
+
+/// Get the endpoint URL of the backend, None to use its default endpoint.
+fn backend_endpoint_url(backend: Backend, global: &GlobalArgs) -> Option<String> {
+    match backend {
+        #[cfg(feature = "ssm")]
+        Backend::Ssm => service_endpoint_url(
+            global.ssm_endpoint_url.as_ref(),
+            global.endpoint_url.as_ref(),
+            "AWS_ENDPOINT_URL_SSM",
+            |variable| std::env::var(variable).ok(),
+        ),
+        #[cfg(feature = "secretsmanager")]
+        Backend::Secretsmanager => service_endpoint_url(
+            global.secretsmanager_endpoint_url.as_ref(),
+            global.endpoint_url.as_ref(),
+            "AWS_ENDPOINT_URL_SECRETS_MANAGER",
+            |variable| std::env::var(variable).ok(),
+        ),
+        #[allow(unreachable_patterns)]
+        _ => global.endpoint_url.clone(),
+    }
+}
+
+/// Determine the region a secret requested from an AWS backend is fetched from, and where it comes
+/// from. Secrets referenced by ARN are fetched in the region of the ARN, others in the region
+/// requested for them, if any.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+fn requested_aws_region(
+    backend: Backend,
+    name: &str,
+    regions: &HashMap<String, String>,
+    aws_sdk_config: &SdkConfig,
+) -> Result<(Option<String>, &'static str), String> {
+    let (requested, secret) = match backend {
+        #[cfg(feature = "ssm")]
+        Backend::Ssm => aws::ssm::parse_requested(name)?,
+        #[cfg(feature = "secretsmanager")]
+        Backend::Secretsmanager => aws::secretsmanager::parse_requested(name)?,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!("only AWS backends are called with the AWS credentials"),
+    };
+    let requested_region = aws::arn::requested_region(&secret, requested, regions)?;
+    match (aws::arn::Arn::parse(&secret.name), requested_region) {
+        (Some(arn), _) if !arn.region.is_empty() => Ok((Some(arn.region.to_string()), "the ARN")),
+        (_, Some(region)) if secret.option("region").is_some() => {
+            Ok((Some(region), "the region option"))
+        }
+        (_, Some(region)) => Ok((Some(region), "the config file")),
+        (arn, None) => {
+            let region = aws_sdk_config.region().map(|region| region.to_string());
+            if let (Some(arn), Some(region)) = (arn, &region) {
+                arn.check_partition(region)?;
+            }
+            Ok((region, "the AWS config"))
+        }
+    }
+}
+
+/// Get the endpoint URL of AWS STS, never the one of --endpoint-url since that's the endpoint of
+/// the backend.
+fn sts_endpoint_url(global: &GlobalArgs) -> Option<String> {
+    service_endpoint_url(
+        global.sts_endpoint_url.as_ref(),
+        None,
+        "AWS_ENDPOINT_URL_STS",
+        |variable| std::env::var(variable).ok(),
+    )
+}
+
+/// Create a client for AWS STS.
+fn sts_client(aws_sdk_config: &SdkConfig, global: &GlobalArgs) -> StsClient {
+    let mut config_builder = aws_sdk_sts::config::Builder::from(aws_sdk_config);
+    if let Some(endpoint_url) = sts_endpoint_url(global) {
+        config_builder = config_builder.endpoint_url(endpoint_url);
+    }
+    StsClient::from_conf(config_builder.build())
+}
+
+/// Open the disk cache if one was configured, decrypting its data key with KMS if needed. The
+/// helper runs without the cache if it can't be opened, e.g. because KMS can't be reached before
+/// the deadline of the request.
+async fn disk_cache(
+    aws_sdk_config: &LazySdkConfig,
+    global: &GlobalArgs,
+) -> Option<Arc<cache::DiskCache>> {
+    let args = &global.cache;
+    let dir = args.disk_cache.as_ref()?;
+    let key = match (&args.cache_key_file, &args.cache_kms_key_id) {
+        (Some(path), _) => cache::host_key(path),
+        (None, Some(key_id)) => {
+            // Loading credentials and calling KMS count towards the deadline.
+            let data_key = async {
+                let client = aws_sdk_kms::Client::new(aws_sdk_config.get().await);
+                aws::kms::data_key(&client, key_id, &dir.join("data-key")).await
+            };
+            aws::loader::before_deadline(data_key)
+                .await
+                .unwrap_or_else(|error| Err(format!("failed to get the data key: {}", error)))
+        }
+        // Clap requires one of the keys along with the cache directory.
+        (None, None) => unreachable!(),
+    };
+    match key.and_then(|key| cache::DiskCache::new(dir, &key, args.cache_ttl)) {
+        Ok(disk_cache) => Some(Arc::new(disk_cache)),
+        Err(error) => {
+            Diagnostic::new(
+                Level::Warning,
+                "disk_cache_disabled",
+                format!("running without the disk cache: {}", error),
+            )
+            .emit();
+            None
+        }
+    }
+}
+
+/// Get the HTTP timeouts of the AWS clients, overriding those of the AWS SDK config with the ones
+/// passed on the command line.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+fn timeout_config(aws_sdk_config: &SdkConfig, global: &GlobalArgs) -> TimeoutConfig {
+    let mut builder = TimeoutConfig::builder();
+    if let Some(connect_timeout) = global.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if let Some(read_timeout) = global.read_timeout {
+        builder = builder.read_timeout(read_timeout);
+    }
+    match aws_sdk_config.timeout_config() {
+        Some(defaults) => builder.take_unset_from(defaults.to_builder()).build(),
+        None => builder.build(),
+    }
+}
+
+/// Resolve the endpoint URL of an AWS service from its own flag, then `--endpoint-url`, then the
+/// standard environment variable of the service, like the AWS CLI. None uses the endpoint of the
+/// region.
+fn service_endpoint_url(
+    service_endpoint_url: Option<&String>,
+    endpoint_url: Option<&String>,
+    variable: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Option<String> {
+    service_endpoint_url
+        .or(endpoint_url)
+        .cloned()
+        .or_else(|| lookup(variable).filter(|url| !url.is_empty()))
+}
+
+/// Create a client for AWS SSM Parameter Store.
+#[cfg(feature = "ssm")]
+fn ssm_client(aws_sdk_config: &SdkConfig, global: &GlobalArgs) -> SsmClient {
+    // Retries are handled by the secrets loader according to the retry policy.
+    let mut config_builder = aws_sdk_ssm::config::Builder::from(aws_sdk_config)
+        .retry_config(RetryConfig::disabled())
+        .timeout_config(timeout_config(aws_sdk_config, global));
+    if let Some(endpoint_url) = backend_endpoint_url(Backend::Ssm, global) {
+        config_builder = config_builder.endpoint_url(endpoint_url);
+    }
+    SsmClient::from_conf(config_builder.build())
+}
+
+/// Create a client for AWS Secrets Manager.
+#[cfg(feature = "secretsmanager")]
+fn secretsmanager_client(aws_sdk_config: &SdkConfig, global: &GlobalArgs) -> SecretsManagerClient {
+    // Retries are handled by the secrets loader according to the retry policy.
+    let mut config_builder = aws_sdk_secretsmanager::config::Builder::from(aws_sdk_config)
+        .retry_config(RetryConfig::disabled())
+        .timeout_config(timeout_config(aws_sdk_config, global));
+    if let Some(endpoint_url) = backend_endpoint_url(Backend::Secretsmanager, global) {
+        config_builder = config_builder.endpoint_url(endpoint_url);
+    }
+    SecretsManagerClient::from_conf(config_builder.build())
+}
+
+/// Create a secrets loader for AWS SSM Parameter Store, routing parameters referenced by ARN to
+/// the region of the ARN.
+#[cfg(feature = "ssm")]
+fn ssm_loader(
+    client: SsmClient,
+    regions: HashMap<String, String>,
+    global: &GlobalArgs,
+) -> aws::ssm::SsmSecretsLoader {
+    let default_region = client.config().region().map(|region| region.to_string());
+    let config = client.config().clone();
+    let clients = aws::arn::RegionalClients::new(client, default_region, move |region: &str| {
+        let region = Region::new(region.to_string());
+        SsmClient::from_conf(config.to_builder().region(region).build())
+    });
+    // Always decrypt SecureString parameters.
+    aws::ssm::SsmSecretsLoader::new(
+        clients,
+        true,
+        global.as_of,
+        regions,
+        global.loader_settings(),
+    )
+}
+
+/// Create a secrets loader for AWS Secrets Manager, routing secrets referenced by ARN to the
+/// region of the ARN.
+#[cfg(feature = "secretsmanager")]
+fn secretsmanager_loader(
+    client: SecretsManagerClient,
+    regions: HashMap<String, String>,
+    global: &GlobalArgs,
+) -> aws::secretsmanager::SecretsManagerSecretsLoader {
+    let default_region = client.config().region().map(|region| region.to_string());
+    let config = client.config().clone();
+    let clients = aws::arn::RegionalClients::new(client, default_region, move |region: &str| {
+        let region = Region::new(region.to_string());
+        SecretsManagerClient::from_conf(config.to_builder().region(region).build())
+    });
+    aws::secretsmanager::SecretsManagerSecretsLoader::new(
+        clients,
+        global.status_checks,
+        regions,
+        global.loader_settings(),
+    )
+}
+
+/// Create a client for Google Cloud Secret Manager authenticating with Application Default
+/// Credentials.
+#[cfg(feature = "gcp")]
+async fn gcp_client(global: &GlobalArgs) -> gcp::GcpClient {
+    gcp::GcpClient::new(
+        global.connections().reqwest(),
+        global.endpoint_url.as_deref(),
+    )
+    .await
+}
+
+/// Create a secrets loader for Azure Key Vault authenticating with the service principal or managed
+/// identity selected by the environment.
+#[cfg(feature = "azure")]
+fn azure_loader(global: &GlobalArgs) -> azure::AzureSecretsLoader {
+    let client = azure::AzureClient::new(
+        global.connections().reqwest(),
+        azure::AzureCredential::from_env(),
+    );
+    azure::AzureSecretsLoader::new(
+        client,
+        global.endpoint_url.clone(),
+        global.azure_vault.clone(),
+        global.loader_settings(),
+    )
+}
+
+/// Create a secrets loader for the backend, expanding `${VAR}` placeholders in the requested names
+/// and returning the defaults of missing optional secrets, on top of the backend loader.
+async fn loader(
+    backend: Backend,
+    aws_sdk_config: &LazySdkConfig,
+    config: &config::Config,
+    global: &GlobalArgs,
+    disk_cache: Option<&Arc<cache::DiskCache>>,
+) -> Box<dyn LoadSecrets + Send + Sync> {
+    let loader = backend_loader(backend, aws_sdk_config, config, global, disk_cache).await;
+    requested_loader(
+        loader,
+        config,
+        global,
+        &[backend],
+        global.allowed_variables(),
+    )
+}
+
+/// Wrap a secrets loader to handle the requested names before they reach the backends, expanding
+/// JSON secrets into their fields, fetching secrets without an account from all matching accounts,
+/// expanding `${VAR}` placeholders with the allowed variables, if any, and returning the defaults
+/// of missing optional secrets.
+#[cfg_attr(
+    not(any(feature = "ssm", feature = "secretsmanager")),
+    allow(unused_variables)
+)]
+fn requested_loader(
+    loader: Box<dyn LoadSecrets + Send + Sync>,
+    config: &config::Config,
+    global: &GlobalArgs,
+    backends: &[Backend],
+    allowed_variables: interpolate::AllowedVariables,
+) -> Box<dyn LoadSecrets + Send + Sync> {
+    // Placeholders are expanded first, so that the optional secrets in the config file, the cache,
+    // validation rules and audit log all see the names actually fetched.
+    let loader = Box::new(optional::OptionalLoader::new(
+        loader,
+        config.optional_secrets(),
+    ));
+    #[allow(unused_mut)]
+    let mut loader: Box<dyn LoadSecrets + Send + Sync> = match allowed_variables.is_empty() {
+        true => loader,
+        false => Box::new(interpolate::InterpolatedLoader::new(
+            loader,
+            allowed_variables,
+        )),
+    };
+    // Secrets are fanned out to the accounts before placeholders are expanded, since the loaders
+    // below only return secrets under the names they were passed.
+    #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+    if !config.accounts.is_empty() && backends.iter().any(|backend| backend.is_aws()) {
+        let accounts = config
+            .accounts
+            .iter()
+            .map(|(name, account)| {
+                let patterns = account.secrets.iter().map(|pattern| pattern.0.clone());
+                (name.0.clone(), patterns.collect())
+            })
+            .collect();
+        let backends = backends
+            .iter()
+            .map(|backend| (backend.name(), backend.is_aws()))
+            .collect();
+        loader = Box::new(accounts::FanOutLoader::new(loader, accounts, backends));
+    }
+    let expanded_secrets = config.expanded_secrets();
+    if !global.expand_json && expanded_secrets.is_empty() {
+        return loader;
+    }
+    Box::new(expand::ExpandedLoader::new(
+        loader,
+        global.expand_json,
+        expanded_secrets,
+    ))
+}
+
+/// Create a secrets loader for the backend, caching fetched values if a disk cache is passed,
+/// validating them against the rules in the config file, recording accessed secrets in the audit
+/// log and writing metric records if enabled.
+async fn backend_loader(
+    backend: Backend,
+    aws_sdk_config: &LazySdkConfig,
+    config: &config::Config,
+    global: &GlobalArgs,
+    disk_cache: Option<&Arc<cache::DiskCache>>,
+) -> Box<dyn LoadSecrets + Send + Sync> {
+    #[cfg(feature = "ssm")]
+    if global.as_of.is_some() && !matches!(backend, Backend::Ssm) {
+        Diagnostic::new(
+            Level::Warning,
+            "as_of_ignored",
+            format!(
+                "--as-of only applies to SSM parameters, secrets from {} are fetched as they are now",
+                backend.name()
+            ),
+        )
+        .emit();
+    }
+    // The backend is only set up once a request needs it, e.g. not for secrets in the disk cache.
+    let (sdk_config, global_args) = (aws_sdk_config.clone(), global.clone());
+    // Only the identity calling AWS is recorded in the audit log, and it's only looked up along
+    // with setting up the backend, so requests that never reach it don't call STS.
+    let caller = Arc::new(OnceLock::new());
+    let audited_caller = (global.audit_log.is_some() && backend.is_aws()).then(|| caller.clone());
+    #[cfg_attr(
+        not(any(feature = "ssm", feature = "secretsmanager")),
+        allow(unused_variables)
+    )]
+    let regions = config.secret_regions();
+    let mut loader: Box<dyn LoadSecrets + Send + Sync> =
+        Box::new(lazy::LazyLoader::new(async move {
+            #[cfg_attr(
+                not(any(feature = "ssm", feature = "secretsmanager")),
+                allow(unused_variables)
+            )]
+            let (aws_sdk_config, global) = (&sdk_config, &global_args);
+            let loader: Box<dyn LoadSecrets + Send + Sync> = match backend {
+                #[cfg(feature = "ssm")]
+                Backend::Ssm => {
+                    aws_loader(aws_sdk_config, |sdk_config| {
+                        Box::new(ssm_loader(
+                            ssm_client(sdk_config, global),
+                            regions.clone(),
+                            global,
+                        ))
+                    })
+                    .await
+                }
+                #[cfg(feature = "secretsmanager")]
+                Backend::Secretsmanager => {
+                    aws_loader(aws_sdk_config, |sdk_config| {
+                        Box::new(secretsmanager_loader(
+                            secretsmanager_client(sdk_config, global),
+                            regions.clone(),
+                            global,
+                        ))
+                    })
+                    .await
+                }
+                #[cfg(feature = "gcp")]
+                Backend::Gcp => Box::new(gcp::GcpSecretsLoader::new(
+                    gcp_client(global).await,
+                    global.loader_settings(),
+                )),
+                #[cfg(feature = "azure")]
+                Backend::Azure => Box::new(azure_loader(global)),
+                #[cfg(feature = "k8s")]
+                Backend::K8s => Box::new(k8s::K8sSecretsLoader::new(
+                    k8s::K8sClient::new(global.endpoint_url.as_deref()),
+                    global.loader_settings(),
+                )),
+                #[cfg(feature = "op")]
+                Backend::Op => Box::new(op::OpSecretsLoader::new(
+                    op::OpClient::new(
+                        global.connections().reqwest(),
+                        global.endpoint_url.as_deref(),
+                    ),
+                    global.loader_settings(),
+                )),
+            };
+            if let Some(audited_caller) = audited_caller {
+                if let Some(caller) = sdk_config.caller_arn().await {
+                    let _ = audited_caller.set(caller);
+                }
+            }
+            loader
+        }));
+    if let Some(disk_cache) = disk_cache {
+        loader = Box::new(cache::CachedLoader::new(
+            loader,
+            backend.name(),
+            disk_cache.clone(),
+            global.cache.if_changed,
+        ));
+    }
+    let validations = config.validations();
+    if !validations.is_empty() {
+        loader = Box::new(validate::ValidatedLoader::new(loader, validations));
+    }
+
+    if let Some(audit_log) = &global.audit_log {
+        loader = Box::new(audit::AuditedLoader::new(
+            loader,
+            backend.name(),
+            caller,
+            audit_log.clone(),
+        ));
+    }
+    if let Some(destination) = &global.emf {
+        let sdk_config = aws_sdk_config.clone();
+        loader = Box::new(emf::EmfLoader::new(
+            loader,
+            backend.name(),
+            &global.emf_namespace,
+            emf::Sink::new(destination, async move { sdk_config.get().await.clone() }),
+        ));
+    }
+    loader
+}
+
+/// Wrap a secrets loader to record metrics about it if metrics are enabled.
+fn metered(
+    loader: Box<dyn LoadSecrets + Send + Sync>,
+    backend: &str,
+    metrics: Option<&Arc<metrics::Metrics>>,
+) -> Box<dyn LoadSecrets + Send + Sync> {
+    match metrics {
+        Some(metrics) => Box::new(metrics::MeteredLoader::new(
+            loader,
+            backend,
+            metrics.clone(),
+        )),
+        None => loader,
+    }
+}
+
+/// Write the output of a command to the writer, failing with 1 if that fails.
+fn print(writer: &mut dyn Write, output: impl Display) -> Result<(), exit::Failure> {
+    write!(writer, "{}", output)
+        .and_then(|_| writer.flush())
+        .map_err(|error| exit::Failure::new(1, format!("failed to write stdout: {}", error)))
+}
+
+/// The reader requests are read from, which can be moved to a blocking thread to read a request
+/// while its secrets are fetched.
+type Reader = Box<dyn BufRead + Send>;
+
+/// Open the input file if any, otherwise use the reader, returning the input with its name.
+fn open_input<'a>(
+    input_file: Option<PathBuf>,
+    reader: impl BufRead + 'a,
+) -> Result<(Box<dyn BufRead + 'a>, String), exit::Failure> {
+    match input_file {
+        Some(path) => Ok((Box::new(open_file(&path)?), path.display().to_string())),
+        None => Ok((Box::new(reader), String::from("stdin"))),
+    }
+}
+
+/// Open the input file for reading.
+fn open_file(path: &PathBuf) -> Result<BufReader<File>, exit::Failure> {
+    File::open(path).map(BufReader::new).map_err(|error| {
+        exit::Failure::new(
+            exit::INPUT_ERROR,
+            format!("failed to open {}: {}", path.display(), error),
+        )
+    })
+}
+
+/// Take the secret names from the CLI arguments if any, otherwise parse the request from the input
+/// file or the reader, failing if it can't be read or has too many secrets.
+fn read_request(
+    secrets: Vec<String>,
+    input_file: Option<PathBuf>,
+    input_format: input::InputFormat,
+    global: &GlobalArgs,
+    reader: &mut dyn BufRead,
+) -> Result<vector::SecretsToFetch, exit::Failure> {
+    let secrets_to_fetch = if !secrets.is_empty() {
+        vector::SecretsToFetch::from_names(secrets)
+    } else {
+        let (input, source) = open_input(input_file, reader)?;
+        input::read(input, input_format).map_err(|error| {
+            exit::Failure::new(exit::INPUT_ERROR, format!("{} from {}", error, source))
+        })?
+    };
+    secrets_to_fetch
+        .check_limit(global.max_secrets)
+        .map_err(|error| exit::Failure::new(exit::INPUT_ERROR, error))?;
+    Ok(secrets_to_fetch)
+}
+
+/// Fetch the requested secrets from the backend and return them in the format expected by Vector,
+/// returning the exit code for them.
+async fn fetch(
+    backend: Backend,
+    args: FetchArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    reader: &mut Reader,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    if let Some(interval) = args.watch {
+        return watch(
+            backend,
+            args,
+            interval,
+            config,
+            global,
+            reader.as_mut(),
+            writer,
+        )
+        .await;
+    }
+    // One-shot requests have a single deadline covering setting up the backends, e.g. resolving
+    // credentials and assuming roles, and all of their fetches. Persistent and interactive ones
+    // get a deadline per request instead.
+    let deadline = one_shot_deadline(&args, global);
+    aws::loader::with_deadline(deadline, async move {
+        // Persistent requests and requests whose response is streamed are only read once the loader
+        // is ready, the latter so that fetches start while the rest of the request is read.
+        let interactive = is_interactive(&args, global);
+        let read_streamed = !args.persistent && args.secrets.is_empty() && is_streamable(&args);
+        let secrets_to_fetch = match args.persistent || interactive || read_streamed {
+            true => None,
+            false => Some(read_request(
+                args.secrets.clone(),
+                args.input_file.clone(),
+                args.input_format,
+                global,
+                reader.as_mut(),
+            )?),
+        };
+
+        let aws_sdk_config = LazySdkConfig::new(config, global);
+        let disk_cache = disk_cache(&aws_sdk_config, global).await;
+        let secrets_loader = loader(
+            backend,
+            &aws_sdk_config,
+            config,
+            global,
+            disk_cache.as_ref(),
+        )
+        .await;
+        match secrets_to_fetch {
+            Some(secrets_to_fetch) => {
+                respond(secrets_loader.as_ref(), secrets_to_fetch, &args, writer).await
+            }
+            None if interactive => {
+                prompt(
+                    secrets_loader.as_ref(),
+                    &args,
+                    global,
+                    reader.as_mut(),
+                    writer,
+                )
+                .await
+            }
+            None if read_streamed => {
+                check_output_args(&args)?;
+                // The request is read on a blocking thread, taking over the reader.
+                let (input, source): (Reader, String) = match args.input_file.clone() {
+                    Some(path) => (Box::new(open_file(&path)?), path.display().to_string()),
+                    None => (
+                        std::mem::replace(reader, Box::new(std::io::empty())),
+                        String::from("stdin"),
+                    ),
+                };
+                let input_format = args.input_format;
+                // Batches are fetched before the version may be read, with the only version there is.
+                let read = move |on_name: &mut dyn FnMut(String)| {
+                    input::read_names(input, input_format, on_name)
+                        .map(|_| ())
+                        .map_err(|error| {
+                            exit::Failure::new(
+                                exit::INPUT_ERROR,
+                                format!("{} from {}", error, source),
+                            )
+                        })
+                };
+                let max_secrets = global.max_secrets;
+                let secrets_loader = Arc::from(secrets_loader);
+                respond_streamed(secrets_loader, read, max_secrets, &args, writer).await
+            }
+            None => {
+                let reader = reader.as_mut();
+                respond_persistently(secrets_loader.as_ref(), &args, global, reader, writer).await
+            }
+        }
+    })
+    .await
+}
+
+/// The deadline of a request answered once, None for persistent and interactive requests.
+fn one_shot_deadline(args: &FetchArgs, global: &GlobalArgs) -> Option<Duration> {
+    global
+        .deadline
+        .filter(|_| !args.persistent && !is_interactive(args, global))
+}
+
+/// Whether the secrets are fetched at the interactive prompt, which is the case when neither names
+/// nor an input file were passed and stdin is a terminal rather than Vector.
+fn is_interactive(args: &FetchArgs, global: &GlobalArgs) -> bool {
+    !args.persistent
+        && args.secrets.is_empty()
+        && args.input_file.is_none()
+        && global.stdin_is_terminal
+}
+
+/// Offer the interactive prompt, returning the exit code once stdin is closed.
+async fn prompt(
+    secrets_loader: &(dyn LoadSecrets + Send + Sync),
+    args: &FetchArgs,
+    global: &GlobalArgs,
+    reader: &mut dyn BufRead,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    interactive::prompt(
+        secrets_loader,
+        args.reveal,
+        global.max_secrets,
+        global.deadline,
+        reader,
+        writer,
+    )
+    .await
+    .map_err(|error| exit::Failure::new(1, format!("failed to prompt for secrets: {}", error)))?;
+    Ok(0)
+}
+
+/// Whether the response to a request is written batch by batch, which requires it to be written
+/// to stdout as is.
+fn is_streamable(args: &FetchArgs) -> bool {
+    args.output_format == output::OutputFormat::Vector
+        && args.output_file.is_none()
+        && args.strip_prefix.is_none()
+        && args.key_case.is_none()
+        && !args.raw
+}
+
+/// Fetch the requested secrets in batches of this size when streaming the response.
+const STREAMED_BATCH_SIZE: usize = 100;
+
+/// Fetch the requested secrets in concurrent batches and write the entries of every batch to the
+/// response as soon as it's fetched, returning the exit code for them. The request is read on a
+/// blocking thread dispatching batches to a task fetching them while the rest of the names are
+/// read, so that fetches overlap with reading a large request on any runtime and the names are
+/// never held at once, and all batches are fetched at once, so that the deadline and concurrency
+/// are the same as for a single request. Once the request turns out to be over the limit or
+/// invalid, no more batches are dispatched and the fetches in flight are cancelled. Nothing is
+/// written until the request was read in full.
+async fn respond_streamed(
+    secrets_loader: Arc<dyn LoadSecrets + Send + Sync>,
+    read: impl FnOnce(&mut dyn FnMut(String)) -> Result<(), exit::Failure> + Send + 'static,
+    max_secrets: usize,
+    args: &FetchArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    use futures::StreamExt;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    let (batch_sender, mut batch_receiver) = unbounded_channel::<Vec<String>>();
+    let (fetched_sender, fetched_receiver) = unbounded_channel();
+    // The fetches in flight are cancelled when the task is dropped, e.g. on an invalid request.
+    let mut fetching = tokio::task::JoinSet::new();
+    let request = aws::loader::current_request();
+    let fetches = fetching.spawn(aws::loader::within(request, async move {
+        let mut batches = futures::stream::FuturesUnordered::new();
+        let mut reading = true;
+        while reading || !batches.is_empty() {
+            tokio::select! {
+                names = batch_receiver.recv(), if reading => match names {
+                    Some(names) => {
+                        batches.push(secrets_loader.load(vector::SecretsToFetch::from_names(names)))
+                    }
+                    None => reading = false,
+                },
+                Some(fetched_secrets) = batches.next() => {
+                    let _ = fetched_sender.send(fetched_secrets);
+                }
+            }
+        }
+    }));
+
+    let reading = tokio::task::spawn_blocking(move || {
+        // Names repeated across batches would be fetched again.
+        let mut requested = std::collections::HashSet::new();
+        let mut batch = Vec::new();
+        let mut count = 0;
+        read(&mut |name| {
+            // Names over the limit are only counted, to report their number.
+            count += 1;
+            if count > max_secrets {
+                fetches.abort();
+                return;
+            }
+            if !requested.insert(name.clone()) {
+                return;
+            }
+            batch.push(name);
+            if batch.len() == STREAMED_BATCH_SIZE {
+                let _ = batch_sender.send(std::mem::take(&mut batch));
+            }
+        })?;
+        vector::SecretsToFetch::check_count(count, max_secrets)
+            .map_err(|error| exit::Failure::new(exit::INPUT_ERROR, error))?;
+        if !batch.is_empty() {
+            let _ = batch_sender.send(batch);
+        }
+        Ok(())
+    });
+    reading.await.map_err(|error| {
+        exit::Failure::new(1, format!("failed to read the request: {}", error))
+    })??;
+
+    let fetched = tokio_stream::wrappers::UnboundedReceiverStream::new(fetched_receiver);
+    let code = write_streamed(fetched, args, writer).await?;
+    // A task that failed would have cut the response short.
+    while let Some(result) = fetching.join_next().await {
+        result.map_err(|error| {
+            exit::Failure::new(1, format!("failed to fetch the secrets: {}", error))
+        })?;
+    }
+    Ok(code)
+}
+
+/// Write the entries of every batch of fetched secrets to the response as soon as it's fetched,
+/// returning the exit code for them.
+async fn write_streamed(
+    mut batches: impl futures::Stream<Item = vector::FetchedSecrets> + Unpin,
+    args: &FetchArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    use futures::StreamExt;
+
+    let mut response = output::StreamedResponse::new(writer);
+    let mut tally = exit::Tally::default();
+    // Keys added by the loaders, e.g. expanded or fanned out ones, can repeat those of earlier
+    // batches, which would make the response invalid JSON, so only their first entry is written.
+    let mut written = std::collections::HashSet::new();
+    while let Some(mut fetched_secrets) = batches.next().await {
+        fetched_secrets.0.retain(|key, _| {
+            let first = written.insert(key.clone());
+            if !first {
+                Diagnostic::new(
+                    Level::Warning,
+                    "duplicate_key_skipped",
+                    format!("skipped the repeated entry of {}", key),
+                )
+                .secret(key)
+                .emit();
+            }
+            first
+        });
+        if let Some(encoding) = args.encode_values {
+            output::encode_values(&mut fetched_secrets, encoding);
+        }
+        if args.hash_only {
+            output::hash_values(&mut fetched_secrets);
+        }
+        if diagnostics::format() == diagnostics::Format::Json {
+            report_failures(&fetched_secrets);
+        }
+        tally.add(&fetched_secrets);
+        response
+            .write(&fetched_secrets)
+            .map_err(|error| exit::Failure::new(1, format!("failed to write stdout: {}", error)))?;
+    }
+    response
+        .finish()
+        .map_err(|error| exit::Failure::new(1, format!("failed to write stdout: {}", error)))?;
+    Ok(tally.code(args.exit_on))
+}
+
+/// Answer the requests read from the reader, one JSON request per line, with a response line each
+/// until the reader is closed. A request that can't be parsed is answered with an empty response,
+/// so that every request line still gets its response line.
+async fn respond_persistently(
+    secrets_loader: &(dyn LoadSecrets + Send + Sync),
+    args: &FetchArgs,
+    global: &GlobalArgs,
+    reader: &mut dyn BufRead,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    if args.input_format != input::InputFormat::Vector
+        || args.output_format != output::OutputFormat::Vector
+    {
+        return Err(exit::Failure::new(
+            2,
+            "--persistent requires the vector input and output formats",
+        ));
+    }
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line).map_err(|error| {
+            exit::Failure::new(
+                exit::INPUT_ERROR,
+                format!("failed to read stdin: {}", error),
+            )
+        })?;
+        if read == 0 {
+            return Ok(0);
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let secrets_to_fetch =
+            input::read(line.as_bytes(), args.input_format).and_then(|secrets_to_fetch| {
+                secrets_to_fetch.check_limit(global.max_secrets)?;
+                Ok(secrets_to_fetch)
+            });
+        match secrets_to_fetch {
+            Ok(secrets_to_fetch) => {
+                let response = respond(secrets_loader, secrets_to_fetch, args, writer);
+                aws::loader::with_deadline(global.deadline, response).await?;
+            }
+            Err(error) => {
+                Diagnostic::new(
+                    Level::Error,
+                    "invalid_request",
+                    format!("{} from stdin", error),
+                )
+                .emit();
+                print(writer, "{}\n")?;
+            }
+        }
+    }
+}
+
+/// An enum representing why refreshing the files of the requested secrets stopped.
+#[derive(Debug, PartialEq)]
+enum Wake {
+    /// SIGTERM or SIGINT was received.
+    Shutdown,
+    /// SIGHUP was received, asking to reload the config file and flush the disk cache.
+    Reload,
+}
+
+/// Fetch the requested secrets every interval and write them to the output directory until
+/// SIGTERM or SIGINT is received, reusing the clients and caches between refreshes. The request is
+/// read once. On SIGHUP the config file and the AWS SDK config are reloaded, the clients recreated
+/// and the disk cache flushed, keeping the current config if the config file is invalid.
+async fn watch(
+    backend: Backend,
+    args: FetchArgs,
+    interval: Duration,
+    config: &config::Config,
+    global: &GlobalArgs,
+    reader: &mut dyn BufRead,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    check_output_args(&args)?;
+    let names = read_request(
+        args.secrets.clone(),
+        args.input_file.clone(),
+        args.input_format,
+        global,
+        reader,
+    )?
+    .secrets;
+    // The signals are handled from the start, so that one received during a refresh takes effect
+    // once the refresh is done, never leaving the files half updated.
+    let mut shutdown = std::pin::pin!(shutdown_signal()?);
+    let mut hangup = hangup_signal()?;
+
+    let mut reloaded = None;
+    loop {
+        let config = reloaded.as_ref().unwrap_or(config);
+        let aws_sdk_config = LazySdkConfig::new(config, global);
+        let disk_cache = disk_cache(&aws_sdk_config, global).await;
+        let secrets_loader = loader(
+            backend,
+            &aws_sdk_config,
+            config,
+            global,
+            disk_cache.as_ref(),
+        )
+        .await;
+        let wake = async {
+            tokio::select! {
+                _ = &mut shutdown => Wake::Shutdown,
+                Some(_) = hangup.recv() => Wake::Reload,
+            }
+        };
+        let woken = refresh(
+            secrets_loader.as_ref(),
+            &names,
+            &args,
+            interval,
+            global,
+            writer,
+            wake,
+        );
+        match woken.await {
+            Wake::Shutdown => break,
+            Wake::Reload => {
+                if let Some(config) = reload_config(global, disk_cache.as_deref()) {
+                    reloaded = Some(config);
+                }
+            }
+        }
+    }
+    Diagnostic::new(
+        Level::Info,
+        "shutting_down",
+        "shutting down after the last refresh",
+    )
+    .emit();
+    Ok(0)
+}
+
+/// Refresh the files of the requested secrets every interval until woken, each refresh with a
+/// deadline of its own. Every file is replaced atomically, so readers never see a partially
+/// written one, and the files of secrets that couldn't be fetched are left in place. A failed
+/// refresh is reported and retried at the next interval. A refresh in progress is always finished
+/// before returning.
+async fn refresh(
+    secrets_loader: &(dyn LoadSecrets + Send + Sync),
+    names: &[String],
+    args: &FetchArgs,
+    interval: Duration,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+    wake: impl std::future::Future<Output = Wake>,
+) -> Wake {
+    let mut wake = std::pin::pin!(wake);
+    loop {
+        let secrets_to_fetch = vector::SecretsToFetch::from_names(names.to_vec());
+        let response = respond(secrets_loader, secrets_to_fetch, args, writer);
+        if let Err(failure) = aws::loader::with_deadline(global.deadline, response).await {
+            Diagnostic::new(
+                Level::Error,
+                "refresh_failed",
+                format!("failed to refresh the secrets: {}", failure.message),
+            )
+            .emit();
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            wake = &mut wake => return wake,
+        }
+    }
+}
+
+/// Fetch the requested secrets from several backends in order of precedence and return them in
+/// the format expected by Vector, returning the exit code for them.
+#[cfg(all(feature = "ssm", feature = "secretsmanager"))]
+async fn fetch_multi(
+    args: MultiArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    reader: &mut dyn BufRead,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    if args.fetch.watch.is_some() {
+        return Err(exit::Failure::new(2, "--watch can't be used with multi"));
+    }
+    // One-shot requests have a single deadline covering setting up the backends, e.g. resolving
+    // credentials and assuming roles, and all of their fetches. Persistent and interactive ones
+    // get a deadline per request instead.
+    let deadline = one_shot_deadline(&args.fetch, global);
+    aws::loader::with_deadline(deadline, async move {
+        let mut names = std::collections::HashSet::new();
+        if let Some(backend) = args
+            .precedence
+            .iter()
+            .find(|backend| !names.insert(backend.name()))
+        {
+            return Err(exit::Failure::new(
+                1,
+                format!("duplicate backend in --precedence: {}", backend.name()),
+            ));
+        }
+        let fetch_args = args.fetch;
+        let interactive = is_interactive(&fetch_args, global);
+        let secrets_to_fetch = match fetch_args.persistent || interactive {
+            true => None,
+            false => Some(read_request(
+                fetch_args.secrets.clone(),
+                fetch_args.input_file.clone(),
+                fetch_args.input_format,
+                global,
+                reader,
+            )?),
+        };
+
+        // Every backend is wrapped in its own cache, validation and audit log, while optional secrets
+        // only get their defaults once they weren't found in any backend.
+        let aws_sdk_config = LazySdkConfig::new(config, global);
+        let disk_cache = disk_cache(&aws_sdk_config, global).await;
+        let mut backends = Vec::new();
+        for backend in args.precedence.iter().copied() {
+            let loader = backend_loader(
+                backend,
+                &aws_sdk_config,
+                config,
+                global,
+                disk_cache.as_ref(),
+            )
+            .await;
+            backends.push((backend.name(), loader));
+        }
+        let secrets_loader = requested_loader(
+            Box::new(multi::MultiLoader::new(backends)),
+            config,
+            global,
+            &args.precedence,
+            global.allowed_variables(),
+        );
+        match secrets_to_fetch {
+            Some(secrets_to_fetch) => {
+                respond(
+                    secrets_loader.as_ref(),
+                    secrets_to_fetch,
+                    &fetch_args,
+                    writer,
+                )
+                .await
+            }
+            None if interactive => {
+                prompt(secrets_loader.as_ref(), &fetch_args, global, reader, writer).await
+            }
+            None => {
+                respond_persistently(secrets_loader.as_ref(), &fetch_args, global, reader, writer)
+                    .await
+            }
+        }
+    })
+    .await
+}
+
+/// Report the secrets that couldn't be fetched on stderr, for output formats that can't hold
+/// errors and for JSON diagnostics.
+fn report_failures(fetched_secrets: &vector::FetchedSecrets) {
+    for (name, secret) in &fetched_secrets.0 {
+        if let Some(error) = &secret.error {
+            Diagnostic::new(
+                Level::Error,
+                "secret_fetch_failed",
+                format!("failed to fetch {}: {}", name, error),
+            )
+            .secret(name)
+            .code(secret.code)
+            .emit();
+        }
+    }
+}
+
+/// Check the output options that clap can't, failing with 2 if they don't fit the output format.
+fn check_output_args(args: &FetchArgs) -> Result<(), exit::Failure> {
+    // Clap requires the options of the output format, but can't reject those of other formats.
+    let writes_files = matches!(
+        args.output_format,
+        output::OutputFormat::SystemdCreds | output::OutputFormat::DockerSecrets
+    );
+    if args.output_dir.is_some() && !writes_files {
+        return Err(exit::Failure::new(
+            2,
+            "--output-dir requires a format writing a file per secret, e.g. --output-format systemd-creds",
+        ));
+    }
+    if args.secret_name.is_some() && args.output_format != output::OutputFormat::K8sSecret {
+        return Err(exit::Failure::new(
+            2,
+            "--secret-name requires --output-format k8s-secret",
+        ));
+    }
+    Ok(())
+}
+
+/// Fetch the requested secrets with the loader and write them to stdout or the output file in the
+/// format expected by Vector, returning the exit code for them.
+async fn respond(
+    secrets_loader: &(dyn LoadSecrets + Send + Sync),
+    secrets_to_fetch: vector::SecretsToFetch,
+    args: &FetchArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    check_output_args(args)?;
+    if args.raw {
+        return respond_raw(secrets_loader, secrets_to_fetch, args, writer).await;
+    }
+    // Large responses written to stdout as is are streamed, while the other outputs need all keys
+    // at once, e.g. to check that rewritten keys don't collide.
+    if is_streamable(args) && secrets_to_fetch.secrets.len() > STREAMED_BATCH_SIZE {
+        // The limit was checked when the request was read.
+        let mut requested = std::collections::HashSet::new();
+        let mut names = secrets_to_fetch.secrets;
+        names.retain(|name| requested.insert(name.clone()));
+        let batches: futures::stream::FuturesUnordered<_> = names
+            .chunks(STREAMED_BATCH_SIZE)
+            .map(|names| secrets_loader.load(vector::SecretsToFetch::from_names(names.to_vec())))
+            .collect();
+        return write_streamed(batches, args, writer).await;
+    }
+    let mut fetched_secrets: vector::FetchedSecrets = secrets_loader.load(secrets_to_fetch).await;
+    if let Some(encoding) = args.encode_values {
+        output::encode_values(&mut fetched_secrets, encoding);
+    }
+    if args.hash_only {
+        output::hash_values(&mut fetched_secrets);
+    }
+    let fetched_secrets =
+        output::rewrite_keys(fetched_secrets, args.strip_prefix.as_deref(), args.key_case)
+            .map_err(|error| exit::Failure::new(1, error))?;
+    let output = match args.output_format {
+        output::OutputFormat::Vector => {
+            // The response holds the errors, which agents can't see, so they are only summarized
+            // for them.
+            if diagnostics::format() == diagnostics::Format::Json {
+                report_failures(&fetched_secrets);
+            }
+            serde_json::to_string(&fetched_secrets).unwrap()
+        }
+        output::OutputFormat::SystemdCreds | output::OutputFormat::DockerSecrets => {
+            report_failures(&fetched_secrets);
+            let dir = args.output_dir.as_ref().unwrap();
+            let read_only = args.output_format == output::OutputFormat::DockerSecrets;
+            output::write_files(dir, &fetched_secrets, read_only)
+                .map_err(|error| exit::Failure::new(1, error))?;
+            return Ok(exit::code(&fetched_secrets, args.exit_on));
+        }
+        output::OutputFormat::K8sSecret => {
+            report_failures(&fetched_secrets);
+            let name = args.secret_name.as_deref().unwrap();
+            let manifest =
+                output::k8s_secret_manifest(&fetched_secrets, name, args.namespace.as_deref())
+                    .map_err(|error| exit::Failure::new(1, error))?;
+            manifest.trim_end().to_string()
+        }
+    };
+    let (result, destination) = match &args.output_file {
+        Some(path) => (
+            output::write_atomically(path, format!("{}\n", output).as_bytes()),
+            path.display().to_string(),
+        ),
+        None => (output::write_line(writer, &output), String::from("stdout")),
+    };
+    if let Err(error) = result {
+        return Err(exit::Failure::new(
+            1,
+            format!("failed to write {}: {}", destination, error),
+        ));
+    }
+
+    Ok(exit::code(&fetched_secrets, args.exit_on))
+}
+
+/// Fetch the single requested secret with the loader and print only its value, reporting its
+/// error on stderr if it couldn't be fetched, returning the exit code for it.
+async fn respond_raw(
+    secrets_loader: &(dyn LoadSecrets + Send + Sync),
+    secrets_to_fetch: vector::SecretsToFetch,
+    args: &FetchArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    if secrets_to_fetch.secrets.len() != 1 {
+        return Err(exit::Failure::new(
+            2,
+            format!(
+                "--raw requires exactly one secret, {} were requested",
+                secrets_to_fetch.secrets.len()
+            ),
+        ));
+    }
+    let name = secrets_to_fetch.secrets[0].clone();
+    let mut fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+    if let Some(encoding) = args.encode_values {
+        output::encode_values(&mut fetched_secrets, encoding);
+    }
+    // Expanded JSON secrets come with their fields, of which only the requested secret is printed.
+    fetched_secrets.0.retain(|key, _| *key == name);
+    match fetched_secrets.0.get(&name) {
+        Some(vector::FetchedSecret {
+            value: Some(value), ..
+        }) => print(writer, value.expose_secret())?,
+        _ => report_failures(&fetched_secrets),
+    }
+    // Nothing is printed for a failed secret, so its failure must show in the exit code.
+    Ok(exit::code(&fetched_secrets, exit::ExitOn::Total))
+}
+
+/// Create the secrets loaders for all backends served over gRPC, which never expand placeholders in
+/// the requested names, since clients could read the environment of the server through them.
+async fn serve_loaders(
+    config: &config::Config,
+    global: &GlobalArgs,
+    metrics: Option<&Arc<metrics::Metrics>>,
+    disk_cache: Option<&Arc<cache::DiskCache>>,
+) -> server::Loaders {
+    let aws_sdk_config = LazySdkConfig::new(config, global);
+
+    let mut loaders: server::Loaders = Default::default();
+    for &backend in Backend::value_variants() {
+        let loader = backend_loader(backend, &aws_sdk_config, config, global, disk_cache).await;
+        let loader = requested_loader(loader, config, global, &[backend], Default::default());
+        let loader = metered(loader, backend.name(), metrics);
+        loaders.insert(backend.name().to_string(), loader);
+    }
+    loaders
+}
+
+/// Fetch a single secret and print its value or JSON entry, reporting where and as whom it was
+/// fetched on stderr, returning the exit code for it.
+async fn get(
+    args: GetArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let endpoint_url = backend_endpoint_url(args.backend, global);
+    Diagnostic::new(
+        Level::Info,
+        "backend_selected",
+        format!(
+            "backend: {} at {}",
+            args.backend.name(),
+            endpoint_url.as_deref().unwrap_or("its default endpoint")
+        ),
+    )
+    .detail("backend", args.backend.name())
+    .detail("endpoint_url", endpoint_url)
+    .emit();
+
+    let aws_sdk_config = LazySdkConfig::new(config, global);
+    #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+    if args.backend.is_aws() {
+        let regions = config.secret_regions();
+        report_aws_identity(
+            args.backend,
+            &args.name,
+            &regions,
+            aws_sdk_config.get().await,
+            global,
+        )
+        .await;
+    }
+    let disk_cache = disk_cache(&aws_sdk_config, global).await;
+    if let Some(dir) = disk_cache.as_ref().and(global.cache.disk_cache.as_ref()) {
+        Diagnostic::new(
+            Level::Info,
+            "disk_cache_enabled",
+            format!("disk cache: {}", dir.display()),
+        )
+        .detail("dir", dir.display().to_string())
+        .emit();
+    }
+    let loader = loader(
+        args.backend,
+        &aws_sdk_config,
+        config,
+        global,
+        disk_cache.as_ref(),
+    )
+    .await;
+
+    let start = std::time::Instant::now();
+    let fetched_secrets = loader
+        .load(vector::SecretsToFetch::from_names(vec![args.name.clone()]))
+        .await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+    let Some(secret) = fetched_secrets.0.get(&args.name) else {
+        return Err(exit::Failure::new(
+            1,
+            format!("no result for {}", args.name),
+        ));
+    };
+    match (&secret.value, &secret.error) {
+        (Some(value), _) => {
+            Diagnostic::new(
+                Level::Info,
+                "secret_fetched",
+                format!("fetched {} in {} ms", args.name, duration_ms),
+            )
+            .secret(&args.name)
+            .detail("duration_ms", duration_ms)
+            .emit();
+            if args.raw {
+                print(writer, value.expose_secret())?;
+            }
+        }
+        (None, error) => {
+            Diagnostic::new(
+                Level::Error,
+                "secret_fetch_failed",
+                format!(
+                    "failed to fetch {} in {} ms: {}",
+                    args.name,
+                    duration_ms,
+                    error.as_deref().unwrap_or_default()
+                ),
+            )
+            .secret(&args.name)
+            .code(secret.code)
+            .detail("duration_ms", duration_ms)
+            .emit();
+        }
+    }
+    if !args.raw {
+        print(
+            writer,
+            format!("{}\n", serde_json::to_string(&fetched_secrets).unwrap()),
+        )?;
+    }
+    Ok(exit::code(&fetched_secrets, exit::ExitOn::Total))
+}
+
+/// Report the region, credentials and identity used to fetch a secret from AWS on stderr.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+async fn report_aws_identity(
+    backend: Backend,
+    name: &str,
+    regions: &HashMap<String, String>,
+    aws_sdk_config: &SdkConfig,
+    global: &GlobalArgs,
+) {
+    use aws_credential_types::provider::ProvideCredentials;
+
+    match requested_aws_region(backend, name, regions, aws_sdk_config) {
+        Ok((region, source)) => {
+            let partition = region.as_deref().map(aws::arn::partition_of);
+            Diagnostic::new(
+                Level::Info,
+                "region_selected",
+                match (&region, partition) {
+                    (Some(region), Some(partition)) => {
+                        format!("region: {} ({}) from {}", region, partition, source)
+                    }
+                    _ => String::from("region: not configured"),
+                },
+            )
+            .detail("region", region)
+            .detail("partition", partition)
+            .emit();
+        }
+        Err(error) => {
+            Diagnostic::new(Level::Error, "region_invalid", format!("region: {}", error)).emit()
+        }
+    }
+
+    let Some(provider) = aws_sdk_config.credentials_provider() else {
+        Diagnostic::new(
+            Level::Error,
+            "credentials_failed",
+            "credentials: no credentials provider configured",
+        )
+        .emit();
+        return;
+    };
+    match provider.provide_credentials().await {
+        Ok(credentials) => {
+            let expiry = credentials
+                .expiry()
+                .map(|expiry| humantime::format_rfc3339_seconds(expiry).to_string());
+            Diagnostic::new(
+                Level::Info,
+                "credentials_loaded",
+                format!(
+                    "credentials: access key {}{}",
+                    credentials.access_key_id(),
+                    expiry
+                        .as_ref()
+                        .map(|expiry| format!(" expiring at {}", expiry))
+                        .unwrap_or_default()
+                ),
+            )
+            .detail("access_key_id", credentials.access_key_id())
+            .detail("expiry", expiry)
+            .emit();
+        }
+        // The shared credentials already reported the failure.
+        Err(_) => return,
+    }
+    match aws::sts::caller_arn(&sts_client(aws_sdk_config, global)).await {
+        Ok(caller) => Diagnostic::new(
+            Level::Info,
+            "caller_identified",
+            format!("caller: {}", caller),
+        )
+        .detail("caller", caller)
+        .emit(),
+        Err(error) => Diagnostic::new(
+            Level::Warning,
+            "caller_identity_failed",
+            format!("failed to get the caller identity: {}", error),
+        )
+        .emit(),
+    }
+}
+
+/// Serve secrets from every enabled backend over gRPC until the server fails or is asked to shut
+/// down.
+async fn serve(
+    args: ServeArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+) -> Result<i32, exit::Failure> {
+    let metrics = args.metrics_addr.map(|_| Arc::new(metrics::Metrics::new()));
+    let disk_cache = disk_cache(&LazySdkConfig::new(config, global), global).await;
+    let loaders = server::SharedLoaders::new(
+        serve_loaders(config, global, metrics.as_ref(), disk_cache.as_ref()).await,
+    );
+
+    // Reload the config file and the AWS SDK config, recreate the loaders and flush the disk cache
+    // on SIGHUP, keeping the current loaders if the config file is invalid.
+    {
+        let mut hangup = hangup_signal()?;
+        let (loaders, global, metrics) = (loaders.clone(), global.clone(), metrics.clone());
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if let Some(config) = reload_config(&global, disk_cache.as_deref()) {
+                    loaders.replace(
+                        serve_loaders(&config, &global, metrics.as_ref(), disk_cache.as_ref())
+                            .await,
+                    );
+                }
+            }
+        });
+    }
+
+    // Expose the metrics while the gRPC server is running, stopping it if the metrics can't be.
+    let metrics_server = async {
+        match (args.metrics_addr, metrics) {
+            (Some(addr), Some(metrics)) => metrics::serve(addr, metrics).await,
+            _ => std::future::pending().await,
+        }
+    };
+
+    // Serve requests until the server fails or SIGTERM or SIGINT is received, nothing is read
+    // from stdin in this mode.
+    let settings = server::ServerSettings {
+        addr: args.grpc,
+        tls_cert: args.tls_cert,
+        tls_key: args.tls_key,
+        request_timeout: args.request_timeout,
+    };
+    let service = server::SecretsResolverService::new(loaders, global.max_secrets);
+    let shutdown_signal = shutdown_signal()?;
+    let shutdown = async {
+        shutdown_signal.await;
+        Diagnostic::new(
+            Level::Info,
+            "shutting_down",
+            "shutting down after requests in flight are done",
+        )
+        .emit();
+    };
+    tokio::select! {
+        served = server::serve(settings, service, shutdown) => match served {
+            Ok(()) => Ok(0),
+            Err(error) => Err(exit::Failure::new(
+                1,
+                format!("failed to run the gRPC server: {}", error),
+            )),
+        },
+        Err(error) = metrics_server => Err(exit::Failure::new(
+            1,
+            format!("failed to run the metrics server: {}", error),
+        )),
+    }
+}
+
+/// Handle the signals requesting a resident mode to shut down, i.e. SIGTERM and SIGINT.
+fn shutdown_signal() -> Result<impl std::future::Future<Output = ()>, exit::Failure> {
+    server::shutdown_signal().map_err(|error| {
+        exit::Failure::new(1, format!("failed to handle SIGTERM and SIGINT: {}", error))
+    })
+}
+
+/// Handle SIGHUP, which asks a resident mode to reload the config file and flush the disk cache.
+fn hangup_signal() -> Result<server::Hangup, exit::Failure> {
+    server::Hangup::new()
+        .map_err(|error| exit::Failure::new(1, format!("failed to handle SIGHUP: {}", error)))
+}
+
+/// Flush the disk cache, if any, and reload the config file, returning None if it's invalid so
+/// that the current config is kept.
+fn reload_config(
+    global: &GlobalArgs,
+    disk_cache: Option<&cache::DiskCache>,
+) -> Option<config::Config> {
+    Diagnostic::new(Level::Info, "config_reloading", "reloading the config").emit();
+    if let Some(disk_cache) = disk_cache {
+        match disk_cache.clear() {
+            Ok(removed) => Diagnostic::new(
+                Level::Info,
+                "disk_cache_flushed",
+                format!("flushed {} cached secrets", removed),
+            )
+            .detail("removed", removed)
+            .emit(),
+            Err(error) => Diagnostic::new(
+                Level::Warning,
+                "disk_cache_flush_failed",
+                format!("failed to flush the disk cache: {}", error),
+            )
+            .emit(),
+        }
+    }
+    match global.load_config() {
+        Ok(config) => Some(config),
+        Err(error) => {
+            Diagnostic::new(
+                Level::Error,
+                "config_reload_failed",
+                format!("failed to reload the config: {}", error),
+            )
+            .emit();
+            None
+        }
+    }
+}
+
+/// Check the health of the backend and print a report, returning 1 if it is unhealthy.
+async fn healthcheck(
+    args: HealthcheckArgs,
+    #[cfg_attr(
+        not(any(feature = "ssm", feature = "secretsmanager")),
+        allow(unused_variables)
+    )]
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let report = match args.backend {
+        #[cfg(feature = "ssm")]
+        Backend::Ssm => {
+            let aws_sdk_config = load_aws_sdk_config(&config.role_chain, None, global).await;
+            let credentials = healthcheck::check_credentials(&aws_sdk_config).await;
+            let client = ssm_client(&aws_sdk_config, global);
+            let loader = ssm_loader(client.clone(), config.secret_regions(), global);
+            let canary = args.canary.map(|name| (&loader as &dyn LoadSecrets, name));
+            healthcheck::run(credentials, &client, canary).await
+        }
+        #[cfg(feature = "secretsmanager")]
+        Backend::Secretsmanager => {
+            let aws_sdk_config = load_aws_sdk_config(&config.role_chain, None, global).await;
+            let credentials = healthcheck::check_credentials(&aws_sdk_config).await;
+            let client = secretsmanager_client(&aws_sdk_config, global);
+            let loader = secretsmanager_loader(client.clone(), config.secret_regions(), global);
+            let canary = args.canary.map(|name| (&loader as &dyn LoadSecrets, name));
+            healthcheck::run(credentials, &client, canary).await
+        }
+        #[cfg(feature = "gcp")]
+        Backend::Gcp => {
+            let client = gcp_client(global).await;
+            let credentials = client.check_credentials().await;
+            let loader = gcp::GcpSecretsLoader::new(client.clone(), global.loader_settings());
+            let canary = args.canary.map(|name| (&loader as &dyn LoadSecrets, name));
+            healthcheck::run(credentials, &client, canary).await
+        }
+        #[cfg(feature = "azure")]
+        Backend::Azure => {
+            let http = global.connections().reqwest();
+            let client = azure::AzureClient::new(http.clone(), azure::AzureCredential::from_env());
+            let credentials = client.check_credentials().await;
+            let loader = azure_loader(global);
+            let endpoint = azure::VaultEndpoint {
+                http,
+                vault_url: loader.vault_url(None),
+            };
+            let canary = args.canary.map(|name| (&loader as &dyn LoadSecrets, name));
+            healthcheck::run(credentials, &endpoint, canary).await
+        }
+        #[cfg(feature = "k8s")]
+        Backend::K8s => {
+            let client = k8s::K8sClient::new(global.endpoint_url.as_deref());
+            let credentials = client.check_credentials().await;
+            let loader = k8s::K8sSecretsLoader::new(client.clone(), global.loader_settings());
+            let canary = args.canary.map(|name| (&loader as &dyn LoadSecrets, name));
+            healthcheck::run(credentials, &client, canary).await
+        }
+        #[cfg(feature = "op")]
+        Backend::Op => {
+            let client = op::OpClient::new(
+                global.connections().reqwest(),
+                global.endpoint_url.as_deref(),
+            );
+            let credentials = client.check_credentials().await;
+            let loader = op::OpSecretsLoader::new(client.clone(), global.loader_settings());
+            let canary = args.canary.map(|name| (&loader as &dyn LoadSecrets, name));
+            healthcheck::run(credentials, &client, canary).await
+        }
+    };
+
+    print(writer, &report)?;
+    Ok(if report.is_healthy() { 0 } else { 1 })
+}
+
+/// Print the secrets referenced in Vector configs as requests, or fetch them and print whether each
+/// of them could be fetched, never their values, returning 1 if any of them can't be fetched.
+async fn scan(
+    args: ScanArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let mut references = scan::References::new();
+    for path in &args.paths {
+        scan::scan(path, &mut references).map_err(|error| {
+            exit::Failure::new(1, format!("failed to scan {}: {}", path.display(), error))
+        })?;
+    }
+
+    // Print the requests for all secrets backends unless a single one was selected.
+    let Some(secret_backend) = args.secret_backend else {
+        let requests: BTreeMap<_, _> = references
+            .into_iter()
+            .map(|(name, secrets)| {
+                let secrets_to_fetch =
+                    vector::SecretsToFetch::from_names(secrets.into_iter().collect());
+                (name, secrets_to_fetch)
+            })
+            .collect();
+        print(
+            writer,
+            format!("{}\n", serde_json::to_string(&requests).unwrap()),
+        )?;
+        return Ok(0);
+    };
+    let secrets = references.remove(&secret_backend).unwrap_or_default();
+    let secrets_to_fetch = vector::SecretsToFetch::from_names(secrets.into_iter().collect());
+
+    let Some(backend) = args.fetch else {
+        print(
+            writer,
+            format!("{}\n", serde_json::to_string(&secrets_to_fetch).unwrap()),
+        )?;
+        return Ok(0);
+    };
+    secrets_to_fetch
+        .check_limit(global.max_secrets)
+        .map_err(|error| exit::Failure::new(1, error))?;
+    let aws_sdk_config = LazySdkConfig::new(config, global);
+    let fetched_secrets = loader(backend, &aws_sdk_config, config, global, None)
+        .await
+        .load(secrets_to_fetch)
+        .await;
+    // Only the status of every secret is printed, so that values never end up in CI logs.
+    let mut failed = false;
+    let fetched: BTreeMap<_, _> = fetched_secrets.0.into_iter().collect();
+    for (name, secret) in &fetched {
+        match (&secret.error, secret.code) {
+            (None, _) => print(writer, format!("ok: {}\n", name))?,
+            (Some(error), code) => {
+                failed = true;
+                let code = code.unwrap_or(vector::ErrorCode::Unknown).as_str();
+                print(writer, format!("failed: {}: {}: {}\n", name, code, error))?;
+            }
+        }
+    }
+    Ok(if failed { 1 } else { 0 })
+}
+
+/// Benchmark fetching secrets from the backend and print a report.
+async fn bench(
+    args: BenchArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let aws_sdk_config = LazySdkConfig::new(config, global);
+    // Load the AWS SDK config up front, so that it isn't measured as part of the first request.
+    if args.backend.is_aws() {
+        aws_sdk_config.get().await;
+    }
+    // The disk cache is bypassed to measure fetching from AWS.
+    let loader = loader(args.backend, &aws_sdk_config, config, global, None).await;
+
+    let settings = bench::BenchSettings {
+        iterations: args.iterations as usize,
+        concurrency: args.concurrency as usize,
+    };
+    let report = bench::run(
+        loader.as_ref(),
+        args.backend.name(),
+        args.secrets,
+        &settings,
+    )
+    .await;
+    print(writer, &report)?;
+    Ok(0)
+}
+
+/// Print the identity and region used to call AWS, failing with 1 if it can't be determined.
+async fn whoami(
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let aws_sdk_config = load_aws_sdk_config(&config.role_chain, None, global).await;
+    let identity = aws::sts::caller_identity(&sts_client(&aws_sdk_config, global))
+        .await
+        .map_err(|error| {
+            exit::Failure::new(1, format!("failed to get the caller identity: {}", error))
+        })?;
+    print(writer, &identity)?;
+    match aws_sdk_config.region() {
+        Some(region) => print(writer, format!("region: {}\n", region))?,
+        None => print(writer, "region: not configured\n")?,
+    }
+    Ok(0)
+}
+
+/// Fetch secrets into the disk cache, failing with 1 if the cache isn't configured and returning 1
+/// if any of the secrets can't be fetched or cached.
+async fn warm(
+    args: WarmArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    reader: &mut dyn BufRead,
+) -> Result<i32, exit::Failure> {
+    let secrets_to_fetch = read_request(
+        args.secrets,
+        args.input_file,
+        args.input_format,
+        global,
+        reader,
+    )?;
+    // Secrets are cached under the names they are fetched with.
+    let secrets = secrets_to_fetch
+        .secrets
+        .iter()
+        .map(|name| interpolate::expand_env(name, &global.allowed_variables()))
+        .collect::<Result<_, _>>()
+        .map_err(|error| exit::Failure::new(exit::INPUT_ERROR, error))?;
+    let secrets_to_fetch = vector::SecretsToFetch::from_names(secrets);
+    if global.cache.disk_cache.is_none() {
+        return Err(exit::Failure::new(
+            1,
+            "the warm command requires --disk-cache",
+        ));
+    }
+    let aws_sdk_config = LazySdkConfig::new(config, global);
+    let Some(disk_cache) = disk_cache(&aws_sdk_config, global).await else {
+        return Err(exit::Failure::new(1, "failed to open the disk cache"));
+    };
+
+    // Always fetch from AWS, so that the TTL of the cached secrets starts now. The defaults of
+    // optional secrets are never cached.
+    let loader = backend_loader(args.backend, &aws_sdk_config, config, global, None).await;
+    let requested = secrets_to_fetch.secrets.len();
+    let errors = cache::warm(
+        &disk_cache,
+        args.backend.name(),
+        loader.as_ref(),
+        secrets_to_fetch,
+        global.cache.if_changed,
+    )
+    .await;
+    for (name, error) in &errors {
+        Diagnostic::new(
+            Level::Error,
+            "secret_cache_failed",
+            format!("failed to cache {}: {}", name, error),
+        )
+        .secret(name)
+        .emit();
+    }
+    Diagnostic::new(
+        Level::Info,
+        "warm_finished",
+        format!(
+            "cached {} of {} secrets",
+            requested - errors.len(),
+            requested
+        ),
+    )
+    .detail("cached", requested - errors.len())
+    .detail("requested", requested)
+    .emit();
+    Ok(if errors.is_empty() { 0 } else { 1 })
+}
+
+/// Render a template with the secrets it references, failing with 1 without writing anything if
+/// any of them can't be fetched.
+async fn render(
+    args: RenderArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let template = std::fs::read_to_string(&args.template).map_err(|error| {
+        exit::Failure::new(
+            exit::INPUT_ERROR,
+            format!("failed to read {}: {}", args.template.display(), error),
+        )
+    })?;
+    let secrets_to_fetch = vector::SecretsToFetch::from_names(render::references(&template));
+    secrets_to_fetch
+        .check_limit(global.max_secrets)
+        .map_err(|error| exit::Failure::new(exit::INPUT_ERROR, error))?;
+
+    let aws_sdk_config = LazySdkConfig::new(config, global);
+    let disk_cache = disk_cache(&aws_sdk_config, global).await;
+    let loader = loader(
+        args.backend,
+        &aws_sdk_config,
+        config,
+        global,
+        disk_cache.as_ref(),
+    )
+    .await;
+    let fetched_secrets = loader.load(secrets_to_fetch).await;
+    let rendered = render::render(&template, &fetched_secrets).map_err(|failed| {
+        for name in &failed {
+            let error = fetched_secrets
+                .0
+                .get(name)
+                .and_then(|secret| secret.error.as_deref());
+            Diagnostic::new(
+                Level::Error,
+                "secret_fetch_failed",
+                format!("failed to fetch {}: {}", name, error.unwrap_or_default()),
+            )
+            .secret(name)
+            .code(fetched_secrets.0.get(name).and_then(|secret| secret.code))
+            .emit();
+        }
+        exit::Failure::new(
+            1,
+            format!(
+                "not rendering {}: {} secrets couldn't be fetched",
+                args.template.display(),
+                failed.len()
+            ),
+        )
+    })?;
+
+    match &args.output_file {
+        Some(path) => output::write_atomically(path, rendered.as_bytes()).map_err(|error| {
+            exit::Failure::new(1, format!("failed to write {}: {}", path.display(), error))
+        })?,
+        None => print(writer, rendered)?,
+    }
+    Ok(0)
+}
+
+/// Create or update the parameters and secrets of the fixtures file, returning 1 if any of them
+/// can't be written.
+#[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+async fn seed(
+    args: SeedArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+) -> Result<i32, exit::Failure> {
+    let fixtures = aws::seed::Fixtures::load(&args.fixtures)
+        .map_err(|error| exit::Failure::new(exit::INPUT_ERROR, error))?;
+    let aws_sdk_config = load_aws_sdk_config(&config.role_chain, None, global).await;
+
+    let mut errors = Vec::new();
+    let mut seeded = 0;
+    if !fixtures.ssm.is_empty() {
+        #[cfg(feature = "ssm")]
+        {
+            let client = ssm_client(&aws_sdk_config, global);
+            errors.extend(aws::seed::seed(&client, &fixtures.ssm).await);
+            seeded += fixtures.ssm.len();
+        }
+        #[cfg(not(feature = "ssm"))]
+        return Err(exit::Failure::new(
+            1,
+            "seeding SSM requires the ssm feature",
+        ));
+    }
+    if !fixtures.secretsmanager.is_empty() {
+        #[cfg(feature = "secretsmanager")]
+        {
+            let client = secretsmanager_client(&aws_sdk_config, global);
+            errors.extend(aws::seed::seed(&client, &fixtures.secretsmanager).await);
+            seeded += fixtures.secretsmanager.len();
+        }
+        #[cfg(not(feature = "secretsmanager"))]
+        return Err(exit::Failure::new(
+            1,
+            "seeding Secrets Manager requires the secretsmanager feature",
+        ));
+    }
+
+    for (name, error) in &errors {
+        Diagnostic::new(
+            Level::Error,
+            "secret_seed_failed",
+            format!("failed to seed {}: {}", name, error),
+        )
+        .secret(name)
+        .emit();
+    }
+    Diagnostic::new(
+        Level::Info,
+        "seed_finished",
+        format!("seeded {} of {} secrets", seeded - errors.len(), seeded),
+    )
+    .detail("seeded", seeded - errors.len())
+    .detail("requested", seeded)
+    .emit();
+    Ok(if errors.is_empty() { 0 } else { 1 })
+}
+
+/// Run the helper with the command line arguments, the first of which is the name of the program,
+/// reading requests from the reader and writing the output to the writer instead of stdin and
+/// stdout, so that the whole flow can be embedded and tested in-process. Whether the reader is a
+/// terminal decides if the interactive prompt is offered. The reader is moved to a blocking thread
+/// to read a large request while its secrets are fetched. Diagnostics are written to stderr in
+/// the format selected by `--diagnostics`, which applies to the whole process.
+///
+/// Returns the exit code documented in the README: 0 once the response is written, 1 for any
+/// other error, 2 for invalid arguments, 3 for an invalid request and 4 to 6 for failed secrets
+/// as selected by `--exit-on`.
+pub async fn run<I, T>(
+    args: I,
+    stdin_is_terminal: bool,
+    reader: impl BufRead + Send + 'static,
+    writer: impl Write,
+) -> ExitCode
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    // Parse the CLI arguments and the config file if one was passed.
+    let mut cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(error) => {
+            // Help and version requests are printed like errors, but succeed.
+            let _ = error.print();
+            return ExitCode::from(u8::try_from(error.exit_code()).unwrap_or(2));
+        }
+    };
+    diagnostics::set_format(cli.global.diagnostics);
+    cli.global.stdin_is_terminal = stdin_is_terminal;
+    let config = match cli.global.load_config() {
+        Ok(config) => config,
+        Err(error) => {
+            Diagnostic::new(Level::Error, "config_invalid", error).emit();
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // Run the command against the reader and writer, and exit with its code.
+    let code = match run_command(cli, config, reader, writer).await {
+        Ok(code) => code,
+        Err(failure) => {
+            Diagnostic::new(Level::Error, "command_failed", failure.message)
+                .detail("exit_code", failure.code)
+                .emit();
+            failure.code
+        }
+    };
+    ExitCode::from(u8::try_from(code).unwrap_or(1))
+}
+
+/// Check the combinations of arguments and config file that can't work before running the
+/// command, failing with a usage error.
+fn validate_args(cli: &Cli, config: &config::Config) -> Result<(), exit::Failure> {
+    let global = &cli.global;
+    // The MFA device and session tags only apply to assumed roles, so they would be silently
+    // ignored without any.
+    let has_roles = !global.role_arn.is_empty()
+        || !config.role_chain.is_empty()
+        || config
+            .accounts
+            .values()
+            .any(|account| account.role_arn.is_some());
+    if global.mfa_serial.is_some() && !has_roles {
+        return Err(exit::Failure::new(
+            2,
+            "--mfa-serial requires a role to assume with --role-arn or role_chain",
+        ));
+    }
+    // A code can only be used once, so the session assumed with it couldn't be refreshed without
+    // prompting from a process nobody is watching.
+    if global.mfa_serial.is_some() && cli.command.is_resident() {
+        return Err(exit::Failure::new(
+            2,
+            "--mfa-serial can't be used with serve or --persistent, as their sessions can't be \
+             refreshed without a new code",
+        ));
+    }
+    if let Some(tags) = global.session_tags() {
+        if !has_roles {
+            return Err(exit::Failure::new(
+                2,
+                "--session-tag requires a role to assume with --role-arn or role_chain",
+            ));
+        }
+        tags.check().map_err(|error| exit::Failure::new(2, error))?;
+    }
+
+    if matches!(cli.command, Commands::Serve(_)) && !global.interpolate_env.is_empty() {
+        return Err(exit::Failure::new(
+            2,
+            "--interpolate-env doesn't apply to requests over gRPC",
+        ));
+    }
+    Ok(())
+}
+
+/// Run the command with the parsed config file, reading requests from the reader and writing the
+/// output to the writer. Returns the exit code, or the failure that ended the command early.
+async fn run_command(
+    cli: Cli,
+    config: config::Config,
+    reader: impl BufRead + Send + 'static,
+    mut writer: impl Write,
+) -> Result<i32, exit::Failure> {
+    let global = &cli.global;
+    let config = &config;
+    let mut reader: Reader = Box::new(reader);
+    let (reader, writer): (&mut Reader, &mut dyn Write) = (&mut reader, &mut writer);
+
+    validate_args(&cli, config)?;
+    let command = match cli.command.into_fetch() {
+        Ok((backend, args)) => return fetch(backend, args, config, global, reader, writer).await,
+        Err(command) => *command,
+    };
+    match command {
+        #[cfg(all(feature = "ssm", feature = "secretsmanager"))]
+        Commands::Multi(args) => fetch_multi(args, config, global, reader.as_mut(), writer).await,
+        Commands::Get(args) => get(args, config, global, writer).await,
+        Commands::Serve(args) => serve(args, config, global).await,
+        Commands::Warm(args) => warm(args, config, global, reader.as_mut()).await,
+        Commands::Render(args) => render(args, config, global, writer).await,
+        #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+        Commands::Seed(args) => seed(args, config, global).await,
+        command => run_tool(command, config, global, writer).await,
+    }
+}
+
+/// Run one of the commands checking or inspecting the setup of the helper rather than answering
+/// requests for secrets.
+async fn run_tool(
+    command: Commands,
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    match command {
+        Commands::Healthcheck(args) => healthcheck(args, config, global, writer).await,
+        Commands::Scan(args) => scan(args, config, global, writer).await,
+        Commands::Bench(args) => bench(args, config, global, writer).await,
+        Commands::Whoami => whoami(config, global, writer).await,
+        Commands::ValidateConfig(args) => validate_config(args, writer),
+        Commands::Diff(args) => diff(args, config, global, writer).await,
+        Commands::Cache(command) => cache(command, config, global, writer).await,
+        _ => unreachable!("commands answering requests are run by run_command"),
+    }
+}
+
+/// Check the config files, printing their problems as `path:line:column: message`.
+fn validate_config(args: ValidateConfigArgs, writer: &mut dyn Write) -> Result<i32, exit::Failure> {
+    let mut code = 0;
+    for path in &args.paths {
+        let contents = std::fs::read_to_string(path).map_err(|error| {
+            exit::Failure::new(1, format!("failed to read {}: {}", path.display(), error))
+        })?;
+        for problem in config::Config::check(&contents) {
+            print(
+                writer,
+                format!(
+                    "{}:{}:{}: {}\n",
+                    path.display(),
+                    problem.line,
+                    problem.column,
+                    problem.message
+                ),
+            )?;
+            code = 1;
+        }
+    }
+    Ok(code)
+}
+
+/// Fetch secrets and print the ones that differ from the previous output or the disk cache as
+/// `change: name`. Exits with 5 if some secrets couldn't be fetched, 6 if none could, and 1 if any
+/// secret differs and --exit-code is passed.
+async fn diff(
+    args: DiffArgs,
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let previous_output = match &args.against {
+        Some(path) => {
+            let contents = std::fs::read_to_string(path).map_err(|error| {
+                exit::Failure::new(1, format!("failed to read {}: {}", path.display(), error))
+            })?;
+            let output: vector::FetchedSecrets =
+                serde_json::from_str(&contents).map_err(|error| {
+                    exit::Failure::new(
+                        exit::INPUT_ERROR,
+                        format!("failed to parse {}: {}", path.display(), error),
+                    )
+                })?;
+            Some(output)
+        }
+        None => None,
+    };
+    let names = match (args.secrets.is_empty(), &previous_output) {
+        (true, Some(output)) => output.0.keys().cloned().collect(),
+        _ => args.secrets,
+    };
+
+    let aws_sdk_config = LazySdkConfig::new(config, global);
+    let previous: BTreeMap<String, secret::Secret<String>> = match previous_output {
+        Some(output) => output
+            .0
+            .into_iter()
+            .filter_map(|(name, secret)| Some((name, secret.value?)))
+            .collect(),
+        None => {
+            if global.cache.disk_cache.is_none() {
+                return Err(exit::Failure::new(
+                    1,
+                    "--against-cache requires --disk-cache",
+                ));
+            }
+            let Some(disk_cache) = disk_cache(&aws_sdk_config, global).await else {
+                return Err(exit::Failure::new(1, "failed to open the disk cache"));
+            };
+            // Secrets are cached under the names they are fetched with.
+            names
+                .iter()
+                .filter_map(|name| {
+                    let fetched_name =
+                        interpolate::expand_env(name, &global.allowed_variables()).ok()?;
+                    let entry = disk_cache.get(args.backend.name(), &fetched_name)?;
+                    Some((name.clone(), entry.value))
+                })
+                .collect()
+        }
+    };
+
+    // Always fetch from the backend, since the disk cache may be what's compared against.
+    let loader = loader(args.backend, &aws_sdk_config, config, global, None).await;
+    let current = loader.load(vector::SecretsToFetch::from_names(names)).await;
+    report_changes(&previous, &current, args.exit_code, writer)
+}
+
+/// Compare the fetched secrets with the previous values and print the changes as
+/// `change: name`, returning the exit code of the diff command.
+fn report_changes(
+    previous: &BTreeMap<String, secret::Secret<String>>,
+    current: &vector::FetchedSecrets,
+    exit_code: bool,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    let changes = diff::compare(previous, current);
+    let mut failed = 0;
+    for (name, change) in &changes {
+        match change {
+            diff::Change::Failed(error) => {
+                failed += 1;
+                print(writer, format!("{}: {}: {}\n", change, name, error))?;
+            }
+            _ => print(writer, format!("{}: {}\n", change, name))?,
+        }
+    }
+    Ok(match failed {
+        0 if exit_code && !changes.is_empty() => 1,
+        0 => 0,
+        failed if failed == current.0.len() => exit::TOTAL_FAILURE,
+        _ => exit::PARTIAL_FAILURE,
+    })
+}
+
+/// Run a cache command against the disk cache, failing with 1 if the cache isn't configured or
+/// can't be accessed.
+async fn cache(
+    command: CacheCommands,
+    config: &config::Config,
+    global: &GlobalArgs,
+    writer: &mut dyn Write,
+) -> Result<i32, exit::Failure> {
+    if global.cache.disk_cache.is_none() {
+        return Err(exit::Failure::new(
+            1,
+            "the cache command requires --disk-cache",
+        ));
+    }
+    let Some(disk_cache) = disk_cache(&LazySdkConfig::new(config, global), global).await else {
+        return Err(exit::Failure::new(1, "failed to open the disk cache"));
+    };
+    match command {
+        CacheCommands::Status => cache_status(&disk_cache, writer)?,
+        CacheCommands::Clear => cache_clear(&disk_cache)?,
+        CacheCommands::Evict(args) => cache_evict(args, &disk_cache, global)?,
+    }
+    Ok(0)
+}
+
+/// The failure of a cache command that couldn't access the disk cache.
+fn cache_failure(error: std::io::Error) -> exit::Failure {
+    exit::Failure::new(1, format!("failed to access the disk cache: {}", error))
+}
+
+/// Print a table of the cached secrets with their backend, age, freshness and version.
+fn cache_status(
+    disk_cache: &cache::DiskCache,
+    writer: &mut dyn Write,
+) -> Result<(), exit::Failure> {
+    let entries = disk_cache.entries().map_err(cache_failure)?;
+    let unreadable = entries.iter().filter(|entry| entry.is_none()).count();
+    if unreadable > 0 {
+        Diagnostic::new(
+            Level::Warning,
+            "cache_entries_unreadable",
+            format!(
+                "{} cached secrets can't be decrypted, e.g. because they were encrypted with another key",
+                unreadable
+            ),
+        )
+        .detail("unreadable", unreadable)
+        .emit();
+    }
+    let mut rows: Vec<_> = entries
+        .into_iter()
+        .flatten()
+        .map(|entry| {
+            let age = Duration::from_secs(entry.age().as_secs());
+            let state = match disk_cache.is_fresh(&entry) {
+                true => "fresh",
+                false => "stale",
+            };
+            [
+                entry.backend.unwrap_or_else(|| String::from("-")),
+                entry.name.unwrap_or_else(|| String::from("-")),
+                humantime::format_duration(age).to_string(),
+                state.to_string(),
+                entry.version.unwrap_or_else(|| String::from("-")),
+            ]
+        })
+        .collect();
+    rows.sort();
+    let header = ["BACKEND", "NAME", "AGE", "STATE", "VERSION"].map(String::from);
+    let rows: Vec<_> = std::iter::once(header).chain(rows).collect();
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| format!("{:width$}", column, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        print(writer, format!("{}\n", line.trim_end()))?;
+    }
+    Ok(())
+}
+
+/// Remove all cached secrets.
+fn cache_clear(disk_cache: &cache::DiskCache) -> Result<(), exit::Failure> {
+    let removed = disk_cache.clear().map_err(cache_failure)?;
+    Diagnostic::new(
+        Level::Info,
+        "disk_cache_flushed",
+        format!("removed {} cached secrets", removed),
+    )
+    .detail("removed", removed)
+    .emit();
+    Ok(())
+}
+
+/// Remove the cached secrets with the names, from the backend or all of them.
+fn cache_evict(
+    args: CacheEvictArgs,
+    disk_cache: &cache::DiskCache,
+    global: &GlobalArgs,
+) -> Result<(), exit::Failure> {
+    let backends = match args.backend {
+        Some(backend) => vec![backend],
+        None => Backend::value_variants().to_vec(),
+    };
+    let mut removed = 0;
+    for name in &args.names {
+        // Secrets are cached under the names they are fetched with.
+        let name = interpolate::expand_env(name, &global.allowed_variables())
+            .map_err(|error| exit::Failure::new(exit::INPUT_ERROR, error))?;
+        let mut cached = false;
+        for backend in &backends {
+            if disk_cache
+                .evict(backend.name(), &name)
+                .map_err(cache_failure)?
+            {
+                cached = true;
+                removed += 1;
+            }
+        }
+        if !cached {
+            Diagnostic::new(
+                Level::Warning,
+                "secret_not_cached",
+                format!("{} isn't cached", name),
+            )
+            .secret(&name)
+            .emit();
+        }
+    }
+    Diagnostic::new(
+        Level::Info,
+        "disk_cache_evicted",
+        format!("removed {} cached secrets", removed),
+    )
+    .detail("removed", removed)
+    .emit();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run_args(args: &[&str], input: &str) -> (Result<i32, exit::Failure>, String) {
+        let cli = Cli::try_parse_from(["vector-aws-secrets-helper"].iter().chain(args)).unwrap();
+        let mut output = Vec::new();
+        let input = std::io::Cursor::new(input.to_string());
+        let result = run_command(cli, Default::default(), input, &mut output).await;
+        (result, String::from_utf8(output).unwrap())
+    }
+
+    #[tokio::test]
+    async fn run_parses_the_arguments_and_returns_the_exit_code() {
+        let backend = Backend::value_variants()[0].name();
+        let program = "vector-aws-secrets-helper";
+        let mut output = Vec::new();
+
+        let code = run(
+            [program, backend],
+            false,
+            "some_secret\n".as_bytes(),
+            &mut output,
+        )
+        .await;
+        assert_eq!(code, ExitCode::from(exit::INPUT_ERROR as u8));
+        let code = run(
+            [program, "--no-such-flag", backend],
+            false,
+            "".as_bytes(),
+            &mut output,
+        )
+        .await;
+        assert_eq!(code, ExitCode::from(2));
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_fail_with_input_error() {
+        let backend = Backend::value_variants()[0].name();
+
+        let (result, output) = run_args(&[backend], "some_secret\n").await;
+        assert_eq!(
+            result,
+            Err(exit::Failure::new(
+                exit::INPUT_ERROR,
+                "failed to parse JSON from stdin"
+            ))
+        );
+        assert!(output.is_empty());
+
+        let (result, _) = run_args(
+            &["--max-secrets", "1", backend],
+            r#"{"version": "1.0", "secrets": ["secret_1", "secret_2"]}"#,
+        )
+        .await;
+        assert_eq!(
+            result,
+            Err(exit::Failure::new(
+                exit::INPUT_ERROR,
+                "too many secrets requested: 2 exceeds the limit of 1"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn persistent_mode_answers_every_request_line() {
+        let backend = Backend::value_variants()[0].name();
+        let empty_request = r#"{"version": "1.0", "secrets": []}"#;
+
+        let (result, output) = run_args(
+            &[backend, "--persistent"],
+            &format!("{}\nsome_secret\n\n{}\n", empty_request, empty_request),
+        )
+        .await;
+        assert_eq!(result, Ok(0));
+        assert_eq!(output, "{}\n{}\n{}\n");
+
+        let (result, _) = run_args(
+            &[backend, "--persistent", "--input-format", "lines"],
+            "some_secret\n",
+        )
+        .await;
+        assert_eq!(result.unwrap_err().code, 2);
+    }
+
+    #[tokio::test]
+    async fn raw_output_requires_a_single_secret() {
+        let backend = Backend::value_variants()[0].name();
+
+        let (result, output) = run_args(&[backend, "--raw", "secret_1", "secret_2"], "").await;
+        assert_eq!(
+            result,
+            Err(exit::Failure::new(
+                2,
+                "--raw requires exactly one secret, 2 were requested"
+            ))
+        );
+        assert!(output.is_empty());
+
+        let (result, _) =
+            run_args(&[backend, "--raw"], r#"{"version": "1.0", "secrets": []}"#).await;
+        assert_eq!(result.unwrap_err().code, 2);
+    }
+
+    #[tokio::test]
+    async fn serve_rejects_interpolation() {
+        let args = [
+            "--interpolate-env",
+            "DEPLOY_ENV",
+            "serve",
+            "--grpc",
+            "127.0.0.1:0",
+        ];
+        let (result, _) = run_args(&args, "").await;
+        assert_eq!(
+            result,
+            Err(exit::Failure::new(
+                2,
+                "--interpolate-env doesn't apply to requests over gRPC"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_fails_if_the_metrics_server_fails() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let metrics_addr = taken.local_addr().unwrap().to_string();
+        let args = [
+            "serve",
+            "--grpc",
+            "127.0.0.1:0",
+            "--metrics-addr",
+            &metrics_addr,
+        ];
+        let (result, _) = run_args(&args, "").await;
+        let failure = result.unwrap_err();
+        assert_eq!(failure.code, 1);
+        assert!(failure
+            .message
+            .starts_with("failed to run the metrics server: "));
+    }
+
+    #[tokio::test]
+    async fn resident_commands_reject_mfa() {
+        let backend = Backend::value_variants()[0].name();
+        let role = ["--role-arn", "arn:aws:iam::111111111111:role/admin"];
+        let mfa = ["--mfa-serial", "arn:aws:iam::111111111111:mfa/jane"];
+        let failure = Err(exit::Failure::new(
+            2,
+            "--mfa-serial can't be used with serve or --persistent, as their sessions can't be \
+             refreshed without a new code",
+        ));
+
+        let serve = [&role[..], &mfa, &["serve", "--grpc", "127.0.0.1:0"]].concat();
+        assert_eq!(run_args(&serve, "").await.0, failure);
+        let persistent = [&role[..], &mfa, &[backend, "--persistent"]].concat();
+        assert_eq!(run_args(&persistent, "").await.0, failure);
+    }
+
+    #[tokio::test]
+    async fn setting_up_the_disk_cache_and_backends_counts_towards_the_deadline() {
+        use aws_credential_types::provider::SharedCredentialsProvider;
+        use aws_credential_types::Credentials;
+
+        // The endpoint accepts connections, but never responds to KMS, STS or the backends.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let sdk_config = SdkConfig::builder()
+            .region(Region::new("us-east-1"))
+            .endpoint_url(format!("http://{}", listener.local_addr().unwrap()))
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                "AKID", "SECRET", None, None, "test",
+            )))
+            .sleep_impl(aws_smithy_async::rt::sleep::default_async_sleep().unwrap())
+            .build();
+        let dir = tempfile::tempdir().unwrap();
+        let backend = Backend::value_variants()[0].name();
+        let cli = Cli::parse_from([
+            "vector-aws-secrets-helper",
+            "--disk-cache",
+            dir.path().to_str().unwrap(),
+            "--cache-kms-key-id",
+            "alias/vector",
+            "--audit-log",
+            dir.path().join("audit.jsonl").to_str().unwrap(),
+            backend,
+        ]);
+        let global = &cli.global;
+        let config = config::Config::default();
+        let mut aws_sdk_config = LazySdkConfig::new(&config, global);
+        aws_sdk_config.sdk_config = Arc::new(tokio::sync::OnceCell::new_with(Some(sdk_config)));
+
+        let deadline = Some(Duration::from_millis(200));
+        let started = std::time::Instant::now();
+        let disk_cache =
+            aws::loader::with_deadline(deadline, disk_cache(&aws_sdk_config, global)).await;
+        assert!(disk_cache.is_none());
+
+        let backend = Backend::value_variants()[0];
+        let loader = backend_loader(backend, &aws_sdk_config, &config, global, None).await;
+        let secrets_to_fetch = vector::SecretsToFetch::from_names(vec![String::from("a")]);
+        let fetched_secrets =
+            aws::loader::with_deadline(deadline, loader.load(secrets_to_fetch)).await;
+        assert!(fetched_secrets.0["a"].error.is_some());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    struct CountingLoader {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl LoadSecrets for CountingLoader {
+        async fn load(&self, secrets: vector::SecretsToFetch) -> vector::FetchedSecrets {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            vector::FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| (name.clone(), Ok(name).into()))
+                    .collect(),
+            )
+        }
+    }
+
+    #[derive(Parser)]
+    struct FetchCli {
+        #[command(flatten)]
+        fetch: FetchArgs,
+    }
+
+    #[tokio::test]
+    async fn streamed_requests_are_fetched_while_they_are_read() {
+        let args = FetchCli::parse_from(["fetch"]).fetch;
+        let loader = Arc::new(CountingLoader {
+            calls: Default::default(),
+        });
+        let counted = loader.clone();
+        let calls = move || counted.calls.load(std::sync::atomic::Ordering::SeqCst);
+
+        // Fetches overlap with reading even on a single-threaded runtime.
+        let mut output = Vec::new();
+        let read = move |on_name: &mut dyn FnMut(String)| {
+            for index in 0..STREAMED_BATCH_SIZE {
+                on_name(format!("secret_{}", index));
+            }
+            // The first batch is fetched before the rest of the request is read.
+            let started = std::time::Instant::now();
+            while calls() == 0 && started.elapsed() < Duration::from_secs(10) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert_eq!(calls(), 1);
+            for index in 0..STREAMED_BATCH_SIZE + 10 {
+                on_name(format!("secret_{}", index));
+            }
+            Ok(())
+        };
+        let result = respond_streamed(loader.clone(), read, usize::MAX, &args, &mut output).await;
+        assert_eq!(result, Ok(0));
+        assert_eq!(loader.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        let response: vector::FetchedSecrets = serde_json::from_slice(&output).unwrap();
+        assert_eq!(response.0.len(), STREAMED_BATCH_SIZE + 10);
+    }
+
+    /// A loader counting the loads that finished, which take a while.
+    struct SlowLoader {
+        finished: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl LoadSecrets for SlowLoader {
+        async fn load(&self, secrets: vector::SecretsToFetch) -> vector::FetchedSecrets {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.finished
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            CountingLoader {
+                calls: Default::default(),
+            }
+            .load(secrets)
+            .await
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_requests_over_the_limit_are_not_fetched() {
+        let args = FetchCli::parse_from(["fetch"]).fetch;
+        let loader = Arc::new(SlowLoader {
+            finished: Default::default(),
+        });
+
+        // Full batches are dispatched before the request turns out to be over the limit.
+        let mut output = Vec::new();
+        let read = |on_name: &mut dyn FnMut(String)| {
+            (0..3 * STREAMED_BATCH_SIZE).for_each(|index| on_name(format!("secret_{}", index)));
+            Ok(())
+        };
+        let max_secrets = STREAMED_BATCH_SIZE + 1;
+        let result = respond_streamed(loader.clone(), read, max_secrets, &args, &mut output).await;
+        assert_eq!(result.unwrap_err().code, exit::INPUT_ERROR);
+        assert!(output.is_empty());
+
+        let read = |on_name: &mut dyn FnMut(String)| {
+            (0..2 * STREAMED_BATCH_SIZE).for_each(|index| on_name(format!("secret_{}", index)));
+            Err(exit::Failure::new(exit::INPUT_ERROR, "invalid request"))
+        };
+        let result = respond_streamed(loader.clone(), read, usize::MAX, &args, &mut output).await;
+        assert_eq!(result.unwrap_err().code, exit::INPUT_ERROR);
+        assert!(output.is_empty());
+
+        // The fetches dispatched while the request was read are cancelled before they finish.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(loader.finished.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn streamed_responses_skip_keys_repeated_across_batches() {
+        struct SharedKeyLoader {}
+
+        #[async_trait::async_trait]
+        impl LoadSecrets for SharedKeyLoader {
+            async fn load(&self, secrets: vector::SecretsToFetch) -> vector::FetchedSecrets {
+                let mut fetched_secrets = CountingLoader {
+                    calls: Default::default(),
+                }
+                .load(secrets)
+                .await;
+                // Every batch adds the same key, like an expanded secret would.
+                let shared = Ok(String::from("shared")).into();
+                fetched_secrets.0.insert(String::from("shared"), shared);
+                fetched_secrets
+            }
+        }
+
+        let args = FetchCli::parse_from(["fetch"]).fetch;
+        let mut output = Vec::new();
+        let read = |on_name: &mut dyn FnMut(String)| {
+            (0..2 * STREAMED_BATCH_SIZE).for_each(|index| on_name(format!("secret_{}", index)));
+            Ok(())
+        };
+        let loader = Arc::new(SharedKeyLoader {});
+        let result = respond_streamed(loader, read, usize::MAX, &args, &mut output).await;
+        assert_eq!(result, Ok(0));
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("\"shared\":").count(), 1);
+        let response: vector::FetchedSecrets = serde_json::from_str(&output).unwrap();
+        assert_eq!(response.0.len(), 2 * STREAMED_BATCH_SIZE + 1);
+    }
+
+    #[tokio::test]
+    async fn watched_files_are_refreshed_every_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = FetchCli::parse_from([
+            "fetch",
+            "--output-format",
+            "systemd-creds",
+            "--output-dir",
+            dir.path().to_str().unwrap(),
+            "--watch",
+            "10ms",
+        ])
+        .fetch;
+        let backend = Backend::value_variants()[0].name();
+        let global = Cli::parse_from(["vector-aws-secrets-helper", backend]).global;
+        let loader = CountingLoader {
+            calls: Default::default(),
+        };
+
+        let names = [String::from("db_password")];
+        let interval = args.watch.unwrap();
+        let mut output = Vec::new();
+        let wake = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Wake::Shutdown
+        };
+        let wake = refresh(&loader, &names, &args, interval, &global, &mut output, wake).await;
+        assert_eq!(wake, Wake::Shutdown);
+        assert!(loader.calls.load(std::sync::atomic::Ordering::SeqCst) >= 2);
+        let value = std::fs::read_to_string(dir.path().join("db_password")).unwrap();
+        assert_eq!(value, "db_password");
+    }
+
+    #[tokio::test]
+    async fn cache_commands_list_and_remove_cached_secrets() {
+        let backend = Backend::value_variants()[0].name();
+        let dir = tempfile::tempdir().unwrap();
+        let key_file = dir.path().join("cache.key");
+        let cache_dir = dir.path().join("cache");
+        let key = cache::host_key(&key_file).unwrap();
+        let disk_cache = cache::DiskCache::new(&cache_dir, &key, Duration::from_secs(60)).unwrap();
+        let value = secret::Secret::new(String::from("qwerty"));
+        disk_cache
+            .put(backend, "/app/db", &value, Some("3"))
+            .unwrap();
+        disk_cache.put(backend, "/app/api", &value, None).unwrap();
+
+        let (cache_dir, key_file) = (cache_dir.to_str().unwrap(), key_file.to_str().unwrap());
+        let cache_args = [
+            "--disk-cache",
+            cache_dir,
+            "--cache-key-file",
+            key_file,
+            "cache",
+        ];
+        let run_cache = |command: &'static [&'static str]| {
+            let args: Vec<_> = cache_args.iter().chain(command).copied().collect();
+            async move { run_args(&args, "").await }
+        };
+
+        let (result, output) = run_cache(&["status"]).await;
+        assert_eq!(result, Ok(0));
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let columns = |line: &str| {
+            line.split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(columns(lines[0])[..3], ["BACKEND", "NAME", "AGE"]);
+        assert_eq!(columns(lines[1])[..2], [backend, "/app/api"]);
+        assert!(lines[1].ends_with("fresh  -"));
+        assert!(lines[2].ends_with("fresh  3"));
+        assert!(!output.contains("qwerty"));
+
+        let (result, _) = run_cache(&["evict", "/app/db", "/app/missing"]).await;
+        assert_eq!(result, Ok(0));
+        assert_eq!(disk_cache.get(backend, "/app/db"), None);
+        assert!(disk_cache.get(backend, "/app/api").is_some());
+
+        let (result, _) = run_cache(&["clear"]).await;
+        assert_eq!(result, Ok(0));
+        assert_eq!(disk_cache.entries().unwrap().len(), 0);
+
+        let (result, _) = run_args(&["cache", "status"], "").await;
+        assert_eq!(result.unwrap_err().code, 1);
+    }
+
+    #[cfg(any(feature = "ssm", feature = "secretsmanager"))]
+    #[test]
+    fn requested_aws_regions_are_reported_with_their_source() {
+        let backend = Backend::value_variants()[0];
+        let regions = HashMap::from([(String::from("configured"), String::from("eu-west-2"))]);
+        let sdk_config = SdkConfig::builder()
+            .region(Region::new("eu-west-1"))
+            .build();
+        let region = |name| requested_aws_region(backend, name, &regions, &sdk_config);
+
+        assert_eq!(
+            region("plain"),
+            Ok((Some(String::from("eu-west-1")), "the AWS config"))
+        );
+        assert_eq!(
+            region("plain?region=us-east-1"),
+            Ok((Some(String::from("us-east-1")), "the region option"))
+        );
+        assert_eq!(
+            region("configured"),
+            Ok((Some(String::from("eu-west-2")), "the config file"))
+        );
+        assert_eq!(
+            region("arn:aws:ssm:eu-central-1:123456789012:parameter/app"),
+            Ok((Some(String::from("eu-central-1")), "the ARN"))
+        );
+        assert_eq!(
+            region("arn:aws-cn:ssm::123456789012:parameter/app"),
+            Err(String::from(
+                "region eu-west-1 is in the aws partition, not in the aws-cn partition of the ARN"
+            ))
+        );
+        assert_eq!(
+            region("plain?label=foo"),
+            Err(String::from("unknown option: label"))
+        );
+    }
+
+    #[test]
+    fn every_run_gets_its_own_connection_pools() {
+        let backend = Backend::value_variants()[0].name();
+        let global = || Cli::parse_from(["vector-aws-secrets-helper", backend]).global;
+        let (first, second) = (global(), global());
+
+        // Clones, e.g. the arguments config reloads recreate the clients with, share the pools.
+        assert!(Arc::ptr_eq(
+            &first.connections(),
+            &first.clone().connections()
+        ));
+        assert!(!Arc::ptr_eq(&first.connections(), &second.connections()));
+    }
+
+    #[test]
+    fn service_endpoint_urls_take_precedence() {
+        let lookup = |variable: &str| match variable {
+            "AWS_ENDPOINT_URL_SSM" => Some(String::from("http://env:4566")),
+            _ => Some(String::new()),
+        };
+        let service = String::from("http://service:4566");
+        let global = String::from("http://global:4566");
+
+        assert_eq!(
+            service_endpoint_url(
+                Some(&service),
+                Some(&global),
+                "AWS_ENDPOINT_URL_SSM",
+                lookup
+            ),
+            Some(service.clone())
+        );
+        assert_eq!(
+            service_endpoint_url(None, Some(&global), "AWS_ENDPOINT_URL_SSM", lookup),
+            Some(global.clone())
+        );
+        assert_eq!(
+            service_endpoint_url(None, None, "AWS_ENDPOINT_URL_SSM", lookup),
+            Some(String::from("http://env:4566"))
+        );
+        assert_eq!(
+            service_endpoint_url(None, None, "AWS_ENDPOINT_URL_SECRETS_MANAGER", lookup),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_writes_requests_to_the_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("vector.toml"),
+            "[sinks.datadog]\ndefault_api_key = \"SECRET[aws_ssm.datadog_api_key]\"\n",
+        )
+        .unwrap();
+
+        let path = dir.path().to_str().unwrap();
+        let (result, output) = run_args(&["scan", path], "").await;
+        assert_eq!(result, Ok(0));
+        assert_eq!(
+            output,
+            "{\"aws_ssm\":{\"version\":\"1.0\",\"secrets\":[\"datadog_api_key\"]}}\n"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! This module contains Prometheus metrics for the long-running modes and the HTTP endpoint
+//! exposing them.
+
+use crate::aws::loader::LoadSecrets;
+use crate::vector::{ErrorCode, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+/// A struct holding the metrics about the AWS credentials, which are shared by all backends and
+/// loaded independently of the metrics.
+pub struct CredentialMetrics {
+    pub refresh_failures: IntCounter,
+    pub expiry: IntGauge,
+}
+
+/// The metrics about the AWS credentials of the process.
+pub fn credentials() -> &'static CredentialMetrics {
+    static CREDENTIALS: OnceLock<CredentialMetrics> = OnceLock::new();
+    CREDENTIALS.get_or_init(|| CredentialMetrics {
+        refresh_failures: IntCounter::new(
+            "credential_refresh_failures_total",
+            "Number of failed attempts to load or refresh AWS credentials.",
+        )
+        .unwrap(),
+        expiry: IntGauge::new(
+            "credentials_expiry_timestamp_seconds",
+            "Unix time at which the current AWS credentials expire, 0 if they don't.",
+        )
+        .unwrap(),
+    })
+}
+
+/// A struct holding all metrics collected by the helper.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    secrets: IntCounterVec,
+    fetch_errors: IntCounterVec,
+    request_duration: HistogramVec,
+}
+
+/// Implement the Metrics constructor and rendering.
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(String::from("vector_secrets_helper")), None)
+            .expect("metrics namespace must be valid");
+        let requests = IntCounterVec::new(
+            Opts::new("requests_total", "Number of requests to resolve secrets."),
+            &["backend"],
+        )
+        .unwrap();
+        let secrets = IntCounterVec::new(
+            Opts::new("secrets_total", "Number of secrets requested."),
+            &["backend"],
+        )
+        .unwrap();
+        let fetch_errors = IntCounterVec::new(
+            Opts::new(
+                "fetch_errors_total",
+                "Number of secrets that failed to be fetched.",
+            ),
+            &["backend", "class"],
+        )
+        .unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "request_duration_seconds",
+                "Time spent resolving a request.",
+            ),
+            &["backend"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(secrets.clone())).unwrap();
+        registry.register(Box::new(fetch_errors.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration.clone()))
+            .unwrap();
+        let credentials = credentials();
+        registry
+            .register(Box::new(credentials.refresh_failures.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(credentials.expiry.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            requests,
+            secrets,
+            fetch_errors,
+            request_duration,
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+/// Implement the Default trait for Metrics.
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A struct wrapping a secrets loader to record metrics about the requests it serves.
+pub struct MeteredLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    backend: String,
+    metrics: Arc<Metrics>,
+}
+
+/// Implement the MeteredLoader constructor.
+impl MeteredLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        backend: &str,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            inner,
+            backend: backend.to_string(),
+            metrics,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for MeteredLoader.
+#[async_trait]
+impl LoadSecrets for MeteredLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let backend = self.backend.as_str();
+        self.metrics.requests.with_label_values(&[backend]).inc();
+        self.metrics
+            .secrets
+            .with_label_values(&[backend])
+            .inc_by(secrets.secrets.len() as u64);
+
+        let start = Instant::now();
+        let fetched_secrets = self.inner.load(secrets).await;
+        self.metrics
+            .request_duration
+            .with_label_values(&[backend])
+            .observe(start.elapsed().as_secs_f64());
+
+        for secret in fetched_secrets
+            .0
+            .values()
+            .filter(|secret| secret.error.is_some())
+        {
+            let class = secret.code.unwrap_or(ErrorCode::Unknown).as_str();
+            self.metrics
+                .fetch_errors
+                .with_label_values(&[backend, class])
+                .inc();
+        }
+
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        self.inner.current_version(requested).await
+    }
+}
+
+/// Serve the metrics on the `/metrics` path over HTTP until the server fails.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    let app = Router::new().route("/metrics", get(move || async move { metrics.render() }));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::vector::FetchedSecret;
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, _secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                [
+                    (
+                        "test.secret_1".to_string(),
+                        FetchedSecret {
+                            value: Some(Secret::new("qwerty".to_string())),
+                            error: None,
+                            code: None,
+                        },
+                    ),
+                    (
+                        "test.secret_2".to_string(),
+                        FetchedSecret {
+                            value: None,
+                            error: Some("parameter not found".to_string()),
+                            code: Some(ErrorCode::NotFound),
+                        },
+                    ),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn metered_loader_records_metrics() {
+        let metrics = Arc::new(Metrics::new());
+        let secrets_loader = MeteredLoader::new(Box::new(MockLoader {}), "ssm", metrics.clone());
+
+        let secrets_to_fetch = SecretsToFetch {
+            version: String::from("1.0"),
+            secrets: vec![String::from("test.secret_1"), String::from("test.secret_2")],
+        };
+        secrets_loader.load(secrets_to_fetch).await;
+
+        let output = metrics.render();
+        assert!(output.contains("vector_secrets_helper_requests_total{backend=\"ssm\"} 1"));
+        assert!(output.contains("vector_secrets_helper_secrets_total{backend=\"ssm\"} 2"));
+        assert!(output.contains(
+            "vector_secrets_helper_fetch_errors_total{backend=\"ssm\",class=\"NOT_FOUND\"} 1"
+        ));
+        assert!(output
+            .contains("vector_secrets_helper_request_duration_seconds_count{backend=\"ssm\"} 1"));
+    }
+}
@@ -0,0 +1,307 @@
+//! This module contains the loader fetching secrets from several backends in one request, e.g.
+//! while migrating secrets from SSM Parameter Store to Secrets Manager.
+
+use crate::aws::loader::LoadSecrets;
+use crate::vector::{ErrorCode, FetchedSecret, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+
+/// A struct representing a requested secret waiting to be fetched from one of the backends.
+struct Pending {
+    /// The name as requested, used as the key of the output.
+    requested: String,
+    /// The name passed to the backend, without the backend prefix.
+    name: String,
+    /// The index of the backend to fetch the secret from.
+    backend: usize,
+    /// Whether the next backend is tried if the secret isn't found, false if the secret was
+    /// requested from a single backend.
+    fall_through: bool,
+}
+
+/// A struct fetching secrets from several backends. A secret requested with a backend prefix,
+/// e.g. `ssm:/app/db_password`, is only fetched from that backend. Any other secret is fetched
+/// from the backends in order of precedence, returning the first one that is found.
+pub struct MultiLoader {
+    backends: Vec<(&'static str, Box<dyn LoadSecrets + Send + Sync>)>,
+}
+
+/// Implement the MultiLoader constructor.
+impl MultiLoader {
+    pub fn new(backends: Vec<(&'static str, Box<dyn LoadSecrets + Send + Sync>)>) -> Self {
+        Self { backends }
+    }
+}
+
+/// Implement the MultiLoader methods.
+impl MultiLoader {
+    /// Route a requested secret to the backend of its prefix, or to the first backend.
+    fn route(&self, requested: String) -> Pending {
+        for (index, (backend, _)) in self.backends.iter().enumerate() {
+            if let Some(name) = requested
+                .strip_prefix(backend)
+                .and_then(|name| name.strip_prefix(':'))
+            {
+                return Pending {
+                    name: name.to_string(),
+                    requested,
+                    backend: index,
+                    fall_through: false,
+                };
+            }
+        }
+        Pending {
+            name: requested.clone(),
+            requested,
+            backend: 0,
+            fall_through: true,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for MultiLoader.
+#[async_trait]
+impl LoadSecrets for MultiLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let mut pending: Vec<_> = secrets
+            .secrets
+            .into_iter()
+            .map(|requested| self.route(requested))
+            .collect();
+        let mut fetched_secrets = FetchedSecrets::default();
+
+        // Fetch the pending secrets from all backends at once, then move the secrets that weren't
+        // found on to the next backend until every secret is resolved.
+        while !pending.is_empty() {
+            let mut batches: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+            for secret in &pending {
+                batches
+                    .entry(secret.backend)
+                    .or_default()
+                    .push(secret.name.clone());
+            }
+            let results: BTreeMap<usize, FetchedSecrets> =
+                futures::future::join_all(batches.into_iter().map(|(backend, names)| async move {
+                    let (_, loader) = &self.backends[backend];
+                    let fetched = loader.load(SecretsToFetch::from_names(names)).await;
+                    (backend, fetched)
+                }))
+                .await
+                .into_iter()
+                .collect();
+
+            let mut next = Vec::new();
+            for mut secret in pending {
+                let fetched_secret = results[&secret.backend]
+                    .0
+                    .get(&secret.name)
+                    .cloned()
+                    .unwrap_or_else(missing);
+                let has_next = secret.backend + 1 < self.backends.len();
+                if secret.fall_through
+                    && has_next
+                    && fetched_secret.code == Some(ErrorCode::NotFound)
+                {
+                    secret.backend += 1;
+                    next.push(secret);
+                } else {
+                    fetched_secrets.0.insert(secret.requested, fetched_secret);
+                }
+            }
+            pending = next;
+        }
+        fetched_secrets
+    }
+}
+
+/// The result of a secret a backend didn't return.
+fn missing() -> FetchedSecret {
+    FetchedSecret {
+        value: None,
+        error: Some(String::from(
+            "secret missing from the response of the backend",
+        )),
+        code: Some(ErrorCode::Unknown),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+    use crate::secret::Secret;
+    use std::sync::{Arc, Mutex};
+
+    struct MockLoader {
+        backend: &'static str,
+        names: &'static [&'static str],
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| {
+                        self.requests
+                            .lock()
+                            .unwrap()
+                            .push(format!("{}:{}", self.backend, name));
+                        let secret = if self.names.contains(&name.as_str()) {
+                            Ok(format!("{} from {}", name, self.backend))
+                        } else {
+                            Err(SecretsHelperError::NotFound(format!("{} not found", name)))
+                        };
+                        (name, secret.into())
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    fn loader(requests: &Arc<Mutex<Vec<String>>>) -> MultiLoader {
+        MultiLoader::new(vec![
+            (
+                "ssm",
+                Box::new(MockLoader {
+                    backend: "ssm",
+                    names: &["/app/a", "/app/b"],
+                    requests: requests.clone(),
+                }),
+            ),
+            (
+                "secretsmanager",
+                Box::new(MockLoader {
+                    backend: "secretsmanager",
+                    names: &["/app/b", "app/c"],
+                    requests: requests.clone(),
+                }),
+            ),
+        ])
+    }
+
+    fn value(fetched_secrets: &FetchedSecrets, name: &str) -> Option<Secret<String>> {
+        fetched_secrets.0[name].value.clone()
+    }
+
+    #[tokio::test]
+    async fn secrets_are_resolved_in_order_of_precedence() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let fetched_secrets = loader(&requests)
+            .load(SecretsToFetch::from_names(vec![
+                String::from("/app/a"),
+                String::from("/app/b"),
+                String::from("app/c"),
+                String::from("app/d"),
+            ]))
+            .await;
+
+        assert_eq!(
+            value(&fetched_secrets, "/app/a"),
+            Some(Secret::new(String::from("/app/a from ssm")))
+        );
+        assert_eq!(
+            value(&fetched_secrets, "/app/b"),
+            Some(Secret::new(String::from("/app/b from ssm")))
+        );
+        assert_eq!(
+            value(&fetched_secrets, "app/c"),
+            Some(Secret::new(String::from("app/c from secretsmanager")))
+        );
+        assert_eq!(fetched_secrets.0["app/d"].code, Some(ErrorCode::NotFound));
+
+        // Secrets found in the first backend aren't fetched from the second one.
+        let mut requests = requests.lock().unwrap().clone();
+        requests.sort();
+        assert_eq!(
+            requests,
+            vec![
+                "secretsmanager:app/c",
+                "secretsmanager:app/d",
+                "ssm:/app/a",
+                "ssm:/app/b",
+                "ssm:app/c",
+                "ssm:app/d",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn prefixed_secrets_are_fetched_from_their_backend() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let fetched_secrets = loader(&requests)
+            .load(SecretsToFetch::from_names(vec![
+                String::from("secretsmanager:/app/b"),
+                String::from("ssm:app/c"),
+            ]))
+            .await;
+
+        assert_eq!(
+            value(&fetched_secrets, "secretsmanager:/app/b"),
+            Some(Secret::new(String::from("/app/b from secretsmanager")))
+        );
+        assert_eq!(
+            fetched_secrets.0["ssm:app/c"].code,
+            Some(ErrorCode::NotFound)
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn fallbacks_share_the_deadline_of_the_request() {
+        use crate::aws::loader::{fetch_all, with_deadline, LoaderSettings};
+        use std::time::Duration;
+
+        // A backend taking most of the deadline to answer, with a deadline of its own per load.
+        struct SlowLoader {
+            settings: LoaderSettings,
+            found: bool,
+        }
+
+        #[async_trait]
+        impl LoadSecrets for SlowLoader {
+            async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+                fetch_all(secrets, &self.settings, |name| async move {
+                    tokio::time::sleep(Duration::from_millis(80)).await;
+                    match self.found {
+                        true => Ok(name).into(),
+                        false => SecretsHelperError::NotFound(name).into(),
+                    }
+                })
+                .await
+            }
+        }
+
+        let deadline = Duration::from_millis(120);
+        let settings = LoaderSettings {
+            deadline: Some(deadline),
+            ..Default::default()
+        };
+        let loader = MultiLoader::new(vec![
+            (
+                "ssm",
+                Box::new(SlowLoader {
+                    settings: settings.clone(),
+                    found: false,
+                }),
+            ),
+            (
+                "secretsmanager",
+                Box::new(SlowLoader {
+                    settings,
+                    found: true,
+                }),
+            ),
+        ]);
+
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![String::from("/app/a")]);
+        let fetched_secrets = with_deadline(Some(deadline), loader.load(secrets_to_fetch)).await;
+        assert_eq!(
+            fetched_secrets.0["/app/a"].code,
+            Some(ErrorCode::DeadlineExceeded)
+        );
+    }
+}
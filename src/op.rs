@@ -0,0 +1,378 @@
+//! This module contains the secrets loader implementation for 1Password Connect.
+
+use crate::aws::loader::{fetch_all, LoadSecrets, LoaderSettings};
+use crate::aws::options::invalid_request;
+use crate::error::{describe, SecretsHelperError};
+use crate::extract;
+use crate::healthcheck::PingEndpoint;
+use crate::http;
+use crate::secret::Secret;
+use crate::vector::{FetchedSecret, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// The length of the ids 1Password assigns to vaults and items.
+const ID_LEN: usize = 26;
+
+/// A trait for fetching a single item from a 1Password Connect server.
+#[async_trait]
+pub trait OpFetchItem {
+    /// Fetch the item with the title or id from the vault with the name or id.
+    async fn fetch_item(&self, vault: String, item: String) -> Result<Item, SecretsHelperError>;
+}
+
+/// A struct representing an item of a vault, of which only the fields are used.
+#[derive(Debug, Deserialize)]
+pub struct Item {
+    #[serde(default)]
+    fields: Vec<Field>,
+}
+
+/// A struct representing a field of an item.
+#[derive(Debug, Deserialize)]
+struct Field {
+    id: String,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    purpose: Option<String>,
+    #[serde(default)]
+    value: Option<String>,
+}
+
+/// A struct representing a vault or item in a list filtered by name or title.
+#[derive(Deserialize)]
+struct Overview {
+    id: String,
+}
+
+/// Whether a vault or item is referenced by its id rather than by its name or title.
+fn is_id(reference: &str) -> bool {
+    reference.len() == ID_LEN
+        && reference
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit())
+}
+
+/// A struct for calling the 1Password Connect API, caching the ids of vaults looked up by name.
+#[derive(Clone)]
+pub struct OpClient {
+    http: reqwest::Client,
+    /// The URL of the Connect server, or why none is configured.
+    host: Result<String, String>,
+    token: Option<String>,
+    vault_ids: Arc<Mutex<HashMap<String, String>>>,
+}
+
+/// Implement the OpClient constructor.
+impl OpClient {
+    /// Create a client for the Connect server at the endpoint URL or `OP_CONNECT_HOST`,
+    /// authenticating with the access token in `OP_CONNECT_TOKEN`.
+    pub fn new(http: reqwest::Client, endpoint_url: Option<&str>) -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let host = match endpoint_url
+            .map(str::to_string)
+            .or_else(|| var("OP_CONNECT_HOST"))
+        {
+            Some(host) => Ok(host.trim_end_matches('/').to_string()),
+            None => Err(String::from(
+                "no 1Password Connect server selected with OP_CONNECT_HOST or --endpoint-url",
+            )),
+        };
+        Self {
+            http,
+            host,
+            token: var("OP_CONNECT_TOKEN"),
+            vault_ids: Default::default(),
+        }
+    }
+}
+
+/// Implement the OpClient methods.
+impl OpClient {
+    /// Check that the access token is accepted by listing the vaults it grants access to.
+    pub async fn check_credentials(&self) -> Result<(), String> {
+        let request = self.get("/v1/vaults").map_err(|error| error.to_string())?;
+        match http::get_json::<Vec<Overview>>(request).await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(error.to_string()),
+        }
+    }
+
+    /// Create an authenticated request to the path of the Connect API.
+    fn get(&self, path: &str) -> Result<reqwest::RequestBuilder, SecretsHelperError> {
+        let host = self
+            .host
+            .as_ref()
+            .map_err(|error| SecretsHelperError::Credentials(error.clone()))?;
+        let token = self.token.as_ref().ok_or_else(|| {
+            SecretsHelperError::Credentials(String::from(
+                "no 1Password Connect token in OP_CONNECT_TOKEN",
+            ))
+        })?;
+        Ok(self
+            .http
+            .get(format!("{}{}", host, path))
+            .bearer_auth(token))
+    }
+
+    /// Get the id of the single vault or item in the list at the path filtered by name or title.
+    async fn find_id(
+        &self,
+        path: &str,
+        attribute: &str,
+        kind: &str,
+        reference: &str,
+    ) -> Result<String, SecretsHelperError> {
+        // Quotes can't be escaped in SCIM filters, so names with them can only be matched by id.
+        if reference.contains('"') {
+            return Err(SecretsHelperError::InvalidRequest(format!(
+                "{} {} can't be looked up by name",
+                kind, reference
+            )));
+        }
+        let request = self
+            .get(path)?
+            .query(&[("filter", format!("{} eq \"{}\"", attribute, reference))]);
+        let mut found = http::get_json::<Vec<Overview>>(request).await?;
+        match found.len() {
+            1 => Ok(found.remove(0).id),
+            0 => Err(SecretsHelperError::NotFound(format!(
+                "{} not found: {}",
+                kind, reference
+            ))),
+            _ => Err(SecretsHelperError::InvalidRequest(format!(
+                "multiple {}s named {}, select one by id",
+                kind, reference
+            ))),
+        }
+    }
+
+    /// Get the id of the vault with the name or id.
+    async fn vault_id(&self, vault: &str) -> Result<String, SecretsHelperError> {
+        if is_id(vault) {
+            return Ok(vault.to_string());
+        }
+        // The lock is held while looking up the vault, so that concurrent fetches share one request.
+        let mut vault_ids = self.vault_ids.lock().await;
+        if let Some(id) = vault_ids.get(vault) {
+            return Ok(id.clone());
+        }
+        let id = self.find_id("/v1/vaults", "name", "vault", vault).await?;
+        vault_ids.insert(vault.to_string(), id.clone());
+        Ok(id)
+    }
+}
+
+/// Implement the PingEndpoint trait for OpClient.
+#[async_trait]
+impl PingEndpoint for OpClient {
+    async fn ping(&self) -> Result<(), String> {
+        let host = self.host.as_ref().map_err(Clone::clone)?;
+        // The heartbeat doesn't need a token, so it checks reachability alone.
+        match self.http.get(format!("{}/heartbeat", host)).send().await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(describe(&error)),
+        }
+    }
+}
+
+/// Implement the OpFetchItem trait for OpClient.
+#[async_trait]
+impl OpFetchItem for OpClient {
+    async fn fetch_item(&self, vault: String, item: String) -> Result<Item, SecretsHelperError> {
+        let vault_id = self.vault_id(&vault).await?;
+        let items_path = format!("/v1/vaults/{}/items", vault_id);
+        let item_id = if is_id(&item) {
+            item
+        } else {
+            self.find_id(&items_path, "title", "item", &item).await?
+        };
+        let request = self.get(&format!("{}/{}", items_path, item_id))?;
+        http::get_json(request).await
+    }
+}
+
+/// Select the value of the field with the label or id from an item, the password if none is
+/// given.
+fn select_field(item: Item, field: Option<&str>) -> FetchedSecret {
+    let found = item.fields.into_iter().find(|candidate| match field {
+        Some(field) => candidate.label.as_deref() == Some(field) || candidate.id == field,
+        None => candidate.purpose.as_deref() == Some("PASSWORD"),
+    });
+    match found {
+        Some(Field {
+            value: Some(value), ..
+        }) => FetchedSecret {
+            value: Some(Secret::new(value)),
+            error: None,
+            code: None,
+        },
+        Some(_) => SecretsHelperError::NotFound(String::from("field has no value")).into(),
+        None => SecretsHelperError::NotFound(match field {
+            Some(field) => format!("item has no field {}", field),
+            None => String::from("item has no password, select a field with #field"),
+        })
+        .into(),
+    }
+}
+
+/// A struct for loading secrets from 1Password Connect.
+pub struct OpSecretsLoader {
+    client: Box<dyn OpFetchItem + Send + Sync>,
+    settings: LoaderSettings,
+}
+
+/// Implement the OpSecretsLoader constructor.
+impl OpSecretsLoader {
+    pub fn new(client: impl OpFetchItem + Send + Sync + 'static, settings: LoaderSettings) -> Self {
+        Self {
+            client: Box::new(client),
+            settings,
+        }
+    }
+}
+
+/// Implement the OpSecretsLoader methods.
+impl OpSecretsLoader {
+    /// Fetch a single requested secret in the form `vault/item#field`, applying the `#yaml:`
+    /// selector following the field.
+    async fn fetch(&self, requested: String) -> FetchedSecret {
+        let (requested, yaml_path) = match requested.rsplit_once("#yaml:") {
+            Some((requested, path)) => (requested, Some(path)),
+            None => (requested.as_str(), None),
+        };
+        let (path, field) = match requested.split_once('#') {
+            Some((path, field)) => (path, Some(field)),
+            None => (requested, None),
+        };
+        let Some((vault, item)) = path.split_once('/') else {
+            return invalid_request(format!(
+                "invalid secret {}, expected vault/item#field",
+                requested
+            ));
+        };
+        if vault.is_empty() || item.is_empty() || field == Some("") {
+            return invalid_request(format!(
+                "invalid secret {}, expected vault/item#field",
+                requested
+            ));
+        }
+        let fetched_secret = match self
+            .client
+            .fetch_item(vault.to_string(), item.to_string())
+            .await
+        {
+            Ok(item) => select_field(item, field),
+            Err(error) => return error.into(),
+        };
+        match yaml_path {
+            Some(path) => extract::apply(fetched_secret, |value| extract::yaml_path(value, path)),
+            None => fetched_secret,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for OpSecretsLoader.
+#[async_trait]
+impl LoadSecrets for OpSecretsLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        fetch_all(secrets, &self.settings, |requested| self.fetch(requested)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::ErrorCode;
+
+    struct MockOpFetchItem {}
+
+    #[async_trait]
+    impl OpFetchItem for MockOpFetchItem {
+        async fn fetch_item(
+            &self,
+            vault: String,
+            item: String,
+        ) -> Result<Item, SecretsHelperError> {
+            match (vault.as_str(), item.as_str()) {
+                ("Infra", "Postgres") => Ok(serde_json::from_str(
+                    r#"{"fields": [
+                        {"id": "username", "label": "username", "purpose": "USERNAME", "value": "vector"},
+                        {"id": "password", "label": "password", "purpose": "PASSWORD", "value": "hunter2"},
+                        {"id": "x4fjq2", "label": "host", "value": "db.internal"},
+                        {"id": "notesPlain", "label": "notes", "purpose": "NOTES"},
+                        {"id": "a8cmr0", "label": "config", "value": "port: 5432"}
+                    ]}"#,
+                )
+                .unwrap()),
+                _ => Err(SecretsHelperError::NotFound(format!(
+                    "item not found: {}",
+                    item
+                ))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn op_secrets_loader_selects_fields() {
+        let secrets_loader = OpSecretsLoader::new(MockOpFetchItem {}, LoaderSettings::default());
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![
+            String::from("Infra/Postgres"),
+            String::from("Infra/Postgres#username"),
+            String::from("Infra/Postgres#x4fjq2"),
+            String::from("Infra/Postgres#notes"),
+            String::from("Infra/Postgres#port"),
+            String::from("Infra/Postgres#config#yaml:port"),
+            String::from("Infra/Redis#password"),
+            String::from("Postgres#password"),
+        ]);
+
+        let fetched_secrets = secrets_loader.load(secrets_to_fetch).await;
+
+        let secret = |name: &str| &fetched_secrets.0[name];
+        assert_eq!(
+            secret("Infra/Postgres").value,
+            Some(Secret::new(String::from("hunter2")))
+        );
+        assert_eq!(
+            secret("Infra/Postgres#username").value,
+            Some(Secret::new(String::from("vector")))
+        );
+        assert_eq!(
+            secret("Infra/Postgres#x4fjq2").value,
+            Some(Secret::new(String::from("db.internal")))
+        );
+        assert_eq!(
+            secret("Infra/Postgres#notes").code,
+            Some(ErrorCode::NotFound)
+        );
+        assert_eq!(
+            secret("Infra/Postgres#port").code,
+            Some(ErrorCode::NotFound)
+        );
+        assert_eq!(
+            secret("Infra/Postgres#config#yaml:port").value,
+            Some(Secret::new(String::from("5432")))
+        );
+        assert_eq!(
+            secret("Infra/Redis#password").code,
+            Some(ErrorCode::NotFound)
+        );
+        assert_eq!(
+            secret("Postgres#password").code,
+            Some(ErrorCode::InvalidRequest)
+        );
+    }
+
+    #[test]
+    fn ids_are_told_apart_from_names() {
+        assert!(is_id("abcdefghijklmnopqrstuvwxyz"));
+        assert!(is_id("2fcbqwe9ndg175zg2dzwftvkpa"));
+        assert!(!is_id("Infra"));
+        assert!(!is_id("ABCDEFGHIJKLMNOPQRSTUVWXYZ"));
+    }
+}
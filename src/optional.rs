@@ -0,0 +1,227 @@
+//! This module contains the handling of optional secrets, marked in the config file or requested
+//! with the `optional=true` option, e.g. `/app/debug_sink_token?optional=true`. A missing optional
+//! secret is returned with its default value, or an empty one, instead of failing.
+
+use crate::aws::loader::LoadSecrets;
+use crate::aws::options::invalid_request;
+use crate::vector::{ErrorCode, FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+/// The option marking a requested secret as optional.
+const OPTIONAL: &str = "optional";
+
+/// Split the `optional` option off a requested secret, returning the name with the remaining
+/// options and the value of the option, None if not given.
+fn strip_optional(requested: &str) -> Result<(String, Option<bool>), String> {
+    let Some((name, query)) = requested.split_once('?') else {
+        return Ok((requested.to_string(), None));
+    };
+
+    let mut optional = None;
+    let mut options = Vec::new();
+    for option in query.split('&').filter(|option| !option.is_empty()) {
+        match option.split_once('=') {
+            Some((OPTIONAL, value)) => {
+                if optional.is_some() {
+                    return Err(format!("duplicate option: {}", OPTIONAL));
+                }
+                let value = value
+                    .parse()
+                    .map_err(|_| format!("invalid value of the {} option: {}", OPTIONAL, value))?;
+                optional = Some(value);
+            }
+            _ => options.push(option),
+        }
+    }
+    if options.is_empty() {
+        return Ok((name.to_string(), optional));
+    }
+    Ok((format!("{}?{}", name, options.join("&")), optional))
+}
+
+/// A struct wrapping a secrets loader to return the default values of the optional secrets that
+/// don't exist. Other errors, e.g. denied access, are still returned for optional secrets.
+pub struct OptionalLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    /// The default values of the secrets marked as optional in the config file, keyed by name.
+    defaults: HashMap<String, String>,
+}
+
+/// Implement the OptionalLoader constructor.
+impl OptionalLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        defaults: HashMap<String, String>,
+    ) -> Self {
+        Self { inner, defaults }
+    }
+}
+
+/// Implement the OptionalLoader methods.
+impl OptionalLoader {
+    /// The default value of a secret if it's optional. The option of the requested secret takes
+    /// precedence over the config file, which is looked up by the name with and without options.
+    fn default_value(&self, name: &str, optional: Option<bool>) -> Option<String> {
+        let configured = self.defaults.get(name).or_else(|| {
+            name.split_once('?')
+                .and_then(|(base, _)| self.defaults.get(base))
+        });
+        match (optional, configured) {
+            (Some(false), _) => None,
+            (_, Some(default)) => Some(default.clone()),
+            (Some(true), None) => Some(String::new()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Implement the LoadSecrets trait for OptionalLoader.
+#[async_trait]
+impl LoadSecrets for OptionalLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let mut fetched_secrets = FetchedSecrets::default();
+        let mut requested_names: HashMap<String, Vec<(String, Option<String>)>> = HashMap::new();
+        for requested in secrets.secrets {
+            match strip_optional(&requested) {
+                Ok((name, optional)) => {
+                    let default = self.default_value(&name, optional);
+                    requested_names
+                        .entry(name)
+                        .or_default()
+                        .push((requested, default));
+                }
+                Err(error) => {
+                    fetched_secrets.0.insert(requested, invalid_request(error));
+                }
+            }
+        }
+        if requested_names.is_empty() {
+            return fetched_secrets;
+        }
+
+        let names = requested_names.keys().cloned().collect();
+        let fetched = self
+            .inner
+            .load(SecretsToFetch {
+                version: secrets.version,
+                secrets: names,
+            })
+            .await;
+        for (name, secret) in fetched.0 {
+            for (requested, default) in requested_names.remove(&name).unwrap_or_default() {
+                let secret = match default {
+                    Some(default) if secret.code == Some(ErrorCode::NotFound) => Ok(default).into(),
+                    _ => secret.clone(),
+                };
+                fetched_secrets.0.insert(requested, secret);
+            }
+        }
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        let (name, _) = strip_optional(requested).ok()?;
+        self.inner.current_version(&name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+    use crate::secret::Secret;
+
+    #[test]
+    fn optional_options_are_stripped() {
+        assert_eq!(
+            strip_optional("/app/a?optional=true"),
+            Ok((String::from("/app/a"), Some(true)))
+        );
+        assert_eq!(
+            strip_optional("app/b?key=password&optional=false&stage=AWSPREVIOUS"),
+            Ok((
+                String::from("app/b?key=password&stage=AWSPREVIOUS"),
+                Some(false)
+            ))
+        );
+        assert_eq!(
+            strip_optional("app/c?key=password"),
+            Ok((String::from("app/c?key=password"), None))
+        );
+        assert_eq!(
+            strip_optional("/app/d?optional=yes"),
+            Err(String::from("invalid value of the optional option: yes"))
+        );
+    }
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| {
+                        let secret = if name.starts_with("/found") {
+                            Ok(format!("value of {}", name))
+                        } else if name.starts_with("/denied") {
+                            Err(SecretsHelperError::AccessDenied(String::from("denied")))
+                        } else {
+                            Err(SecretsHelperError::NotFound(String::from("not found")))
+                        };
+                        (name, secret.into())
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_optional_secrets_get_their_defaults() {
+        let loader = OptionalLoader::new(
+            Box::new(MockLoader {}),
+            HashMap::from([
+                (String::from("/missing/configured"), String::from("none")),
+                (String::from("/missing/overridden"), String::new()),
+            ]),
+        );
+
+        let fetched_secrets = loader
+            .load(SecretsToFetch::from_names(vec![
+                String::from("/found/a?optional=true"),
+                String::from("/missing/a?optional=true"),
+                String::from("/missing/configured"),
+                String::from("/missing/overridden?optional=false"),
+                String::from("/missing/required"),
+                String::from("/denied/a?optional=true"),
+            ]))
+            .await;
+
+        let value = |name: &str| fetched_secrets.0[name].value.clone();
+        let code = |name: &str| fetched_secrets.0[name].code;
+        assert_eq!(
+            value("/found/a?optional=true"),
+            Some(Secret::new(String::from("value of /found/a")))
+        );
+        assert_eq!(
+            value("/missing/a?optional=true"),
+            Some(Secret::new(String::new()))
+        );
+        assert_eq!(
+            value("/missing/configured"),
+            Some(Secret::new(String::from("none")))
+        );
+        assert_eq!(
+            code("/missing/overridden?optional=false"),
+            Some(ErrorCode::NotFound)
+        );
+        assert_eq!(code("/missing/required"), Some(ErrorCode::NotFound));
+        assert_eq!(
+            code("/denied/a?optional=true"),
+            Some(ErrorCode::AccessDenied)
+        );
+    }
+}
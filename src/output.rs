@@ -0,0 +1,571 @@
+//! This module contains helpers for writing the fetched secrets to their destination.
+
+use crate::secret::Secret;
+use crate::vector::FetchedSecrets;
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+/// An enum representing the formats the fetched secrets can be written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// The JSON response expected by the Vector exec backend.
+    #[default]
+    Vector,
+    /// One file per secret in the output directory, named after its key, to be loaded with the
+    /// LoadCredential= or ImportCredential= settings of systemd units.
+    #[value(name = "systemd-creds")]
+    SystemdCreds,
+    /// One read-only file per secret in the output directory, named after its key, following the
+    /// convention of Docker and Compose secrets mounted at /run/secrets.
+    #[value(name = "docker-secrets")]
+    DockerSecrets,
+    /// A Kubernetes Secret manifest holding the secrets under their keys, to be applied with
+    /// kubectl.
+    #[value(name = "k8s-secret")]
+    K8sSecret,
+}
+
+/// A struct representing the manifest of a Kubernetes Secret.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SecretManifest<'a> {
+    api_version: &'static str,
+    kind: &'static str,
+    metadata: SecretMetadata<'a>,
+    #[serde(rename = "type")]
+    secret_type: &'static str,
+    data: BTreeMap<&'a str, String>,
+}
+
+/// A struct representing the metadata of a Kubernetes Secret.
+#[derive(Debug, Serialize)]
+struct SecretMetadata<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<&'a str>,
+}
+
+/// An enum representing the encodings fetched values can be returned in.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ValueEncoding {
+    /// Standard base64 with padding.
+    Base64,
+}
+
+/// Encode the values of the fetched secrets, leaving errors untouched.
+pub fn encode_values(fetched_secrets: &mut FetchedSecrets, encoding: ValueEncoding) {
+    for value in fetched_secrets
+        .0
+        .values_mut()
+        .filter_map(|secret| secret.value.as_mut())
+    {
+        *value = Secret::new(match encoding {
+            ValueEncoding::Base64 => {
+                base64::engine::general_purpose::STANDARD.encode(value.expose_secret())
+            }
+        });
+    }
+}
+
+/// The hex-encoded SHA-256 digest of a secret value.
+pub fn digest(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Replace the values of the fetched secrets with the hex-encoded SHA-256 digests of them, so that
+/// changes can be detected without handling the values, leaving errors untouched.
+pub fn hash_values(fetched_secrets: &mut FetchedSecrets) {
+    for value in fetched_secrets
+        .0
+        .values_mut()
+        .filter_map(|secret| secret.value.as_mut())
+    {
+        *value = Secret::new(digest(value.expose_secret()));
+    }
+}
+
+/// An enum representing the case the keys of the output can be rewritten to.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum KeyCase {
+    /// E.g. DB_PASSWORD, the shape of environment variable names.
+    #[value(name = "upper_snake")]
+    UpperSnake,
+    /// E.g. db_password.
+    #[value(name = "lower_snake")]
+    LowerSnake,
+    /// E.g. db-password.
+    Kebab,
+}
+
+/// Convert a key to the case, treating every run of characters that aren't alphanumeric as a word
+/// separator.
+fn convert_case(key: &str, case: KeyCase) -> String {
+    let words: Vec<_> = key
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    match case {
+        KeyCase::UpperSnake => words.join("_").to_uppercase(),
+        KeyCase::LowerSnake => words.join("_").to_lowercase(),
+        KeyCase::Kebab => words.join("-").to_lowercase(),
+    }
+}
+
+/// Rewrite the keys of the fetched secrets independently of the names of the AWS resources,
+/// failing if two secrets end up with the same key.
+pub fn rewrite_keys(
+    fetched_secrets: FetchedSecrets,
+    strip_prefix: Option<&str>,
+    key_case: Option<KeyCase>,
+) -> Result<FetchedSecrets, String> {
+    let mut rewritten = FetchedSecrets::default();
+    for (name, secret) in fetched_secrets.0 {
+        let key = match strip_prefix {
+            Some(prefix) => name.strip_prefix(prefix).unwrap_or(&name),
+            None => &name,
+        };
+        let key = match key_case {
+            Some(case) => convert_case(key, case),
+            None => key.to_string(),
+        };
+        if rewritten.0.contains_key(&key) {
+            return Err(format!("multiple secrets are rewritten to the key {}", key));
+        }
+        rewritten.0.insert(key, secret);
+    }
+    Ok(rewritten)
+}
+
+/// Write the output to the writer, e.g. stdout, followed by a single newline and flush it, so that
+/// the whole response is written before the process exits.
+pub fn write_line(writer: &mut dyn Write, output: &str) -> std::io::Result<()> {
+    writer.write_all(output.as_bytes())?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+/// A struct writing the JSON response expected by Vector in parts, entry by entry, so that the
+/// fetched secrets of a large request don't have to be held in memory and reach Vector sooner.
+pub struct StreamedResponse<'a> {
+    writer: &'a mut dyn Write,
+    entries: usize,
+}
+
+/// Implement the StreamedResponse constructor.
+impl<'a> StreamedResponse<'a> {
+    pub fn new(writer: &'a mut dyn Write) -> Self {
+        Self { writer, entries: 0 }
+    }
+}
+
+/// Implement the StreamedResponse methods.
+impl StreamedResponse<'_> {
+    /// Write the entries of the fetched secrets and flush them. Keys must not repeat those of
+    /// earlier parts, so that the response stays valid JSON.
+    pub fn write(&mut self, fetched_secrets: &FetchedSecrets) -> std::io::Result<()> {
+        for (key, secret) in &fetched_secrets.0 {
+            let separator: &[u8] = if self.entries == 0 { b"{" } else { b"," };
+            self.writer.write_all(separator)?;
+            serde_json::to_writer(&mut *self.writer, key)?;
+            self.writer.write_all(b":")?;
+            serde_json::to_writer(&mut *self.writer, secret)?;
+            self.entries += 1;
+        }
+        self.writer.flush()
+    }
+
+    /// Close the JSON object and end the response with a newline like write_line.
+    pub fn finish(self) -> std::io::Result<()> {
+        let end: &[u8] = if self.entries == 0 { b"{}\n" } else { b"}\n" };
+        self.writer.write_all(end)?;
+        self.writer.flush()
+    }
+}
+
+/// Check that a key can be used as the name of a file in the output directory.
+fn check_file_name(key: &str) -> Result<(), String> {
+    if key.is_empty() || key == "." || key == ".." || key.contains(['/', '\\', '\0']) {
+        return Err(format!(
+            "key {} can't be used as a file name, rewrite it with --strip-prefix or --key-case",
+            key
+        ));
+    }
+    Ok(())
+}
+
+/// Write the value of every fetched secret to a file named after its key in the directory,
+/// creating the directory readable only by its owner if it doesn't exist. Every file is replaced
+/// atomically and made read-only if requested. Secrets that couldn't be fetched are skipped,
+/// leaving a previously written file in place.
+pub fn write_files(
+    dir: &Path,
+    fetched_secrets: &FetchedSecrets,
+    read_only: bool,
+) -> Result<(), String> {
+    for key in fetched_secrets.0.keys() {
+        check_file_name(key)?;
+    }
+    if !dir.exists() {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(dir)
+            .map_err(|error| format!("failed to create {}: {}", dir.display(), error))?;
+    }
+    for (key, secret) in &fetched_secrets.0 {
+        let Some(value) = &secret.value else {
+            continue;
+        };
+        let path = dir.join(key);
+        replace_file(&path, value.expose_secret().as_bytes(), read_only)
+            .map_err(|error| format!("failed to write {}: {}", path.display(), error))?;
+    }
+    Ok(())
+}
+
+/// Build the YAML manifest of an Opaque Kubernetes Secret with the base64-encoded values of the
+/// fetched secrets. Secrets that couldn't be fetched are left out.
+pub fn k8s_secret_manifest(
+    fetched_secrets: &FetchedSecrets,
+    name: &str,
+    namespace: Option<&str>,
+) -> Result<String, String> {
+    let mut data = BTreeMap::new();
+    for (key, secret) in &fetched_secrets.0 {
+        let valid = !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(format!(
+                "key {} can't be used in a Kubernetes Secret, rewrite it with --strip-prefix or --key-case",
+                key
+            ));
+        }
+        if let Some(value) = &secret.value {
+            let encoded = base64::engine::general_purpose::STANDARD.encode(value.expose_secret());
+            data.insert(key.as_str(), encoded);
+        }
+    }
+    let manifest = SecretManifest {
+        api_version: "v1",
+        kind: "Secret",
+        metadata: SecretMetadata { name, namespace },
+        secret_type: "Opaque",
+        data,
+    };
+    Ok(serde_yaml::to_string(&manifest).unwrap())
+}
+
+/// Write the contents to a file by writing a temporary file next to it and renaming it into place,
+/// so that readers never observe a partially written file. The file is only readable and writable
+/// by its owner.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    replace_file(path, contents, false)
+}
+
+/// Atomically replace the file with the contents, only readable and writable by its owner or, if
+/// read-only, only readable by its owner.
+fn replace_file(path: &Path, contents: &[u8], read_only: bool) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    // Temporary files are created with 0600 permissions on Unix.
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(contents)?;
+    #[cfg(unix)]
+    if read_only {
+        use std::os::unix::fs::PermissionsExt;
+        file.as_file()
+            .set_permissions(std::fs::Permissions::from_mode(0o400))?;
+    }
+    #[cfg(not(unix))]
+    if read_only {
+        let mut permissions = file.as_file().metadata()?.permissions();
+        permissions.set_readonly(true);
+        file.as_file().set_permissions(permissions)?;
+    }
+    file.as_file().sync_all()?;
+    file.persist(path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+    use crate::vector::FetchedSecret;
+
+    #[test]
+    fn encode_values_encodes_only_values() {
+        let mut fetched_secrets = FetchedSecrets(
+            [
+                (
+                    "test.secret_1".to_string(),
+                    FetchedSecret {
+                        value: Some(Secret::new("qwerty\n".to_string())),
+                        error: None,
+                        code: None,
+                    },
+                ),
+                (
+                    "test.secret_2".to_string(),
+                    FetchedSecret {
+                        value: None,
+                        error: Some("failed to fetch".to_string()),
+                        code: None,
+                    },
+                ),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+
+        encode_values(&mut fetched_secrets, ValueEncoding::Base64);
+
+        assert_eq!(
+            fetched_secrets.0["test.secret_1"].value,
+            Some(Secret::new("cXdlcnR5Cg==".to_string()))
+        );
+        assert_eq!(
+            fetched_secrets.0["test.secret_2"].error,
+            Some("failed to fetch".to_string())
+        );
+    }
+
+    #[test]
+    fn rewrite_keys_strips_prefix_and_converts_case() {
+        let secret = FetchedSecret {
+            value: Some(Secret::new("qwerty".to_string())),
+            error: None,
+            code: None,
+        };
+        let fetched_secrets = FetchedSecrets(
+            [
+                ("/prod/vector/db-password".to_string(), secret.clone()),
+                ("/prod/vector/api.key".to_string(), secret.clone()),
+            ]
+            .iter()
+            .cloned()
+            .collect(),
+        );
+
+        let rewritten = rewrite_keys(
+            fetched_secrets.clone(),
+            Some("/prod/vector/"),
+            Some(KeyCase::UpperSnake),
+        )
+        .unwrap();
+        let mut keys: Vec<_> = rewritten.0.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["API_KEY", "DB_PASSWORD"]);
+
+        let rewritten = rewrite_keys(fetched_secrets.clone(), None, Some(KeyCase::Kebab)).unwrap();
+        assert!(rewritten.0.contains_key("prod-vector-db-password"));
+
+        assert_eq!(
+            rewrite_keys(
+                FetchedSecrets(
+                    [
+                        ("a.b".to_string(), secret.clone()),
+                        ("a_b".to_string(), secret)
+                    ]
+                    .iter()
+                    .cloned()
+                    .collect()
+                ),
+                None,
+                Some(KeyCase::LowerSnake),
+            ),
+            Err(String::from(
+                "multiple secrets are rewritten to the key a_b"
+            ))
+        );
+    }
+
+    #[test]
+    fn write_atomically_replaces_file_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.json");
+        std::fs::write(&path, "old").unwrap();
+
+        write_atomically(&path, b"new").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn write_files_writes_a_file_per_secret() {
+        let dir = tempfile::tempdir().unwrap();
+        let output_dir = dir.path().join("credentials");
+        let mut fetched_secrets = FetchedSecrets::default();
+        fetched_secrets.0.insert(
+            String::from("db_password"),
+            Ok(String::from("hunter2")).into(),
+        );
+        fetched_secrets.0.insert(
+            String::from("api_key"),
+            SecretsHelperError::NotFound(String::from("not found")).into(),
+        );
+
+        write_files(&output_dir, &fetched_secrets, false).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(output_dir.join("db_password")).unwrap(),
+            "hunter2"
+        );
+        assert!(!output_dir.join("api_key").exists());
+
+        fetched_secrets.0.insert(
+            String::from("/app/token"),
+            Ok(String::from("qwerty")).into(),
+        );
+        assert_eq!(
+            write_files(&output_dir, &fetched_secrets, false),
+            Err(String::from(
+                "key /app/token can't be used as a file name, rewrite it with --strip-prefix or --key-case"
+            ))
+        );
+    }
+
+    #[test]
+    fn hash_values_replaces_values_with_digests() {
+        let mut fetched_secrets = FetchedSecrets::default();
+        fetched_secrets.0.insert(
+            String::from("db_password"),
+            Ok(String::from("hunter2")).into(),
+        );
+        fetched_secrets.0.insert(
+            String::from("api_key"),
+            SecretsHelperError::NotFound(String::from("not found")).into(),
+        );
+
+        hash_values(&mut fetched_secrets);
+
+        assert_eq!(
+            fetched_secrets.0["db_password"].value,
+            Some(Secret::new(String::from(
+                "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7"
+            )))
+        );
+        assert_eq!(
+            fetched_secrets.0["api_key"].code,
+            Some(crate::vector::ErrorCode::NotFound)
+        );
+    }
+
+    #[test]
+    fn k8s_secret_manifest_holds_the_fetched_values() {
+        let mut fetched_secrets = FetchedSecrets::default();
+        fetched_secrets.0.insert(
+            String::from("db_password"),
+            Ok(String::from("hunter2")).into(),
+        );
+        fetched_secrets.0.insert(
+            String::from("api_key"),
+            SecretsHelperError::NotFound(String::from("not found")).into(),
+        );
+
+        assert_eq!(
+            k8s_secret_manifest(&fetched_secrets, "vector-secrets", Some("obs")).unwrap(),
+            "apiVersion: v1
+kind: Secret
+metadata:
+  name: vector-secrets
+  namespace: obs
+type: Opaque
+data:
+  db_password: aHVudGVyMg==
+"
+        );
+
+        fetched_secrets.0.insert(
+            String::from("/app/token"),
+            Ok(String::from("qwerty")).into(),
+        );
+        assert!(k8s_secret_manifest(&fetched_secrets, "vector-secrets", None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomically_restricts_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secrets.json");
+
+        write_atomically(&path, b"{}").unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_files_are_replaced_atomically() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut fetched_secrets = FetchedSecrets::default();
+        fetched_secrets
+            .0
+            .insert(String::from("db_password"), Ok(String::from("old")).into());
+        write_files(dir.path(), &fetched_secrets, true).unwrap();
+
+        fetched_secrets
+            .0
+            .insert(String::from("db_password"), Ok(String::from("new")).into());
+        write_files(dir.path(), &fetched_secrets, true).unwrap();
+
+        let path = dir.path().join("db_password");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o400);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn streamed_responses_are_valid_json() {
+        let part = |names: &[&str]| {
+            FetchedSecrets(
+                names
+                    .iter()
+                    .map(|name| (name.to_string(), Ok(format!("{}-value", name)).into()))
+                    .collect(),
+            )
+        };
+
+        let mut output = Vec::new();
+        let mut response = StreamedResponse::new(&mut output);
+        response.write(&part(&["a\"1", "b"])).unwrap();
+        response.write(&part(&[])).unwrap();
+        response.write(&part(&["c"])).unwrap();
+        response.finish().unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.ends_with("}\n"));
+        let response: FetchedSecrets = serde_json::from_str(&output).unwrap();
+        assert_eq!(response.0.len(), 3);
+        assert_eq!(
+            response.0["a\"1"].value.as_ref().unwrap().expose_secret(),
+            "a\"1-value"
+        );
+
+        let mut output = Vec::new();
+        StreamedResponse::new(&mut output).finish().unwrap();
+        assert_eq!(output, b"{}\n");
+    }
+}
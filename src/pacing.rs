@@ -0,0 +1,97 @@
+//! This module contains the pacer spreading the starts of fetch calls evenly over time.
+
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A struct pacing calls to a fixed rate, like a token bucket holding a single token, so that a
+/// large request turns into a steady stream of API calls instead of a burst.
+#[derive(Debug)]
+pub struct Pacer {
+    interval: Duration,
+    /// The earliest time the next call may start.
+    next: Mutex<Option<Instant>>,
+}
+
+/// Implement the Pacer constructor.
+impl Pacer {
+    pub fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next: Mutex::new(None),
+        }
+    }
+}
+
+/// Implement the Pacer methods.
+impl Pacer {
+    /// Reserve the earliest free slot at or after now, returning when it starts.
+    fn reserve(&self, now: Instant) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let start = match *next {
+            Some(next) if next > now => next,
+            _ => now,
+        };
+        *next = Some(start + self.interval);
+        start
+    }
+
+    /// Wait for the next free slot.
+    pub async fn wait(&self) {
+        let start = self.reserve(Instant::now());
+        tokio::time::sleep_until(start).await;
+    }
+}
+
+/// Parse a positive rate of requests per second, whose interval between calls can be represented
+/// as a duration.
+pub fn parse_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(parsed) if parsed.is_finite() && parsed > 0.0 => {
+            match Duration::try_from_secs_f64(1.0 / parsed) {
+                Ok(_) => Ok(parsed),
+                Err(_) => Err(format!("{} is too low a rate", rate)),
+            }
+        }
+        _ => Err(format!("{} is not a positive number", rate)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_are_spread_at_the_rate() {
+        let pacer = Pacer::new(4.0);
+        let now = Instant::now();
+
+        let starts: Vec<_> = (0..3).map(|_| pacer.reserve(now) - now).collect();
+        assert_eq!(
+            starts,
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(250),
+                Duration::from_millis(500)
+            ]
+        );
+
+        // Unused slots don't accumulate into a burst after an idle period.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(pacer.reserve(later), later);
+        assert_eq!(pacer.reserve(later) - later, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn rates_must_be_positive() {
+        assert_eq!(parse_rate("2.5"), Ok(2.5));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("-1").is_err());
+        assert!(parse_rate("inf").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert_eq!(
+            parse_rate("1e-20"),
+            Err(String::from("1e-20 is too low a rate"))
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! This module contains the renderer of templates referencing secrets with `{{ secret "name" }}`
+//! placeholders, used to hydrate companion config files of Vector.
+
+use crate::vector::FetchedSecrets;
+use regex::{Captures, Regex};
+use std::sync::OnceLock;
+
+/// The placeholders of secrets in templates, capturing the name of the secret.
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r#"\{\{\s*secret\s+"([^"]+)"\s*\}\}"#).unwrap())
+}
+
+/// Find the names of the secrets referenced in the template, in order of first appearance.
+pub fn references(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for captures in placeholder().captures_iter(template) {
+        if !names.iter().any(|name| name == &captures[1]) {
+            names.push(captures[1].to_string());
+        }
+    }
+    names
+}
+
+/// Render the template by replacing the placeholders with the values of the fetched secrets,
+/// failing with the names of the secrets that couldn't be fetched.
+pub fn render(template: &str, fetched_secrets: &FetchedSecrets) -> Result<String, Vec<String>> {
+    let mut failed = Vec::new();
+    let rendered = placeholder().replace_all(template, |captures: &Captures| {
+        let value = fetched_secrets
+            .0
+            .get(&captures[1])
+            .and_then(|secret| secret.value.as_ref());
+        match value {
+            Some(value) => value.expose_secret().clone(),
+            None => {
+                if !failed.iter().any(|name| name == &captures[1]) {
+                    failed.push(captures[1].to_string());
+                }
+                String::new()
+            }
+        }
+    });
+    if !failed.is_empty() {
+        return Err(failed);
+    }
+    Ok(rendered.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecretsHelperError;
+
+    const TEMPLATE: &str = r#"[auth]
+user = "{{ secret "/app/user" }}"
+password = "{{secret "/app/password"}}"
+# {{ not_a_secret }}
+again = "{{  secret  "/app/user"  }}"
+"#;
+
+    #[test]
+    fn references_are_extracted_once() {
+        assert_eq!(references(TEMPLATE), vec!["/app/user", "/app/password"]);
+    }
+
+    #[test]
+    fn templates_are_rendered_with_the_fetched_values() {
+        let mut fetched_secrets = FetchedSecrets::default();
+        fetched_secrets
+            .0
+            .insert(String::from("/app/user"), Ok(String::from("vector")).into());
+        fetched_secrets.0.insert(
+            String::from("/app/password"),
+            Ok(String::from("hunter2")).into(),
+        );
+
+        assert_eq!(
+            render(TEMPLATE, &fetched_secrets).unwrap(),
+            r#"[auth]
+user = "vector"
+password = "hunter2"
+# {{ not_a_secret }}
+again = "vector"
+"#
+        );
+
+        fetched_secrets.0.insert(
+            String::from("/app/password"),
+            SecretsHelperError::NotFound(String::from("not found")).into(),
+        );
+        assert_eq!(
+            render(TEMPLATE, &fetched_secrets),
+            Err(vec![String::from("/app/password")])
+        );
+    }
+}
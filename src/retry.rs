@@ -0,0 +1,169 @@
+//! This module contains the retry policy applied to fetching individual secrets.
+
+use crate::vector::{ErrorCode, FetchedSecret};
+use std::future::Future;
+use std::time::Duration;
+
+/// A struct describing how failed fetches are retried.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled with every following retry.
+    pub initial_backoff: Duration,
+    /// The upper bound of the delay between attempts.
+    pub max_backoff: Duration,
+}
+
+/// Implement the Default trait for RetryPolicy.
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Implement the RetryPolicy methods.
+impl RetryPolicy {
+    /// Whether a failure of the given class may succeed when retried. Permanent failures like a
+    /// missing secret or denied access fail immediately without spending further attempts.
+    pub fn is_transient(code: ErrorCode) -> bool {
+        matches!(
+            code,
+            ErrorCode::Throttled
+                | ErrorCode::Timeout
+                | ErrorCode::ConnectionFailure
+                | ErrorCode::ServiceError
+        )
+    }
+
+    /// The delay before the given retry, using exponential backoff with full jitter.
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        backoff.mul_f64(fastrand::f64())
+    }
+
+    /// Run the fetch, retrying it while it fails with transient errors and attempts are left.
+    #[cfg(test)]
+    pub async fn run<F, Fut>(&self, fetch: F) -> FetchedSecret
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = FetchedSecret>,
+    {
+        self.run_while(fetch, || true).await
+    }
+
+    /// Run the fetch, retrying it while it fails with transient errors, attempts are left and
+    /// retries are allowed, e.g. by the retry budget.
+    pub async fn run_while<F, Fut>(
+        &self,
+        mut fetch: F,
+        allow_retry: impl Fn() -> bool,
+    ) -> FetchedSecret
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = FetchedSecret>,
+    {
+        let mut attempt = 1;
+        loop {
+            let fetched_secret = fetch().await;
+            let transient = fetched_secret.code.is_some_and(Self::is_transient);
+            if !transient || attempt >= self.max_attempts || !allow_retry() {
+                return fetched_secret;
+            }
+            tokio::time::sleep(self.backoff(attempt - 1)).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    fn failure(code: ErrorCode) -> FetchedSecret {
+        FetchedSecret {
+            value: None,
+            error: Some(String::from("failed to fetch")),
+            code: Some(code),
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_policy_retries_transient_errors() {
+        let attempts = AtomicU32::new(0);
+        let fetched_secret = policy()
+            .run(|| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => failure(ErrorCode::Throttled),
+                    _ => FetchedSecret {
+                        value: Some(Secret::new(String::from("qwerty"))),
+                        error: None,
+                        code: None,
+                    },
+                }
+            })
+            .await;
+
+        assert_eq!(
+            fetched_secret.value,
+            Some(Secret::new(String::from("qwerty")))
+        );
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_policy_gives_up_after_max_attempts() {
+        let attempts = AtomicU32::new(0);
+        let fetched_secret = policy()
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                failure(ErrorCode::Timeout)
+            })
+            .await;
+
+        assert_eq!(fetched_secret.code, Some(ErrorCode::Timeout));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retry_policy_fails_fast_on_permanent_errors() {
+        let attempts = AtomicU32::new(0);
+        let fetched_secret = policy()
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                failure(ErrorCode::NotFound)
+            })
+            .await;
+
+        assert_eq!(fetched_secret.code, Some(ErrorCode::NotFound));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retry_policy_backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        assert!(policy.backoff(0) <= Duration::from_millis(100));
+        assert!(policy.backoff(8) <= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,109 @@
+//! This module contains the scanner extracting secret references from Vector configs.
+
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// The extensions of the files considered to be Vector configs when scanning directories.
+const CONFIG_EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+
+/// A type alias for the secret names referenced in Vector configs, keyed by secrets backend name.
+pub type References = BTreeMap<String, BTreeSet<String>>;
+
+/// Find all `SECRET[backend.name]` references in the text, using the same syntax as Vector.
+pub fn find_references(text: &str, references: &mut References) {
+    static SECRET_REFERENCE: OnceLock<Regex> = OnceLock::new();
+    let secret_reference = SECRET_REFERENCE
+        .get_or_init(|| Regex::new(r"SECRET\[([[:word:]]+)\.([[:word:].]+)\]").unwrap());
+
+    for captures in secret_reference.captures_iter(text) {
+        references
+            .entry(captures[1].to_string())
+            .or_default()
+            .insert(captures[2].to_string());
+    }
+}
+
+/// Scan a Vector config file, or all config files in a directory recursively, for secret
+/// references.
+pub fn scan(path: &Path, references: &mut References) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            let is_config = entry
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| CONFIG_EXTENSIONS.contains(&extension));
+            if entry.is_dir() || is_config {
+                scan(&entry, references)?;
+            }
+        }
+    } else {
+        find_references(&std::fs::read_to_string(path)?, references);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_references_extracts_secret_references() {
+        let config = r#"
+            [sinks.datadog]
+            default_api_key = "SECRET[aws_ssm.datadog_api_key]"
+
+            [sinks.http.auth]
+            user = "SECRET[aws_secrets_manager.http.user]"
+            password = "SECRET[aws_secrets_manager.http.password]"
+            other = "SECRET[aws_ssm.datadog_api_key]"
+        "#;
+
+        let mut references = References::new();
+        find_references(config, &mut references);
+
+        assert_eq!(
+            references,
+            [
+                (
+                    "aws_secrets_manager".to_string(),
+                    ["http.password".to_string(), "http.user".to_string()].into()
+                ),
+                (
+                    "aws_ssm".to_string(),
+                    ["datadog_api_key".to_string()].into()
+                ),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn scan_reads_config_directories_recursively() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sinks")).unwrap();
+        std::fs::write(
+            dir.path().join("sinks").join("datadog.yaml"),
+            "default_api_key: SECRET[aws_ssm.datadog_api_key]",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "SECRET[aws_ssm.ignored]").unwrap();
+
+        let mut references = References::new();
+        scan(dir.path(), &mut references).unwrap();
+
+        assert_eq!(
+            references,
+            [(
+                "aws_ssm".to_string(),
+                ["datadog_api_key".to_string()].into()
+            )]
+            .into()
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! This module contains a wrapper for secret values that keeps them out of error messages and logs.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A struct wrapping a secret value whose Debug and Display implementations are redacted, so that
+/// the value can't leak through formatting. It is serialized as the bare value, since returning
+/// the value to Vector is the whole point of the helper.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+/// Implement the Secret methods.
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Get the secret value. Callers are responsible for not logging it.
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+
+    /// Take the secret value out of the wrapper.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// Implement conversion of a string into a Secret.
+impl From<String> for Secret<String> {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+/// Implement the Debug trait for Secret, never printing the value.
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+/// Implement the Display trait for Secret, never printing the value.
+impl<T> fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_redacted_when_formatted() {
+        let secret = Secret::new(String::from("qwerty"));
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        assert_eq!(format!("{:?}", Some(&secret)), "Some(Secret([REDACTED]))");
+        assert_eq!(secret.to_string(), "[REDACTED]");
+        assert_eq!(secret.expose_secret(), "qwerty");
+    }
+
+    #[test]
+    fn secret_is_serialized_as_the_bare_value() {
+        let secret = Secret::new(String::from("qwerty"));
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"qwerty\"");
+        let secret: Secret<String> = serde_json::from_str("\"qwerty\"").unwrap();
+        assert_eq!(secret.into_inner(), "qwerty");
+    }
+}
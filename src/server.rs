@@ -0,0 +1,322 @@
+//! This module contains the gRPC server exposing the secrets loaders to other services.
+
+use crate::aws::loader::{with_deadline, LoadSecrets};
+use crate::secret::Secret;
+use crate::vector::{FetchedSecret, FetchedSecrets, SecretsToFetch};
+use futures::StreamExt;
+use proto::secrets_resolver_server::{SecretsResolver, SecretsResolverServer};
+use proto::{ResolveSecretsRequest, ResolveSecretsResponse};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_stream::Stream;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status, Streaming};
+
+/// Types generated from the protobuf definition of the gRPC service.
+pub mod proto {
+    tonic::include_proto!("vector_aws_secrets_helper.v1");
+}
+
+/// A type alias for the secrets loaders served by the gRPC server, keyed by backend name.
+pub type Loaders = HashMap<String, Box<dyn LoadSecrets + Send + Sync>>;
+
+/// A struct holding the settings of the gRPC server.
+pub struct ServerSettings {
+    pub addr: SocketAddr,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub request_timeout: Duration,
+}
+
+/// A struct holding the secrets loaders served by the gRPC server, which can be replaced while
+/// the server is running. Requests in flight keep using the loaders they started with.
+#[derive(Clone)]
+pub struct SharedLoaders(Arc<RwLock<Arc<Loaders>>>);
+
+/// Implement the SharedLoaders methods.
+impl SharedLoaders {
+    pub fn new(loaders: Loaders) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(loaders))))
+    }
+
+    /// Get the current loaders.
+    pub fn get(&self) -> Arc<Loaders> {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Replace the loaders used by subsequent requests.
+    pub fn replace(&self, loaders: Loaders) {
+        *self.0.write().unwrap() = Arc::new(loaders);
+    }
+}
+
+/// A struct implementing the SecretsResolver gRPC service on top of the secrets loaders.
+pub struct SecretsResolverService {
+    loaders: SharedLoaders,
+    max_secrets: usize,
+}
+
+/// Implement the SecretsResolverService constructor.
+impl SecretsResolverService {
+    pub fn new(loaders: SharedLoaders, max_secrets: usize) -> Self {
+        Self {
+            loaders,
+            max_secrets,
+        }
+    }
+}
+
+/// Resolve a single batch of secrets using the loader for the requested backend.
+async fn resolve(
+    loaders: &Loaders,
+    max_secrets: usize,
+    request: ResolveSecretsRequest,
+) -> Result<ResolveSecretsResponse, Status> {
+    let loader = loaders
+        .get(&request.backend)
+        .ok_or_else(|| Status::invalid_argument(format!("unknown backend: {}", request.backend)))?;
+
+    let secrets_to_fetch = SecretsToFetch {
+        version: request.version,
+        secrets: request.secrets,
+    };
+    secrets_to_fetch
+        .check_limit(max_secrets)
+        .map_err(Status::invalid_argument)?;
+    // Every request gets a retry budget of its own, and is bounded by the timeout of the server.
+    let fetched_secrets = with_deadline(None, loader.load(secrets_to_fetch)).await;
+
+    Ok(fetched_secrets.into())
+}
+
+/// Implement conversion of the fetched secrets into the gRPC response.
+impl From<FetchedSecrets> for ResolveSecretsResponse {
+    fn from(fetched_secrets: FetchedSecrets) -> Self {
+        Self {
+            secrets: fetched_secrets
+                .0
+                .into_iter()
+                .map(|(name, FetchedSecret { value, error, code })| {
+                    let value = value.map(Secret::into_inner);
+                    let code = code.map(|code| code.as_str().to_string());
+                    (name, proto::FetchedSecret { value, error, code })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Implement the SecretsResolver gRPC service for SecretsResolverService.
+#[tonic::async_trait]
+impl SecretsResolver for SecretsResolverService {
+    type ResolveSecretsStreamStream =
+        Pin<Box<dyn Stream<Item = Result<ResolveSecretsResponse, Status>> + Send>>;
+
+    async fn resolve_secrets(
+        &self,
+        request: Request<ResolveSecretsRequest>,
+    ) -> Result<Response<ResolveSecretsResponse>, Status> {
+        resolve(&self.loaders.get(), self.max_secrets, request.into_inner())
+            .await
+            .map(Response::new)
+    }
+
+    async fn resolve_secrets_stream(
+        &self,
+        request: Request<Streaming<ResolveSecretsRequest>>,
+    ) -> Result<Response<Self::ResolveSecretsStreamStream>, Status> {
+        let (loaders, max_secrets) = (self.loaders.clone(), self.max_secrets);
+        let responses = request.into_inner().then(move |message| {
+            let loaders = loaders.get();
+            async move { resolve(&loaders, max_secrets, message?).await }
+        });
+        Ok(Response::new(Box::pin(responses)))
+    }
+}
+
+/// Handle the signals requesting the server to shut down, i.e. SIGTERM and SIGINT, returning a
+/// future waiting for one of them. Signals received before the future is polled aren't lost.
+pub fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        Ok(async move {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+        })
+    }
+    #[cfg(not(unix))]
+    Ok(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+/// A struct receiving SIGHUP, which asks resident modes to reload the config file and flush the
+/// disk cache. It's never received on platforms without it.
+pub struct Hangup {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
+
+/// Implement the Hangup constructor.
+impl Hangup {
+    pub fn new() -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            Ok(Self {
+                signal: signal(SignalKind::hangup())?,
+            })
+        }
+        #[cfg(not(unix))]
+        Ok(Self {})
+    }
+}
+
+/// Implement the Hangup methods.
+impl Hangup {
+    /// Wait for the next SIGHUP, None once no more can be received.
+    pub async fn recv(&mut self) -> Option<()> {
+        #[cfg(unix)]
+        {
+            self.signal.recv().await
+        }
+        #[cfg(not(unix))]
+        std::future::pending().await
+    }
+}
+
+/// Run the gRPC server until it fails or the shutdown future completes, in which case requests in
+/// flight are drained before returning.
+pub async fn serve(
+    settings: ServerSettings,
+    service: SecretsResolverService,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut server = Server::builder().timeout(settings.request_timeout);
+    if let (Some(cert), Some(key)) = (settings.tls_cert, settings.tls_key) {
+        let identity = Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?);
+        server = server.tls_config(ServerTlsConfig::new().identity(identity))?;
+    }
+
+    server
+        .add_service(SecretsResolverServer::new(service))
+        .serve_with_shutdown(settings.addr, shutdown)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| {
+                        let secret = FetchedSecret {
+                            value: Some(Secret::new(format!("value of {}", name))),
+                            error: None,
+                            code: None,
+                        };
+                        (name, secret)
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    fn service() -> SecretsResolverService {
+        let mut loaders: Loaders = HashMap::new();
+        loaders.insert(String::from("ssm"), Box::new(MockLoader {}));
+        SecretsResolverService::new(SharedLoaders::new(loaders), 10)
+    }
+
+    #[tokio::test]
+    async fn secrets_resolver_service_resolves_secrets() {
+        let request = Request::new(ResolveSecretsRequest {
+            backend: String::from("ssm"),
+            version: String::from("1.0"),
+            secrets: vec![String::from("test.secret_1")],
+        });
+
+        let response = service().resolve_secrets(request).await.unwrap();
+
+        assert_eq!(
+            response.into_inner().secrets,
+            [(
+                "test.secret_1".to_string(),
+                proto::FetchedSecret {
+                    value: Some("value of test.secret_1".to_string()),
+                    error: None,
+                    code: None,
+                }
+            )]
+            .iter()
+            .cloned()
+            .collect()
+        );
+    }
+
+    #[tokio::test]
+    async fn secrets_resolver_service_rejects_unknown_backend() {
+        let request = Request::new(ResolveSecretsRequest {
+            backend: String::from("vault"),
+            version: String::from("1.0"),
+            secrets: vec![String::from("test.secret_1")],
+        });
+
+        let status = service().resolve_secrets(request).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn secrets_resolver_service_rejects_too_many_secrets() {
+        let request = Request::new(ResolveSecretsRequest {
+            backend: String::from("ssm"),
+            version: String::from("1.0"),
+            secrets: (0..11).map(|i| format!("test.secret_{}", i)).collect(),
+        });
+
+        let status = service().resolve_secrets(request).await.unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn secrets_resolver_service_uses_replaced_loaders() {
+        let shared_loaders = SharedLoaders::new(HashMap::new());
+        let service = SecretsResolverService::new(shared_loaders.clone(), 10);
+
+        let mut loaders: Loaders = HashMap::new();
+        loaders.insert(String::from("ssm"), Box::new(MockLoader {}));
+        shared_loaders.replace(loaders);
+
+        let request = Request::new(ResolveSecretsRequest {
+            backend: String::from("ssm"),
+            version: String::from("1.0"),
+            secrets: vec![String::from("test.secret_1")],
+        });
+        assert!(service.resolve_secrets(request).await.is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+//! This module contains the validation of fetched secret values against the rules declared in the
+//! config file, so that a bad value is reported as an error instead of being passed to Vector.
+
+use crate::aws::loader::LoadSecrets;
+use crate::config::Validation;
+use crate::error::SecretsHelperError;
+use crate::vector::{FetchedSecrets, SecretsToFetch};
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+/// Check a secret value against the validation rules, describing the first violated rule. The
+/// value itself is never included in the description.
+pub fn check(value: &str, validation: &Validation) -> Result<(), String> {
+    let length = value.chars().count();
+    if validation.non_empty && value.is_empty() {
+        return Err(String::from("must not be empty"));
+    }
+    if let Some(min_length) = validation.min_length.filter(|&min| length < min) {
+        return Err(format!("must be at least {} characters long", min_length));
+    }
+    if let Some(max_length) = validation.max_length.filter(|&max| length > max) {
+        return Err(format!("must be at most {} characters long", max_length));
+    }
+    if let Some(pattern) = validation
+        .regex
+        .as_ref()
+        .filter(|pattern| !pattern.0.is_match(value))
+    {
+        return Err(format!("must match the regex {}", pattern.0));
+    }
+    if validation.json && serde_json::from_str::<serde_json::Value>(value).is_err() {
+        return Err(String::from("must be valid JSON"));
+    }
+    Ok(())
+}
+
+/// A struct wrapping a secrets loader to validate the values it fetches.
+pub struct ValidatedLoader {
+    inner: Box<dyn LoadSecrets + Send + Sync>,
+    validations: HashMap<String, Validation>,
+}
+
+/// Implement the ValidatedLoader constructor.
+impl ValidatedLoader {
+    pub fn new(
+        inner: Box<dyn LoadSecrets + Send + Sync>,
+        validations: HashMap<String, Validation>,
+    ) -> Self {
+        Self { inner, validations }
+    }
+}
+
+/// Implement the LoadSecrets trait for ValidatedLoader.
+#[async_trait]
+impl LoadSecrets for ValidatedLoader {
+    async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+        let mut fetched_secrets = self.inner.load(secrets).await;
+        for (name, secret) in fetched_secrets.0.iter_mut() {
+            let (Some(value), Some(validation)) = (&secret.value, self.validations.get(name))
+            else {
+                continue;
+            };
+            if let Err(error) = check(value.expose_secret(), validation) {
+                *secret = SecretsHelperError::Validation(format!(
+                    "secret value failed validation: {}",
+                    error
+                ))
+                .into();
+            }
+        }
+        fetched_secrets
+    }
+
+    async fn current_version(&self, requested: &str) -> Option<String> {
+        self.inner.current_version(requested).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::secret::Secret;
+    use crate::vector::{ErrorCode, FetchedSecret};
+
+    struct MockLoader {}
+
+    #[async_trait]
+    impl LoadSecrets for MockLoader {
+        async fn load(&self, secrets: SecretsToFetch) -> FetchedSecrets {
+            FetchedSecrets(
+                secrets
+                    .secrets
+                    .into_iter()
+                    .map(|name| {
+                        let secret = FetchedSecret {
+                            value: Some(Secret::new(String::new())),
+                            error: None,
+                            code: None,
+                        };
+                        (name, secret)
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    #[test]
+    fn values_are_checked_against_all_rules() {
+        let config = Config::parse(
+            "[secrets.s.validate]\nmin_length = 2\nmax_length = 8\nregex = \"^[a-z{}:\\\"]+$\"\njson = true\n",
+        )
+        .unwrap();
+        let validation = &config.validations()["s"];
+
+        assert_eq!(
+            check("a", validation),
+            Err(String::from("must be at least 2 characters long"))
+        );
+        assert_eq!(
+            check("abcdefghi", validation),
+            Err(String::from("must be at most 8 characters long"))
+        );
+        assert_eq!(
+            check("ABC", validation),
+            Err(String::from("must match the regex ^[a-z{}:\"]+$"))
+        );
+        assert_eq!(
+            check("abc", validation),
+            Err(String::from("must be valid JSON"))
+        );
+        assert_eq!(check("{\"a\":{}}", validation), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn validated_loader_reports_invalid_values() {
+        let config =
+            Config::parse("[secrets.\"test.secret_1\".validate]\nnon_empty = true\n").unwrap();
+        let loader = ValidatedLoader::new(Box::new(MockLoader {}), config.validations());
+
+        let fetched_secrets = loader
+            .load(SecretsToFetch::from_names(vec![
+                String::from("test.secret_1"),
+                String::from("test.secret_2"),
+            ]))
+            .await;
+
+        assert_eq!(
+            fetched_secrets.0["test.secret_1"],
+            FetchedSecret {
+                value: None,
+                error: Some(String::from(
+                    "secret value failed validation: must not be empty"
+                )),
+                code: Some(ErrorCode::ValidationFailed),
+            }
+        );
+        assert_eq!(
+            fetched_secrets.0["test.secret_2"].value,
+            Some(Secret::new(String::new()))
+        );
+    }
+}
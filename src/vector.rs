@@ -1,5 +1,6 @@
 //! This module contains structs for communicating with Vector.
 
+use crate::secret::Secret;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -10,11 +11,82 @@ pub struct SecretsToFetch {
     pub secrets: Vec<String>,
 }
 
+/// Implement the SecretsToFetch constructor for requests not coming from Vector.
+impl SecretsToFetch {
+    pub fn from_names(secrets: Vec<String>) -> Self {
+        Self {
+            version: String::from("1.0"),
+            secrets,
+        }
+    }
+
+    /// Reject requests with more secrets than the limit before anything is fetched.
+    pub fn check_limit(&self, max_secrets: usize) -> Result<(), String> {
+        Self::check_count(self.secrets.len(), max_secrets)
+    }
+
+    /// Reject a number of requested secrets over the limit, e.g. counted while reading a request.
+    pub fn check_count(requested: usize, max_secrets: usize) -> Result<(), String> {
+        if requested > max_secrets {
+            return Err(format!(
+                "too many secrets requested: {} exceeds the limit of {}",
+                requested, max_secrets
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// An enum classifying the reasons a secret could not be retrieved from the target backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    NotFound,
+    AccessDenied,
+    Throttled,
+    QuotaExceeded,
+    DecryptionFailure,
+    Timeout,
+    ConnectionFailure,
+    InvalidRequest,
+    ServiceError,
+    DeadlineExceeded,
+    ValidationFailed,
+    CredentialsUnavailable,
+    Unknown,
+}
+
+/// Implement the ErrorCode methods.
+impl ErrorCode {
+    /// The code as it appears in the JSON output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::AccessDenied => "ACCESS_DENIED",
+            ErrorCode::Throttled => "THROTTLED",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::DecryptionFailure => "DECRYPTION_FAILURE",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::ConnectionFailure => "CONNECTION_FAILURE",
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::ServiceError => "SERVICE_ERROR",
+            ErrorCode::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::CredentialsUnavailable => "CREDENTIALS_UNAVAILABLE",
+            ErrorCode::Unknown => "UNKNOWN",
+        }
+    }
+}
+
 /// A struct representing a single secret value retrieved from the target backend.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct FetchedSecret {
-    pub value: Option<String>,
+    /// The value of the secret, redacted when formatted so that it can't leak into logs.
+    pub value: Option<Secret<String>>,
     pub error: Option<String>,
+    /// The class of the error, omitted on success so that the output stays the same as before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
 }
 
 /// A struct representing the JSON output to Vector.
@@ -40,14 +112,35 @@ mod tests {
         assert_eq!(secrets_to_fetch.secrets, vec!["some_secret"]);
     }
 
+    #[test]
+    fn secrets_to_fetch_can_be_created_from_names() {
+        let secrets_to_fetch = SecretsToFetch::from_names(vec![String::from("some_secret")]);
+        assert_eq!(secrets_to_fetch.version, "1.0");
+        assert_eq!(secrets_to_fetch.secrets, vec!["some_secret"]);
+    }
+
+    #[test]
+    fn secrets_to_fetch_over_the_limit_are_rejected() {
+        let secrets_to_fetch =
+            SecretsToFetch::from_names(vec![String::from("secret_1"), String::from("secret_2")]);
+        assert!(secrets_to_fetch.check_limit(2).is_ok());
+        assert_eq!(
+            secrets_to_fetch.check_limit(1),
+            Err(String::from(
+                "too many secrets requested: 2 exceeds the limit of 1"
+            ))
+        );
+    }
+
     #[test]
     fn fetched_secrets_success_struct_serialization() {
         let mut fetched_secrets = FetchedSecrets::default();
         fetched_secrets.0.insert(
             "/test/secret_1".to_string(),
             FetchedSecret {
-                value: Some("qwerty".to_string()),
+                value: Some(Secret::new("qwerty".to_string())),
                 error: None,
+                code: None,
             },
         );
 
@@ -64,6 +157,7 @@ mod tests {
             FetchedSecret {
                 value: None,
                 error: Some("failed to fetch".to_string()),
+                code: None,
             },
         );
 
@@ -71,4 +165,21 @@ mod tests {
         let output = serde_json::to_string(&fetched_secrets).unwrap();
         assert_eq!(output, expected_output);
     }
+
+    #[test]
+    fn fetched_secrets_classified_failure_struct_serialization() {
+        let mut fetched_secrets = FetchedSecrets::default();
+        fetched_secrets.0.insert(
+            "/test/secret_3".to_string(),
+            FetchedSecret {
+                value: None,
+                error: Some("parameter not found".to_string()),
+                code: Some(ErrorCode::NotFound),
+            },
+        );
+
+        let expected_output = "{\"/test/secret_3\":{\"value\":null,\"error\":\"parameter not found\",\"code\":\"NOT_FOUND\"}}";
+        let output = serde_json::to_string(&fetched_secrets).unwrap();
+        assert_eq!(output, expected_output);
+    }
 }